@@ -0,0 +1,77 @@
+/* C interface to the sound-send data packet codec (Rust feature `ffi`).
+ *
+ * Build the library with
+ *   cargo rustc --lib --release --features ffi --crate-type cdylib
+ * and keep this header in step with src/ffi.rs.
+ *
+ * The caller owns every buffer. ss_decode_packet points
+ * SsDecoded.payload into the caller's input buffer, so it is only valid
+ * while that buffer is. ss_encode_packet writes into a caller-allocated
+ * buffer of at least SS_HEADER_LEN + payload_len bytes and never
+ * allocates. Input and output buffers must not overlap. */
+
+#ifndef SOUND_SEND_H
+#define SOUND_SEND_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SS_OK 0
+#define SS_ERR_TOO_SHORT -1
+#define SS_ERR_BAD_MAGIC -2
+#define SS_ERR_BAD_VERSION -3
+#define SS_ERR_LENGTH_MISMATCH -4
+#define SS_ERR_BUFFER_TOO_SMALL -5
+#define SS_ERR_BAD_CHANNELS -6
+#define SS_ERR_PARTIAL_FRAME -7
+/* A required pointer argument was null. */
+#define SS_ERR_NULL_POINTER -8
+/* SsMeta.sample_format is not a known wire code. */
+#define SS_ERR_BAD_FORMAT -9
+/* The payload does not fit the 16-bit length field. */
+#define SS_ERR_PAYLOAD_TOO_LONG -10
+
+/* Size of the data packet header preceding the payload. */
+#define SS_HEADER_LEN 28
+
+/* sample_format uses the wire codes: 1 = f32, 2 = i16, 3 = u16, 4 = u32. */
+typedef struct SsMeta {
+  uint8_t channels;
+  uint8_t sample_format;
+  uint32_t sample_rate;
+} SsMeta;
+
+/* Flags are 0 or 1. */
+typedef struct SsDecoded {
+  uint64_t seq;
+  uint64_t timestamp_ms;
+  uint8_t monotonic_ts;
+  uint8_t big_endian;
+  SsMeta meta;
+  const uint8_t *payload;
+  size_t payload_len;
+} SsDecoded;
+
+/* Decode the data packet in data[0..len) into *out. Returns SS_OK or a
+ * negative SS_ERR_* code, in which case *out is left untouched. *out must
+ * not overlap data. */
+int32_t ss_decode_packet(const uint8_t *data, size_t len, SsDecoded *out);
+
+/* Encode a data packet into out[0..out_cap), storing the packet length in
+ * *out_len. monotonic_ts (0 or 1) flags timestamp_ms as coming from a
+ * monotonic clock. payload may be NULL when payload_len is 0; it must not
+ * overlap out. Returns SS_OK or a negative SS_ERR_* code. */
+int32_t ss_encode_packet(uint64_t seq, uint64_t timestamp_ms,
+                         uint8_t monotonic_ts, SsMeta meta,
+                         const uint8_t *payload, size_t payload_len,
+                         uint8_t *out, size_t out_cap, size_t *out_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SOUND_SEND_H */
@@ -5,6 +5,9 @@ set -euo pipefail
 cargo build
 cargo build --release
 
+# Packet codec without std/alloc
+cargo build --lib --no-default-features
+
 cargo build --features cpal
 cargo build --release --features cpal
 
@@ -0,0 +1,145 @@
+// Sliding-window replay protection for sequence numbers, as in IPsec
+// (RFC 4303 section 3.4.3): a bitmap of the last `window` sequence numbers
+// below the highest one accepted.
+
+const WORD_BITS: u64 = 64;
+const WORDS: usize = 16;
+
+/// Largest window `AntiReplay` supports, in sequence numbers.
+pub const MAX_ANTI_REPLAY_WINDOW: u64 = WORD_BITS * WORDS as u64;
+
+/// Accepts each sequence number at most once, and rejects any that fall
+/// `window` or more behind the highest accepted so far. Jumps forward are
+/// always accepted, so this stops replays of captured datagrams, not a peer
+/// that can forge sequence numbers.
+#[derive(Debug, Clone)]
+pub struct AntiReplay {
+  window: u64,
+  highest: Option<u64>,
+  // Bit `seq % MAX_ANTI_REPLAY_WINDOW` is set once `seq` is accepted
+  bitmap: [u64; WORDS],
+}
+
+impl AntiReplay {
+  /// `window` is clamped to `1..=MAX_ANTI_REPLAY_WINDOW`.
+  pub fn new(window: u64) -> Self {
+    Self {
+      window: window.clamp(1, MAX_ANTI_REPLAY_WINDOW),
+      highest: None,
+      bitmap: [0; WORDS],
+    }
+  }
+
+  pub fn window(&self) -> u64 {
+    self.window
+  }
+
+  /// Highest sequence number accepted so far.
+  pub fn highest(&self) -> Option<u64> {
+    self.highest
+  }
+
+  /// Returns true and records `seq` if it is new and inside the window;
+  /// returns false for duplicates and for packets that are too old.
+  pub fn check_and_set(&mut self, seq: u64) -> bool {
+    let Some(highest) = self.highest else {
+      self.highest = Some(seq);
+      self.set(seq);
+      return true;
+    };
+    if seq > highest {
+      self.advance(highest, seq);
+      self.highest = Some(seq);
+      self.set(seq);
+      return true;
+    }
+    if highest - seq >= self.window || self.is_set(seq) {
+      return false;
+    }
+    self.set(seq);
+    true
+  }
+
+  /// Forget every sequence number, e.g. for a new session.
+  pub fn reset(&mut self) {
+    self.highest = None;
+    self.bitmap = [0; WORDS];
+  }
+
+  // Clear the bits of the sequence numbers the window slides over
+  fn advance(&mut self, from: u64, to: u64) {
+    if to - from >= MAX_ANTI_REPLAY_WINDOW {
+      self.bitmap = [0; WORDS];
+      return;
+    }
+    for seq in from + 1..=to {
+      let (word, bit) = Self::slot(seq);
+      self.bitmap[word] &= !bit;
+    }
+  }
+
+  fn slot(seq: u64) -> (usize, u64) {
+    let idx = seq % MAX_ANTI_REPLAY_WINDOW;
+    ((idx / WORD_BITS) as usize, 1 << (idx % WORD_BITS))
+  }
+
+  fn set(&mut self, seq: u64) {
+    let (word, bit) = Self::slot(seq);
+    self.bitmap[word] |= bit;
+  }
+
+  fn is_set(&self, seq: u64) -> bool {
+    let (word, bit) = Self::slot(seq);
+    self.bitmap[word] & bit != 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejects_duplicates_and_packets_behind_the_window() {
+    let mut ar = AntiReplay::new(64);
+    assert!(ar.check_and_set(100));
+    assert!(!ar.check_and_set(100), "duplicate of the highest");
+    // Reordered but inside the window: accepted once
+    assert!(ar.check_and_set(90));
+    assert!(!ar.check_and_set(90));
+    // Oldest sequence still inside the window, and the first outside it
+    assert!(ar.check_and_set(100 - 63));
+    assert!(!ar.check_and_set(100 - 64));
+    assert!(!ar.check_and_set(0));
+  }
+
+  #[test]
+  fn far_future_jump_slides_the_whole_window() {
+    let mut ar = AntiReplay::new(MAX_ANTI_REPLAY_WINDOW);
+    for seq in 0..10 {
+      assert!(ar.check_and_set(seq));
+    }
+    let far = 10 * MAX_ANTI_REPLAY_WINDOW + 3;
+    assert!(ar.check_and_set(far));
+    assert_eq!(ar.highest(), Some(far));
+    // Old packets are now far behind; ones that share their bitmap slots
+    // but sit inside the new window are still new
+    assert!(!ar.check_and_set(3));
+    assert!(ar.check_and_set(far - MAX_ANTI_REPLAY_WINDOW + 1));
+    assert!(ar.check_and_set(far - 1));
+    assert!(!ar.check_and_set(far - 1));
+    // A small step forward clears only the slots it passes over
+    assert!(ar.check_and_set(far + 2));
+    assert!(!ar.check_and_set(far - 1));
+  }
+
+  #[test]
+  fn window_is_clamped_and_reset_forgets() {
+    assert_eq!(AntiReplay::new(0).window(), 1);
+    assert_eq!(AntiReplay::new(u64::MAX).window(), MAX_ANTI_REPLAY_WINDOW);
+    let mut ar = AntiReplay::new(1);
+    assert!(ar.check_and_set(5));
+    assert!(!ar.check_and_set(4));
+    ar.reset();
+    assert!(ar.check_and_set(4));
+  }
+}
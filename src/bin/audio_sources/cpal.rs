@@ -1,13 +1,25 @@
+use std::sync::{Arc, Mutex};
+
 use anyhow::{Context, Result, bail};
-use sound_send::packet::{Meta, SampleFormat, SampleRate};
+use log::{debug, error, info, warn};
+use sound_send::input_config::{
+  ConfigRange, ConfigRequest, InputConfig, select_config,
+};
+use sound_send::packet::{Meta, SampleFormat};
 
 use super::{InputOptions, InputSource, ProcessChunk};
-use crate::MAX_PAYLOAD;
+
+// Callbacks whose size is logged after the stream starts; cpal may settle
+// on a different cadence than requested, so report what actually arrives
+const REPORTED_CALLBACKS: u32 = 3;
+
+type ChunkerSlot = Arc<Mutex<Option<ProcessChunk>>>;
 
 pub struct CpalInput {
   device: cpal::Device,
   supported_config: Option<cpal::SupportedStreamConfig>,
   stream: Option<cpal::Stream>,
+  buffer_frames: Option<u32>,
 }
 
 impl CpalInput {
@@ -20,22 +32,29 @@ impl CpalInput {
       device,
       supported_config: supported_config,
       stream: None,
+      buffer_frames: None,
     }
   }
 }
 
 impl InputSource for CpalInput {
-  fn validate_options(&self, opts: &InputOptions) -> Result<()> {
+  fn validate_options(&self, _opts: &InputOptions) -> Result<()> {
+    // --channels/--rate/--format select among the device's configs
+    Ok(())
+  }
+
+  fn prepare_meta(&mut self, opts: &InputOptions) -> Result<Meta> {
+    self.buffer_frames = opts.cpal_frames;
     if opts.channels.is_some()
       || opts.sample_rate.is_some()
       || opts.format.is_some()
     {
-      bail!("--channels/--rate/--format are only valid with --input stdin");
+      self.supported_config = Some(select_input_config(
+        &self.device,
+        self.supported_config.as_ref(),
+        opts,
+      )?);
     }
-    Ok(())
-  }
-
-  fn prepare_meta(&mut self, _opts: &InputOptions) -> Result<Meta> {
     generate_cpal_meta(
       &self.device,
       self.supported_config.as_ref().ok_or(anyhow::anyhow!(
@@ -45,43 +64,155 @@ impl InputSource for CpalInput {
   }
 
   fn start(&mut self, meta: &Meta, process_chunk: ProcessChunk) -> Result<()> {
-    self.stream = Some(generate_cpal_stream(
-      &self.device,
-      &self
-        .supported_config
-        .as_ref()
-        .context("no default input device or supported config found")?
-        .config(),
-      meta.sample_format,
-      process_chunk,
-    )?);
+    use cpal::traits::StreamTrait;
+
+    let supported = self
+      .supported_config
+      .as_ref()
+      .context("no default input device or supported config found")?;
+    let mut config = supported.config();
+    // A failed build drops its callback, so the chunker waits in a slot that
+    // the stream actually built takes it from
+    let chunker = Arc::new(Mutex::new(Some(process_chunk)));
+    let mut stream = None;
+    if let Some(frames) = select_buffer_frames(supported, self.buffer_frames) {
+      config.buffer_size = cpal::BufferSize::Fixed(frames);
+      match build_cpal_stream(
+        &self.device,
+        &config,
+        meta.sample_format,
+        Arc::clone(&chunker),
+      ) {
+        Ok(built) => {
+          info!("Requested buffer: {frames} frames per callback");
+          stream = Some(built);
+        }
+        Err(e) => {
+          warn!(
+            "--cpal-frames {frames} was refused ({e:#}); using the default \
+             buffer size"
+          );
+          config.buffer_size = cpal::BufferSize::Default;
+        }
+      }
+    }
+    let stream = match stream {
+      Some(stream) => stream,
+      None => {
+        build_cpal_stream(&self.device, &config, meta.sample_format, chunker)?
+      }
+    };
+    stream.play().context("failed to start input stream")?;
+    self.stream = Some(stream);
     Ok(())
   }
 }
 
-fn generate_cpal_stream(
+// The wire format for a cpal sample format, if it has one
+fn from_cpal_format(format: cpal::SampleFormat) -> Option<SampleFormat> {
+  match format {
+    cpal::SampleFormat::F32 => Some(SampleFormat::F32),
+    cpal::SampleFormat::I16 => Some(SampleFormat::I16),
+    cpal::SampleFormat::U16 => Some(SampleFormat::U16),
+    cpal::SampleFormat::U32 => Some(SampleFormat::U32),
+    _ => None,
+  }
+}
+
+/// Pick a supported input config honouring the --channels/--rate/--format
+/// overrides. Fields not overridden follow the default config where some
+/// matching range allows it.
+fn select_input_config(
+  device: &cpal::Device,
+  default: Option<&cpal::SupportedStreamConfig>,
+  opts: &InputOptions,
+) -> Result<cpal::SupportedStreamConfig> {
+  use cpal::traits::DeviceTrait;
+
+  let mut ranges: Vec<_> = device
+    .supported_input_configs()
+    .context("failed to query supported input configs")?
+    .collect();
+  let summary: Vec<ConfigRange> = ranges
+    .iter()
+    .map(|r| ConfigRange {
+      format: from_cpal_format(r.sample_format()),
+      channels: r.channels(),
+      min_rate: r.min_sample_rate().0,
+      max_rate: r.max_sample_rate().0,
+    })
+    .collect();
+  let default = default.map(|d| InputConfig {
+    format: from_cpal_format(d.sample_format()),
+    channels: d.channels(),
+    rate: d.sample_rate().0,
+  });
+  let want = ConfigRequest {
+    format: opts.format,
+    channels: opts.channels.map(u16::from),
+    rate: opts.sample_rate,
+  };
+  let (index, rate) = select_config(&summary, default.as_ref(), &want)
+    .with_context(|| {
+      format!(
+        "no supported input config matches --channels {} --rate {} --format {}",
+        describe(opts.channels),
+        describe(opts.sample_rate),
+        describe(opts.format.map(|f| format!("{f:?}"))),
+      )
+    })?;
+  Ok(
+    ranges
+      .swap_remove(index)
+      .with_sample_rate(cpal::SampleRate(rate)),
+  )
+}
+
+/// The --cpal-frames value worth trying: the device's reported range must
+/// contain it, and a device that reports no range gets to try it anyway.
+fn select_buffer_frames(
+  supported: &cpal::SupportedStreamConfig,
+  frames: Option<u32>,
+) -> Option<u32> {
+  let frames = frames?;
+  match *supported.buffer_size() {
+    cpal::SupportedBufferSize::Range { min, max }
+      if !(min..=max).contains(&frames) =>
+    {
+      warn!(
+        "--cpal-frames {frames} is outside the device range {min}..={max}; \
+         using the default buffer size"
+      );
+      None
+    }
+    _ => Some(frames),
+  }
+}
+
+fn describe<T: std::fmt::Display>(v: Option<T>) -> String {
+  v.map_or_else(|| "(any)".to_string(), |v| v.to_string())
+}
+
+fn build_cpal_stream(
   device: &cpal::Device,
   config: &cpal::StreamConfig,
   sample_format: SampleFormat,
-  process_chunk: ProcessChunk,
+  chunker: ChunkerSlot,
 ) -> Result<cpal::Stream> {
-  use cpal::traits::{DeviceTrait, StreamTrait};
-
-  let stream: cpal::Stream = match sample_format {
+  match sample_format {
     SampleFormat::F32 => {
-      build_cpal_input_stream::<f32>(device, &config, process_chunk)?
+      build_cpal_input_stream::<f32>(device, config, chunker)
     }
     SampleFormat::I16 => {
-      build_cpal_input_stream::<i16>(device, &config, process_chunk)?
+      build_cpal_input_stream::<i16>(device, config, chunker)
     }
     SampleFormat::U16 => {
-      build_cpal_input_stream::<u16>(device, &config, process_chunk)?
+      build_cpal_input_stream::<u16>(device, config, chunker)
     }
-    other => bail!("unsupported sample format: {:?}", other),
-  };
-  stream.play().context("failed to start input stream")?;
-
-  Ok(stream)
+    SampleFormat::U32 => {
+      build_cpal_input_stream::<u32>(device, config, chunker)
+    }
+  }
 }
 
 fn generate_cpal_meta(
@@ -90,40 +221,34 @@ fn generate_cpal_meta(
 ) -> Result<Meta> {
   use cpal::traits::DeviceTrait;
 
-  // Metadata to include in each packet
-  let mut packet_meta = Meta {
-    channels: 0,
-    sample_rate: SampleRate(0),
-    sample_format: SampleFormat::F32,
-  };
   let config = supported_config.config();
 
-  println!("Input: CPAL (default audio input)");
-  eprintln!("Device: {:?}", device.name().ok());
-  eprintln!(
+  info!("Input: CPAL (default audio input)");
+  debug!("Device: {:?}", device.name().ok());
+  debug!(
     "  Sample Format: {:?}\n  Sample Rate: {} Hz\n  Channels: {}",
     supported_config.sample_format(),
     config.sample_rate.0,
     config.channels
   );
 
-  // Build metadata (1 byte each)
-  packet_meta.channels = config.channels.min(255) as u8;
-  packet_meta.sample_rate = config.sample_rate.into();
-  packet_meta.sample_format = match supported_config.sample_format() {
-    cpal::SampleFormat::F32 => SampleFormat::F32,
-    cpal::SampleFormat::I16 => SampleFormat::I16,
-    cpal::SampleFormat::U16 => SampleFormat::U16,
-    _ => SampleFormat::Unknown,
+  // Metadata to include in each packet; formats without a wire code are
+  // refused here rather than mislabeled
+  let sample_format = match from_cpal_format(supported_config.sample_format()) {
+    Some(format) => format,
+    None => bail!(
+      "input device only offers {:?} samples; expected f32, i16, u16 or u32",
+      supported_config.sample_format()
+    ),
   };
-
-  Ok(packet_meta)
+  Meta::new(config.channels, config.sample_rate.0, sample_format)
+    .context("unsupported input device configuration")
 }
 
 fn build_cpal_input_stream<T>(
   device: &cpal::Device,
   config: &cpal::StreamConfig,
-  process_chunk: ProcessChunk,
+  slot: ChunkerSlot,
 ) -> Result<cpal::Stream>
 where
   T: cpal::Sample + cpal::SizedSample + bytemuck::Pod + bytemuck::Zeroable,
@@ -131,13 +256,34 @@ where
   use cpal::traits::DeviceTrait;
 
   // Cast &[T] -> &[u8] safely via bytemuck
-  let err_fn = |err| eprintln!("input stream error: {err}");
+  let err_fn = |err| error!("input stream error: {err}");
 
-  let mut chunker = process_chunk;
+  let channels = usize::from(config.channels.max(1));
+  let rate = config.sample_rate.0.max(1);
+  let mut reported = 0;
+  let mut chunker: Option<ProcessChunk> = None;
   let stream = device.build_input_stream(
     config,
     move |data: &[T], _| {
-      chunker(bytemuck::cast_slice(data));
+      if reported < REPORTED_CALLBACKS {
+        reported += 1;
+        let frames = data.len() / channels;
+        info!(
+          "Callback {reported}: {frames} frames ({:.1} ms)",
+          frames as f64 * 1000.0 / f64::from(rate)
+        );
+      }
+      // An empty chunk would read as end of stream
+      if data.is_empty() {
+        return;
+      }
+      // Only the first callbacks touch the slot, and never wait on it
+      if chunker.is_none() {
+        chunker = slot.try_lock().ok().and_then(|mut slot| slot.take());
+      }
+      if let Some(chunker) = chunker.as_mut() {
+        let _ = chunker(bytemuck::cast_slice(data));
+      }
     },
     err_fn,
     None,
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use log::{error, info};
+use sound_send::packet::Meta;
+use sound_send::wav::{DataReader, read_wav_header};
+
+use super::{InputOptions, InputSource, ProcessChunk};
+use crate::MAX_PAYLOAD;
+
+/// Streams the PCM data chunk of a WAV file, paced to real time.
+pub struct FileInput {
+  path: PathBuf,
+  data: Option<(BufReader<File>, u64)>,
+  looping: bool,
+}
+
+impl FileInput {
+  pub fn new(path: PathBuf) -> Self {
+    Self {
+      path,
+      data: None,
+      looping: false,
+    }
+  }
+}
+
+impl InputSource for FileInput {
+  fn validate_options(&self, opts: &InputOptions) -> Result<()> {
+    if opts.channels.is_some()
+      || opts.sample_rate.is_some()
+      || opts.format.is_some()
+    {
+      bail!("--channels/--rate/--format are read from the WAV header");
+    }
+    Ok(())
+  }
+
+  fn prepare_meta(&mut self, opts: &InputOptions) -> Result<Meta> {
+    let file = File::open(&self.path)
+      .with_context(|| format!("failed to open {}", self.path.display()))?;
+    let mut reader = BufReader::new(file);
+    let (meta, data_len) = read_wav_header(&mut reader)
+      .with_context(|| format!("invalid WAV file {}", self.path.display()))?;
+    info!("Input: file {}", self.path.display());
+    info!(
+      "  Sample Format: {:?}\n  Sample Rate: {} Hz\n  Channels: {}",
+      meta.sample_format, meta.sample_rate.0, meta.channels
+    );
+    self.data = Some((reader, data_len));
+    self.looping = opts.looping;
+    Ok(meta)
+  }
+
+  fn start(&mut self, meta: &Meta, process_chunk: ProcessChunk) -> Result<()> {
+    let (reader, data_len) = self
+      .data
+      .take()
+      .context("WAV header must be read before starting")?;
+    let meta = *meta;
+    let looping = self.looping;
+    std::thread::spawn(move || {
+      crate::boost_current_thread_priority();
+      let result = stream_paced(reader, data_len, meta, looping, process_chunk);
+      if let Err(err) = result {
+        error!("file input error: {err:?}");
+      }
+    });
+    Ok(())
+  }
+
+  fn is_live(&self) -> bool {
+    false
+  }
+}
+
+/// Sends the data chunk at real-time pace. With `looping`, the data starts
+/// over at its end; pacing and the sender's sequence numbers carry on
+/// across loops.
+fn stream_paced(
+  reader: BufReader<File>,
+  data_len: u64,
+  meta: Meta,
+  looping: bool,
+  mut process_chunk: ProcessChunk,
+) -> Result<()> {
+  let bps = meta.bytes_per_sample();
+  let frame_bytes = meta.frame_size();
+  let sample_rate = meta.sample_rate.0 as f64;
+  // Whole frames only, so every chunk is sample- and frame-aligned
+  let chunk = (MAX_PAYLOAD / frame_bytes).max(1) * frame_bytes;
+  let mut data = DataReader::new(reader, data_len, frame_bytes, looping)?;
+  let mut buf = vec![0u8; chunk];
+  let start = Instant::now();
+  let mut frames_sent: u64 = 0;
+  loop {
+    let n = read_full(&mut data, &mut buf)?;
+    let n = n - n % frame_bytes;
+    if n == 0 {
+      return process_chunk(&[]);
+    }
+    let chunk = &mut buf[..n];
+    // WAV samples are little-endian; payloads are sent in native order
+    if cfg!(target_endian = "big") {
+      for sample in chunk.chunks_exact_mut(bps) {
+        sample.reverse();
+      }
+    }
+    process_chunk(chunk)?;
+    frames_sent += (n / frame_bytes) as u64;
+    let due = start + Duration::from_secs_f64(frames_sent as f64 / sample_rate);
+    let now = Instant::now();
+    if due > now {
+      std::thread::sleep(due - now);
+    }
+  }
+}
+
+// Fill `buf` as far as possible; returns fewer bytes only at end of input
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    match r.read(&mut buf[filled..])? {
+      0 => break,
+      n => filled += n,
+    }
+  }
+  Ok(filled)
+}
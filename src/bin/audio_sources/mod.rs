@@ -1,28 +1,56 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use sound_send::packet::{Meta, SampleFormat};
+use sound_send::watchdog::InputHeartbeat;
 
+/// Called with each chunk of captured audio. Finite sources call it once
+/// more with an empty chunk when they reach the end of their input, so
+/// other chunks must never be empty.
 pub type ProcessChunk = Box<dyn FnMut(&[u8]) -> Result<()> + Send + 'static>;
 
 pub struct InputOptions {
   pub channels: Option<u8>,
   pub sample_rate: Option<u32>,
   pub format: Option<SampleFormat>,
+  pub path: Option<PathBuf>,
+  /// Restart finite inputs from the beginning instead of ending the stream.
+  /// Ignored by live device inputs.
+  pub looping: bool,
+  /// Stdin starts with a `stream_header` describing the samples, in place
+  /// of the channels/rate/format options.
+  pub stdin_header: bool,
+  /// Frames per capture callback to request from cpal; the device default
+  /// when unset or outside what the device supports.
+  #[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+  pub cpal_frames: Option<u32>,
 }
 
 pub trait InputSource {
   fn validate_options(&self, opts: &InputOptions) -> Result<()>;
   fn prepare_meta(&mut self, opts: &InputOptions) -> Result<Meta>;
   fn start(&mut self, meta: &Meta, process_chunk: ProcessChunk) -> Result<()>;
+  /// Live sources deliver audio from a realtime callback that must never
+  /// block, so send pacing is not applied to them.
+  fn is_live(&self) -> bool {
+    true
+  }
+  /// Every chunk delivered feeds the input watchdog; sources that can tell
+  /// they are alive while delivering nothing, such as a loopback capture
+  /// during silence, also beat `heartbeat` then.
+  fn set_heartbeat(&mut self, _heartbeat: InputHeartbeat) {}
 }
 
 #[cfg(feature = "cpal")]
 pub mod cpal;
+pub mod file;
 pub mod stdin;
 #[cfg(target_os = "windows")]
 pub mod wasapi;
 
 #[cfg(feature = "cpal")]
 pub use cpal::CpalInput;
+pub use file::FileInput;
 pub use stdin::StdinInput;
 #[cfg(target_os = "windows")]
 pub use wasapi::WasapiInput;
@@ -1,7 +1,9 @@
 use std::io::{self, Read};
 
-use anyhow::Result;
-use sound_send::packet::{Meta, SampleFormat, SampleRate};
+use anyhow::{Context, Result, bail};
+use log::info;
+use sound_send::packet::{Meta, SampleFormat};
+use sound_send::stream_header::{STREAM_HEADER_LEN, decode_stream_header};
 
 use super::{InputOptions, InputSource, ProcessChunk};
 use crate::MAX_PAYLOAD;
@@ -9,20 +11,46 @@ use crate::MAX_PAYLOAD;
 pub struct StdinInput;
 
 impl InputSource for StdinInput {
-  fn validate_options(&self, _opts: &InputOptions) -> Result<()> {
+  fn validate_options(&self, opts: &InputOptions) -> Result<()> {
+    if opts.looping {
+      bail!("--loop is not supported with --input stdin (cannot rewind)");
+    }
+    let explicit = opts.channels.is_some()
+      || opts.sample_rate.is_some()
+      || opts.format.is_some();
+    if opts.stdin_header && explicit {
+      bail!(
+        "--stdin-header takes the format from the stream; drop \
+         --channels/--rate/--format"
+      );
+    }
     Ok(())
   }
 
   fn prepare_meta(&mut self, opts: &InputOptions) -> Result<Meta> {
-    Ok(Meta {
-      channels: opts.channels.unwrap_or(2),
-      sample_rate: SampleRate(opts.sample_rate.unwrap_or(48_000)),
-      sample_format: opts.format.unwrap_or(SampleFormat::U32),
-    })
+    if opts.stdin_header {
+      // Stdin is buffered process-wide, so the samples read later in
+      // `start` pick up right after the header
+      let mut header = [0u8; STREAM_HEADER_LEN];
+      io::stdin()
+        .read_exact(&mut header)
+        .context("failed to read stream header from stdin")?;
+      let meta = decode_stream_header(&header)?;
+      info!(
+        "stdin header: {} ch, {} Hz, {:?}",
+        meta.channels, meta.sample_rate.0, meta.sample_format
+      );
+      return Ok(meta);
+    }
+    Ok(Meta::new(
+      opts.channels.unwrap_or(2) as u16,
+      opts.sample_rate.unwrap_or(48_000),
+      opts.format.unwrap_or(SampleFormat::U32),
+    )?)
   }
 
   fn start(&mut self, _meta: &Meta, process_chunk: ProcessChunk) -> Result<()> {
-    println!("Input: stdin (reading raw bytes)");
+    info!("Input: stdin (reading raw bytes)");
     std::thread::spawn(move || {
       crate::boost_current_thread_priority();
       let mut chunker = process_chunk;
@@ -30,7 +58,10 @@ impl InputSource for StdinInput {
       let mut buf = vec![0u8; MAX_PAYLOAD];
       loop {
         match stdin.read(&mut buf) {
-          Ok(0) => break,
+          Ok(0) => {
+            let _ = chunker(&[]);
+            break;
+          }
           Ok(n) => {
             if chunker(&buf[..n]).is_err() {
               break;
@@ -42,4 +73,8 @@ impl InputSource for StdinInput {
     });
     Ok(())
   }
+
+  fn is_live(&self) -> bool {
+    false
+  }
 }
@@ -1,15 +1,18 @@
-use std::{ffi::c_void, thread};
+use std::{ffi::c_void, thread, time::Duration};
 
 use anyhow::{Context, Result, anyhow, bail};
-use sound_send::packet::{Meta, SampleFormat, SampleRate};
+use log::{debug, error, info, warn};
+use sound_send::packet::{Meta, SampleFormat};
+use sound_send::watchdog::{InputHeartbeat, capture_following};
 use windows::Win32::{
   Foundation::{CloseHandle, HANDLE, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT},
   Media::Audio::{
-    AUDCLNT_BUFFERFLAGS_SILENT, AUDCLNT_SHAREMODE_SHARED,
-    AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
-    AUDCLNT_STREAMFLAGS_LOOPBACK, AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
-    IAudioCaptureClient, IAudioClient3, IMMDevice, IMMDeviceEnumerator,
-    MMDeviceEnumerator, WAVEFORMATEX, WAVEFORMATEXTENSIBLE, eConsole,
+    AUDCLNT_BUFFERFLAGS_SILENT, AUDCLNT_E_DEVICE_INVALIDATED,
+    AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+    AUDCLNT_STREAMFLAGS_EVENTCALLBACK, AUDCLNT_STREAMFLAGS_LOOPBACK,
+    AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY, IAudioCaptureClient,
+    IAudioClient3, IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator,
+    WAVEFORMATEX, WAVEFORMATEXTENSIBLE, eConsole,
   },
   Media::KernelStreaming::KSDATAFORMAT_SUBTYPE_PCM,
   Media::Multimedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
@@ -27,10 +30,13 @@ use crate::{MAX_PAYLOAD, PAYLOAD_ALIGNMENT};
 
 const WAVE_FORMAT_IEEE_FLOAT_TAG: u16 = 0x0003;
 const WAVE_FORMAT_EXTENSIBLE_TAG: u16 = 0xFFFE;
+// While no default render device exists (e.g. mid-switch), poll this often
+const DEVICE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Default)]
 pub struct WasapiInput {
   config: Option<LoopbackConfig>,
+  heartbeat: Option<InputHeartbeat>,
 }
 
 impl InputSource for WasapiInput {
@@ -39,7 +45,10 @@ impl InputSource for WasapiInput {
       || opts.sample_rate.is_some()
       || opts.format.is_some()
     {
-      bail!("--channels/--rate/--format are only valid with --input stdin");
+      bail!(
+        "--channels/--rate/--format are not supported with WASAPI loopback \
+         (the mix format is fixed)"
+      );
     }
     Ok(())
   }
@@ -51,14 +60,18 @@ impl InputSource for WasapiInput {
   }
 
   fn start(&mut self, _meta: &Meta, process_chunk: ProcessChunk) -> Result<()> {
-    println!("Input: WASAPI loopback (default render mix)");
+    info!("Input: WASAPI loopback (default render mix)");
     let config = self
       .config
       .take()
       .expect("wasapi configuration missing before capture start");
-    spawn_loopback_capture(config, process_chunk)?;
+    spawn_loopback_capture(config, process_chunk, self.heartbeat.take())?;
     Ok(())
   }
+
+  fn set_heartbeat(&mut self, heartbeat: InputHeartbeat) {
+    self.heartbeat = Some(heartbeat);
+  }
 }
 
 struct AudioFormat {
@@ -335,49 +348,67 @@ pub(super) fn prepare_loopback() -> Result<(Meta, LoopbackConfig)> {
   let periods = query_shared_mode_engine_period(&audio_client, &format)
     .context("failed to query shared-mode engine period for loopback")?;
 
-  let meta = Meta {
-    channels: channels.min(255) as u8,
-    sample_rate: SampleRate(sample_rate),
-    sample_format: SampleFormat::F32,
-  };
+  let meta = Meta::new(channels, sample_rate, SampleFormat::F32)
+    .context("unsupported loopback mix format")?;
 
   Ok((meta, LoopbackConfig { format, periods }))
 }
 
+/// Print the default render device's mix format and engine periods, as
+/// capture would use them, without starting a stream.
+pub fn print_loopback_info() -> Result<()> {
+  let (_, config) = prepare_loopback()?;
+  for line in loopback_details(&config) {
+    println!("{line}");
+  }
+  Ok(())
+}
+
+// Mix format, engine periods and the buffer duration capture will request
+fn loopback_details(config: &LoopbackConfig) -> Vec<String> {
+  let format = &config.format;
+  let periods = &config.periods;
+  let buffer_duration_hns =
+    frames_to_100ns(periods.min_period_frames, format.sample_rate());
+  vec![
+    format!("Channels: {}", format.channels()),
+    format!("Sample Rate: {}", format.sample_rate()),
+    format!("Sample Bits: {}", format.bits_per_sample()),
+    format!("Sample Format: {}", format.subformat_label()),
+    format!("Block Align: {}", format.block_align()),
+    format!(
+      "Engine Periods (frames): default={}, fundamental={}, min={}, max={}",
+      periods.default_period_frames,
+      periods.fundamental_period_frames,
+      periods.min_period_frames,
+      periods.max_period_frames
+    ),
+    format!("Selected buffer duration (100ns units): {buffer_duration_hns}"),
+    format!(
+      "Selected buffer duration (ms): {:.3}",
+      buffer_duration_hns as f64 / 10_000.0
+    ),
+  ]
+}
+
 pub(super) fn spawn_loopback_capture(
   config: LoopbackConfig,
   process_chunk: ProcessChunk,
+  heartbeat: Option<InputHeartbeat>,
 ) -> Result<()> {
-  let channels = config.format.channels();
-  let sample_rate = config.format.sample_rate();
-  let buffer_duration_hns =
-    frames_to_100ns(config.periods.min_period_frames, sample_rate);
-
-  eprintln!("Channels: {channels}");
-  eprintln!("Sample Rate: {sample_rate}");
-  eprintln!("Sample Bits: {}", config.format.bits_per_sample());
-  eprintln!("Sample Format: {}", config.format.subformat_label());
-  eprintln!("Block Align: {}", config.format.block_align());
-  eprintln!(
-    "Engine Periods (frames): default={}, fundamental={}, min={}, max={}",
-    config.periods.default_period_frames,
-    config.periods.fundamental_period_frames,
-    config.periods.min_period_frames,
-    config.periods.max_period_frames
-  );
-  eprintln!("Selected buffer duration (100ns units): {buffer_duration_hns}");
-  eprintln!(
-    "Selected buffer duration (ms): {:.3}",
-    buffer_duration_hns as f64 / 10_000.0
-  );
+  for line in loopback_details(&config) {
+    debug!("{line}");
+  }
 
   thread::Builder::new()
     .name("wasapi-loopback".to_string())
     .spawn(move || {
       crate::boost_current_thread_priority();
       let mut chunker = process_chunk;
-      if let Err(err) = run_loopback_capture(config, &mut chunker) {
-        eprintln!("WASAPI loopback capture error: {err:?}");
+      if let Err(err) =
+        run_loopback_capture(config, &mut chunker, heartbeat.as_ref())
+      {
+        error!("WASAPI loopback capture error: {err:?}");
       }
     })
     .context("failed to spawn WASAPI loopback thread")?;
@@ -387,14 +418,87 @@ pub(super) fn spawn_loopback_capture(
 fn run_loopback_capture(
   config: LoopbackConfig,
   process_chunk: &mut dyn FnMut(&[u8]) -> Result<()>,
+  heartbeat: Option<&InputHeartbeat>,
 ) -> Result<()> {
   let _com = ComGuard::init_mta()?;
 
   let device = get_default_render_device(Role::Console)
     .context("no default render device for loopback")?;
+  // Changing or unplugging the default device invalidates the client; follow
+  // the new default instead of stopping. The stream format stays fixed and
+  // AUTOCONVERTPCM converts if the new device mixes at another format.
+  capture_following(
+    device,
+    |device| capture_device(device, &config, process_chunk, heartbeat),
+    is_device_invalidated,
+    || {
+      info!("Loopback device invalidated; switching to the new default");
+      // Give the switch a moment so we don't spin on the old endpoint
+      thread::sleep(DEVICE_RETRY_INTERVAL / 10);
+      let device = wait_for_default_render_device();
+      info!("Loopback capture now on device {}", device_id(&device));
+      device
+    },
+  )
+}
+
+fn is_device_invalidated(err: &anyhow::Error) -> bool {
+  err.chain().any(|e| {
+    e.downcast_ref::<windows::core::Error>()
+      .is_some_and(|e| e.code() == AUDCLNT_E_DEVICE_INVALIDATED)
+  })
+}
+
+fn wait_for_default_render_device() -> IMMDevice {
+  let mut warned = false;
+  loop {
+    match get_default_render_device(Role::Console) {
+      Ok(device) => return device,
+      Err(err) if !warned => {
+        warn!("No default render device yet; waiting: {err:#}");
+        warned = true;
+      }
+      Err(err) => debug!("still no default render device: {err:#}"),
+    }
+    thread::sleep(DEVICE_RETRY_INTERVAL);
+  }
+}
+
+fn device_id(device: &IMMDevice) -> String {
+  unsafe {
+    match device.GetId() {
+      Ok(id) => {
+        let s = id.to_string().unwrap_or_else(|_| "<invalid id>".into());
+        CoTaskMemFree(Some(id.0 as *const c_void));
+        s
+      }
+      Err(_) => "<unknown>".into(),
+    }
+  }
+}
+
+fn capture_device(
+  device: &IMMDevice,
+  config: &LoopbackConfig,
+  process_chunk: &mut dyn FnMut(&[u8]) -> Result<()>,
+  heartbeat: Option<&InputHeartbeat>,
+) -> Result<()> {
   let audio_client: IAudioClient3 =
     unsafe { device.Activate::<IAudioClient3>(CLSCTX_ALL, None) }
       .context("failed to activate IAudioClient3 for loopback")?;
+  let mix = query_mix_format(&audio_client)
+    .context("failed to query mix format for loopback")?;
+  if mix.sample_rate() != config.format.sample_rate()
+    || mix.channels() != config.format.channels()
+  {
+    info!(
+      "Loopback device mixes at {} Hz/{} ch; converting to {} Hz/{} ch",
+      mix.sample_rate(),
+      mix.channels(),
+      config.format.sample_rate(),
+      config.format.channels()
+    );
+  }
   let sample_rate = config.format.sample_rate();
   let buffer_duration_hns =
     frames_to_100ns(config.periods.min_period_frames, sample_rate);
@@ -428,8 +532,8 @@ fn run_loopback_capture(
     .context("failed to start WASAPI loopback stream")?;
 
   let frame_bytes = config.format.block_align() as usize;
-  assert!(PAYLOAD_ALIGNMENT % frame_bytes == 0);
-  assert!(MAX_PAYLOAD % frame_bytes == 0);
+  assert!(PAYLOAD_ALIGNMENT.is_multiple_of(frame_bytes));
+  assert!(MAX_PAYLOAD.is_multiple_of(frame_bytes));
 
   let run_result: Result<(), anyhow::Error> = loop {
     if let Err(err) =
@@ -437,6 +541,11 @@ fn run_loopback_capture(
     {
       break Err(err);
     }
+    // Loopback delivers no packets while nothing plays; a client that
+    // still answers GetNextPacketSize is alive all the same
+    if let Some(heartbeat) = heartbeat {
+      heartbeat.beat();
+    }
 
     match event.wait(2000)? {
       EventWait::Signaled => {}
@@ -448,8 +557,15 @@ fn run_loopback_capture(
     .context("failed to stop WASAPI loopback stream");
 
   if let Err(run_err) = run_result {
-    if let Err(stop_err) = stop_result {
-      eprintln!("failed to stop WASAPI loopback stream: {stop_err:?}");
+    match stop_result {
+      // Stopping a client on an invalidated device fails the same way
+      Err(stop_err) if is_device_invalidated(&run_err) => {
+        debug!("stop after device invalidation: {stop_err:?}")
+      }
+      Err(stop_err) => {
+        error!("failed to stop WASAPI loopback stream: {stop_err:?}")
+      }
+      Ok(()) => {}
     }
     Err(run_err)
   } else {
@@ -464,7 +580,7 @@ fn drain_packets(
   frame_bytes: usize,
   process_chunk: &mut dyn FnMut(&[u8]) -> Result<()>,
 ) -> Result<()> {
-  assert!(chunk_stride % frame_bytes == 0);
+  assert!(chunk_stride.is_multiple_of(frame_bytes));
   assert!(chunk_stride >= frame_bytes);
 
   loop {
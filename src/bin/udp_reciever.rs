@@ -1,35 +1,397 @@
 use std::collections::HashMap;
 use std::env;
+use std::fs::File;
 use std::io::{self, Write};
-use std::net::UdpSocket;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
+use log::{debug, info, warn};
+use sound_send::anti_replay::{AntiReplay, MAX_ANTI_REPLAY_WINDOW};
+use sound_send::capture::CaptureWriter;
+use sound_send::clients::{StreamEvent, evict_least_recent, stream_event};
+use sound_send::convert::swap_sample_bytes;
+use sound_send::dsp::AudioFrameReader;
+use sound_send::gain::MAX_GAIN_DB;
 use sound_send::packet::{
-  Message, SyncMessage, decode_message, respond_to_ping,
+  ByteOrder, DecodeError, DecodeErrorCounts, Message, Meta, SampleFormat,
+  SyncMessage, VersionPolicy, decode_message, decode_messages_with_policy,
+  encode_sync, respond_to_ping, unix_time_ms,
 };
-use sound_send::payload_sink::BinarySink;
-use sound_send::recv_stats::RecvStats;
-use sound_send::sync_controller::DefaultSyncController;
+#[cfg(feature = "cpal")]
+use sound_send::payload_sink::output_device;
+use sound_send::payload_sink::{
+  BinarySink, ClosedOutputs, DEFAULT_PLAYBACK_DEPTH, SinkTarget, probe_player,
+};
+use sound_send::plc::{LossConcealer, PlcMode};
+use sound_send::recv_stats::{
+  MAX_REORDER_WINDOW, RecvStats, SeqVerdict, describe_meta,
+};
+use sound_send::reorder::{ReorderBuffer, ReorderEvent};
+use sound_send::sockopt;
+use sound_send::status::{Heartbeat, init_logging, set_quiet, set_verbosity};
+use sound_send::sync_controller::{
+  DefaultSyncController, SyncAlgorithm, SyncController, SyncStateCache,
+};
+use sound_send::trace::{PacketTrace, TraceRow};
+use sound_send::transport::{DualStackUdp, TcpServer, Transport, recv_or_idle};
 // no local process spawning; handled by payload_sink
 
 // RecvStats moved to sound_send::recv_stats
 
 // Sync controller moved to sound_send::sync_controller
 
+// Upper bound on tracked senders; each one owns a sink (possibly a pw-cat
+// process), so this also bounds what a spoofed-source flood can allocate.
+const DEFAULT_MAX_CLIENTS: usize = 64;
+// A client without data packets for this long is flagged in the status
+const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(2);
+// Minimum spacing between FormatRequests re-sent to a sender that has not
+// switched to the requested format yet
+const FORMAT_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+// Spacing of the Control messages sent with --remote-gain-db/--remote-mute
+const CONTROL_INTERVAL: Duration = Duration::from_secs(1);
+// Lost packets in one gap that trigger an immediate time-sync ping
+const FAST_PING_GAP: u64 = 8;
+// Packets held waiting for a missing one by default (--reorder)
+const DEFAULT_REORDER_WINDOW: usize = 4;
+// Sequence numbers tracked by --anti-replay without an explicit window
+const DEFAULT_ANTI_REPLAY_WINDOW: u64 = MAX_ANTI_REPLAY_WINDOW;
+// Held packets are released once their sender has been quiet this long
+const REORDER_MAX_HOLD: Duration = Duration::from_millis(100);
+
+// Raised by signal handlers (see install_hist_signal_handlers) and polled
+// by the receive loop
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// With --latency-hist: SIGUSR1 asks for a histogram dump, SIGINT and
+/// SIGTERM for a dump and a clean exit.
+#[cfg(unix)]
+fn install_hist_signal_handlers() {
+  extern "C" fn on_dump(_: libc::c_int) {
+    DUMP_REQUESTED.store(true, Ordering::Relaxed);
+  }
+  extern "C" fn on_stop(_: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+  }
+  // Without SA_RESTART a signal interrupts a blocking receive at once. std
+  // retries some calls on EINTR regardless, such as opening a FIFO that has
+  // no reader yet, so the stop handlers are one-shot (SA_RESETHAND): a
+  // second Ctrl+C gets the default disposition and ends the process.
+  let install = |signal, handler: extern "C" fn(libc::c_int), flags| {
+    // SAFETY: the handlers only store to atomics, which is
+    // async-signal-safe, and `action` is fully initialized
+    unsafe {
+      let mut action: libc::sigaction = std::mem::zeroed();
+      action.sa_sigaction = handler as libc::sighandler_t;
+      action.sa_flags = flags;
+      libc::sigemptyset(&mut action.sa_mask);
+      libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+  };
+  install(libc::SIGUSR1, on_dump, 0);
+  install(libc::SIGINT, on_stop, libc::SA_RESETHAND);
+  install(libc::SIGTERM, on_stop, libc::SA_RESETHAND);
+}
+
+#[cfg(not(unix))]
+fn install_hist_signal_handlers() {
+  warn!("--latency-hist cannot catch signals on this platform");
+}
+
 fn main() -> io::Result<()> {
+  init_logging();
   // 1. Parse listening address and options
   let mut args = env::args();
   let prog = args.next().unwrap_or_else(|| "udp_reciever".into());
   let mut listen_addr: Option<String> = None;
-  let mut use_pipewire = false;
+  let mut sink_target = SinkTarget::Stdout;
+  let mut paplay_fallback = false;
+  let mut playback_depth = DEFAULT_PLAYBACK_DEPTH;
+  let mut sink_buffer_bytes: usize = 0;
   let mut show_progress = false;
-  for arg in args {
+  let mut use_tcp = false;
+  let mut show_hist = false;
+  let mut info_mode = false;
+  let mut record_path: Option<String> = None;
+  let mut trace_path: Option<String> = None;
+  let mut sync_algo = SyncAlgorithm::Ewma;
+  let mut max_clients: usize = DEFAULT_MAX_CLIENTS;
+  let mut request_format: Option<SampleFormat> = None;
+  let mut request_rate: u32 = 0;
+  // Level every sender is asked for with Control messages, if any
+  let mut remote_gain_db: Option<i8> = None;
+  let mut remote_mute = false;
+  let mut out_format: Option<SampleFormat> = None;
+  let mut heartbeat: Option<Duration> = None;
+  let mut latency_hist = false;
+  let mut stale_after = DEFAULT_STALE_AFTER;
+  let mut rcvbuf: Option<usize> = None;
+  let mut interface: Option<String> = None;
+  let mut reorder_window = DEFAULT_REORDER_WINDOW;
+  let mut anti_replay_window: Option<u64> = None;
+  let mut version_policy = VersionPolicy::Lenient;
+  let mut plc_mode = PlcMode::None;
+  let mut out_device: Option<String> = None;
+  let mut verbosity: u8 = 0;
+  while let Some(arg) = args.next() {
     match arg.as_str() {
-      "--pipewire" => use_pipewire = true,
+      "--pipewire" | "--aplay" => {
+        let target = if arg == "--aplay" {
+          SinkTarget::Aplay
+        } else {
+          SinkTarget::PipeWire
+        };
+        let is_player =
+          matches!(sink_target, SinkTarget::PipeWire | SinkTarget::Aplay);
+        if is_player && sink_target != target {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--pipewire and --aplay are mutually exclusive",
+          ));
+        }
+        sink_target = target;
+      }
+      #[cfg(feature = "cpal")]
+      "--cpal" => sink_target = SinkTarget::Cpal,
+      #[cfg(not(feature = "cpal"))]
+      "--cpal" => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "--cpal requires a build with the use_cpal feature",
+        ));
+      }
+      "--out-device" => {
+        out_device = Some(args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--out-device requires a device name",
+          )
+        })?);
+      }
+      _ if arg.starts_with("--out-device=") => {
+        out_device = Some(arg[13..].to_string());
+      }
+      // --jitter-buffer-ms is the original name
+      "--target-latency-ms" | "--jitter-buffer-ms" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{arg} requires a value"),
+          )
+        })?;
+        playback_depth = parse_target_latency_ms(&val)?;
+      }
+      _ if arg.starts_with("--target-latency-ms=") => {
+        playback_depth = parse_target_latency_ms(&arg[20..])?;
+      }
+      _ if arg.starts_with("--jitter-buffer-ms=") => {
+        playback_depth = parse_target_latency_ms(&arg[19..])?;
+      }
+      "--paplay-fallback" => paplay_fallback = true,
+      "--fifo" => {
+        let path = args.next().ok_or_else(|| {
+          io::Error::new(io::ErrorKind::InvalidInput, "--fifo requires a path")
+        })?;
+        sink_target = SinkTarget::Fifo(path.into());
+      }
+      _ if arg.starts_with("--fifo=") => {
+        sink_target = SinkTarget::Fifo(arg[7..].into());
+      }
+      "--sink-buffer-bytes" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--sink-buffer-bytes requires a value",
+          )
+        })?;
+        sink_buffer_bytes = parse_sink_buffer_bytes(&val)?;
+      }
+      _ if arg.starts_with("--sink-buffer-bytes=") => {
+        sink_buffer_bytes = parse_sink_buffer_bytes(&arg[20..])?;
+      }
       "--progress" => show_progress = true,
+      "--tcp" => use_tcp = true,
+      "--hist" => show_hist = true,
+      "--strict-version" => version_policy = VersionPolicy::Strict,
+      "--info" => info_mode = true,
+      "--latency-hist" => latency_hist = true,
+      "-q" | "--quiet" => set_quiet(true),
+      "-v" | "--verbose" => verbosity = verbosity.saturating_add(1),
+      "-vv" => verbosity = verbosity.saturating_add(2),
+      "--record" => {
+        record_path = Some(args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--record requires a file",
+          )
+        })?);
+      }
+      _ if arg.starts_with("--record=") => {
+        record_path = Some(arg[9..].to_string());
+      }
+      "--trace" => {
+        trace_path = Some(args.next().ok_or_else(|| {
+          io::Error::new(io::ErrorKind::InvalidInput, "--trace requires a file")
+        })?);
+      }
+      _ if arg.starts_with("--trace=") => {
+        trace_path = Some(arg[8..].to_string());
+      }
+      "--max-clients" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--max-clients requires a value",
+          )
+        })?;
+        max_clients = parse_max_clients(&val)?;
+      }
+      _ if arg.starts_with("--max-clients=") => {
+        max_clients = parse_max_clients(&arg[14..])?;
+      }
+      "--sync-algo" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--sync-algo requires a value (ewma|median)",
+          )
+        })?;
+        sync_algo = parse_sync_algo(&val)?;
+      }
+      _ if arg.starts_with("--sync-algo=") => {
+        sync_algo = parse_sync_algo(&arg[12..])?;
+      }
+      "--request-format" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--request-format requires a value (f32|i16|u16|u32)",
+          )
+        })?;
+        request_format = Some(parse_sample_format("--request-format", &val)?);
+      }
+      _ if arg.starts_with("--request-format=") => {
+        request_format =
+          Some(parse_sample_format("--request-format", &arg[17..])?);
+      }
+      "--out-format" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--out-format requires a value (f32|i16|u16|u32)",
+          )
+        })?;
+        out_format = Some(parse_sample_format("--out-format", &val)?);
+      }
+      _ if arg.starts_with("--out-format=") => {
+        out_format = Some(parse_sample_format("--out-format", &arg[13..])?);
+      }
+      "--request-rate" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--request-rate requires a value",
+          )
+        })?;
+        request_rate = parse_request_rate(&val)?;
+      }
+      _ if arg.starts_with("--request-rate=") => {
+        request_rate = parse_request_rate(&arg[15..])?;
+      }
+      "--remote-gain-db" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--remote-gain-db requires a value",
+          )
+        })?;
+        remote_gain_db = Some(parse_remote_gain_db(&val)?);
+      }
+      _ if arg.starts_with("--remote-gain-db=") => {
+        remote_gain_db = Some(parse_remote_gain_db(&arg[17..])?);
+      }
+      "--remote-mute" => remote_mute = true,
+      "--anti-replay" => {
+        anti_replay_window = Some(DEFAULT_ANTI_REPLAY_WINDOW);
+      }
+      _ if arg.starts_with("--anti-replay=") => {
+        anti_replay_window = Some(parse_anti_replay(&arg[14..])?);
+      }
+      "--reorder" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--reorder requires a value",
+          )
+        })?;
+        reorder_window = parse_reorder(&val)?;
+      }
+      _ if arg.starts_with("--reorder=") => {
+        reorder_window = parse_reorder(&arg[10..])?;
+      }
+      "--plc" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--plc requires a value (silence|repeat|none)",
+          )
+        })?;
+        plc_mode = parse_plc(&val)?;
+      }
+      _ if arg.starts_with("--plc=") => {
+        plc_mode = parse_plc(&arg[6..])?;
+      }
+      "--interface" => {
+        interface = Some(args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--interface requires a name",
+          )
+        })?);
+      }
+      _ if arg.starts_with("--interface=") => {
+        interface = Some(arg[12..].to_string());
+      }
+      "--rcvbuf" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--rcvbuf requires a value",
+          )
+        })?;
+        rcvbuf = Some(parse_rcvbuf(&val)?);
+      }
+      _ if arg.starts_with("--rcvbuf=") => {
+        rcvbuf = Some(parse_rcvbuf(&arg[9..])?);
+      }
+      "--heartbeat-secs" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--heartbeat-secs requires a value",
+          )
+        })?;
+        heartbeat = Some(parse_heartbeat_secs(&val)?);
+      }
+      _ if arg.starts_with("--heartbeat-secs=") => {
+        heartbeat = Some(parse_heartbeat_secs(&arg[17..])?);
+      }
+      "--stale-ms" => {
+        let val = args.next().ok_or_else(|| {
+          io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--stale-ms requires a value",
+          )
+        })?;
+        stale_after = parse_stale_ms(&val)?;
+      }
+      _ if arg.starts_with("--stale-ms=") => {
+        stale_after = parse_stale_ms(&arg[11..])?;
+      }
       "-h" | "--help" => {
-        eprintln!("Usage: {} <listen_addr:port> [--pipewire]", prog);
-        eprintln!("Example: {} 127.0.0.1:12345", prog);
+        print_usage(&prog);
         return Ok(());
       }
       s if s.starts_with('-') => {
@@ -50,160 +412,275 @@ fn main() -> io::Result<()> {
       }
     }
   }
+  set_verbosity(verbosity);
   let listen_addr = listen_addr.ok_or_else(|| {
     io::Error::new(io::ErrorKind::InvalidInput, "missing listen address")
   })?;
+  // Only describe the streams; the audio itself goes nowhere
+  if info_mode {
+    if sink_target != SinkTarget::Stdout {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "--info does not play audio; drop the output option",
+      ));
+    }
+    sink_target = SinkTarget::Discard;
+  }
+  #[cfg(feature = "cpal")]
+  let cpal_output = sink_target == SinkTarget::Cpal;
+  #[cfg(not(feature = "cpal"))]
+  let cpal_output = false;
+  if out_device.is_some() && !cpal_output {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "--out-device requires --cpal",
+    ));
+  }
+  // Fail fast if the player is missing instead of on the first packet
+  match sink_target {
+    SinkTarget::PipeWire => {
+      if let Err(e) = probe_player("pw-cat") {
+        if !paplay_fallback {
+          return Err(e);
+        }
+        probe_player("paplay")?;
+        info!("pw-cat not found; falling back to paplay");
+      }
+    }
+    SinkTarget::Aplay => probe_player("aplay")?,
+    #[cfg(feature = "cpal")]
+    SinkTarget::Cpal => {
+      use cpal::traits::DeviceTrait;
+
+      let device = output_device(out_device.as_deref())?;
+      info!(
+        "Output device: {}",
+        device.name().unwrap_or_else(|_| "(unnamed)".into())
+      );
+    }
+    _ => {}
+  }
+  let remote_level = (remote_gain_db.is_some() || remote_mute)
+    .then(|| (remote_gain_db.unwrap_or(0), remote_mute));
+  if request_rate != 0 && request_format.is_none() {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "--request-rate requires --request-format",
+    ));
+  }
 
-  // 2. Bind UDP socket and start listening
-  let socket = UdpSocket::bind(listen_addr)?;
-  eprintln!("Listening on {} ...", socket.local_addr()?);
+  // 2. Bind UDP socket (or TCP listener) and start listening
+  if use_tcp && rcvbuf.is_some() {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "--rcvbuf is not supported with --tcp",
+    ));
+  }
+  if use_tcp && interface.is_some() {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "--interface is not supported with --tcp",
+    ));
+  }
+  let socket: Box<dyn Transport> = if use_tcp {
+    let server = TcpServer::bind_with_limit(listen_addr, max_clients)?;
+    info!("Listening on tcp {} ...", server.local_addr());
+    Box::new(server)
+  } else {
+    let (socket, dual_stack) = bind_udp(&listen_addr)?;
+    if dual_stack {
+      info!("Listening on {} (IPv4 and IPv6) ...", socket.local_addr()?);
+    } else {
+      info!("Listening on {} ...", socket.local_addr()?);
+    }
+    if let Some(name) = interface.as_deref() {
+      match sockopt::bind_to_device(&socket, name) {
+        Ok(()) => info!("Receiving only on interface {name}"),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+          warn!("--interface ignored: {e}; listening on every interface");
+        }
+        Err(e) => {
+          return Err(io::Error::new(
+            e.kind(),
+            format!("cannot bind to interface {name}: {e}"),
+          ));
+        }
+      }
+    }
+    if let Some(bytes) = rcvbuf {
+      sockopt::set_recv_buffer_size(&socket, bytes)?;
+      // The OS often doubles or caps the request, so report what stuck
+      info!(
+        "Receive buffer: {} bytes (requested {bytes})",
+        sockopt::recv_buffer_size(&socket)?
+      );
+    }
+    if dual_stack {
+      Box::new(DualStackUdp::new(socket))
+    } else {
+      Box::new(socket)
+    }
+  };
+  socket.set_recv_timeout(Some(UPDATE_INTERVAL))?;
+
+  // Optionally record every raw datagram for later replay (udp_replay)
+  let mut recorder = match record_path {
+    Some(path) => {
+      info!("Recording datagrams to {}", path);
+      Some(CaptureWriter::new(File::create(path)?)?)
+    }
+    None => None,
+  };
+  let record_start = Instant::now();
+  // Optionally log every data packet's timing as CSV
+  let mut trace = match trace_path {
+    Some(path) => {
+      info!("Tracing packets to {}", path);
+      Some(PacketTrace::new(File::create(path)?)?)
+    }
+    None => None,
+  };
 
   // 3. Prepare receive buffer and statistics
   // UDP max payload is 65507 bytes, but typical MTU is ~1500
   // Use a buffer larger than the client's chunk size to be safe
   let mut buf = [0; 2048];
+  // Scratch space for byte-swapping foreign-endian payloads
+  let mut swap_buf: Vec<u8> = Vec::new();
   // stats update interval (0.2s)
   const UPDATE_INTERVAL: Duration = Duration::from_millis(200);
   const WINDOW: Duration = Duration::from_secs(10);
   const VOLUME_WINDOW: Duration = Duration::from_secs(1);
 
-  // Per-client context: sink + stats + expected seq + last seen time
-  struct ClientCtx {
-    sink: BinarySink,
-    stats: RecvStats,
-    expected_seq: u64,
-    last_seen: Instant,
-  }
-
   let mut clients: HashMap<std::net::SocketAddr, ClientCtx> = HashMap::new();
+  // Clients whose output was closed by its reader; their packets are
+  // ignored until they start a new session instead of opening a new output
+  let mut closed_outputs = ClosedOutputs::default();
+  // Clients found with a closed output during this pass
+  let mut closed_clients: Vec<SocketAddr> = Vec::new();
   const SINK_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+  // How long a departed sender's time-sync estimate is kept for reuse
+  const SYNC_STATE_TTL: Duration = Duration::from_secs(300);
+  let mut sync_cache = SyncStateCache::new(SYNC_STATE_TTL);
+
+  let mut last_trace_flush = Instant::now();
+  // Decode failures from every source, outliving evicted clients
+  let mut decode_errors = DecodeErrorCounts::default();
+  // Data packets from every source since startup, for the heartbeat
+  let mut total_packets: u64 = 0;
+  let mut heartbeat =
+    heartbeat.map(|every| Heartbeat::new(every, Instant::now()));
 
   // Render state for multi-line display
   let mut rendered_lines: usize = 0;
   let mut last_render = Instant::now();
   // Hide cursor for smoother refresh
-  eprint!("\x1b[?25l");
+  let render = show_progress || show_hist || info_mode;
+  if render {
+    eprint!("\x1b[?25l");
+  }
+
+  if latency_hist {
+    install_hist_signal_handlers();
+  }
 
   // 4. Receive loop
   loop {
-    // Receive data; get byte count and source address
-    let (bytes_received, src_addr) = socket.recv_from(&mut buf)?;
-
-    // Decode control or audio packet in a unified match
-    let ctx = clients.entry(src_addr).or_insert_with(|| ClientCtx {
-      sink: BinarySink::new(use_pipewire),
-      stats: RecvStats::new(
-        WINDOW,
-        VOLUME_WINDOW,
-        DefaultSyncController::with_default_estimator(0.2, 0.2, 1_000),
-      ),
-      expected_seq: 0,
-      last_seen: Instant::now(),
-    });
-    ctx.stats.register_sender(src_addr);
+    // Housekeeping and rendering run first on every pass, so neither
+    // silence (the receive times out) nor a stream of packets that are
+    // skipped with `continue` can freeze the display
+    let now = Instant::now();
 
-    let data = &buf[..bytes_received];
-    match decode_message(data) {
-      Ok(Message::Sync(SyncMessage::Pong {
-        t0_ms,
-        t1_ms,
-        t2_ms,
-      })) => {
-        ctx.stats.on_pong(t0_ms, t1_ms, t2_ms);
-      }
-      Ok(Message::Sync(SyncMessage::Ping { t0_ms })) => {
-        respond_to_ping(&socket, src_addr, t0_ms);
-      }
-      Ok(Message::Data(decoded)) => {
-        let received_sequence = decoded.seq;
-        let payload = decoded.payload;
-        let sent_ts_ms = decoded.timestamp_ms;
-
-        // Update rolling byte rate, latency, and volume
-        let now_inst = Instant::now();
-        let latency_ms = ctx.stats.compute_latency_ms(sent_ts_ms);
-        ctx.stats.on_packet(
-          bytes_received,
-          payload.len(),
-          latency_ms,
-          now_inst,
-        );
-        match decoded.meta.sample_format {
-          sound_send::packet::SampleFormat::F32 => {
-            let samples: &[f32] = unsafe {
-              std::slice::from_raw_parts(
-                payload.as_ptr() as *const f32,
-                payload.len() / 4,
-              )
-            };
-            ctx.stats.volume.add_samples_f32(now_inst, samples);
-          }
-          sound_send::packet::SampleFormat::I16 => {
-            let mut v = Vec::with_capacity(payload.len() / 2);
-            for b in payload.chunks_exact(2) {
-              v.push(i16::from_ne_bytes([b[0], b[1]]));
-            }
-            ctx.stats.volume.add_samples_i16(now_inst, &v);
-          }
-          sound_send::packet::SampleFormat::U16 => {
-            let mut v = Vec::with_capacity(payload.len() / 2);
-            for b in payload.chunks_exact(2) {
-              v.push(u16::from_ne_bytes([b[0], b[1]]));
-            }
-            ctx.stats.volume.add_samples_u16(now_inst, &v);
-          }
-          sound_send::packet::SampleFormat::U32 => {
-            let mut v = Vec::with_capacity(payload.len() / 4);
-            for b in payload.chunks_exact(4) {
-              v.push(u32::from_ne_bytes([b[0], b[1], b[2], b[3]]));
-            }
-            ctx.stats.volume.add_samples_u32(now_inst, &v);
-          }
-          _ => {}
+    let stop = STOP_REQUESTED.load(Ordering::Relaxed);
+    if stop || DUMP_REQUESTED.swap(false, Ordering::Relaxed) {
+      let mut addrs: Vec<_> = clients.keys().cloned().collect();
+      addrs.sort_by_key(|a| (a.ip().to_string(), a.port()));
+      for addr in &addrs {
+        eprint!("\n{}", clients[addr].stats.dump_histogram(addr));
+      }
+      rendered_lines = 0;
+    }
+    if stop {
+      if render {
+        eprint!("\x1b[?25h");
+      }
+      return Ok(());
+    }
+
+    // Close and remove clients that have been idle for too long, keeping
+    // their time-sync estimate in case they reconnect
+    clients.retain(|addr, ctx| {
+      let keep = now.duration_since(ctx.last_seen) < SINK_IDLE_TIMEOUT;
+      if !keep {
+        if let Some(state) = ctx.stats.converged_sync_state() {
+          sync_cache.store(addr.ip(), state, now);
         }
+      }
+      keep
+    });
 
-        // Check packet loss/order; write payload only for in-order packets
-        if received_sequence == ctx.expected_seq {
-          // In-order packet: write payload to the client-specific sink
-          ctx.sink.process(&decoded.meta, payload)?;
-          ctx.expected_seq = ctx.expected_seq.wrapping_add(1);
-        } else if received_sequence > ctx.expected_seq {
-          // Some packets were lost.
-          // This packet is in-order relative to its sequence; write it
-          ctx.sink.process(&decoded.meta, payload)?;
-          // Do not count initial gap as loss if this is the
-          // first packet observed for this client
-          if ctx.expected_seq != 0 {
-            let lost_count = received_sequence - ctx.expected_seq;
-            ctx.stats.mark_lost(lost_count);
-          }
-          ctx.expected_seq = received_sequence + 1;
-        } else {
-          // received_sequence < expected_sequence
-          // Late/out-of-order packet: count it but do not write payload
-          ctx.stats.mark_out_of_order();
+    // Trigger pings independent of rendering, and push out buffered audio
+    // that has waited too long
+    for (addr, ctx) in clients.iter_mut() {
+      if closed_clients.contains(addr) {
+        continue;
+      }
+      ctx.stats.maybe_ping(&*socket);
+      // Repeated, as any one datagram may be lost and senders restart
+      if let Some((gain_db, muted)) = remote_level {
+        if ctx
+          .last_control
+          .is_none_or(|t| now.duration_since(t) >= CONTROL_INTERVAL)
+        {
+          let msg = SyncMessage::Control { gain_db, muted };
+          let _ = socket.send_packet_to(&encode_sync(&msg), *addr);
+          ctx.last_control = Some(now);
         }
       }
-      Err(_) => {
-        // Unknown payload; skip
+      // Direct playback follows the sender's clock drift
+      ctx.sink.set_resample_ratio(ctx.stats.resample_ratio());
+      // A gap nothing has arrived to fill for a while is not reordering
+      if ctx.reorder.held() > 0
+        && now.duration_since(ctx.last_data) >= REORDER_MAX_HOLD
+        && output_closed(ctx.reorder.flush(&mut |ev| {
+          on_reorder_event(&mut ctx.sink, &mut ctx.stats, &mut ctx.plc, ev, now)
+        }))?
+      {
+        closed_clients.push(*addr);
         continue;
       }
+      if output_closed(ctx.sink.flush_if_stale())? {
+        closed_clients.push(*addr);
+      }
+    }
+    if !closed_clients.is_empty() && sink_target.is_shared() {
+      warn!("output was closed by its reader; no client can play anymore");
+      if render {
+        eprint!("\x1b[?25h");
+      }
+      return Ok(());
+    }
+    for addr in closed_clients.drain(..) {
+      clients.remove(&addr);
+      closed_outputs.close(addr);
+      warn!("output for {addr} was closed by its reader; dropped the client");
     }
 
-    // Update and print stats periodically
-    let now = Instant::now();
-    ctx.last_seen = now;
-
-    // Close and remove clients that have been idle for too long
-    clients
-      .retain(|_, ctx| now.duration_since(ctx.last_seen) < SINK_IDLE_TIMEOUT);
+    if now.duration_since(last_trace_flush) >= UPDATE_INTERVAL {
+      last_trace_flush = now;
+      if let Some(trace) = trace.as_mut() {
+        trace.flush()?;
+      }
+    }
 
-    // Trigger pings independent of rendering
-    for ctx in clients.values_mut() {
-      ctx.stats.maybe_ping(&socket);
+    // Liveness for log monitoring. Written straight to stderr: asking for
+    // it outranks --quiet, and it must not depend on the display
+    if heartbeat.as_mut().is_some_and(|h| h.due(now)) {
+      let lead = if rendered_lines > 0 { "\n" } else { "" };
+      eprintln!("{lead}{}", Heartbeat::line(clients.len(), total_packets));
     }
 
-    if show_progress && now.duration_since(last_render) >= UPDATE_INTERVAL {
+    if render && now.duration_since(last_render) >= UPDATE_INTERVAL {
       // Deterministic order by address
       let mut addrs: Vec<_> = clients.keys().cloned().collect();
       addrs.sort_by_key(|a| (a.ip().to_string(), a.port()));
@@ -217,16 +694,39 @@ fn main() -> io::Result<()> {
       let mut printed = 0usize;
       for addr in addrs.iter() {
         if let Some(ctx) = clients.get_mut(addr) {
+          let stale = now.duration_since(ctx.last_data) >= stale_after;
+          let stale_tag = if stale { "[STALE]" } else { "" };
+          if info_mode {
+            let line = ctx.stats.format_info_line(now, addr, ctx.last_meta);
+            eprint!("\r\x1b[2K{}{}\n", line, stale_tag);
+            printed += 1;
+            continue;
+          }
           let line = ctx.stats.format_status_line(
             now,
-            ctx.expected_seq,
+            ctx.reorder.next_seq(),
             addr,
             ctx.stats.offset_ms(),
             ctx.stats.drift_ppm(),
+            ctx.stats.delay_ms(),
           );
+          let playback =
+            ctx.sink.playback_status().map_or_else(String::new, |p| {
+              format!(
+                "| Buf: {:.0}/{:.0} ms | Xrun: {}/{}   ",
+                p.buffered.as_secs_f64() * 1000.0,
+                p.target.as_secs_f64() * 1000.0,
+                p.underruns,
+                p.overruns
+              )
+            });
           // Clear line and print
-          eprint!("\r\x1b[2K{}\n", line);
+          eprint!("\r\x1b[2K{}{}{}\n", line, playback, stale_tag);
           printed += 1;
+          if show_hist {
+            eprint!("\r\x1b[2K  Sizes: {}\n", ctx.stats.payload_hist());
+            printed += 1;
+          }
         }
       }
 
@@ -238,6 +738,738 @@ fn main() -> io::Result<()> {
       rendered_lines = printed;
       last_render = now;
     }
+
+    // Time out periodically so stale clients are noticed even when no
+    // packets arrive at all
+    let Some((bytes_received, src_addr)) = recv_or_idle(&*socket, &mut buf)?
+    else {
+      continue;
+    };
+    let recv_ms = unix_time_ms();
+    if let Some(rec) = recorder.as_mut() {
+      rec.write_packet(record_start.elapsed(), &buf[..bytes_received])?;
+    }
+
+    if !closed_outputs.admit(src_addr, &buf[..bytes_received]) {
+      continue;
+    }
+
+    // A finished sender is torn down immediately rather than waiting out
+    // the idle timeout. Only a client that has streamed audio can end its
+    // stream, so a stray datagram cannot create or tear down anything else;
+    // repeated copies find nothing left to remove
+    if let Ok(Message::Sync(SyncMessage::EndOfStream)) =
+      decode_message(&buf[..bytes_received])
+    {
+      let streamed = clients
+        .get(&src_addr)
+        .is_some_and(|ctx| ctx.last_meta.is_some());
+      let Some(mut ctx) = streamed.then(|| clients.remove(&src_addr)).flatten()
+      else {
+        continue;
+      };
+      if let Some(state) = ctx.stats.converged_sync_state() {
+        sync_cache.store(src_addr.ip(), state, Instant::now());
+      }
+      let now = Instant::now();
+      // Being torn down anyway, so a closed output changes nothing
+      output_closed(ctx.reorder.flush(&mut |ev| {
+        on_reorder_event(&mut ctx.sink, &mut ctx.stats, &mut ctx.plc, ev, now)
+      }))?;
+      drop(ctx);
+      info!("{src_addr} ended its stream; sink closed");
+      continue;
+    }
+
+    let data = &buf[..bytes_received];
+    // Only a datagram with at least one decodable message gets a new source
+    // a client, so garbage from unknown addresses can neither displace a
+    // sender nor leave an empty context behind
+    if !clients.contains_key(&src_addr)
+      && !decode_messages_with_policy(data, version_policy).any(|m| m.is_ok())
+    {
+      for e in decode_messages_with_policy(data, version_policy)
+        .filter_map(Result::err)
+      {
+        decode_errors.record(&e);
+        debug!("{src_addr}: undecodable datagram from unknown source: {e}");
+      }
+      continue;
+    }
+
+    // Make room for a new sender by evicting the least recently seen one;
+    // dropping its context tears down the sink
+    if let Some((addr, _)) =
+      evict_least_recent(&mut clients, &src_addr, max_clients, |ctx| {
+        ctx.last_seen
+      })
+    {
+      warn!(
+        "max clients ({}) reached: evicted least recently seen {}",
+        max_clients, addr
+      );
+    }
+
+    // Warm-start a new client's time sync from the same host's previous
+    // session: a departed one (cache) or one still within its idle timeout
+    let seed = if clients.contains_key(&src_addr) {
+      None
+    } else {
+      sync_cache.take(src_addr.ip(), Instant::now()).or_else(|| {
+        clients
+          .iter()
+          .filter(|(addr, _)| addr.ip() == src_addr.ip())
+          .find_map(|(_, ctx)| ctx.stats.converged_sync_state())
+      })
+    };
+    let ctx = clients.entry(src_addr).or_insert_with(|| {
+      debug!(
+        "new client {src_addr} (warm sync start: {})",
+        seed.is_some()
+      );
+      let mut sync = DefaultSyncController::with_algorithm(sync_algo, 1_000);
+      if let Some(state) = seed {
+        sync.seed_state(state);
+      }
+      let mut sink = BinarySink::new(sink_target.clone());
+      sink.set_paplay_fallback(paplay_fallback);
+      sink.set_buffer_bytes(sink_buffer_bytes);
+      sink.set_playback_depth(playback_depth);
+      sink.set_output_device(out_device.clone());
+      sink.set_output_format(out_format);
+      ClientCtx {
+        sink,
+        stats: RecvStats::new(WINDOW, VOLUME_WINDOW, sync),
+        reorder: ReorderBuffer::new(reorder_window),
+        plc: LossConcealer::new(plc_mode),
+        anti_replay: anti_replay_window.map(AntiReplay::new),
+        last_seen: Instant::now(),
+        last_data: Instant::now(),
+        warned_frame_align: false,
+        last_meta: None,
+        last_format_request: None,
+        last_control: None,
+      }
+    });
+    ctx.stats.register_sender(src_addr);
+
+    // Set when this client's output is gone for good
+    let mut closed = false;
+    // Batched data packets are handled one by one; an undecodable
+    // datagram leaves the client's last-seen time alone
+    let mut any_valid = false;
+    for message in decode_messages_with_policy(data, version_policy) {
+      any_valid |= message.is_ok();
+      let env = MessageEnv {
+        socket: &*socket,
+        src_addr,
+        recv_ms,
+        datagram: data,
+        trace: trace.as_mut(),
+        record_start,
+        total_packets: &mut total_packets,
+        decode_errors: &mut decode_errors,
+        swap_buf: &mut swap_buf,
+        stale_after,
+        request_format,
+        request_rate,
+      };
+      closed = handle_message(ctx, env, message)?;
+      if closed {
+        break;
+      }
+    }
+    if !any_valid {
+      continue;
+    }
+
+    ctx.last_seen = Instant::now();
+    if closed {
+      // Dropped with the next pass's housekeeping
+      closed_clients.push(src_addr);
+    }
+  }
+  // This loop is typically interrupted with Ctrl+C, which only returns
+  // cleanly with --latency-hist
+}
+
+fn parse_max_clients(s: &str) -> io::Result<usize> {
+  match s.parse::<usize>() {
+    Ok(n) if n > 0 => Ok(n),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid --max-clients: {} (expected a positive integer)", s),
+    )),
+  }
+}
+
+fn parse_sink_buffer_bytes(s: &str) -> io::Result<usize> {
+  s.parse::<usize>().map_err(|_| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "invalid --sink-buffer-bytes: {} (expected bytes, 0 = off)",
+        s
+      ),
+    )
+  })
+}
+
+fn parse_target_latency_ms(s: &str) -> io::Result<Duration> {
+  match s.parse::<u64>() {
+    Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "invalid --target-latency-ms: {} (expected a positive integer)",
+        s
+      ),
+    )),
   }
-  // This loop is typically interrupted with Ctrl+C
+}
+
+// Per-client context: sink + stats + expected seq + last seen time
+struct ClientCtx {
+  sink: BinarySink,
+  stats: RecvStats,
+  reorder: ReorderBuffer,
+  plc: LossConcealer,
+  anti_replay: Option<AntiReplay>,
+  last_seen: Instant,
+  // Last data packet; sync traffic alone keeps last_seen fresh even when
+  // the audio has stopped
+  last_data: Instant,
+  warned_frame_align: bool,
+  // Stream format of the previous data packet
+  last_meta: Option<Meta>,
+  last_format_request: Option<Instant>,
+  last_control: Option<Instant>,
+}
+
+// Receive-loop state that handling one client's message needs besides the
+// client's own context
+struct MessageEnv<'a> {
+  socket: &'a dyn Transport,
+  src_addr: SocketAddr,
+  // When the datagram arrived (ms since the UNIX epoch)
+  recv_ms: u64,
+  // The whole datagram, for diagnostics
+  datagram: &'a [u8],
+  trace: Option<&'a mut PacketTrace<File>>,
+  record_start: Instant,
+  total_packets: &'a mut u64,
+  decode_errors: &'a mut DecodeErrorCounts,
+  // Scratch space for byte-swapping foreign-endian payloads
+  swap_buf: &'a mut Vec<u8>,
+  stale_after: Duration,
+  request_format: Option<SampleFormat>,
+  request_rate: u32,
+}
+
+/// Handle one message of a datagram from an existing client. Returns
+/// whether the client's output was closed by its reader.
+fn handle_message(
+  ctx: &mut ClientCtx,
+  env: MessageEnv<'_>,
+  message: Result<Message<'_>, DecodeError>,
+) -> io::Result<bool> {
+  let MessageEnv {
+    socket,
+    src_addr,
+    recv_ms,
+    datagram,
+    trace,
+    record_start,
+    total_packets,
+    decode_errors,
+    swap_buf,
+    stale_after,
+    request_format,
+    request_rate,
+  } = env;
+  let mut closed = false;
+  let verdict = match &message {
+    Ok(Message::Data(decoded)) => Some(ctx.stats.admit_seq(
+      ctx.anti_replay.as_mut(),
+      ctx.reorder.next_seq(),
+      decoded.seq,
+    )),
+    _ => None,
+  };
+  match message {
+    Ok(Message::Sync(SyncMessage::Pong {
+      t0_ms,
+      t1_ms,
+      t2_ms,
+      ..
+    })) => {
+      ctx.stats.on_pong(t0_ms, t1_ms, t2_ms);
+    }
+    Ok(Message::Sync(SyncMessage::Ping { t0_ms })) => {
+      respond_to_ping(socket, src_addr, t0_ms, recv_ms, true);
+    }
+    Ok(Message::Sync(
+      SyncMessage::FormatRequest { .. } | SyncMessage::Control { .. },
+    )) => {
+      // Only senders act on format requests and level control
+    }
+    Ok(Message::Sync(SyncMessage::EndOfStream)) => {
+      // Handled before the client lookup
+    }
+    Ok(Message::Sync(SyncMessage::Hello { meta })) => {
+      // Sent both before data and after the handshake, so often twice
+      debug!(
+        "{src_addr} announced {:?}/{} Hz/{} ch",
+        meta.sample_format, meta.sample_rate.0, meta.channels
+      );
+      // Best effort: the first data packet retries the open
+      if let Err(e) = ctx.sink.prepare(&meta) {
+        warn!("failed to prepare output for {src_addr}: {e}");
+      }
+      // A restarted sender announces itself again and counts from 0
+      if let Some(ar) = ctx.anti_replay.as_mut() {
+        ar.reset();
+      }
+    }
+    Ok(Message::Data(decoded)) if verdict == Some(SeqVerdict::Replayed) => {
+      if ctx.stats.replayed_packets() == 1 {
+        warn!(
+          "{src_addr}: dropped replayed or stale packet (seq {})",
+          decoded.seq
+        );
+      }
+    }
+    Ok(Message::Data(_)) if verdict == Some(SeqVerdict::Duplicate) => {
+      // Duplicated datagram: counted in stats, payload not written again
+    }
+    Ok(Message::Data(decoded)) => {
+      *total_packets += 1;
+      let received_sequence = decoded.seq;
+      let sent_ts_ms = decoded.timestamp_ms;
+
+      // Sync traffic keeps last_seen fresh through silence, so gaps are
+      // measured between data packets
+      let gap = Instant::now().duration_since(ctx.last_data);
+      match stream_event(ctx.last_meta, decoded.meta, gap, stale_after) {
+        Some(StreamEvent::Started) => info!(
+          "stream started from {src_addr} fmt={}",
+          describe_meta(&decoded.meta)
+        ),
+        Some(StreamEvent::Resumed { gap }) => info!(
+          "stream resumed from {src_addr} after {:.1}s",
+          gap.as_secs_f64()
+        ),
+        // The sink reconfigures itself on a format change; per-client
+        // state derived from the old format is reset here
+        Some(StreamEvent::FormatChanged { from: prev }) => {
+          info!(
+            "{src_addr} changed format: {:?}/{} Hz/{} ch -> {:?}/{} Hz/{} ch",
+            prev.sample_format,
+            prev.sample_rate.0,
+            prev.channels,
+            decoded.meta.sample_format,
+            decoded.meta.sample_rate.0,
+            decoded.meta.channels
+          );
+          ctx.stats.on_format_change();
+          ctx.warned_frame_align = false;
+        }
+        None => {}
+      }
+      ctx.last_meta = Some(decoded.meta);
+
+      // Ask the sender to switch formats until it does
+      if let Some(sample_format) = request_format {
+        let due = ctx
+          .last_format_request
+          .is_none_or(|t| t.elapsed() >= FORMAT_REQUEST_INTERVAL);
+        if decoded.meta.sample_format != sample_format && due {
+          let req = SyncMessage::FormatRequest {
+            sample_format,
+            sample_rate: request_rate,
+          };
+          let _ = socket.send_packet_to(&encode_sync(&req), src_addr);
+          ctx.last_format_request = Some(Instant::now());
+        }
+      }
+
+      // Drop a ragged tail that does not form a whole frame so sinks and
+      // the volume meter only ever see complete frames
+      let frame_bytes = decoded.meta.frame_size();
+      let mut payload = decoded.payload;
+      if frame_bytes > 0 && !payload.len().is_multiple_of(frame_bytes) {
+        if !ctx.warned_frame_align {
+          warn!(
+            "payload length {} from {} is not a multiple of the frame size \
+             ({} bytes); dropping trailing bytes",
+            payload.len(),
+            src_addr,
+            frame_bytes
+          );
+          ctx.warned_frame_align = true;
+        }
+        payload = &payload[..payload.len() - payload.len() % frame_bytes];
+      }
+      // Samples from a host of the other byte order are swapped once
+      // here so the meter and sink only ever see native samples
+      if decoded.byte_order != ByteOrder::NATIVE {
+        let bps = decoded.meta.sample_format.bytes_per_sample();
+        swap_sample_bytes(payload, bps, swap_buf);
+        payload = swap_buf;
+      }
+
+      // Update rolling byte rate, latency, and volume
+      let now_inst = Instant::now();
+      ctx.last_data = now_inst;
+      let latency_ms = ctx.stats.compute_latency_ms(sent_ts_ms, decoded.clock);
+      if let Some(trace) = trace {
+        trace.write_row(&TraceRow {
+          recv_offset: now_inst.duration_since(record_start),
+          client: src_addr,
+          seq: received_sequence,
+          sent_ts_ms,
+          latency_ms,
+          offset_ms: ctx.stats.offset_ms(),
+          frame_counter: decoded.frame_counter,
+        })?;
+      }
+      ctx.stats.on_packet(
+        decoded.wire_len(),
+        payload.len(),
+        latency_ms,
+        sent_ts_ms,
+        now_inst,
+      );
+      let reader = AudioFrameReader::new(payload, &decoded.meta);
+      ctx.stats.volume.add_frames(now_inst, &reader);
+
+      let next_seq = ctx.reorder.next_seq();
+      if verdict == Some(SeqVerdict::Restarted) {
+        // Far behind anything reordering explains: the sender restarted
+        info!(
+          "{src_addr} restarted its stream (seq {received_sequence}, expected \
+           {next_seq})"
+        );
+        closed = output_closed(ctx.reorder.flush(&mut |ev| {
+          on_reorder_event(
+            &mut ctx.sink,
+            &mut ctx.stats,
+            &mut ctx.plc,
+            ev,
+            now_inst,
+          )
+        }))?;
+        ctx.reorder.reset();
+      }
+      // Payloads reach the sink in sequence order; packets ahead of a
+      // gap wait in the reorder window for the missing ones
+      closed |= output_closed(ctx.reorder.push(
+        received_sequence,
+        decoded.meta,
+        payload,
+        &mut |ev| {
+          on_reorder_event(
+            &mut ctx.sink,
+            &mut ctx.stats,
+            &mut ctx.plc,
+            ev,
+            now_inst,
+          )
+        },
+      ))?;
+    }
+    Err(e) => {
+      // Undecodable: count by cause so a broken stream is diagnosable
+      ctx.stats.on_decode_error(&e);
+      decode_errors.record(&e);
+      let counts = ctx.stats.decode_errors();
+      if e.is_version_mismatch() && counts.version == 1 {
+        warn!(
+          "{src_addr} sends packet version {} that this build cannot read; \
+           sender and receiver are probably different builds",
+          datagram[1]
+        );
+      } else if counts.total() == 1 {
+        warn!("{src_addr}: undecodable datagram: {e}");
+      }
+    }
+  }
+  Ok(closed)
+}
+
+/// Sort a sink result: a reader that went away (such as whatever our stdout
+/// was piped into) ends one client's output, reported as `Ok(true)`, while
+/// any other error stops the receiver.
+fn output_closed(result: io::Result<()>) -> io::Result<bool> {
+  match result {
+    Ok(()) => Ok(false),
+    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(true),
+    Err(e) => Err(e),
+  }
+}
+
+/// Apply one reorder-buffer outcome to a client's sink and statistics.
+fn on_reorder_event(
+  sink: &mut BinarySink,
+  stats: &mut RecvStats,
+  plc: &mut LossConcealer,
+  ev: ReorderEvent<'_>,
+  now: Instant,
+) -> io::Result<()> {
+  match ev {
+    ReorderEvent::Deliver { meta, payload, .. } => {
+      plc.on_deliver(&meta, payload);
+      sink.process(&meta, payload)
+    }
+    ReorderEvent::Lost(lost_count) => {
+      stats.mark_lost(lost_count, now);
+      // A loss burst likely left the clock estimate stale too
+      if lost_count >= FAST_PING_GAP {
+        stats.request_ping_now();
+      }
+      // With --plc the gap is filled so later audio keeps its timing
+      match plc.conceal(lost_count) {
+        Some((meta, fill)) => sink.process(&meta, fill),
+        None => Ok(()),
+      }
+    }
+    // Its slot was already played or given up on; count it only
+    ReorderEvent::Late => {
+      stats.mark_out_of_order();
+      Ok(())
+    }
+  }
+}
+
+// The unspecified IPv6 address ([::]) also takes IPv4 senders where the
+// OS allows it; any other address binds exactly as given
+fn bind_udp(listen_addr: &str) -> io::Result<(UdpSocket, bool)> {
+  let first = listen_addr.to_socket_addrs()?.next();
+  if let Some(SocketAddr::V6(addr)) = first {
+    if addr.ip().is_unspecified() {
+      match sockopt::bind_dual_stack(addr) {
+        Ok(socket) => return Ok((socket, true)),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+          warn!("cannot accept IPv4 on {listen_addr}: {e}; IPv6 senders only");
+        }
+        Err(e) => return Err(e),
+      }
+    }
+  }
+  Ok((UdpSocket::bind(listen_addr)?, false))
+}
+
+fn parse_anti_replay(s: &str) -> io::Result<u64> {
+  match s.parse::<u64>() {
+    Ok(n) if (1..=MAX_ANTI_REPLAY_WINDOW).contains(&n) => Ok(n),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "invalid --anti-replay: {} (expected 1..={})",
+        s, MAX_ANTI_REPLAY_WINDOW
+      ),
+    )),
+  }
+}
+
+fn parse_reorder(s: &str) -> io::Result<usize> {
+  match s.parse::<usize>() {
+    Ok(n) if n <= MAX_REORDER_WINDOW => Ok(n),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "invalid --reorder: {} (expected 0..={})",
+        s, MAX_REORDER_WINDOW
+      ),
+    )),
+  }
+}
+
+fn parse_plc(s: &str) -> io::Result<PlcMode> {
+  match s.to_ascii_lowercase().as_str() {
+    "silence" => Ok(PlcMode::Silence),
+    "repeat" => Ok(PlcMode::Repeat),
+    "none" => Ok(PlcMode::None),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid --plc: {} (expected silence|repeat|none)", s),
+    )),
+  }
+}
+
+fn parse_rcvbuf(s: &str) -> io::Result<usize> {
+  match s.parse::<usize>() {
+    Ok(n) if n > 0 => Ok(n),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid --rcvbuf: {} (expected a positive byte count)", s),
+    )),
+  }
+}
+
+fn parse_stale_ms(s: &str) -> io::Result<Duration> {
+  match s.parse::<u64>() {
+    Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid --stale-ms: {} (expected a positive integer)", s),
+    )),
+  }
+}
+
+fn parse_heartbeat_secs(s: &str) -> io::Result<Duration> {
+  match s.parse::<u64>() {
+    Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "invalid --heartbeat-secs: {} (expected a positive integer)",
+        s
+      ),
+    )),
+  }
+}
+
+fn parse_sync_algo(s: &str) -> io::Result<SyncAlgorithm> {
+  SyncAlgorithm::parse(s).ok_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid --sync-algo: {} (expected: ewma|median)", s),
+    )
+  })
+}
+
+fn parse_sample_format(flag: &str, s: &str) -> io::Result<SampleFormat> {
+  match s.to_ascii_lowercase().as_str() {
+    "f32" => Ok(SampleFormat::F32),
+    "i16" => Ok(SampleFormat::I16),
+    "u16" => Ok(SampleFormat::U16),
+    "u32" => Ok(SampleFormat::U32),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid {}: {} (expected: f32|i16|u16|u32)", flag, s),
+    )),
+  }
+}
+
+fn parse_remote_gain_db(s: &str) -> io::Result<i8> {
+  match s.parse::<i8>() {
+    Ok(db) if f64::from(db) <= MAX_GAIN_DB => Ok(db),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "invalid --remote-gain-db: {} (expected -128..={} dB)",
+        s, MAX_GAIN_DB
+      ),
+    )),
+  }
+}
+
+fn parse_request_rate(s: &str) -> io::Result<u32> {
+  match s.parse::<u32>() {
+    Ok(n) if n > 0 => Ok(n),
+    _ => Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid --request-rate: {} (expected a rate in Hz)", s),
+    )),
+  }
+}
+
+fn print_usage(prog: &str) {
+  eprintln!("Usage: {} <listen_addr:port> [options]", prog);
+  eprintln!("Example: {} 127.0.0.1:12345", prog);
+  eprintln!("Options:");
+  eprintln!(
+    "--pipewire                  Play through pw-cat instead of stdout"
+  );
+  eprintln!("--aplay                     Play through ALSA aplay");
+  eprintln!(
+    "--cpal                      Play directly on the default output device"
+  );
+  eprintln!(
+    "--out-device <name>         Play --cpal output on the named device \
+     instead"
+  );
+  eprintln!(
+    "--target-latency-ms <ms>    Audio --cpal queues before playing and then \
+     holds (default: {}; alias --jitter-buffer-ms)",
+    DEFAULT_PLAYBACK_DEPTH.as_millis()
+  );
+  eprintln!(
+    "--paplay-fallback           Use paplay if pw-cat is not installed"
+  );
+  eprintln!("--fifo <path>               Write audio to a named pipe");
+  eprintln!("--hist                      Show payload size histograms");
+  eprintln!(
+    "--info                      Describe each sender's stream instead of \
+     playing it"
+  );
+  eprintln!(
+    "--anti-replay[=<n>]         Drop packets repeated or more than n behind \
+     the newest (default n: {}); a restarted sender needs a new port",
+    DEFAULT_ANTI_REPLAY_WINDOW
+  );
+  eprintln!(
+    "--strict-version            Drop data packets from senders on an older \
+     but compatible version"
+  );
+  eprintln!(
+    "--max-clients <n>           Senders tracked at once, and TCP connections \
+     held (default: {})",
+    DEFAULT_MAX_CLIENTS
+  );
+  eprintln!(
+    "--heartbeat-secs <n>        Log an \"alive\" line every n seconds, even \
+     with --quiet"
+  );
+  eprintln!(
+    "--latency-hist              Print latency histograms on SIGUSR1 and at \
+     exit"
+  );
+  eprintln!("--progress                  Show per-client statistics");
+  eprintln!(
+    "--interface <name>          Only receive on this network interface \
+     (Linux)"
+  );
+  eprintln!("--rcvbuf <bytes>            UDP socket receive buffer size");
+  eprintln!(
+    "--plc <silence|repeat|none> Fill lost packets with silence or a fade of \
+     the last frame (default: none)"
+  );
+  eprintln!(
+    "--reorder <n>               Packets held for a late one before counting \
+     loss (default: {})",
+    DEFAULT_REORDER_WINDOW
+  );
+  eprintln!(
+    "--stale-ms <ms>             Flag clients silent this long (default: {})",
+    DEFAULT_STALE_AFTER.as_millis()
+  );
+  eprintln!(
+    "--sink-buffer-bytes <n>     Batch sink writes up to n bytes (default: 0)"
+  );
+  eprintln!("-q, --quiet                 Suppress status output");
+  eprintln!("-v, --verbose               More detail; repeat or -vv for trace");
+  eprintln!(
+    "--tcp                       Accept senders over TCP instead of UDP"
+  );
+  eprintln!("--record <file>             Record raw datagrams for udp_replay");
+  eprintln!(
+    "--trace <file>              Write a CSV timing row per data packet"
+  );
+  eprintln!(
+    "--out-format <fmt>          Convert audio to f32|i16|u16|u32 before \
+     output"
+  );
+  eprintln!("--request-format <fmt>      Ask senders to send f32|i16|u16|u32");
+  eprintln!("--request-rate <hz>         Preferred rate sent with the request");
+  eprintln!(
+    "--remote-gain-db <db>       Ask senders to scale their level by db"
+  );
+  eprintln!(
+    "--remote-mute               Ask senders to send silence (any host that \
+     reaches a sender can do this)"
+  );
+  eprintln!("--sync-algo <ewma|median>   Time-sync estimator (default: ewma)");
+  eprintln!("-h, --help                  Show this help");
 }
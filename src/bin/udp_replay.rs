@@ -0,0 +1,56 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use log::info;
+use sound_send::capture::{CaptureReader, replay};
+use sound_send::status::{init_logging, set_quiet, set_verbosity};
+
+fn main() -> io::Result<()> {
+  init_logging();
+  // 1. Parse capture file and destination
+  let mut args = env::args();
+  let prog = args.next().unwrap_or_else(|| "udp_replay".into());
+  let mut positional: Vec<String> = Vec::new();
+  let mut verbosity: u8 = 0;
+  for arg in args {
+    match arg.as_str() {
+      "-h" | "--help" => {
+        eprintln!(
+          "Usage: {} <capture_file> <dest_addr:port> [--quiet] [-v]",
+          prog
+        );
+        eprintln!("Example: {} session.cap 127.0.0.1:12345", prog);
+        return Ok(());
+      }
+      "-q" | "--quiet" => set_quiet(true),
+      "-v" | "--verbose" => verbosity = verbosity.saturating_add(1),
+      s if s.starts_with('-') => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          format!("unknown flag: {}", s),
+        ));
+      }
+      s => positional.push(s.to_string()),
+    }
+  }
+  set_verbosity(verbosity);
+  let [path, dest] = <[String; 2]>::try_from(positional).map_err(|_| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "expected <capture_file> <dest_addr:port>",
+    )
+  })?;
+  let dest = dest.to_socket_addrs()?.next().ok_or_else(|| {
+    io::Error::new(io::ErrorKind::InvalidInput, "destination did not resolve")
+  })?;
+
+  // 2. Replay with original inter-packet timing
+  let mut reader = CaptureReader::new(BufReader::new(File::open(&path)?))?;
+  let socket = UdpSocket::bind("0.0.0.0:0")?;
+  info!("Replaying {} to {} ...", path, dest);
+  let sent = replay(&mut reader, &socket, dest)?;
+  info!("Done: {} packets sent", sent);
+  Ok(())
+}
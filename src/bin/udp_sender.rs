@@ -1,20 +1,41 @@
 use std::env;
 use std::io;
-use std::net::UdpSocket;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, bail};
-use bytemuck;
+use log::{debug, error, info, warn};
+use sound_send::clock::{Clock, MonotonicClock, SystemClock};
+use sound_send::dsp::{
+  AudioFrameReader, Dither, FormatRequestOutcome, FrameCarry, RequestedFormat,
+  convert_via_f32,
+};
+use sound_send::gain::{Gain, RemoteLevel, apply_gain_bytes};
+use sound_send::handshake::{self, Handshake};
+use sound_send::histogram::PayloadHistogram;
+use sound_send::packet::{
+  DecodeErrorCounts, Message, SampleFormat, SyncMessage, decode_message,
+  encode_sync, respond_to_ping_with,
+};
 use sound_send::packet::{
-  Message, SampleFormat, SyncMessage, decode_message, encode_sync,
-  respond_to_ping,
+  Meta, TimestampClock, encode_packet_with_frame_counter,
 };
-use sound_send::packet::{Meta, encode_packet};
 use sound_send::rate::{RollingMean, RollingRate};
-use sound_send::send_stats::SendStats;
-use sound_send::volume::VolumeMeter;
+use sound_send::send_stats::{
+  DurationBudget, LinkMonitor, SendStats, SilenceSuppressor,
+};
+use sound_send::sockopt;
+use sound_send::spsc;
+use sound_send::status::{
+  init_logging, is_quiet, mark_progress_line, set_quiet, set_verbosity,
+};
+use sound_send::sync_controller::{DefaultSyncController, SyncAlgorithm};
+use sound_send::transport::{TcpClient, Transport, bind_udp_sender};
+use sound_send::volume::{SmoothedLevel, VolumeMeter, chunk_dbfs};
+use sound_send::watchdog::{InputWatchdog, WatchdogEvent};
 
 // 1024 bytes: every 2.67ms in 48kHz stereo f32
 const MAX_PAYLOAD: usize = 1024; // payload only (excludes our header)
@@ -23,6 +44,10 @@ const PAYLOAD_ALIGNMENT: usize = 8;
 const _: [(); MAX_PAYLOAD % PAYLOAD_ALIGNMENT] = [(); 0];
 
 const UPDATE_INTERVAL: Duration = Duration::from_millis(200);
+// Default handshake: 20 attempts x 500 ms, ~10 seconds total
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_HANDSHAKE_ATTEMPTS: usize = 20;
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:0";
 const STATS_WINDOW: Duration = Duration::from_secs(10);
 const VOLUME_WINDOW: Duration = Duration::from_secs(1);
 
@@ -30,6 +55,26 @@ const VOLUME_WINDOW: Duration = Duration::from_secs(1);
 // (at 48kHz, 4800 packets = 100 ms of silence)
 const SUPPRESS_SILENT_PACKETS_THRESHOLD: u64 = 4800;
 
+// EndOfStream is repeated since a single datagram may be lost
+const END_OF_STREAM_REPEATS: usize = 3;
+const END_OF_STREAM_GAP: Duration = Duration::from_millis(20);
+// Undecodable control datagrams are logged once per this many
+const DECODE_ERROR_LOG_EVERY: u64 = 100;
+
+// With --batch, coalesced packets stay within one datagram that fits a
+// 1500-byte MTU over IPv6 (and so IPv4), and a partial batch is sent once
+// its oldest packet is this old
+const BATCH_MAX_BYTES: usize = 1452;
+const BATCH_MAX_DELAY: Duration = Duration::from_millis(20);
+const MAX_BATCH: usize = 64;
+
+// Live captures hand audio to a send thread through a ring this deep by
+// default (--capture-buffer-ms); the thread also polls it this often in
+// case a wakeup is missed, and reports overruns at most this often
+const DEFAULT_CAPTURE_BUFFER: Duration = Duration::from_millis(200);
+const CAPTURE_POLL: Duration = Duration::from_millis(5);
+const CAPTURE_OVERRUN_LOG: Duration = Duration::from_secs(1);
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum InputMode {
   #[cfg(feature = "cpal")]
@@ -39,17 +84,24 @@ enum InputMode {
   WasapiLoopback,
 
   Stdin,
+
+  File,
 }
 
 mod audio_sources;
 
-use audio_sources::{InputOptions, InputSource, ProcessChunk, StdinInput};
+use audio_sources::{
+  FileInput, InputOptions, InputSource, ProcessChunk, StdinInput,
+};
 
-fn build_input_source(input_mode: InputMode) -> Result<Box<dyn InputSource>> {
+fn build_input_source(
+  input_mode: InputMode,
+  opts: &InputOptions,
+) -> Result<Box<dyn InputSource>> {
   match input_mode {
     #[cfg(feature = "cpal")]
     InputMode::Cpal => {
-      use audio_sources::cpal::CpalInput;
+      use audio_sources::CpalInput;
       use cpal::traits::HostTrait;
 
       let host = cpal::default_host();
@@ -64,6 +116,13 @@ fn build_input_source(input_mode: InputMode) -> Result<Box<dyn InputSource>> {
       Ok(Box::new(WasapiInput::default()))
     }
     InputMode::Stdin => Ok(Box::new(StdinInput)),
+    InputMode::File => {
+      let path = opts
+        .path
+        .clone()
+        .context("--input file requires --path <file.wav>")?;
+      Ok(Box::new(FileInput::new(path)))
+    }
   }
 }
 
@@ -77,7 +136,7 @@ fn boost_current_thread_priority() {
     if let Err(err) =
       SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL)
     {
-      eprintln!("warning: failed to raise thread priority: {err}");
+      warn!("failed to raise thread priority: {err}");
     }
   }
 }
@@ -94,7 +153,7 @@ fn boost_process_priority() {
   unsafe {
     if let Err(err) = SetPriorityClass(GetCurrentProcess(), HIGH_PRIORITY_CLASS)
     {
-      eprintln!("warning: failed to raise process priority: {err}");
+      warn!("failed to raise process priority: {err}");
     }
   }
 }
@@ -103,6 +162,7 @@ fn boost_process_priority() {
 fn boost_process_priority() {}
 
 fn main() -> Result<()> {
+  init_logging();
   // --- 1. Parse args and set up socket ---
   let mut args = env::args().skip(1); // skip program name
   boost_process_priority();
@@ -121,13 +181,39 @@ fn main() -> Result<()> {
       InputMode::Stdin
     }
   };
-  let mut server_addr: Option<String> = None;
+  let mut server_addrs: Vec<String> = Vec::new();
   let mut show_status_icon = false;
+  let mut show_hist = false;
   // stdin metadata options
   let mut opt_channels: Option<u8> = None;
   let mut opt_sample_rate: Option<u32> = None;
   let mut opt_format: Option<SampleFormat> = None;
+  let mut opt_path: Option<std::path::PathBuf> = None;
+  let mut handshake_timeout = DEFAULT_HANDSHAKE_TIMEOUT;
+  let mut handshake_attempts = DEFAULT_HANDSHAKE_ATTEMPTS;
+  let mut skip_handshake = false;
+  let mut handshake_json = false;
+  let mut timestamp_clock = TimestampClock::Wall;
+  let mut silence_threshold_db: Option<f64> = None;
+  let mut bind_addr = String::from(DEFAULT_BIND_ADDR);
+  let mut use_tcp = false;
+  let mut max_pps: Option<f64> = None;
+  let mut max_kbps: Option<f64> = None;
+  let mut looping = false;
+  let mut dscp: Option<u8> = None;
+  let mut sndbuf: Option<usize> = None;
+  let mut ttl: Option<u8> = None;
+  let mut input_watchdog: Option<Duration> = None;
+  let mut input_watchdog_exit = false;
+  let mut frame_counter = false;
+  let mut sync_stats = false;
+  let mut stdin_header = false;
+  let mut batch: usize = 1;
+  let mut cpal_frames: Option<u32> = None;
+  let mut duration: Option<f64> = None;
+  let mut capture_buffer: Option<Duration> = None;
 
+  let mut verbosity: u8 = 0;
   while let Some(arg) = args.next() {
     match arg.as_str() {
       "-h" | "--help" => {
@@ -137,6 +223,21 @@ fn main() -> Result<()> {
       "-s" | "--status-icon" => {
         show_status_icon = true;
       }
+      #[cfg(target_os = "windows")]
+      "--wasapi-info" => {
+        audio_sources::wasapi::print_loopback_info()?;
+        return Ok(());
+      }
+      #[cfg(not(target_os = "windows"))]
+      "--wasapi-info" => bail!("--wasapi-info is only available on Windows"),
+      "--hist" => {
+        show_hist = true;
+      }
+      "-q" | "--quiet" => {
+        set_quiet(true);
+      }
+      "-v" | "--verbose" => verbosity = verbosity.saturating_add(1),
+      "-vv" => verbosity = verbosity.saturating_add(2),
       "-c" | "--channels" => {
         let val = args
           .next()
@@ -182,6 +283,151 @@ fn main() -> Result<()> {
         let val = &arg[9..];
         opt_format = Some(parse_sample_format(val)?);
       }
+      "--handshake-timeout-ms" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--handshake-timeout-ms requires a value")
+        })?;
+        handshake_timeout = parse_handshake_timeout(&val)?;
+      }
+      _ if arg.starts_with("--handshake-timeout-ms=") => {
+        handshake_timeout = parse_handshake_timeout(&arg[23..])?;
+      }
+      "--handshake-attempts" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--handshake-attempts requires a value")
+        })?;
+        handshake_attempts = parse_handshake_attempts(&val)?;
+      }
+      _ if arg.starts_with("--handshake-attempts=") => {
+        handshake_attempts = parse_handshake_attempts(&arg[21..])?;
+      }
+      "-b" | "--bind" => {
+        bind_addr = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--bind requires a value (e.g., 0.0.0.0:40000)")
+        })?;
+      }
+      _ if arg.starts_with("--bind=") => {
+        bind_addr = arg[7..].to_string();
+      }
+      "--max-pps" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--max-pps requires a value"))?;
+        max_pps = Some(parse_rate_limit(&val, "--max-pps")?);
+      }
+      _ if arg.starts_with("--max-pps=") => {
+        max_pps = Some(parse_rate_limit(&arg[10..], "--max-pps")?);
+      }
+      "--max-kbps" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--max-kbps requires a value"))?;
+        max_kbps = Some(parse_rate_limit(&val, "--max-kbps")?);
+      }
+      _ if arg.starts_with("--max-kbps=") => {
+        max_kbps = Some(parse_rate_limit(&arg[11..], "--max-kbps")?);
+      }
+      "--dscp" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--dscp requires a value (0..=63)"))?;
+        dscp = Some(parse_dscp(&val)?);
+      }
+      _ if arg.starts_with("--dscp=") => {
+        dscp = Some(parse_dscp(&arg[7..])?);
+      }
+      "--sndbuf" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--sndbuf requires a value (bytes)")
+        })?;
+        sndbuf = Some(parse_sndbuf(&val)?);
+      }
+      _ if arg.starts_with("--sndbuf=") => {
+        sndbuf = Some(parse_sndbuf(&arg[9..])?);
+      }
+      "--ttl" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--ttl requires a value (1..=255)"))?;
+        ttl = Some(parse_ttl(&val)?);
+      }
+      _ if arg.starts_with("--ttl=") => {
+        ttl = Some(parse_ttl(&arg[6..])?);
+      }
+      "--input-watchdog-ms" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--input-watchdog-ms requires a value")
+        })?;
+        input_watchdog = Some(parse_input_watchdog(&val)?);
+      }
+      _ if arg.starts_with("--input-watchdog-ms=") => {
+        input_watchdog = Some(parse_input_watchdog(&arg[20..])?);
+      }
+      "--input-watchdog-exit" => input_watchdog_exit = true,
+      "--frame-counter" => frame_counter = true,
+      "--batch" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--batch requires a value (1..={MAX_BATCH})")
+        })?;
+        batch = parse_batch(&val)?;
+      }
+      _ if arg.starts_with("--batch=") => {
+        batch = parse_batch(&arg[8..])?;
+      }
+      "--cpal-frames" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--cpal-frames requires a value"))?;
+        cpal_frames = Some(parse_cpal_frames(&val)?);
+      }
+      _ if arg.starts_with("--cpal-frames=") => {
+        cpal_frames = Some(parse_cpal_frames(&arg[14..])?);
+      }
+      "--duration" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--duration requires seconds"))?;
+        duration = Some(parse_duration(&val)?);
+      }
+      _ if arg.starts_with("--duration=") => {
+        duration = Some(parse_duration(&arg[11..])?);
+      }
+      "--capture-buffer-ms" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--capture-buffer-ms requires a value")
+        })?;
+        capture_buffer = Some(parse_capture_buffer(&val)?);
+      }
+      _ if arg.starts_with("--capture-buffer-ms=") => {
+        capture_buffer = Some(parse_capture_buffer(&arg[20..])?);
+      }
+      "--sync-stats" => sync_stats = true,
+      "--tcp" => use_tcp = true,
+      "--loop" => looping = true,
+      "--stdin-header" => stdin_header = true,
+      "--no-handshake" => {
+        skip_handshake = true;
+      }
+      "--handshake-json" => handshake_json = true,
+      "--mono-ts" => timestamp_clock = TimestampClock::Monotonic,
+      "--silence-threshold-db" => {
+        let val = args.next().ok_or_else(|| {
+          anyhow::anyhow!("--silence-threshold-db requires a value")
+        })?;
+        silence_threshold_db = Some(parse_silence_threshold(&val)?);
+      }
+      _ if arg.starts_with("--silence-threshold-db=") => {
+        silence_threshold_db = Some(parse_silence_threshold(&arg[23..])?);
+      }
+      "-p" | "--path" => {
+        let val = args
+          .next()
+          .ok_or_else(|| anyhow::anyhow!("--path requires a file path"))?;
+        opt_path = Some(val.into());
+      }
+      _ if arg.starts_with("--path=") => {
+        opt_path = Some(arg[7..].into());
+      }
       "-i" | "--input" => {
         let val = args.next().ok_or_else(|| {
           anyhow::anyhow!("--input requires a value: {}", input_mode_options())
@@ -195,27 +441,112 @@ fn main() -> Result<()> {
       s if s.starts_with('-') => {
         bail!("unknown flag: {}", s);
       }
-      s => {
-        if server_addr.is_none() {
-          server_addr = Some(s.to_string());
-        } else {
-          bail!("unexpected argument: {}", s);
-        }
-      }
+      s => server_addrs.push(s.to_string()),
     }
   }
 
-  let server_addr = server_addr.ok_or_else(|| {
-    anyhow::anyhow!(
-      "missing destination. Usage: udp_sender <addr:port> [--input {}]",
+  set_verbosity(verbosity);
+
+  if server_addrs.is_empty() {
+    bail!(
+      "missing destination. Usage: udp_sender <addr:port>... [--input {}]",
       input_mode_options()
-    )
-  })?;
+    );
+  }
 
-  // Create UDP socket (OS picks an ephemeral local port)
-  let socket =
-    UdpSocket::bind("0.0.0.0:0").context("failed to bind UDP socket")?;
-  println!("Destination: {}", server_addr);
+  let mut dest_addrs = Vec::with_capacity(server_addrs.len());
+  for server_addr in &server_addrs {
+    let addr = server_addr
+      .to_socket_addrs()
+      .with_context(|| format!("failed to resolve {server_addr}"))?
+      .next()
+      .with_context(|| format!("no address found for {server_addr}"))?;
+    dest_addrs.push(addr);
+  }
+
+  if handshake_json && (skip_handshake || use_tcp) {
+    bail!("--handshake-json is not supported with --no-handshake or --tcp");
+  }
+
+  // Create UDP socket (by default the OS picks an ephemeral local port)
+  // shared by all destinations, or one TCP connection per destination; the
+  // UDP socket is kept for the handshake
+  let (transports, udp_socket): (Vec<Arc<dyn Transport>>, Option<UdpSocket>) =
+    if use_tcp {
+      if bind_addr != DEFAULT_BIND_ADDR {
+        bail!("--bind is not supported with --tcp");
+      }
+      if dscp.is_some() {
+        bail!("--dscp is not supported with --tcp");
+      }
+      if sndbuf.is_some() {
+        bail!("--sndbuf is not supported with --tcp");
+      }
+      if ttl.is_some() {
+        bail!("--ttl is not supported with --tcp");
+      }
+      let mut transports: Vec<Arc<dyn Transport>> = Vec::new();
+      for (server_addr, dest_addr) in server_addrs.iter().zip(&dest_addrs) {
+        let client = TcpClient::connect(dest_addr)
+          .with_context(|| format!("failed to connect to {server_addr}"))?;
+        info!("Destination: {} (tcp)", server_addr);
+        info!("Local address: {}", client.local_addr()?);
+        transports.push(Arc::new(client));
+      }
+      (transports, None)
+    } else {
+      let socket = bind_addr
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve --bind {bind_addr}"))?
+        .next()
+        .with_context(|| format!("no address found for --bind {bind_addr}"))
+        .and_then(|bind| {
+          bind_udp_sender(bind, &dest_addrs).with_context(|| {
+            format!("failed to bind UDP socket to {bind_addr}")
+          })
+        })?;
+      for server_addr in &server_addrs {
+        info!("Destination: {}", server_addr);
+      }
+      info!("Local address: {}", socket.local_addr()?);
+      if let Some(dscp) = dscp {
+        // Best effort: without OS support the stream still works unmarked
+        match sockopt::set_dscp(&socket, dscp) {
+          Ok(()) => info!("DSCP: {dscp}"),
+          Err(e) => warn!("failed to set DSCP {dscp}: {e}"),
+        }
+      }
+      if let Some(ttl) = ttl {
+        // Multicast packets use their own TTL; one socket may serve both
+        // kinds of destination
+        sockopt::set_ttl(&socket, ttl, false).context("failed to set --ttl")?;
+        if dest_addrs.iter().any(|a| a.ip().is_multicast()) {
+          sockopt::set_ttl(&socket, ttl, true)
+            .context("failed to set multicast --ttl")?;
+        }
+        info!("TTL: {ttl}");
+      }
+      if let Some(bytes) = sndbuf {
+        sockopt::set_send_buffer_size(&socket, bytes)
+          .context("failed to set --sndbuf")?;
+        // The OS often doubles or caps the request, so report what stuck
+        info!(
+          "Send buffer: {} bytes (requested {bytes})",
+          sockopt::send_buffer_size(&socket)?
+        );
+      }
+      let send_sock: Arc<dyn Transport> = Arc::new(
+        socket
+          .try_clone()
+          .context("failed to clone socket for sender thread")?,
+      );
+      (vec![send_sock; server_addrs.len()], Some(socket))
+    };
+  let destinations = transports
+    .iter()
+    .zip(&dest_addrs)
+    .map(|(transport, addr)| Destination::new(transport.clone(), *addr))
+    .collect();
 
   let meter = Arc::new(Mutex::new(VolumeMeter::new(VOLUME_WINDOW)));
 
@@ -224,41 +555,208 @@ fn main() -> Result<()> {
     channels: opt_channels,
     sample_rate: opt_sample_rate,
     format: opt_format,
+    path: opt_path,
+    looping,
+    stdin_header,
+    cpal_frames,
   };
-  let mut input_source = build_input_source(input_mode)?;
+  if input_options.path.is_some() && input_mode != InputMode::File {
+    bail!("--path is only valid with --input file");
+  }
+  if stdin_header && input_mode != InputMode::Stdin {
+    bail!("--stdin-header is only valid with --input stdin");
+  }
+  #[cfg(feature = "cpal")]
+  let cpal_input = input_mode == InputMode::Cpal;
+  #[cfg(not(feature = "cpal"))]
+  let cpal_input = false;
+  if cpal_frames.is_some() && !cpal_input {
+    bail!("--cpal-frames is only valid with --input cpal");
+  }
+  let mut input_source = build_input_source(input_mode, &input_options)?;
   input_source.validate_options(&input_options)?;
+  if looping && input_source.is_live() {
+    warn!("--loop is ignored for live inputs");
+  }
+  if capture_buffer.is_some() && !input_source.is_live() {
+    warn!("--capture-buffer-ms is ignored for file and stdin inputs");
+  }
   let packet_meta = input_source.prepare_meta(&input_options)?;
 
   // --- 3. Move sending to a worker thread; main prints stats ---
   let (stats_tx, stats_rx) = mpsc::channel::<SendStats>();
-  let send_sock = socket
-    .try_clone()
-    .context("failed to clone socket for sender thread")?;
 
   let mut worker: SendWorker = SendWorker::new(
-    send_sock,
-    server_addr.clone(),
+    destinations,
     packet_meta,
     meter.clone(),
     stats_tx,
     STATS_WINDOW,
     UPDATE_INTERVAL,
   );
+  let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+  worker.set_timestamp_clock(timestamp_clock);
+  worker.set_clock(clock.clone());
+  // With --sync-stats, track each receiver's clock, mirroring what
+  // receivers do for us. Responders feed it Pongs either way; without
+  // pings none arrive.
+  let mut sync =
+    DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+  sync.set_clock(clock.clone());
+  for addr in &dest_addrs {
+    sync.add_peer(*addr);
+  }
+  let peer_sync = Arc::new(Mutex::new(sync));
+  if sync_stats {
+    worker.set_peer_sync(peer_sync.clone());
+  }
+  worker.set_frame_counter(frame_counter);
+  worker.set_carry_partial_frames(!input_source.is_live());
+  worker.set_silence_threshold_db(silence_threshold_db);
+  let format_request = worker.format_request_handle();
+  let remote_level = worker.remote_level_handle();
+  if max_pps.is_some() || max_kbps.is_some() {
+    if input_source.is_live() {
+      warn!("--max-pps/--max-kbps are ignored for live inputs");
+    } else {
+      worker.set_pacer(Pacer::new(max_pps, max_kbps));
+    }
+  }
 
-  let process_chunk: ProcessChunk =
-    Box::new(move |audio_chunk: &[u8]| worker.process_chunk(audio_chunk));
-  let _input_guard = input_source.start(&packet_meta, process_chunk)?;
+  // Announce the stream format so receivers can open their output before
+  // the first data packet. UDP receivers that are not up yet get it again
+  // when the handshake completes.
+  let hello = encode_sync(&SyncMessage::Hello { meta: packet_meta });
+  for (transport, addr) in transports.iter().zip(&dest_addrs) {
+    let _ = transport.send_packet_to(&hello, *addr);
+  }
 
-  // Perform handshake: wait for a Pong reply before starting data send
-  wait_for_pong_handshake(&socket, &server_addr)?;
+  if input_watchdog_exit && input_watchdog.is_none() {
+    bail!("--input-watchdog-exit requires --input-watchdog-ms");
+  }
+  let watchdog = input_watchdog.map(|timeout| {
+    InputWatchdog::new(Arc::new(MonotonicClock::default()), timeout)
+  });
+  let heartbeat = watchdog.as_ref().map(InputWatchdog::heartbeat);
+  if let Some(heartbeat) = &heartbeat {
+    input_source.set_heartbeat(heartbeat.clone());
+  }
+  // Once --duration is used up the worker ends the stream and is dropped,
+  // which closes the stats channel and lets main return. Input starts only
+  // after the handshake, so waiting for a receiver does not use it up.
+  let mut budget = duration.map(|secs| DurationBudget::new(secs, &packet_meta));
+  // Shared with the batch flusher; taking it out ends the stream
+  let worker = Arc::new(Mutex::new(Some(worker)));
+  let batch_worker = Arc::downgrade(&worker);
+  let process_chunk: ProcessChunk = Box::new(move |audio_chunk: &[u8]| {
+    if let Some(heartbeat) = &heartbeat {
+      heartbeat.beat();
+    }
+    let mut worker = worker.lock().unwrap();
+    let Some(active) = worker.as_mut() else {
+      return Ok(());
+    };
+    let Some(budget) = budget.as_mut() else {
+      return active.process_chunk(audio_chunk);
+    };
+    let take = budget.take(audio_chunk.len());
+    let result = active.process_chunk(&audio_chunk[..take]);
+    if budget.is_spent() {
+      info!("Duration reached");
+      let ended = active.process_chunk(&[]);
+      *worker = None;
+      return result.and(ended);
+    }
+    result
+  });
+  // Perform handshake: wait for a Pong reply before starting data send. Over
+  // TCP the established connection already proves the receiver is there.
+  // Batching stays off unless every receiver says in its Pong that it
+  // decodes batched datagrams; older ones would only read the first packet.
+  let mut batch_refused_by = Vec::new();
+  match &udp_socket {
+    Some(_) if skip_handshake => {
+      info!("Handshake skipped (--no-handshake)");
+      batch_refused_by.extend(server_addrs.iter().cloned());
+    }
+    Some(socket) => {
+      for (server_addr, dest_addr) in server_addrs.iter().zip(&dest_addrs) {
+        let handshake = wait_for_pong_handshake(
+          &*clock,
+          socket,
+          server_addr,
+          *dest_addr,
+          handshake_timeout,
+          handshake_attempts,
+          &hello,
+        )?;
+        if !handshake.accepts_batches {
+          batch_refused_by.push(server_addr.clone());
+        }
+        if handshake_json {
+          use std::io::Write;
+
+          // One line per destination on stdout, which nothing else uses
+          println!("{}", handshake.to_json());
+          let _ = io::stdout().flush();
+        }
+      }
+    }
+    None => batch_refused_by.extend(server_addrs.iter().cloned()),
+  }
+  if batch > 1 && !batch_refused_by.is_empty() {
+    warn!(
+      "--batch is ignored: no handshake confirmed batch support by {}",
+      batch_refused_by.join(", ")
+    );
+  } else if let Some(worker) = batch_worker.upgrade().filter(|_| batch > 1) {
+    if let Some(worker) = worker.lock().unwrap().as_mut() {
+      worker.set_batch(batch);
+    }
+    spawn_batch_flusher(batch_worker);
+  }
 
-  // Spawn responder to handle time-sync pings from receiver (after handshake)
-  spawn_timesync_responder(&socket);
+  // Spawn responders to handle time-sync pings from receivers (after
+  // handshake): one for the shared UDP socket, or one per TCP connection
+  let responders = if udp_socket.is_some() {
+    &transports[..1]
+  } else {
+    &transports[..]
+  };
+  for transport in responders {
+    spawn_timesync_responder(
+      transport.clone(),
+      packet_meta,
+      format_request.clone(),
+      remote_level.clone(),
+      peer_sync.clone(),
+      clock.clone(),
+    );
+  }
 
   // Make socket nonblocking for send/recv after handshake
-  socket
-    .set_nonblocking(true)
-    .context("failed to set UDP socket nonblocking")?;
+  if let Some(socket) = &udp_socket {
+    socket
+      .set_nonblocking(true)
+      .context("failed to set UDP socket nonblocking")?;
+  }
+
+  // Encoding and sending can block on the network, which a realtime
+  // capture callback must never do; file and stdin inputs already run on
+  // threads of their own
+  let process_chunk = if input_source.is_live() {
+    spawn_capture_handoff(
+      process_chunk,
+      packet_meta,
+      capture_buffer.unwrap_or(DEFAULT_CAPTURE_BUFFER),
+    )
+  } else {
+    process_chunk
+  };
+  input_source.start(&packet_meta, process_chunk)?;
+  if let Some(watchdog) = watchdog {
+    spawn_input_watchdog(watchdog, input_watchdog_exit);
+  }
 
   // --- 4. Show status icon on macOS, or print stats on other OSes ---
   // On macOS, spawn a status icon in the main thread and let it run there
@@ -266,7 +764,7 @@ fn main() -> Result<()> {
   if show_status_icon {
     #[cfg(target_os = "macos")]
     {
-      println!("Sending started.");
+      info!("Sending started.");
       sound_send::status_icon_mac::show_status_icon(stats_rx);
     }
 
@@ -276,13 +774,23 @@ fn main() -> Result<()> {
   } else {
     use std::io::Write;
 
-    println!("Sending started. Press Ctrl+C to stop.");
+    match duration {
+      Some(secs) => info!("Sending started for {secs} s."),
+      None => info!("Sending started. Press Ctrl+C to stop."),
+    }
 
-    // Main thread: receive stats and render
+    // Main thread: receive stats and render until the worker is gone
+    let started = Instant::now();
+    let mut total_bytes_sent = 0;
+    let mut level = SmoothedLevel::default();
     while let Ok(stats) = stats_rx.recv() {
+      total_bytes_sent = stats.total_bytes_sent;
+      if is_quiet() {
+        continue;
+      }
       let now: Instant = Instant::now();
-      let db = meter.lock().unwrap().dbfs(now);
-      print!(
+      let db = level.dbfs(&mut meter.lock().unwrap(), now);
+      eprint!(
         "\rTotal: {:>7.2} MB | Last 10s avg: {:>7.2} KB/s | Pkts/s: {:>6.1} | \
          Frame: {:>6.2} ms | Vol1s: {:>6.1} dBFS   ",
         stats.total_bytes_sent as f64 / (1024.0 * 1024.0),
@@ -291,8 +799,33 @@ fn main() -> Result<()> {
         stats.average_frame_duration_ms,
         db
       );
-      let _ = io::stdout().flush();
+      if stats.silent_packets_suppressed > 0 {
+        eprint!(
+          "| Silence: {} pkts, {:.2} MB saved   ",
+          stats.silent_packets_suppressed,
+          stats.bytes_saved as f64 / (1024.0 * 1024.0)
+        );
+      }
+      if stats.backpressured_packets > 0 {
+        eprint!("| Backpressure: {} pkts   ", stats.backpressured_packets);
+      }
+      if stats.link_down {
+        eprint!("| LINK DOWN   ");
+      }
+      if sync_stats {
+        eprint!("{}", peer_sync.lock().unwrap().peer_status());
+      }
+      if show_hist {
+        eprint!("| Sizes: {}   ", stats.payload_hist);
+      }
+      let _ = io::stderr().flush();
+      mark_progress_line();
     }
+    info!(
+      "Sent {:.2} MB in {:.1} s",
+      total_bytes_sent as f64 / (1024.0 * 1024.0),
+      started.elapsed().as_secs_f64()
+    );
   }
 
   Ok(())
@@ -305,6 +838,7 @@ fn parse_input_mode(s: &str) -> Result<InputMode> {
     #[cfg(target_os = "windows")]
     "wasapi" | "loopback" => Ok(InputMode::WasapiLoopback),
     "stdin" => Ok(InputMode::Stdin),
+    "file" => Ok(InputMode::File),
     other => bail!(
       "invalid input mode: {} (expected: {})",
       other,
@@ -316,19 +850,19 @@ fn parse_input_mode(s: &str) -> Result<InputMode> {
 fn input_mode_options() -> &'static str {
   #[cfg(all(feature = "cpal", target_os = "windows"))]
   {
-    "cpal|wasapi|stdin"
+    "cpal|wasapi|stdin|file"
   }
   #[cfg(all(feature = "cpal", not(target_os = "windows")))]
   {
-    "cpal|stdin"
+    "cpal|stdin|file"
   }
   #[cfg(all(not(feature = "cpal"), target_os = "windows"))]
   {
-    "wasapi|stdin"
+    "wasapi|stdin|file"
   }
   #[cfg(all(not(feature = "cpal"), not(target_os = "windows")))]
   {
-    "stdin"
+    "stdin|file"
   }
 }
 
@@ -360,53 +894,194 @@ fn parse_sample_format(s: &str) -> Result<SampleFormat> {
   }
 }
 
-fn bytes_per_sample(fmt: SampleFormat) -> usize {
-  match fmt {
-    SampleFormat::F32 => 4,
-    SampleFormat::I16 => 2,
-    SampleFormat::U16 => 2,
-    SampleFormat::U32 => 4,
-    _ => 1,
+fn parse_handshake_timeout(s: &str) -> Result<Duration> {
+  let ms: u64 = s.parse().context("invalid --handshake-timeout-ms value")?;
+  if ms == 0 {
+    bail!("--handshake-timeout-ms must be greater than 0");
+  }
+  Ok(Duration::from_millis(ms))
+}
+
+fn parse_handshake_attempts(s: &str) -> Result<usize> {
+  let n: usize = s.parse().context("invalid --handshake-attempts value")?;
+  if n == 0 {
+    bail!("--handshake-attempts must be at least 1");
+  }
+  Ok(n)
+}
+
+fn parse_sndbuf(s: &str) -> Result<usize> {
+  let n: usize = s.parse().context("invalid --sndbuf value")?;
+  if n == 0 {
+    bail!("--sndbuf must be at least 1 byte");
+  }
+  Ok(n)
+}
+
+fn parse_batch(s: &str) -> Result<usize> {
+  let n: usize = s.parse().context("invalid --batch value")?;
+  if !(1..=MAX_BATCH).contains(&n) {
+    bail!("--batch must be 1..={MAX_BATCH}");
+  }
+  Ok(n)
+}
+
+fn parse_ttl(s: &str) -> Result<u8> {
+  let n: u8 = s.parse().context("invalid --ttl value (1..=255)")?;
+  if n == 0 {
+    bail!("--ttl must be 1..=255");
+  }
+  Ok(n)
+}
+
+fn parse_cpal_frames(s: &str) -> Result<u32> {
+  let n: u32 = s.parse().context("invalid --cpal-frames value")?;
+  if n == 0 {
+    bail!("--cpal-frames must be at least 1");
+  }
+  Ok(n)
+}
+
+fn parse_duration(s: &str) -> Result<f64> {
+  let secs: f64 = s.parse().context("invalid --duration value")?;
+  if !(secs.is_finite() && secs > 0.0) {
+    bail!("--duration must be a positive number of seconds");
+  }
+  Ok(secs)
+}
+
+fn parse_capture_buffer(s: &str) -> Result<Duration> {
+  let ms: u64 = s.parse().context("invalid --capture-buffer-ms value")?;
+  if ms == 0 {
+    bail!("--capture-buffer-ms must be greater than 0");
+  }
+  Ok(Duration::from_millis(ms))
+}
+
+fn parse_input_watchdog(s: &str) -> Result<Duration> {
+  let ms: u64 = s.parse().context("invalid --input-watchdog-ms value")?;
+  if ms == 0 {
+    bail!("--input-watchdog-ms must be greater than 0");
+  }
+  Ok(Duration::from_millis(ms))
+}
+
+fn parse_dscp(s: &str) -> Result<u8> {
+  let n: u8 = s.parse().context("invalid --dscp value")?;
+  if n > 63 {
+    bail!("--dscp must be 0..=63");
+  }
+  Ok(n)
+}
+
+fn parse_silence_threshold(s: &str) -> Result<f64> {
+  let db: f64 = s.parse().context("invalid --silence-threshold-db value")?;
+  if !db.is_finite() {
+    bail!("--silence-threshold-db must be a finite number");
+  }
+  Ok(db)
+}
+
+fn parse_rate_limit(s: &str, flag: &str) -> Result<f64> {
+  let v: f64 = s.parse().with_context(|| format!("invalid {flag} value"))?;
+  if !(v > 0.0 && v.is_finite()) {
+    bail!("{flag} must be a positive number");
+  }
+  Ok(v)
+}
+
+/// Spaces packets out so the send rate stays under the configured packet
+/// and/or bit rate. Only used for non-live inputs (stdin), where sleeping
+/// on the reader thread simply slows down reading.
+struct Pacer {
+  max_pps: Option<f64>,
+  max_bytes_per_sec: Option<f64>,
+  next_send: Option<Instant>,
+}
+
+impl Pacer {
+  fn new(max_pps: Option<f64>, max_kbps: Option<f64>) -> Self {
+    Self {
+      max_pps,
+      max_bytes_per_sec: max_kbps.map(|k| k * 1000.0 / 8.0),
+      next_send: None,
+    }
+  }
+
+  // Sleep until the next packet may be sent, then reserve its slot
+  fn wait(&mut self, packet_bytes: usize) {
+    let now = Instant::now();
+    let start = match self.next_send {
+      Some(t) if t > now => {
+        std::thread::sleep(t - now);
+        t
+      }
+      // Never bank credit from idle time; that would allow a burst
+      _ => now,
+    };
+    let mut interval_secs: f64 = 0.0;
+    if let Some(pps) = self.max_pps {
+      interval_secs = interval_secs.max(1.0 / pps);
+    }
+    if let Some(bps) = self.max_bytes_per_sec {
+      interval_secs = interval_secs.max(packet_bytes as f64 / bps);
+    }
+    self.next_send = Some(start + Duration::from_secs_f64(interval_secs));
   }
 }
 
 fn is_silent_chunk(fmt: SampleFormat, data: &[u8]) -> bool {
   match fmt {
     SampleFormat::F32 => {
-      if data.len() % 4 != 0 {
+      if !data.len().is_multiple_of(4) {
         return false;
       }
       let s: &[f32] = bytemuck::cast_slice(data);
       s.iter().all(|&v| v == 0.0)
     }
     SampleFormat::I16 => {
-      if data.len() % 2 != 0 {
+      if !data.len().is_multiple_of(2) {
         return false;
       }
       let s: &[i16] = bytemuck::cast_slice(data);
       s.iter().all(|&v| v == 0)
     }
     SampleFormat::U16 => {
-      if data.len() % 2 != 0 {
+      if !data.len().is_multiple_of(2) {
         return false;
       }
       let s: &[u16] = bytemuck::cast_slice(data);
       s.iter().all(|&v| v == 0x8000)
     }
     SampleFormat::U32 => {
-      if data.len() % 4 != 0 {
+      if !data.len().is_multiple_of(4) {
         return false;
       }
       let s: &[u32] = bytemuck::cast_slice(data);
       s.iter().all(|&v| v == 0x8000_0000)
     }
-    _ => false,
+  }
+}
+
+// One receiver the stream is sent to
+struct Destination {
+  transport: Arc<dyn Transport>,
+  addr: SocketAddr,
+  link: LinkMonitor,
+}
+
+impl Destination {
+  fn new(transport: Arc<dyn Transport>, addr: SocketAddr) -> Self {
+    Self {
+      transport,
+      addr,
+      link: LinkMonitor::new(),
+    }
   }
 }
 
 struct SendWorker {
-  send_sock: UdpSocket,
-  server_addr: String,
+  destinations: Vec<Destination>,
   packet_meta: Meta,
   meter: Arc<Mutex<VolumeMeter>>,
   stats_tx: mpsc::Sender<SendStats>,
@@ -417,23 +1092,54 @@ struct SendWorker {
   packet_rate: RollingRate,
   chunk_duration: RollingMean,
   warned_sample_align: bool,
-  silent_count: u64,
+  silence: SilenceSuppressor,
+  payload_hist: PayloadHistogram,
+  pacer: Option<Pacer>,
+  // Chunks quieter than this also count as silence
+  silence_threshold_db: Option<f64>,
+  // Format produced by the input source; packets may carry a different one
+  // if the receiver asked for it
+  source_format: SampleFormat,
+  requested_format: Arc<RequestedFormat>,
+  convert_buf: Vec<u8>,
+  // Dithers conversions to 16 bits, as a receiver converting would
+  dither: Dither,
+  // Gain and mute set by receivers' Control messages
+  remote_level: Arc<RemoteLevel>,
+  level_buf: Vec<u8>,
+  timestamp_clock: TimestampClock,
+  // Source of wall-clock packet timestamps
+  clock: Arc<dyn Clock>,
+  // Origin of monotonic packet timestamps
+  start: Instant,
   update_interval: Duration,
+  // Per-destination clock estimates, fed by the time-sync responders
+  peer_sync: Option<Arc<Mutex<DefaultSyncController>>>,
+  // Frames captured so far, sent in each header when `send_frame_counter`
+  frames_captured: u64,
+  send_frame_counter: bool,
+  // Byte-stream inputs may split a frame across chunks; the ragged tail
+  // waits here for the rest of its frame
+  carry: Option<FrameCarry>,
+  // Up to `batch_max` encoded packets waiting to go out as one datagram
+  batch_max: usize,
+  batch: Vec<u8>,
+  batch_count: usize,
+  batch_started: Instant,
 }
 
 impl SendWorker {
   fn new(
-    send_sock: UdpSocket,
-    server_addr: String,
+    destinations: Vec<Destination>,
     packet_meta: Meta,
     meter: Arc<Mutex<VolumeMeter>>,
     stats_tx: mpsc::Sender<SendStats>,
     window: Duration,
     update_interval: Duration,
   ) -> Self {
+    let requested_format = Arc::new(RequestedFormat::new(destinations.len()));
     Self {
-      send_sock,
-      server_addr,
+      destinations,
       packet_meta,
       meter,
       stats_tx,
@@ -444,25 +1150,101 @@ impl SendWorker {
       packet_rate: RollingRate::new(window),
       chunk_duration: RollingMean::new(window),
       warned_sample_align: false,
-      silent_count: 0,
+      silence: SilenceSuppressor::new(SUPPRESS_SILENT_PACKETS_THRESHOLD),
+      payload_hist: PayloadHistogram::default(),
+      pacer: None,
+      silence_threshold_db: None,
+      source_format: packet_meta.sample_format,
+      requested_format,
+      convert_buf: Vec::new(),
+      dither: Dither::default(),
+      remote_level: Arc::default(),
+      level_buf: Vec::new(),
+      timestamp_clock: TimestampClock::Wall,
+      clock: Arc::new(SystemClock),
+      start: Instant::now(),
       update_interval,
+      peer_sync: None,
+      frames_captured: 0,
+      send_frame_counter: false,
+      carry: None,
+      batch_max: 1,
+      batch: Vec::new(),
+      batch_count: 0,
+      batch_started: Instant::now(),
     }
   }
 
-  fn record_chunk_duration(&mut self, now: Instant, chunk_len: usize) {
-    if chunk_len == 0 {
-      return;
+  fn set_pacer(&mut self, pacer: Pacer) {
+    self.pacer = Some(pacer);
+  }
+
+  fn set_silence_threshold_db(&mut self, db: Option<f64>) {
+    self.silence_threshold_db = db;
+  }
+
+  fn set_timestamp_clock(&mut self, clock: TimestampClock) {
+    self.timestamp_clock = clock;
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  fn set_peer_sync(&mut self, sync: Arc<Mutex<DefaultSyncController>>) {
+    self.peer_sync = Some(sync);
+  }
+
+  fn set_frame_counter(&mut self, enabled: bool) {
+    self.send_frame_counter = enabled;
+  }
+
+  fn set_carry_partial_frames(&mut self, enabled: bool) {
+    self.carry =
+      enabled.then(|| FrameCarry::new(self.source_meta().frame_size()));
+  }
+
+  fn set_batch(&mut self, packets: usize) {
+    self.batch_max = packets.max(1);
+  }
+
+  fn frame_bytes(&self) -> usize {
+    self.packet_meta.frame_size()
+  }
+
+  // Layout of the chunks the input source delivers, before any conversion
+  fn source_meta(&self) -> Meta {
+    Meta {
+      sample_format: self.source_format,
+      ..self.packet_meta
     }
-    let channels = self.packet_meta.channels as usize;
-    if channels == 0 {
+  }
+
+  // Shared slot through which a receiver's FormatRequest reaches the worker
+  fn format_request_handle(&self) -> Arc<RequestedFormat> {
+    self.requested_format.clone()
+  }
+
+  fn remote_level_handle(&self) -> Arc<RemoteLevel> {
+    self.remote_level.clone()
+  }
+
+  fn apply_format_request(&mut self) {
+    let Some(want) = self.requested_format.get() else {
       return;
+    };
+    if want != self.packet_meta.sample_format {
+      info!("Converting {:?} input to {:?}", self.source_format, want);
+      self.packet_meta.sample_format = want;
     }
-    let bytes_per_sample = bytes_per_sample(self.packet_meta.sample_format);
-    if bytes_per_sample == 0 {
+  }
+
+  fn record_chunk_duration(&mut self, now: Instant, chunk_len: usize) {
+    if chunk_len == 0 {
       return;
     }
-    let frame_bytes = bytes_per_sample * channels;
-    if frame_bytes == 0 || chunk_len % frame_bytes != 0 {
+    let frame_bytes = self.frame_bytes();
+    if frame_bytes == 0 || !chunk_len.is_multiple_of(frame_bytes) {
       return;
     }
     let frames = chunk_len / frame_bytes;
@@ -478,27 +1260,187 @@ impl SendWorker {
   }
 
   fn process_chunk(&mut self, audio_chunk: &[u8]) -> Result<()> {
+    self.flush_stale_batch();
+    if audio_chunk.is_empty() {
+      self.drop_torn_frame();
+      self.flush_batch();
+      // Source ended; let the receiver close its sink right away, and
+      // leave main the final totals
+      self.send_end_of_stream();
+      self.report_stats(Instant::now());
+      return Ok(());
+    }
+    let Some(mut carry) = self.carry.take() else {
+      return self.process_aligned(audio_chunk);
+    };
+    let result = self.process_aligned(carry.align(audio_chunk));
+    self.carry = Some(carry);
+    result
+  }
+
+  // A partial frame left at end of input is never completed; sending it
+  // padded would only add a discontinuity, so it is dropped
+  fn drop_torn_frame(&mut self) {
+    let torn = self.carry.as_mut().map_or(0, FrameCarry::discard);
+    if torn > 0 {
+      debug!("Dropping {torn}-byte partial frame at end of input");
+    }
+  }
+
+  fn process_aligned(&mut self, audio_chunk: &[u8]) -> Result<()> {
+    if audio_chunk.is_empty() {
+      return Ok(());
+    }
+    self.apply_format_request();
+    if self.packet_meta.sample_format != self.source_format {
+      let mut buf = std::mem::take(&mut self.convert_buf);
+      convert_via_f32(
+        audio_chunk,
+        self.source_format,
+        self.packet_meta.sample_format,
+        Some(&mut self.dither),
+        &mut buf,
+      );
+      let result = self.send_leveled(&buf);
+      self.convert_buf = buf;
+      return result;
+    }
+    self.send_leveled(audio_chunk)
+  }
+
+  // Apply the remote gain, or replace the audio with silence while muted so
+  // it collapses into empty packets like any other silence
+  fn send_leveled(&mut self, audio_chunk: &[u8]) -> Result<()> {
+    let gain = self.remote_level.gain();
+    if gain == Gain::UNITY {
+      return self.send_chunk(audio_chunk);
+    }
+    let mut buf = std::mem::take(&mut self.level_buf);
+    buf.clear();
+    buf.extend_from_slice(audio_chunk);
+    apply_gain_bytes(&mut buf, self.packet_meta.sample_format, gain);
+    let result = self.send_chunk(&buf);
+    self.level_buf = buf;
+    result
+  }
+
+  fn queue_packet(&mut self, packet: &[u8]) {
+    if self.batch_max <= 1 {
+      self.send_datagram(packet);
+      return;
+    }
+    if self.batch.len() + packet.len() > BATCH_MAX_BYTES {
+      self.flush_batch();
+    }
+    if self.batch_count == 0 {
+      self.batch_started = Instant::now();
+    }
+    self.batch.extend_from_slice(packet);
+    self.batch_count += 1;
+    // Hold the batch only while another packet like this one still fits,
+    // so full audio packets go out at once and mostly silence is batched
+    if self.batch_count >= self.batch_max
+      || self.batch.len() + packet.len() > BATCH_MAX_BYTES
+      || self.batch_started.elapsed() >= BATCH_MAX_DELAY
+    {
+      self.flush_batch();
+    }
+  }
+
+  // Send a partial batch whose oldest packet has waited long enough
+  fn flush_stale_batch(&mut self) {
+    if self.batch_count > 0 && self.batch_started.elapsed() >= BATCH_MAX_DELAY {
+      self.flush_batch();
+    }
+  }
+
+  fn flush_batch(&mut self) {
+    if self.batch_count == 0 {
+      return;
+    }
+    let mut batch = std::mem::take(&mut self.batch);
+    self.send_datagram(&batch);
+    batch.clear();
+    self.batch = batch;
+    self.batch_count = 0;
+  }
+
+  fn send_datagram(&mut self, bytes: &[u8]) {
+    // Send errors do not stop the stream; they are reported with backoff
+    for dest in &mut self.destinations {
+      let result = dest.transport.send_packet_to(bytes, dest.addr);
+      dest.link.on_send(&result, dest.addr, Instant::now());
+    }
+  }
+
+  fn report_stats(&mut self, now: Instant) {
+    let average_rate_bps = self.byte_rate.rate_per_sec(now);
+    let average_packets_per_sec = self.packet_rate.rate_per_sec(now);
+    let average_frame_duration_ms = self.chunk_duration.average(now) * 1000.0;
+    let peak_dbfs = self.meter.lock().unwrap().peak_dbfs(now);
+    let _ = self.stats_tx.send(SendStats {
+      total_bytes_sent: self.total_bytes_sent,
+      average_rate_bps,
+      average_packets_per_sec,
+      average_frame_duration_ms,
+      payload_hist: self.payload_hist,
+      peak_dbfs,
+      silent_packets_suppressed: self.silence.packets_suppressed(),
+      bytes_saved: self.silence.bytes_saved(),
+      link_down: self.destinations.iter().any(|d| d.link.is_down()),
+      backpressured_packets: self
+        .destinations
+        .iter()
+        .map(|d| d.link.backpressured())
+        .sum(),
+    });
+  }
+
+  fn send_end_of_stream(&self) {
+    let msg = encode_sync(&SyncMessage::EndOfStream);
+    for i in 0..END_OF_STREAM_REPEATS {
+      if i > 0 {
+        std::thread::sleep(END_OF_STREAM_GAP);
+      }
+      for dest in &self.destinations {
+        let _ = dest.transport.send_packet_to(&msg, dest.addr);
+      }
+    }
+    info!("Input ended; sent end of stream");
+  }
+
+  fn send_chunk(&mut self, audio_chunk: &[u8]) -> Result<()> {
     self.record_chunk_duration(Instant::now(), audio_chunk.len());
 
     // Determine if this chunk is silence and collapse repeated silence
-    let bps = bytes_per_sample(self.packet_meta.sample_format);
-    let aligned = bps == 1 || (audio_chunk.len() % bps == 0);
-    let is_silent =
-      aligned && is_silent_chunk(self.packet_meta.sample_format, audio_chunk);
-    if is_silent {
-      self.silent_count = self
-        .silent_count
-        .saturating_add((audio_chunk.len() / bps) as u64);
-    } else {
-      self.silent_count = 0;
-    }
-    if self.silent_count > SUPPRESS_SILENT_PACKETS_THRESHOLD {
-      return self.process_packet(&[]);
+    let bps = self.packet_meta.bytes_per_sample();
+    let aligned = bps == 1 || audio_chunk.len().is_multiple_of(bps);
+    // Exact digital silence is cheap to detect; only compute the RMS for
+    // the noise-floor threshold when that fails
+    let fmt = self.packet_meta.sample_format;
+    let is_silent = aligned
+      && (is_silent_chunk(fmt, audio_chunk)
+        || self
+          .silence_threshold_db
+          .is_some_and(|t| chunk_dbfs(fmt, audio_chunk) < t));
+    // Keep every packet frame-aligned, even when the frame size does not
+    // divide MAX_PAYLOAD
+    let max_payload = MAX_PAYLOAD - MAX_PAYLOAD % self.frame_bytes().max(1);
+    let samples = (audio_chunk.len() / bps) as u64;
+    if self
+      .silence
+      .suppress(is_silent, samples, audio_chunk.len(), max_payload)
+    {
+      // One empty packet replaces the audio packets this chunk would need
+      let result = self.process_packet(&[]);
+      // The suppressed audio was still captured
+      self.frames_captured += (audio_chunk.len() / self.frame_bytes()) as u64;
+      return result;
     }
 
     let mut offset = 0;
     while offset < audio_chunk.len() {
-      let end = (offset + MAX_PAYLOAD).min(audio_chunk.len());
+      let end = (offset + max_payload).min(audio_chunk.len());
       self.process_packet(&audio_chunk[offset..end])?;
       offset = end;
     }
@@ -507,70 +1449,60 @@ impl SendWorker {
   }
 
   fn process_packet(&mut self, payload: &[u8]) -> Result<()> {
-    let now_ts: Duration = SystemTime::now()
-      .duration_since(UNIX_EPOCH)
-      .unwrap_or_else(|_| Duration::from_millis(0));
-    let ts_ms = now_ts.as_millis() as u64;
+    let ts_ms = match self.timestamp_clock {
+      TimestampClock::Wall => self.clock.now_ms(),
+      TimestampClock::Monotonic => self.start.elapsed().as_millis() as u64,
+    };
 
-    let send_buf =
-      encode_packet(self.sequence_number, payload, self.packet_meta, ts_ms);
+    let send_buf = encode_packet_with_frame_counter(
+      self.sequence_number,
+      payload,
+      self.packet_meta,
+      ts_ms,
+      self.timestamp_clock,
+      self.send_frame_counter.then_some(self.frames_captured),
+    );
+    self.frames_captured += (payload.len() / self.frame_bytes()) as u64;
 
-    if self
-      .send_sock
-      .send_to(&send_buf, &self.server_addr)
-      .is_err()
-    {
-      // Ignore send errors and continue (nonblocking)
+    if let Some(pacer) = self.pacer.as_mut() {
+      pacer.wait(send_buf.len());
     }
+    self.queue_packet(&send_buf);
 
     let now = Instant::now();
-    if payload.len() > 0 {
+    if !payload.is_empty() {
       let mut guard = self.meter.lock().unwrap();
-      let bps = bytes_per_sample(self.packet_meta.sample_format);
-      let aligned = bps == 1 || (payload.len() % bps == 0);
+      let bps = self.packet_meta.bytes_per_sample();
+      let aligned = bps == 1 || payload.len().is_multiple_of(bps);
       if !aligned && !self.warned_sample_align {
-        eprintln!(
-          "warning: payload length {} is not a multiple of 1-sample ({} bytes)",
+        warn!(
+          "payload length {} is not a multiple of 1-sample ({} bytes)",
           payload.len(),
           bps
         );
         self.warned_sample_align = true;
       }
-      if aligned {
-        if self.packet_meta.sample_format == SampleFormat::F32 {
-          let f: &[f32] = bytemuck::cast_slice(payload);
-          guard.add_samples_f32(now, f);
-        } else if self.packet_meta.sample_format == SampleFormat::I16 {
-          let f: &[i16] = bytemuck::cast_slice(payload);
-          guard.add_samples_i16(now, f);
-        } else if self.packet_meta.sample_format == SampleFormat::U16 {
-          let f: &[u16] = bytemuck::cast_slice(payload);
-          guard.add_samples_u16(now, f);
-        } else if self.packet_meta.sample_format == SampleFormat::U32 {
-          let f: &[u32] = bytemuck::cast_slice(payload);
-          guard.add_samples_u32(now, f);
-        }
-      }
+      let reader = AudioFrameReader::new(payload, &self.packet_meta);
+      guard.add_frames(now, &reader);
     } else {
       // Silent packet
       self.meter.lock().unwrap().add_samples_raw(now, 0.0, 0);
     }
 
-    let sent_packet_size = send_buf.len();
-    self.total_bytes_sent += sent_packet_size as u64;
-    self.byte_rate.record(now, sent_packet_size as u64);
+    // Totals count the stream once, however many destinations it went to
+    self.total_bytes_sent += send_buf.len() as u64;
+    self.byte_rate.record(now, send_buf.len() as u64);
     self.packet_rate.record(now, 1);
+    self.payload_hist.record(payload.len());
 
     if now.duration_since(self.last_update_time) >= self.update_interval {
-      let average_rate_bps = self.byte_rate.rate_per_sec(now);
-      let average_packets_per_sec = self.packet_rate.rate_per_sec(now);
-      let average_frame_duration_ms = self.chunk_duration.average(now) * 1000.0;
-      let _ = self.stats_tx.send(SendStats {
-        total_bytes_sent: self.total_bytes_sent,
-        average_rate_bps,
-        average_packets_per_sec,
-        average_frame_duration_ms,
-      });
+      self.report_stats(now);
+      if let Some(sync) = &self.peer_sync {
+        let mut sync = sync.lock().unwrap();
+        for dest in &self.destinations {
+          sync.maybe_ping_peer(&*dest.transport, dest.addr);
+        }
+      }
       self.last_update_time = now;
     }
 
@@ -583,86 +1515,317 @@ impl SendWorker {
 fn print_usage() {
   let input_modes = input_mode_options();
   let default_mode = default_input_mode_name();
+  eprintln!("Usage: udp_sender <server_addr:port>... [options]");
+  eprintln!("Required:");
   eprintln!(
-    "Usage: udp_sender <server_addr:port> \
-     [options]\nRequired:\n<server_addr:port>          Destination \
-     address\nOptions:\n-i, --input <{input_modes}>    Input source (default: \
-     {default_mode})\n-c, --channels <1..255>     Channels for stdin \
-     (default: 2)\n-r, --rate <hz>             Sample rate for stdin \
-     (default: 48000)\n-f, --format <f32|i16|u16|u32>  Sample format for \
-     stdin (default: u32)\n-h, --help                  Show this help"
+    "<server_addr:port>...       Destination address(es); each gets a copy"
   );
+  eprintln!("Options:");
+  eprintln!(
+    "-i, --input <{input_modes}>    Input source (default: {default_mode})"
+  );
+  eprintln!(
+    "-c, --channels <1..255>     Channels for stdin (default: 2) or cpal"
+  );
+  eprintln!(
+    "-r, --rate <hz>             Sample rate for stdin (default: 48000) or \
+     cpal"
+  );
+  eprintln!(
+    "-f, --format <f32|i16|u16|u32>  Sample format for stdin (default: u32) \
+     or cpal"
+  );
+  eprintln!(
+    "--stdin-header              Read channels, rate and format from a header \
+     at the start of stdin"
+  );
+  eprintln!("-p, --path <file.wav>       WAV file for --input file");
+  eprintln!(
+    "--cpal-frames <n>           Frames per cpal capture callback (default: \
+     device default)"
+  );
+  eprintln!(
+    "--loop                      Restart --input file at its end (ignored for \
+     live inputs)"
+  );
+  eprintln!(
+    "-b, --bind <addr:port>      Local bind address (default: 0.0.0.0:0)"
+  );
+  eprintln!(
+    "--handshake-timeout-ms <ms> Wait per handshake attempt (default: {})",
+    DEFAULT_HANDSHAKE_TIMEOUT.as_millis()
+  );
+  eprintln!(
+    "--handshake-attempts <n>    Handshake attempts before giving up \
+     (default: {DEFAULT_HANDSHAKE_ATTEMPTS})"
+  );
+  eprintln!(
+    "--no-handshake              Start sending without waiting for a Pong"
+  );
+  eprintln!(
+    "--handshake-json            Print each completed handshake to stdout as \
+     a JSON line"
+  );
+  eprintln!("--tcp                       Send over TCP instead of UDP");
+  eprintln!("--sndbuf <bytes>            UDP socket send buffer size");
+  eprintln!(
+    "--ttl <1..255>              IP TTL / hop limit for UDP packets, \
+     including multicast"
+  );
+  eprintln!(
+    "--input-watchdog-ms <ms>    Log an error when the input delivers nothing \
+     for this long"
+  );
+  eprintln!(
+    "--input-watchdog-exit       Exit with status 2 when the watchdog fires"
+  );
+  eprintln!(
+    "--dscp <0..63>              Mark UDP packets for QoS, e.g. 46 (EF); may \
+     need privileges and is ignored on some platforms"
+  );
+  eprintln!(
+    "--mono-ts                   Timestamp packets from a monotonic clock"
+  );
+  eprintln!(
+    "--frame-counter             Send frames captured so far in each header, \
+     for A/V alignment"
+  );
+  eprintln!(
+    "--silence-threshold-db <db> Treat chunks below this RMS level as silence"
+  );
+  eprintln!(
+    "--batch <n>                 Coalesce up to n packets per datagram \
+     (default: 1; only to receivers whose handshake accepts batches)"
+  );
+  eprintln!(
+    "--duration <secs>           Stop after this much audio, sending end of \
+     stream"
+  );
+  eprintln!(
+    "--capture-buffer-ms <ms>    Audio held between a live capture and the \
+     send thread (default: {})",
+    DEFAULT_CAPTURE_BUFFER.as_millis()
+  );
+  eprintln!("--max-pps <n>               Limit packets/s (stdin and file)");
+  eprintln!("--max-kbps <n>              Limit kbit/s (stdin and file)");
+  eprintln!("--hist                      Show a payload size histogram");
+  eprintln!(
+    "--sync-stats                Ping receivers and show each one's clock \
+     offset and drift"
+  );
+  eprintln!("-q, --quiet                 Suppress status output");
+  eprintln!("-v, --verbose               More detail; repeat or -vv for trace");
+  eprintln!("-s, --status-icon           Show a status icon (macOS only)");
+  eprintln!(
+    "--wasapi-info               Print the WASAPI loopback mix format and \
+     exit (Windows only)"
+  );
+  eprintln!("-h, --help                  Show this help");
 }
 
-fn wait_for_pong_handshake(
-  socket: &UdpSocket,
-  server_addr: &str,
-) -> Result<()> {
-  // Temporarily set a read timeout for handshake retries
-  let original_timeout = socket.read_timeout().unwrap_or(None);
-  socket.set_read_timeout(Some(Duration::from_millis(500)))?;
-
-  // Send Ping and wait for corresponding Pong
-  // Try a few times before giving up
-  const MAX_ATTEMPTS: usize = 20; // ~10 seconds total
-  for attempt in 1..=MAX_ATTEMPTS {
-    let now = SystemTime::now()
-      .duration_since(UNIX_EPOCH)
-      .unwrap_or_else(|_| Duration::from_millis(0))
-      .as_millis() as u64;
-    let ping = SyncMessage::Ping { t0_ms: now };
-    let v = encode_sync(&ping);
-    let _ = socket.send_to(&v, server_addr);
-
-    let mut buf = [0u8; 128];
-    match socket.recv_from(&mut buf) {
-      Ok((n, _addr)) => {
-        if let Ok(Message::Sync(SyncMessage::Pong { t0_ms, .. })) =
-          decode_message(&buf[..n])
-        {
-          if t0_ms == now {
-            // Matched our ping; handshake complete
-            println!("Handshake complete: received Pong (attempt {attempt})");
-            // Restore timeout before returning
-            socket.set_read_timeout(original_timeout)?;
-            return Ok(());
-          }
-        }
-        // Not a matching pong; continue trying within this attempt window
+// The returned callback only copies each chunk into a lock-free ring and
+// wakes a send thread, which drains the ring into `process_chunk`. Of a
+// chunk that does not fit, the whole frames that do are kept and the rest
+// dropped, so a chunk larger than the ring still gets through in part;
+// each such chunk counts as an overrun.
+fn spawn_capture_handoff(
+  mut process_chunk: ProcessChunk,
+  meta: Meta,
+  depth: Duration,
+) -> ProcessChunk {
+  let frame = meta.frame_size();
+  let frames = (f64::from(meta.sample_rate.0) * depth.as_secs_f64()).ceil();
+  let capacity = (frames as usize).max(1) * frame;
+  let (mut producer, mut consumer) = spsc::ring(capacity);
+  let overruns = Arc::new(AtomicU64::new(0));
+  let dropped = overruns.clone();
+  info!("Capture buffer: {} ms", depth.as_millis());
+
+  let drain = std::thread::spawn(move || {
+    boost_current_thread_priority();
+    // Pushes are whole chunks of whole frames, so the ring only ever
+    // holds whole frames and a frame-sized buffer never splits one
+    let mut buf = vec![0u8; capacity];
+    let mut reported = 0;
+    let mut last_report = Instant::now();
+    loop {
+      let n = consumer.pop(&mut buf);
+      if n == 0 {
+        std::thread::park_timeout(CAPTURE_POLL);
+      } else {
+        // Send failures are reported by the worker itself
+        let _ = process_chunk(&buf[..n]);
       }
-      Err(ref e)
-        if e.kind() == std::io::ErrorKind::WouldBlock
-          || e.kind() == std::io::ErrorKind::TimedOut =>
-      {
-        // Timed out; try next attempt
+      let total = overruns.load(Ordering::Relaxed);
+      if total != reported && last_report.elapsed() >= CAPTURE_OVERRUN_LOG {
+        warn!(
+          "capture buffer overrun: {} chunks cut short ({total} total); try a \
+           larger --capture-buffer-ms",
+          total - reported
+        );
+        reported = total;
+        last_report = Instant::now();
+      }
+    }
+  });
+  let waker = drain.thread().clone();
+  Box::new(move |chunk: &[u8]| {
+    let free = producer.free();
+    let n = chunk.len().min(free - free % frame);
+    producer.push(&chunk[..n]);
+    if n < chunk.len() {
+      dropped.fetch_add(1, Ordering::Relaxed);
+    }
+    // Unparking is a syscall, so only wake a drain thread that may have
+    // found the ring empty; one that missed this wakes on its poll timeout
+    if free == producer.capacity() && n > 0 {
+      waker.unpark();
+    }
+    Ok(())
+  })
+}
+
+// A partial batch otherwise waits for the next chunk, which may be a long
+// time coming when the input stalls or only produces audio in bursts
+fn spawn_batch_flusher(worker: Weak<Mutex<Option<SendWorker>>>) {
+  std::thread::spawn(move || {
+    loop {
+      std::thread::sleep(BATCH_MAX_DELAY / 2);
+      let Some(shared) = worker.upgrade() else {
+        return;
+      };
+      let mut slot = shared.lock().unwrap();
+      let Some(active) = slot.as_mut() else {
+        return;
+      };
+      active.flush_stale_batch();
+    }
+  });
+}
+
+// A stalled capture callback (e.g. a removed device) leaves the sender
+// running but silent; report it, and optionally exit so a supervisor can
+// restart the process.
+fn spawn_input_watchdog(mut watchdog: InputWatchdog, exit: bool) {
+  let timeout = watchdog.timeout();
+  let poll = (timeout / 4).max(Duration::from_millis(10));
+  std::thread::spawn(move || {
+    loop {
+      std::thread::sleep(poll);
+      match watchdog.check() {
+        Some(WatchdogEvent::Stalled(idle)) => error!(
+          "No input for {} ms (--input-watchdog-ms {})",
+          idle.as_millis(),
+          timeout.as_millis()
+        ),
+        Some(WatchdogEvent::Resumed) => info!("Input resumed"),
+        None => {}
       }
-      Err(e) => {
-        // Unexpected error; restore timeout and propagate
-        socket.set_read_timeout(original_timeout)?;
-        return Err(e).context("handshake recv failed");
+      if exit && watchdog.is_stalled() {
+        std::process::exit(2);
       }
     }
-  }
+  });
+}
 
-  // Restore timeout before failing
+fn wait_for_pong_handshake(
+  clock: &dyn Clock,
+  socket: &UdpSocket,
+  server_addr: &str,
+  dest_addr: SocketAddr,
+  timeout: Duration,
+  max_attempts: usize,
+  hello: &[u8],
+) -> Result<Handshake> {
+  // Temporarily set a read timeout for handshake retries
+  let original_timeout = socket.read_timeout().unwrap_or(None);
+  let result = handshake::wait_for_pong(
+    clock,
+    socket,
+    dest_addr,
+    timeout,
+    max_attempts,
+    hello,
+  );
   socket.set_read_timeout(original_timeout)?;
-  bail!("failed to complete ping/pong handshake with receiver");
+  result.with_context(|| {
+    format!("failed to complete ping/pong handshake with {server_addr}")
+  })
 }
 
-fn spawn_timesync_responder(socket: &UdpSocket) {
-  let ts_sock = socket
-    .try_clone()
-    .expect("failed to clone udp socket for timesync");
-
+fn spawn_timesync_responder(
+  ts_sock: Arc<dyn Transport>,
+  source_meta: Meta,
+  format_request: Arc<RequestedFormat>,
+  remote_level: Arc<RemoteLevel>,
+  peer_sync: Arc<Mutex<DefaultSyncController>>,
+  clock: Arc<dyn Clock>,
+) {
   std::thread::spawn(move || {
+    let mut decode_errors = DecodeErrorCounts::default();
+    let mut warned_refusal = false;
     loop {
       let mut buf = [0u8; 64];
-      match ts_sock.recv_from(&mut buf) {
+      match ts_sock.recv_packet_from(&mut buf) {
         Ok((n, addr)) => {
-          if let Ok(Message::Sync(SyncMessage::Ping { t0_ms })) =
-            decode_message(&buf[..n])
-          {
-            respond_to_ping(&ts_sock, addr, t0_ms);
+          let recv_ms = clock.now_ms();
+          match decode_message(&buf[..n]) {
+            Ok(Message::Sync(SyncMessage::Ping { t0_ms })) => {
+              // Senders never decode data packets, batched or not
+              respond_to_ping_with(
+                &*clock, &*ts_sock, addr, t0_ms, recv_ms, false,
+              );
+            }
+            Ok(Message::Sync(SyncMessage::Pong {
+              t0_ms,
+              t1_ms,
+              t2_ms,
+              ..
+            })) => {
+              peer_sync
+                .lock()
+                .unwrap()
+                .on_pong_from(addr, t0_ms, t1_ms, t2_ms);
+            }
+            Ok(Message::Sync(SyncMessage::FormatRequest {
+              sample_format,
+              sample_rate,
+            })) => {
+              handle_format_request(
+                &source_meta,
+                &format_request,
+                addr,
+                sample_format,
+                sample_rate,
+                &mut warned_refusal,
+              );
+            }
+            Ok(Message::Sync(SyncMessage::Control { gain_db, muted })) => {
+              // Any peer that can reach this socket may do this; there is
+              // no authentication on sync messages
+              let changed = remote_level.set(gain_db, muted);
+              if changed && muted {
+                info!("{addr} muted the stream");
+              } else if changed {
+                info!("{addr} set the stream gain to {gain_db} dB");
+              }
+            }
+            Err(e) => {
+              decode_errors.record(&e);
+              if e.is_version_mismatch() && decode_errors.version == 1 {
+                warn!(
+                  "{addr} sends packet version {} that this build cannot \
+                   read; sender and receiver are probably different builds",
+                  buf[1]
+                );
+              } else if decode_errors.total() % DECODE_ERROR_LOG_EVERY == 1 {
+                warn!(
+                  "undecodable datagram from {addr}: {e} (so far: {})",
+                  decode_errors
+                );
+              }
+            }
+            _ => {}
           }
         }
         Err(ref e)
@@ -678,3 +1841,36 @@ fn spawn_timesync_responder(socket: &UdpSocket) {
     }
   });
 }
+
+// Honor a receiver's FormatRequest where possible: sample format
+// conversions are applied by the send worker, resampling is not supported.
+fn handle_format_request(
+  source_meta: &Meta,
+  format_request: &RequestedFormat,
+  addr: SocketAddr,
+  sample_format: SampleFormat,
+  sample_rate: u32,
+  warned_refusal: &mut bool,
+) {
+  match format_request.request(sample_format) {
+    FormatRequestOutcome::Unchanged => return,
+    FormatRequestOutcome::Refused => {
+      // Receivers repeat unanswered requests; once is enough to explain
+      if !std::mem::replace(warned_refusal, true) {
+        warn!(
+          "{addr} requested format {sample_format:?}; ignored, since every \
+           destination gets the same stream"
+        );
+      }
+      return;
+    }
+    FormatRequestOutcome::Accepted => {}
+  }
+  info!("{addr} requested format {sample_format:?} @ {sample_rate} Hz");
+  if sample_rate != 0 && sample_rate != source_meta.sample_rate.0 {
+    warn!(
+      "cannot resample {} Hz to requested {} Hz; keeping {} Hz",
+      source_meta.sample_rate.0, sample_rate, source_meta.sample_rate.0
+    );
+  }
+}
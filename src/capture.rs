@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Raw datagram capture log used by `udp_reciever --record` and
+/// `udp_replay`.
+///
+/// File layout (big-endian):
+/// - 6 bytes: file magic (`CAPTURE_MAGIC`)
+/// - then, repeated per datagram:
+///   - 8 bytes: receive offset since capture start (u64, microseconds)
+///   - 4 bytes: datagram length (u32)
+///   - N bytes: datagram bytes, exactly as received
+pub const CAPTURE_MAGIC: &[u8; 6] = b"SSCAP1";
+
+const RECORD_HEADER_LEN: usize = 8 + 4;
+// Largest UDP datagram; a longer record means a corrupt file
+const MAX_RECORD_LEN: usize = 65_535;
+
+pub struct CaptureWriter<W: Write> {
+  out: W,
+  scratch: Vec<u8>,
+}
+
+impl<W: Write> CaptureWriter<W> {
+  pub fn new(mut out: W) -> io::Result<Self> {
+    out.write_all(CAPTURE_MAGIC)?;
+    Ok(Self {
+      out,
+      scratch: Vec::new(),
+    })
+  }
+
+  /// Appends one datagram received `offset` after the capture started.
+  /// Each record is written with a single `write_all` so an unbuffered
+  /// file never holds a partial record after an interrupt.
+  pub fn write_packet(
+    &mut self,
+    offset: Duration,
+    bytes: &[u8],
+  ) -> io::Result<()> {
+    let len = u32::try_from(bytes.len()).map_err(|_| {
+      io::Error::new(io::ErrorKind::InvalidInput, "datagram too large")
+    })?;
+    let micros = offset.as_micros().min(u64::MAX as u128) as u64;
+    self.scratch.clear();
+    self.scratch.extend_from_slice(&micros.to_be_bytes());
+    self.scratch.extend_from_slice(&len.to_be_bytes());
+    self.scratch.extend_from_slice(bytes);
+    self.out.write_all(&self.scratch)
+  }
+
+  pub fn into_inner(self) -> W {
+    self.out
+  }
+}
+
+pub struct CaptureReader<R: Read> {
+  input: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+  pub fn new(mut input: R) -> io::Result<Self> {
+    let mut magic = [0u8; 6];
+    input.read_exact(&mut magic)?;
+    if &magic != CAPTURE_MAGIC {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "not a capture file (bad magic)",
+      ));
+    }
+    Ok(Self { input })
+  }
+
+  /// Reads the next `(offset, bytes)` record, or `None` at end of file.
+  pub fn next_packet(&mut self) -> io::Result<Option<(Duration, Vec<u8>)>> {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    match self.input.read_exact(&mut header) {
+      Ok(()) => {}
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+      Err(e) => return Err(e),
+    }
+    let mut micros = [0u8; 8];
+    micros.copy_from_slice(&header[..8]);
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[8..]);
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_RECORD_LEN {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("capture record of {len} bytes exceeds limit"),
+      ));
+    }
+    let mut bytes = vec![0u8; len];
+    self.input.read_exact(&mut bytes)?;
+    Ok(Some((
+      Duration::from_micros(u64::from_be_bytes(micros)),
+      bytes,
+    )))
+  }
+}
+
+/// Re-sends every captured datagram to `dest`, sleeping so that packets
+/// leave at the same relative offsets they were recorded with.
+/// Returns the number of datagrams sent.
+pub fn replay<R: Read>(
+  reader: &mut CaptureReader<R>,
+  socket: &UdpSocket,
+  dest: SocketAddr,
+) -> io::Result<u64> {
+  let start = Instant::now();
+  let mut sent = 0u64;
+  while let Some((offset, bytes)) = reader.next_packet()? {
+    let elapsed = start.elapsed();
+    if offset > elapsed {
+      std::thread::sleep(offset - elapsed);
+    }
+    socket.send_to(&bytes, dest)?;
+    sent += 1;
+  }
+  Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn record_then_replay_preserves_bytes() {
+    let packets: Vec<(Duration, Vec<u8>)> = vec![
+      (Duration::from_millis(0), b"first".to_vec()),
+      (Duration::from_millis(3), vec![0u8, 1, 2, 255]),
+      (Duration::from_millis(5), Vec::new()),
+    ];
+    let mut w = CaptureWriter::new(Vec::new()).unwrap();
+    for (offset, bytes) in &packets {
+      w.write_packet(*offset, bytes).unwrap();
+    }
+    let file = w.into_inner();
+
+    let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    rx.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut r = CaptureReader::new(file.as_slice()).unwrap();
+    let sent = replay(&mut r, &tx, rx.local_addr().unwrap()).unwrap();
+    assert_eq!(sent, packets.len() as u64);
+
+    let mut buf = [0u8; 64];
+    for (_, bytes) in &packets {
+      let (n, _) = rx.recv_from(&mut buf).unwrap();
+      assert_eq!(&buf[..n], bytes.as_slice());
+    }
+  }
+
+  #[test]
+  fn rejects_bad_magic() {
+    let r = CaptureReader::new(&b"NOTCAP"[..]);
+    assert!(r.is_err());
+  }
+
+  #[test]
+  fn rejects_oversized_records() {
+    let mut file = CAPTURE_MAGIC.to_vec();
+    file.extend_from_slice(&0u64.to_be_bytes());
+    file.extend_from_slice(&u32::MAX.to_be_bytes());
+    let mut r = CaptureReader::new(file.as_slice()).unwrap();
+    let err = r.next_packet().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+}
@@ -0,0 +1,116 @@
+// Bookkeeping for the receiver's per-sender state.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::packet::Meta;
+
+/// Make room for `new` in `clients`, which holds at most `max` entries, by
+/// removing the one `last_seen` reports as least recently seen. Returns the
+/// evicted entry, so dropping it tears down whatever it owns. Nothing is
+/// evicted for a key already present or while there is room.
+pub fn evict_least_recent<K, V>(
+  clients: &mut HashMap<K, V>,
+  new: &K,
+  max: usize,
+  last_seen: impl Fn(&V) -> Instant,
+) -> Option<(K, V)>
+where
+  K: Eq + Hash + Clone,
+{
+  if clients.contains_key(new) || clients.len() < max {
+    return None;
+  }
+  let oldest = clients
+    .iter()
+    .min_by_key(|(_, v)| last_seen(v))
+    .map(|(k, _)| k.clone())?;
+  clients.remove_entry(&oldest)
+}
+
+/// Something a data packet says about its sender's stream that is worth
+/// logging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamEvent {
+  /// The sender's first data packet.
+  Started,
+  /// Data again after `gap` without any, at least the stale threshold.
+  Resumed { gap: Duration },
+  /// The sender switched formats; state derived from `from` is stale.
+  FormatChanged { from: Meta },
+}
+
+/// Compare a data packet in `meta` against the sender's previous data
+/// packet, which was in `last_meta` (None if there was none) and arrived
+/// `gap` ago.
+pub fn stream_event(
+  last_meta: Option<Meta>,
+  meta: Meta,
+  gap: Duration,
+  stale_after: Duration,
+) -> Option<StreamEvent> {
+  match last_meta {
+    None => Some(StreamEvent::Started),
+    Some(from) if from != meta => Some(StreamEvent::FormatChanged { from }),
+    Some(_) if gap >= stale_after => Some(StreamEvent::Resumed { gap }),
+    Some(_) => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::SampleFormat;
+
+  #[test]
+  fn evicts_the_least_recently_seen_only_when_full() {
+    let base = Instant::now();
+    let at = |secs| base + Duration::from_secs(secs);
+    let mut clients = HashMap::from([("a", at(5)), ("b", at(1)), ("c", at(9))]);
+    let seen = |t: &Instant| *t;
+
+    assert!(evict_least_recent(&mut clients, &"d", 4, seen).is_none());
+    // A known sender never displaces another
+    assert!(evict_least_recent(&mut clients, &"a", 3, seen).is_none());
+    assert_eq!(
+      evict_least_recent(&mut clients, &"d", 3, seen),
+      Some(("b", at(1)))
+    );
+    assert_eq!(clients.len(), 2);
+  }
+
+  #[test]
+  fn a_new_format_is_reported_against_the_previous_one() {
+    let stereo = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let float = Meta::new(2, 48_000, SampleFormat::F32).unwrap();
+    let mono = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let event = |last, meta| {
+      stream_event(last, meta, Duration::ZERO, Duration::from_secs(2))
+    };
+
+    assert_eq!(event(Some(stereo), stereo), None);
+    assert_eq!(
+      event(Some(stereo), float),
+      Some(StreamEvent::FormatChanged { from: stereo })
+    );
+    assert_eq!(
+      event(Some(float), mono),
+      Some(StreamEvent::FormatChanged { from: float })
+    );
+  }
+
+  #[test]
+  fn streams_start_once_and_resume_after_a_stale_gap() {
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let stale = Duration::from_secs(2);
+    let event = |last, gap| stream_event(last, meta, gap, stale);
+
+    assert_eq!(event(None, Duration::ZERO), Some(StreamEvent::Started));
+    assert_eq!(event(Some(meta), Duration::from_millis(1_999)), None);
+    assert_eq!(
+      event(Some(meta), stale),
+      Some(StreamEvent::Resumed { gap: stale })
+    );
+  }
+}
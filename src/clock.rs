@@ -0,0 +1,94 @@
+// Wall-clock source for time sync and packet timestamps, swappable so tests
+// can drive ping/pong exchanges with exact timestamps.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Source of time in milliseconds: since the UNIX epoch, except for
+/// `MonotonicClock`.
+pub trait Clock: Send + Sync {
+  fn now_ms(&self) -> u64;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+  fn now_ms(&self) -> u64 {
+    (**self).now_ms()
+  }
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now_ms(&self) -> u64 {
+    crate::packet::unix_time_ms()
+  }
+}
+
+/// Milliseconds since the clock was created. Never jumps, so it suits
+/// measuring intervals locally, but its readings mean nothing to peers.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+  start: Instant,
+}
+
+impl Default for MonotonicClock {
+  fn default() -> Self {
+    Self {
+      start: Instant::now(),
+    }
+  }
+}
+
+impl Clock for MonotonicClock {
+  fn now_ms(&self) -> u64 {
+    self.start.elapsed().as_millis() as u64
+  }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep one handle while another is owned by the code under test.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+  now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+  pub fn new(now_ms: u64) -> Self {
+    Self {
+      now: Arc::new(AtomicU64::new(now_ms)),
+    }
+  }
+
+  pub fn set(&self, now_ms: u64) {
+    self.now.store(now_ms, Ordering::Relaxed);
+  }
+
+  pub fn advance(&self, ms: u64) {
+    self.now.fetch_add(ms, Ordering::Relaxed);
+  }
+}
+
+impl Clock for MockClock {
+  fn now_ms(&self) -> u64 {
+    self.now.load(Ordering::Relaxed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mock_clock_clones_share_time() {
+    let clock = MockClock::new(1_000);
+    let handle = clock.clone();
+    handle.advance(250);
+    assert_eq!(clock.now_ms(), 1_250);
+    clock.set(7);
+    assert_eq!(handle.now_ms(), 7);
+    assert!(SystemClock.now_ms() > 1_600_000_000_000);
+  }
+}
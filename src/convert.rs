@@ -0,0 +1,57 @@
+// Byte-order conversion for interleaved PCM payloads; sample format
+// conversion is `dsp::convert_via_f32`.
+
+use alloc::vec::Vec;
+
+/// Copy `input` into `out` with the bytes of every sample reversed, for
+/// payloads whose byte order differs from this host's. A trailing partial
+/// sample is dropped.
+pub fn swap_sample_bytes(
+  input: &[u8],
+  bytes_per_sample: usize,
+  out: &mut Vec<u8>,
+) {
+  out.clear();
+  if bytes_per_sample == 0 {
+    return;
+  }
+  out.reserve(input.len());
+  for sample in input.chunks_exact(bytes_per_sample) {
+    out.extend(sample.iter().rev());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn byte_swapped_i16_payload_reads_correctly() {
+    use crate::packet::{
+      ByteOrder, Meta, SampleFormat, decode_packet, encode_packet,
+    };
+
+    // Build a packet as a host of the opposite byte order would send it
+    let samples = [1i16, -2, 0x1234];
+    let foreign: Vec<u8> = samples
+      .iter()
+      .flat_map(|v| match ByteOrder::NATIVE {
+        ByteOrder::Little => v.to_be_bytes(),
+        ByteOrder::Big => v.to_le_bytes(),
+      })
+      .collect();
+    let meta = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let mut pkt = encode_packet(0, &foreign, meta, 0);
+    pkt[6] ^= 0x02; // flip the big-endian flag
+
+    let d = decode_packet(&pkt).unwrap();
+    assert_ne!(d.byte_order, ByteOrder::NATIVE);
+    let mut native = Vec::new();
+    swap_sample_bytes(d.payload, 2, &mut native);
+    let decoded: Vec<i16> = native
+      .chunks_exact(2)
+      .map(|b| i16::from_ne_bytes([b[0], b[1]]))
+      .collect();
+    assert_eq!(decoded, samples);
+  }
+}
@@ -0,0 +1,609 @@
+// Format-aware views over native-endian interleaved PCM payloads.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::packet::{Meta, SampleFormat};
+
+/// Normalize one sample to roughly -1.0..=1.0. `b` must hold at least
+/// `format.bytes_per_sample()` bytes.
+pub fn sample_to_f32(format: SampleFormat, b: &[u8]) -> f32 {
+  match format {
+    SampleFormat::F32 => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+    SampleFormat::I16 => i16::from_ne_bytes([b[0], b[1]]) as f32 / 32768.0,
+    SampleFormat::U16 => {
+      (u16::from_ne_bytes([b[0], b[1]]) as f32 - 32768.0) / 32768.0
+    }
+    SampleFormat::U32 => {
+      let v = u32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64;
+      ((v - 2_147_483_648.0) / 2_147_483_648.0) as f32
+    }
+  }
+}
+
+// `f32::round` needs std
+fn round(x: f32) -> f32 {
+  if x >= 0.0 {
+    (x + 0.5) as i64 as f32
+  } else {
+    (x - 0.5) as i64 as f32
+  }
+}
+
+/// Write normalized `x` into `out` (at least `format.bytes_per_sample()`
+/// bytes) as one sample, adding `dither_lsb` steps of the target's least
+/// significant bit before rounding. Out-of-range values clamp to full
+/// scale.
+pub fn f32_to_sample(
+  format: SampleFormat,
+  x: f32,
+  dither_lsb: f32,
+  out: &mut [u8],
+) {
+  match format {
+    SampleFormat::F32 => out[..4].copy_from_slice(&x.to_ne_bytes()),
+    SampleFormat::I16 => {
+      let v = round(x * 32768.0 + dither_lsb).clamp(-32768.0, 32767.0);
+      out[..2].copy_from_slice(&(v as i16).to_ne_bytes());
+    }
+    SampleFormat::U16 => {
+      let v = round(x * 32768.0 + 32768.0 + dither_lsb).clamp(0.0, 65535.0);
+      out[..2].copy_from_slice(&(v as u16).to_ne_bytes());
+    }
+    SampleFormat::U32 => {
+      // f32 cannot hold every u32, so scale in f64
+      let v = (x as f64 * 2_147_483_648.0 + 2_147_483_648.0)
+        .clamp(0.0, u32::MAX as f64);
+      out[..4].copy_from_slice(&((v + 0.5) as u32).to_ne_bytes());
+    }
+  }
+}
+
+/// Triangular (TPDF) dither of +-1 LSB, which decorrelates the rounding
+/// error from the signal when requantizing to 16 bits. A small xorshift
+/// generator keeps this free of dependencies.
+#[derive(Debug, Clone)]
+pub struct Dither {
+  state: u32,
+}
+
+impl Default for Dither {
+  fn default() -> Self {
+    Self::new(0x9E37_79B9)
+  }
+}
+
+impl Dither {
+  pub fn new(seed: u32) -> Self {
+    // xorshift never leaves zero
+    Self { state: seed.max(1) }
+  }
+
+  fn uniform(&mut self) -> f32 {
+    let mut x = self.state;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    self.state = x;
+    (x >> 8) as f32 / (1u32 << 24) as f32
+  }
+
+  /// Next dither offset in LSB steps, in -1.0..1.0.
+  pub fn next_lsb(&mut self) -> f32 {
+    self.uniform() - self.uniform()
+  }
+}
+
+/// Whether converting `from` to `to` loses resolution enough to be worth
+/// dithering: anything wider requantized to 16 bits.
+pub fn needs_dither(from: SampleFormat, to: SampleFormat) -> bool {
+  let to_16 = matches!(to, SampleFormat::I16 | SampleFormat::U16);
+  let from_16 = matches!(from, SampleFormat::I16 | SampleFormat::U16);
+  to_16 && !from_16
+}
+
+/// Convert `data` sample by sample from `from` to `to` through normalized
+/// f32, replacing the contents of `out`. `dither` is applied when the
+/// conversion `needs_dither`. A trailing partial sample is dropped.
+#[cfg(feature = "alloc")]
+pub fn convert_via_f32(
+  data: &[u8],
+  from: SampleFormat,
+  to: SampleFormat,
+  mut dither: Option<&mut Dither>,
+  out: &mut Vec<u8>,
+) {
+  out.clear();
+  let in_bps = from.bytes_per_sample();
+  let out_bps = to.bytes_per_sample();
+  let samples = data.len() / in_bps;
+  out.resize(samples * out_bps, 0);
+  if !needs_dither(from, to) {
+    dither = None;
+  }
+  for (b, o) in data.chunks_exact(in_bps).zip(out.chunks_exact_mut(out_bps)) {
+    let d = dither.as_deref_mut().map_or(0.0, Dither::next_lsb);
+    f32_to_sample(to, sample_to_f32(from, b), d, o);
+  }
+}
+
+/// Sample format a receiver asked a sender to convert to, shared between
+/// the thread that hears `FormatRequest`s and the send worker. Every
+/// destination of a sender gets the same packets, so with several of them
+/// a request is refused instead of changing the stream under the others.
+#[derive(Debug)]
+pub struct RequestedFormat {
+  destinations: usize,
+  // `SampleFormat::code`, 0 until a request is accepted
+  code: core::sync::atomic::AtomicU8,
+}
+
+/// What `RequestedFormat::request` did with a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatRequestOutcome {
+  Accepted,
+  /// The format was already in effect.
+  Unchanged,
+  /// Other destinations share the stream.
+  Refused,
+}
+
+impl RequestedFormat {
+  pub fn new(destinations: usize) -> Self {
+    Self {
+      destinations,
+      code: core::sync::atomic::AtomicU8::new(0),
+    }
+  }
+
+  pub fn get(&self) -> Option<SampleFormat> {
+    SampleFormat::from_code(
+      self.code.load(core::sync::atomic::Ordering::Relaxed),
+    )
+  }
+
+  pub fn request(&self, format: SampleFormat) -> FormatRequestOutcome {
+    if self.destinations > 1 {
+      return FormatRequestOutcome::Refused;
+    }
+    let code = format.code();
+    if self.code.swap(code, core::sync::atomic::Ordering::Relaxed) == code {
+      FormatRequestOutcome::Unchanged
+    } else {
+      FormatRequestOutcome::Accepted
+    }
+  }
+}
+
+/// Iterate over whole frames of `data`. A trailing partial frame is
+/// skipped.
+pub fn frames<'a>(
+  data: &'a [u8],
+  meta: &Meta,
+) -> impl Iterator<Item = &'a [u8]> + 'a {
+  data.chunks_exact(meta.frame_size().max(1))
+}
+
+/// Every sample of every whole frame, interleaved, as normalized f32.
+pub fn samples_f32<'a>(
+  data: &'a [u8],
+  meta: &Meta,
+) -> impl Iterator<Item = f32> + 'a {
+  let format = meta.sample_format;
+  let bps = format.bytes_per_sample();
+  frames(data, meta)
+    .flat_map(move |frame| frame.chunks_exact(bps))
+    .map(move |b| sample_to_f32(format, b))
+}
+
+/// Deinterleave a single channel as normalized f32. Yields nothing when
+/// `channel` is out of range.
+pub fn channel_f32<'a>(
+  data: &'a [u8],
+  meta: &Meta,
+  channel: usize,
+) -> impl Iterator<Item = f32> + 'a {
+  let format = meta.sample_format;
+  let bps = format.bytes_per_sample();
+  let in_range = channel < meta.channels as usize;
+  frames(data, meta)
+    .filter(move |_| in_range)
+    .map(move |frame| sample_to_f32(format, &frame[channel * bps..]))
+}
+
+/// A payload read through its `Meta`, as whole frames or as samples
+/// normalized by `sample_to_f32`. Every view borrows the payload; only
+/// `to_f32_vec` allocates.
+#[derive(Debug, Clone, Copy)]
+pub struct AudioFrameReader<'a> {
+  data: &'a [u8],
+  meta: Meta,
+}
+
+impl<'a> AudioFrameReader<'a> {
+  pub fn new(data: &'a [u8], meta: &Meta) -> Self {
+    Self { data, meta: *meta }
+  }
+
+  pub fn meta(&self) -> &Meta {
+    &self.meta
+  }
+
+  pub fn frame_count(&self) -> usize {
+    self.data.len() / self.meta.frame_size().max(1)
+  }
+
+  /// The bytes of every whole frame, without a trailing partial frame.
+  pub fn bytes(&self) -> &'a [u8] {
+    &self.data[..self.frame_count() * self.meta.frame_size()]
+  }
+
+  pub fn frames(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+    frames(self.data, &self.meta)
+  }
+
+  pub fn samples_f32(&self) -> impl Iterator<Item = f32> + 'a {
+    samples_f32(self.data, &self.meta)
+  }
+
+  pub fn channel_f32(&self, channel: usize) -> impl Iterator<Item = f32> + 'a {
+    channel_f32(self.data, &self.meta, channel)
+  }
+
+  #[cfg(feature = "alloc")]
+  pub fn to_f32_vec(&self) -> Vec<f32> {
+    self.samples_f32().collect()
+  }
+}
+
+/// Regroups a byte stream whose reads may end mid-frame into whole frames.
+/// A chunk that starts on a frame boundary is handed back in place and only
+/// its ragged tail is kept; while a tail is pending, chunks are appended to
+/// it in one buffer that is reused rather than reallocated.
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub struct FrameCarry {
+  frame_size: usize,
+  buf: Vec<u8>,
+  // Leading bytes of `buf` handed out by the last `align`
+  consumed: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl FrameCarry {
+  pub fn new(frame_size: usize) -> Self {
+    Self {
+      frame_size: frame_size.max(1),
+      buf: Vec::new(),
+      consumed: 0,
+    }
+  }
+
+  /// Bytes of a partial frame waiting for the rest of it.
+  pub fn pending(&self) -> usize {
+    self.buf.len() - self.consumed
+  }
+
+  /// The whole frames available once `chunk` follows the pending bytes.
+  /// What is left of a partial frame waits for the next call.
+  pub fn align<'a>(&'a mut self, chunk: &'a [u8]) -> &'a [u8] {
+    self.buf.drain(..self.consumed);
+    self.consumed = 0;
+    if self.buf.is_empty() {
+      let whole = chunk.len() - chunk.len() % self.frame_size;
+      self.buf.extend_from_slice(&chunk[whole..]);
+      return &chunk[..whole];
+    }
+    self.buf.extend_from_slice(chunk);
+    self.consumed = self.buf.len() - self.buf.len() % self.frame_size;
+    &self.buf[..self.consumed]
+  }
+
+  /// Drop a partial frame that will never be completed and return its
+  /// length. Padding it out instead would put a discontinuity on the wire.
+  pub fn discard(&mut self) -> usize {
+    let torn = self.pending();
+    self.buf.clear();
+    self.consumed = 0;
+    torn
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn meta(channels: u16, format: SampleFormat) -> Meta {
+    Meta::new(channels, 48_000, format).unwrap()
+  }
+
+  fn collect<const N: usize>(it: impl Iterator<Item = f32>) -> [f32; N] {
+    let mut out = [f32::NAN; N];
+    let mut n = 0;
+    for (slot, v) in out.iter_mut().zip(it) {
+      *slot = v;
+      n += 1;
+    }
+    assert_eq!(n, N);
+    out
+  }
+
+  #[test]
+  fn converts_each_format_to_normalized_f32() {
+    let mut buf = [0u8; 16];
+
+    for (b, v) in buf.chunks_exact_mut(4).zip([0.5f32, -1.0, 0.0, 0.25]) {
+      b.copy_from_slice(&v.to_ne_bytes());
+    }
+    let got: [f32; 4] = collect(samples_f32(&buf, &meta(2, SampleFormat::F32)));
+    assert_eq!(got, [0.5, -1.0, 0.0, 0.25]);
+
+    for (b, v) in buf.chunks_exact_mut(2).zip([0i16, 16384, -32768, 32767]) {
+      b.copy_from_slice(&v.to_ne_bytes());
+    }
+    let got: [f32; 4] =
+      collect(samples_f32(&buf[..8], &meta(2, SampleFormat::I16)));
+    assert_eq!(got[..3], [0.0, 0.5, -1.0]);
+    assert!((got[3] - 1.0).abs() < 1e-4);
+
+    for (b, v) in buf.chunks_exact_mut(2).zip([32768u16, 49152, 0, 65535]) {
+      b.copy_from_slice(&v.to_ne_bytes());
+    }
+    let got: [f32; 4] =
+      collect(samples_f32(&buf[..8], &meta(1, SampleFormat::U16)));
+    assert_eq!(got[..3], [0.0, 0.5, -1.0]);
+    assert!((got[3] - 1.0).abs() < 1e-4);
+
+    for (b, v) in
+      buf
+        .chunks_exact_mut(4)
+        .zip([1u32 << 31, 3u32 << 30, 0, u32::MAX])
+    {
+      b.copy_from_slice(&v.to_ne_bytes());
+    }
+    let got: [f32; 4] = collect(samples_f32(&buf, &meta(2, SampleFormat::U32)));
+    assert_eq!(got[..3], [0.0, 0.5, -1.0]);
+    assert!((got[3] - 1.0).abs() < 1e-6);
+  }
+
+  #[cfg(feature = "alloc")]
+  #[test]
+  fn f32_to_i16_and_back_within_tolerance() {
+    let src: Vec<f32> = (0..2_000).map(|i| (i as f32 * 0.01).sin()).collect();
+    let bytes: Vec<u8> = src.iter().flat_map(|v| v.to_ne_bytes()).collect();
+    let mut ints = Vec::new();
+    let mut dither = Dither::default();
+    convert_via_f32(
+      &bytes,
+      SampleFormat::F32,
+      SampleFormat::I16,
+      Some(&mut dither),
+      &mut ints,
+    );
+    assert_eq!(ints.len(), src.len() * 2);
+    let mut back = Vec::new();
+    convert_via_f32(
+      &ints,
+      SampleFormat::I16,
+      SampleFormat::F32,
+      None,
+      &mut back,
+    );
+    let back = samples_f32(&back, &meta(1, SampleFormat::F32));
+    // Rounding plus TPDF dither stays within 1.5 LSB
+    let lsb = 1.0 / 32768.0;
+    for (want, got) in src.iter().zip(back) {
+      assert!((want - got).abs() <= 1.5 * lsb, "{want} vs {got}");
+    }
+  }
+
+  #[cfg(feature = "alloc")]
+  #[test]
+  fn unsigned_formats_are_offset_binary() {
+    let src = 0i16.to_ne_bytes();
+    let mut wide = Vec::new();
+    let mut narrow = Vec::new();
+    convert_via_f32(
+      &src,
+      SampleFormat::I16,
+      SampleFormat::U32,
+      None,
+      &mut wide,
+    );
+    assert_eq!(wide, 0x8000_0000u32.to_ne_bytes());
+    convert_via_f32(
+      &wide,
+      SampleFormat::U32,
+      SampleFormat::U16,
+      None,
+      &mut narrow,
+    );
+    assert_eq!(narrow, 0x8000u16.to_ne_bytes());
+  }
+
+  #[cfg(feature = "alloc")]
+  #[test]
+  fn i16_to_f32_and_back_is_exact() {
+    let src: Vec<u8> = [0i16, 1, -1, 12_345, -32768, 32767]
+      .iter()
+      .flat_map(|v| v.to_ne_bytes())
+      .collect();
+    let mut floats = Vec::new();
+    let mut ints = Vec::new();
+    let mut dither = Dither::default();
+    convert_via_f32(
+      &src,
+      SampleFormat::I16,
+      SampleFormat::F32,
+      None,
+      &mut floats,
+    );
+    // Values already on 16-bit steps survive undithered
+    convert_via_f32(
+      &floats,
+      SampleFormat::F32,
+      SampleFormat::I16,
+      None,
+      &mut ints,
+    );
+    assert_eq!(ints, src);
+    // Dither never pushes full scale past the clamp
+    let loud: Vec<u8> = [1.5f32, -1.5]
+      .iter()
+      .flat_map(|v| v.to_ne_bytes())
+      .collect();
+    convert_via_f32(
+      &loud,
+      SampleFormat::F32,
+      SampleFormat::I16,
+      Some(&mut dither),
+      &mut ints,
+    );
+    let clamped: Vec<u8> = [32767i16, -32768]
+      .iter()
+      .flat_map(|v| v.to_ne_bytes())
+      .collect();
+    assert_eq!(ints, clamped);
+  }
+
+  #[test]
+  fn frames_skip_trailing_partial_frame() {
+    let buf = [0u8; 10];
+    let m = meta(2, SampleFormat::I16);
+    assert_eq!(frames(&buf, &m).count(), 2);
+    assert!(frames(&buf, &m).all(|f| f.len() == 4));
+    assert_eq!(samples_f32(&buf, &m).count(), 4);
+  }
+
+  #[test]
+  fn reader_views_each_format() {
+    fn bytes<const N: usize, T: Copy>(
+      values: [T; 2],
+      to_ne: fn(T) -> [u8; N],
+    ) -> [u8; 10] {
+      // Two mono frames plus a partial one the reader must leave out
+      let mut buf = [0xAAu8; 10];
+      for (b, v) in buf.chunks_exact_mut(N).zip(values) {
+        b.copy_from_slice(&to_ne(v));
+      }
+      buf
+    }
+    let cases: [(SampleFormat, [u8; 10]); 4] = [
+      (SampleFormat::F32, bytes([0.5f32, -0.25], f32::to_ne_bytes)),
+      (
+        SampleFormat::I16,
+        bytes([16384i16, -8192], i16::to_ne_bytes),
+      ),
+      (
+        SampleFormat::U16,
+        bytes([49152u16, 24576], u16::to_ne_bytes),
+      ),
+      (
+        SampleFormat::U32,
+        bytes([3u32 << 30, 3u32 << 29], u32::to_ne_bytes),
+      ),
+    ];
+    for (format, buf) in cases {
+      let bps = format.bytes_per_sample();
+      let m = meta(1, format);
+      let reader = AudioFrameReader::new(&buf[..2 * bps + 1], &m);
+      assert_eq!(reader.frame_count(), 2, "{format:?}");
+      assert_eq!(reader.bytes().as_ptr(), buf.as_ptr());
+      assert_eq!(reader.bytes().len(), 2 * bps);
+      assert!(reader.frames().all(|f| f.len() == bps));
+      let got: [f32; 2] = collect(reader.samples_f32());
+      assert_eq!(got, [0.5, -0.25], "{format:?}");
+      let left: [f32; 1] = collect(reader.channel_f32(0).take(1));
+      assert_eq!(left, [0.5]);
+      #[cfg(feature = "alloc")]
+      assert_eq!(reader.to_f32_vec(), [0.5, -0.25]);
+    }
+  }
+
+  #[test]
+  fn reader_accepts_payloads_at_any_alignment() {
+    // Receive buffers make no alignment promise for multi-byte samples
+    let mut buf = [0u8; 3 + 3 * 4];
+    let m = meta(1, SampleFormat::F32);
+    for offset in 0..4 {
+      let payload = &mut buf[offset..offset + 12];
+      for (b, v) in payload.chunks_exact_mut(4).zip([0.5f32, -1.0, 0.125]) {
+        b.copy_from_slice(&v.to_ne_bytes());
+      }
+      let reader = AudioFrameReader::new(&buf[offset..offset + 12], &m);
+      let got: [f32; 3] = collect(reader.samples_f32());
+      assert_eq!(got, [0.5, -1.0, 0.125], "offset {offset}");
+    }
+  }
+
+  #[test]
+  fn channel_deinterleaves_stereo() {
+    let mut buf = [0u8; 12];
+    for (b, v) in buf.chunks_exact_mut(2).zip([1i16, -1, 2, -2, 3, -3]) {
+      b.copy_from_slice(&(v * 8192).to_ne_bytes());
+    }
+    let m = meta(2, SampleFormat::I16);
+    let left: [f32; 3] = collect(channel_f32(&buf, &m, 0));
+    let right: [f32; 3] = collect(channel_f32(&buf, &m, 1));
+    assert_eq!(left, [0.25, 0.5, 0.75]);
+    assert_eq!(right, [-0.25, -0.5, -0.75]);
+    assert_eq!(channel_f32(&buf, &m, 2).count(), 0);
+  }
+
+  #[test]
+  fn format_requests_only_apply_to_a_single_destination() {
+    let single = RequestedFormat::new(1);
+    assert_eq!(single.get(), None);
+    assert_eq!(
+      single.request(SampleFormat::I16),
+      FormatRequestOutcome::Accepted
+    );
+    assert_eq!(
+      single.request(SampleFormat::I16),
+      FormatRequestOutcome::Unchanged
+    );
+    assert_eq!(single.get(), Some(SampleFormat::I16));
+
+    // One of two receivers asking must not reformat the other's stream
+    let shared = RequestedFormat::new(2);
+    assert_eq!(
+      shared.request(SampleFormat::I16),
+      FormatRequestOutcome::Refused
+    );
+    assert_eq!(shared.get(), None);
+  }
+
+  #[test]
+  fn frame_carry_rejoins_split_samples() {
+    // Stereo i16: 4-byte frames, cut mid-sample and mid-frame
+    let stream: Vec<u8> = (1..=16).collect();
+    let mut carry = FrameCarry::new(4);
+    let mut out = Vec::new();
+    for (cut, pending) in [(0..5, 1), (5..6, 2), (6..13, 1), (13..16, 0)] {
+      let aligned = carry.align(&stream[cut]);
+      assert!(aligned.len().is_multiple_of(4));
+      out.extend_from_slice(aligned);
+      assert_eq!(carry.pending(), pending);
+    }
+    assert_eq!(out, stream[..16]);
+  }
+
+  #[test]
+  fn frame_carry_passes_aligned_chunks_in_place() {
+    let chunk = [7u8; 10];
+    let mut carry = FrameCarry::new(4);
+    let aligned = carry.align(&chunk);
+    assert_eq!(aligned.as_ptr(), chunk.as_ptr());
+    assert_eq!(aligned.len(), 8);
+    assert_eq!(carry.pending(), 2);
+  }
+
+  #[test]
+  fn frame_carry_drops_a_torn_frame() {
+    let mut carry = FrameCarry::new(4);
+    assert!(carry.align(&[1, 2, 3, 4, 5, 6]).len() == 4);
+    assert_eq!(carry.discard(), 2);
+    assert_eq!(carry.pending(), 0);
+    assert_eq!(carry.align(&[9, 9, 9, 9]), [9, 9, 9, 9]);
+  }
+}
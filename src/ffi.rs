@@ -0,0 +1,304 @@
+// C ABI for the data packet codec, so C or Swift code can read and write
+// the same packets as the Rust sender/receiver.
+//
+// Memory ownership: the caller owns every buffer. `ss_decode_packet` points
+// `SsDecoded::payload` into the caller's input buffer, so it is only valid
+// while that buffer is. `ss_encode_packet` writes into a caller-allocated
+// buffer of at least `SS_HEADER_LEN + payload_len` bytes and never
+// allocates. The matching C declarations are in include/sound_send.h.
+
+use crate::packet::{
+  DATA_HEADER_LEN, DataPacketError, Meta, SampleFormat, SampleRate,
+  TimestampClock, decode_packet, encode_packet_into_with_clock,
+};
+
+pub const SS_OK: i32 = 0;
+pub const SS_ERR_TOO_SHORT: i32 = -1;
+pub const SS_ERR_BAD_MAGIC: i32 = -2;
+pub const SS_ERR_BAD_VERSION: i32 = -3;
+pub const SS_ERR_LENGTH_MISMATCH: i32 = -4;
+pub const SS_ERR_BUFFER_TOO_SMALL: i32 = -5;
+pub const SS_ERR_BAD_CHANNELS: i32 = -6;
+pub const SS_ERR_PARTIAL_FRAME: i32 = -7;
+/// A required pointer argument was null.
+pub const SS_ERR_NULL_POINTER: i32 = -8;
+/// `SsMeta::sample_format` is not a known wire code.
+pub const SS_ERR_BAD_FORMAT: i32 = -9;
+/// The payload does not fit the 16-bit length field.
+pub const SS_ERR_PAYLOAD_TOO_LONG: i32 = -10;
+
+/// Size of the data packet header preceding the payload.
+pub const SS_HEADER_LEN: usize = DATA_HEADER_LEN;
+
+/// C mirror of `Meta`. `sample_format` uses the wire codes: 1 = f32,
+/// 2 = i16, 3 = u16, 4 = u32.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SsMeta {
+  pub channels: u8,
+  pub sample_format: u8,
+  pub sample_rate: u32,
+}
+
+/// C mirror of `Decoded`. Flags are 0 or 1.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SsDecoded {
+  pub seq: u64,
+  pub timestamp_ms: u64,
+  pub monotonic_ts: u8,
+  pub big_endian: u8,
+  pub meta: SsMeta,
+  pub payload: *const u8,
+  pub payload_len: usize,
+}
+
+fn error_code(e: DataPacketError) -> i32 {
+  match e {
+    DataPacketError::TooShort => SS_ERR_TOO_SHORT,
+    DataPacketError::BadMagic => SS_ERR_BAD_MAGIC,
+    DataPacketError::BadVersion => SS_ERR_BAD_VERSION,
+    DataPacketError::LengthMismatch => SS_ERR_LENGTH_MISMATCH,
+    DataPacketError::BufferTooSmall => SS_ERR_BUFFER_TOO_SMALL,
+    DataPacketError::BadChannels => SS_ERR_BAD_CHANNELS,
+    DataPacketError::PartialFrame => SS_ERR_PARTIAL_FRAME,
+    DataPacketError::BadFormat => SS_ERR_BAD_FORMAT,
+  }
+}
+
+/// Decode the data packet in `data[..len]` into `*out`. Returns `SS_OK` or
+/// a negative `SS_ERR_*` code, in which case `*out` is left untouched.
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes and `out` valid for a
+/// write of one `SsDecoded`. The two must not overlap; if they do, the
+/// behavior is undefined.
+#[no_mangle]
+pub unsafe extern "C" fn ss_decode_packet(
+  data: *const u8,
+  len: usize,
+  out: *mut SsDecoded,
+) -> i32 {
+  if data.is_null() || out.is_null() {
+    return SS_ERR_NULL_POINTER;
+  }
+  // SAFETY: non-null and valid for `len` bytes per the contract above
+  let data = unsafe { core::slice::from_raw_parts(data, len) };
+  let d = match decode_packet(data) {
+    Ok(d) => d,
+    Err(e) => return error_code(e),
+  };
+  let decoded = SsDecoded {
+    seq: d.seq,
+    timestamp_ms: d.timestamp_ms,
+    monotonic_ts: u8::from(d.clock == TimestampClock::Monotonic),
+    big_endian: u8::from(d.byte_order == crate::packet::ByteOrder::Big),
+    meta: SsMeta {
+      channels: d.meta.channels,
+      sample_format: d.meta.sample_format.code(),
+      sample_rate: d.meta.sample_rate.0,
+    },
+    payload: d.payload.as_ptr(),
+    payload_len: d.payload.len(),
+  };
+  // SAFETY: non-null and valid for writes per the contract above
+  unsafe { out.write(decoded) };
+  SS_OK
+}
+
+/// Encode a data packet into `out[..out_cap]`, storing the packet length
+/// in `*out_len`. `monotonic_ts` (0 or 1) flags `timestamp_ms` as coming
+/// from a monotonic clock. Returns `SS_OK` or a negative `SS_ERR_*` code.
+///
+/// # Safety
+///
+/// `payload` must be valid for reads of `payload_len` bytes (it may be
+/// null when `payload_len` is 0), `out` valid for writes of `out_cap`
+/// bytes and `out_len` valid for a write of one `usize`. None of them may
+/// overlap, so a payload cannot be encoded in place; if they do, the
+/// behavior is undefined.
+#[no_mangle]
+pub unsafe extern "C" fn ss_encode_packet(
+  seq: u64,
+  timestamp_ms: u64,
+  monotonic_ts: u8,
+  meta: SsMeta,
+  payload: *const u8,
+  payload_len: usize,
+  out: *mut u8,
+  out_cap: usize,
+  out_len: *mut usize,
+) -> i32 {
+  if out.is_null()
+    || out_len.is_null()
+    || (payload.is_null() && payload_len > 0)
+  {
+    return SS_ERR_NULL_POINTER;
+  }
+  if meta.channels == 0 {
+    return SS_ERR_BAD_CHANNELS;
+  }
+  let Some(sample_format) = SampleFormat::from_code(meta.sample_format) else {
+    return SS_ERR_BAD_FORMAT;
+  };
+  if payload_len > u16::MAX as usize {
+    return SS_ERR_PAYLOAD_TOO_LONG;
+  }
+  let payload = if payload_len == 0 {
+    &[][..]
+  } else {
+    // SAFETY: non-null and valid for `payload_len` bytes per the contract
+    unsafe { core::slice::from_raw_parts(payload, payload_len) }
+  };
+  // SAFETY: non-null and valid for `out_cap` bytes per the contract
+  let out = unsafe { core::slice::from_raw_parts_mut(out, out_cap) };
+  let meta = Meta {
+    channels: meta.channels,
+    sample_rate: SampleRate(meta.sample_rate),
+    sample_format,
+  };
+  let clock = if monotonic_ts != 0 {
+    TimestampClock::Monotonic
+  } else {
+    TimestampClock::Wall
+  };
+  match encode_packet_into_with_clock(
+    seq,
+    payload,
+    meta,
+    timestamp_ms,
+    clock,
+    out,
+  ) {
+    Ok(n) => {
+      // SAFETY: non-null and valid for writes per the contract above
+      unsafe { out_len.write(n) };
+      SS_OK
+    }
+    Err(e) => error_code(e),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn encode_then_decode_through_c_abi() {
+    let meta = SsMeta {
+      channels: 2,
+      sample_format: 2,
+      sample_rate: 44_100,
+    };
+    let payload = [1u8, 2, 3, 4];
+    let mut buf = [0u8; 64];
+    let mut len = 0usize;
+    let rc = unsafe {
+      ss_encode_packet(
+        9,
+        1234,
+        1,
+        meta,
+        payload.as_ptr(),
+        payload.len(),
+        buf.as_mut_ptr(),
+        buf.len(),
+        &mut len,
+      )
+    };
+    assert_eq!(rc, SS_OK);
+    assert_eq!(len, SS_HEADER_LEN + payload.len());
+
+    let mut out = core::mem::MaybeUninit::<SsDecoded>::uninit();
+    let rc = unsafe { ss_decode_packet(buf.as_ptr(), len, out.as_mut_ptr()) };
+    assert_eq!(rc, SS_OK);
+    let d = unsafe { out.assume_init() };
+    assert_eq!((d.seq, d.timestamp_ms, d.monotonic_ts), (9, 1234, 1));
+    assert_eq!(d.meta, meta);
+    let got = unsafe { core::slice::from_raw_parts(d.payload, d.payload_len) };
+    assert_eq!(got, payload);
+  }
+
+  #[test]
+  fn errors_map_to_codes() {
+    let mut out = core::mem::MaybeUninit::<SsDecoded>::uninit();
+    let short = [b'S'; 4];
+    let rc = unsafe { ss_decode_packet(short.as_ptr(), 4, out.as_mut_ptr()) };
+    assert_eq!(rc, SS_ERR_TOO_SHORT);
+    let rc =
+      unsafe { ss_decode_packet(core::ptr::null(), 0, out.as_mut_ptr()) };
+    assert_eq!(rc, SS_ERR_NULL_POINTER);
+
+    let meta = SsMeta {
+      channels: 1,
+      sample_format: 1,
+      sample_rate: 48_000,
+    };
+    let mut small = [0u8; 8];
+    let mut len = 0usize;
+    let rc = unsafe {
+      ss_encode_packet(
+        0,
+        0,
+        0,
+        meta,
+        core::ptr::null(),
+        0,
+        small.as_mut_ptr(),
+        small.len(),
+        &mut len,
+      )
+    };
+    assert_eq!(rc, SS_ERR_BUFFER_TOO_SMALL);
+    let bad = SsMeta {
+      sample_format: 0,
+      ..meta
+    };
+    let rc = unsafe {
+      ss_encode_packet(
+        0,
+        0,
+        0,
+        bad,
+        core::ptr::null(),
+        0,
+        small.as_mut_ptr(),
+        small.len(),
+        &mut len,
+      )
+    };
+    assert_eq!(rc, SS_ERR_BAD_FORMAT);
+  }
+
+  // The hand-written header must agree with the Rust definitions
+  #[test]
+  fn c_header_matches_constants() {
+    let header = include_str!("../include/sound_send.h");
+    let define = |name: &str| -> i64 {
+      let prefix = format!("#define {name} ");
+      header
+        .lines()
+        .find_map(|l| l.strip_prefix(prefix.as_str()))
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_else(|| panic!("{name} missing from the header"))
+    };
+    let codes = [
+      ("SS_OK", SS_OK),
+      ("SS_ERR_TOO_SHORT", SS_ERR_TOO_SHORT),
+      ("SS_ERR_BAD_MAGIC", SS_ERR_BAD_MAGIC),
+      ("SS_ERR_BAD_VERSION", SS_ERR_BAD_VERSION),
+      ("SS_ERR_LENGTH_MISMATCH", SS_ERR_LENGTH_MISMATCH),
+      ("SS_ERR_BUFFER_TOO_SMALL", SS_ERR_BUFFER_TOO_SMALL),
+      ("SS_ERR_BAD_CHANNELS", SS_ERR_BAD_CHANNELS),
+      ("SS_ERR_PARTIAL_FRAME", SS_ERR_PARTIAL_FRAME),
+      ("SS_ERR_NULL_POINTER", SS_ERR_NULL_POINTER),
+      ("SS_ERR_BAD_FORMAT", SS_ERR_BAD_FORMAT),
+      ("SS_ERR_PAYLOAD_TOO_LONG", SS_ERR_PAYLOAD_TOO_LONG),
+    ];
+    for (name, code) in codes {
+      assert_eq!(define(name), i64::from(code), "{name}");
+    }
+    assert_eq!(define("SS_HEADER_LEN"), SS_HEADER_LEN as i64);
+  }
+}
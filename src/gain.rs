@@ -0,0 +1,270 @@
+// Software gain for PCM samples. Scaling saturates at the format's limits
+// instead of wrapping, so loud input clips rather than turning into noise.
+
+use crate::packet::SampleFormat;
+
+/// Largest gain accepted by `Gain`, in dB.
+pub const MAX_GAIN_DB: f64 = 48.0;
+// 10^(MAX_GAIN_DB / 20), spelled out since `powf` needs std
+const MAX_GAIN_LINEAR: f64 = 251.188_643_150_957_97;
+
+/// A validated linear gain factor: finite and within `0..=MAX_GAIN_DB`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain(f64);
+
+impl Gain {
+  pub const UNITY: Gain = Gain(1.0);
+  /// Turns every sample into silence (the midpoint for unsigned formats).
+  pub const MUTE: Gain = Gain(0.0);
+
+  pub fn from_linear(factor: f64) -> Option<Self> {
+    (factor.is_finite() && (0.0..=MAX_GAIN_LINEAR).contains(&factor))
+      .then_some(Gain(factor))
+  }
+
+  #[cfg(feature = "std")]
+  pub fn from_db(db: f64) -> Option<Self> {
+    if !db.is_finite() || db > MAX_GAIN_DB {
+      return None;
+    }
+    Self::from_linear(10f64.powf(db / 20.0).min(MAX_GAIN_LINEAR))
+  }
+
+  pub fn linear(self) -> f64 {
+    self.0
+  }
+}
+
+/// Level a receiver asked for with `SyncMessage::Control`, shared between
+/// the thread that receives it and the one that sends audio. Gain and mute
+/// are packed into one atomic, so a reader never sees one without the other.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct RemoteLevel(core::sync::atomic::AtomicU16);
+
+#[cfg(feature = "std")]
+impl RemoteLevel {
+  const MUTED: u16 = 0x100;
+
+  fn pack(gain_db: i8, muted: bool) -> u16 {
+    gain_db as u8 as u16 | if muted { Self::MUTED } else { 0 }
+  }
+
+  pub fn get(&self) -> (i8, bool) {
+    let v = self.0.load(core::sync::atomic::Ordering::Relaxed);
+    (v as u8 as i8, v & Self::MUTED != 0)
+  }
+
+  /// Returns whether anything changed.
+  pub fn set(&self, gain_db: i8, muted: bool) -> bool {
+    let new = Self::pack(gain_db, muted);
+    self.0.swap(new, core::sync::atomic::Ordering::Relaxed) != new
+  }
+
+  /// Gain to apply to outgoing audio: `MUTE` while muted, otherwise the
+  /// requested gain capped at `MAX_GAIN_DB`.
+  pub fn gain(&self) -> Gain {
+    match self.get() {
+      (_, true) => Gain::MUTE,
+      (gain_db, false) => Gain::from_db(f64::from(gain_db).min(MAX_GAIN_DB))
+        .unwrap_or(Gain::UNITY),
+    }
+  }
+}
+
+/// A signed 24-bit sample held in the low bits of an `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I24(pub i32);
+
+impl I24 {
+  pub const MIN: i32 = -(1 << 23);
+  pub const MAX: i32 = (1 << 23) - 1;
+}
+
+// `f64::round` needs std; the result is clamped before casting
+fn round(x: f64) -> f64 {
+  if x >= 0.0 {
+    (x + 0.5) as i64 as f64
+  } else {
+    (x - 0.5) as i64 as f64
+  }
+}
+
+// Scale a sample around `center` and clamp to `min..=max`
+fn scale(v: f64, center: f64, min: f64, max: f64, gain: Gain) -> f64 {
+  round((v - center) * gain.0 + center).clamp(min, max)
+}
+
+pub trait SaturatingScale: Copy {
+  /// `self` multiplied by `gain`, saturating at the sample type's limits.
+  /// Unsigned formats are scaled around their midpoint.
+  fn saturating_scale(self, gain: Gain) -> Self;
+}
+
+impl SaturatingScale for f32 {
+  fn saturating_scale(self, gain: Gain) -> Self {
+    (self as f64 * gain.0).clamp(-1.0, 1.0) as f32
+  }
+}
+
+impl SaturatingScale for i16 {
+  fn saturating_scale(self, gain: Gain) -> Self {
+    scale(self as f64, 0.0, i16::MIN as f64, i16::MAX as f64, gain) as i16
+  }
+}
+
+impl SaturatingScale for u8 {
+  fn saturating_scale(self, gain: Gain) -> Self {
+    scale(self as f64, 128.0, 0.0, u8::MAX as f64, gain) as u8
+  }
+}
+
+impl SaturatingScale for u16 {
+  fn saturating_scale(self, gain: Gain) -> Self {
+    scale(self as f64, 32_768.0, 0.0, u16::MAX as f64, gain) as u16
+  }
+}
+
+impl SaturatingScale for u32 {
+  fn saturating_scale(self, gain: Gain) -> Self {
+    scale(self as f64, 2_147_483_648.0, 0.0, u32::MAX as f64, gain) as u32
+  }
+}
+
+impl SaturatingScale for I24 {
+  fn saturating_scale(self, gain: Gain) -> Self {
+    I24(
+      scale(self.0 as f64, 0.0, I24::MIN as f64, I24::MAX as f64, gain) as i32,
+    )
+  }
+}
+
+/// Apply `gain` to every sample in place.
+pub fn apply_gain<T: SaturatingScale>(samples: &mut [T], gain: Gain) {
+  if gain == Gain::UNITY {
+    return;
+  }
+  for s in samples {
+    *s = s.saturating_scale(gain);
+  }
+}
+
+/// Apply `gain` in place to a native-endian payload of `format` samples. A
+/// trailing partial sample is left untouched.
+pub fn apply_gain_bytes(payload: &mut [u8], format: SampleFormat, gain: Gain) {
+  if gain == Gain::UNITY {
+    return;
+  }
+  match format {
+    SampleFormat::F32 => {
+      for b in payload.chunks_exact_mut(4) {
+        let v = f32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+        b.copy_from_slice(&v.saturating_scale(gain).to_ne_bytes());
+      }
+    }
+    SampleFormat::I16 => {
+      for b in payload.chunks_exact_mut(2) {
+        let v = i16::from_ne_bytes([b[0], b[1]]);
+        b.copy_from_slice(&v.saturating_scale(gain).to_ne_bytes());
+      }
+    }
+    SampleFormat::U16 => {
+      for b in payload.chunks_exact_mut(2) {
+        let v = u16::from_ne_bytes([b[0], b[1]]);
+        b.copy_from_slice(&v.saturating_scale(gain).to_ne_bytes());
+      }
+    }
+    SampleFormat::U32 => {
+      for b in payload.chunks_exact_mut(4) {
+        let v = u32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+        b.copy_from_slice(&v.saturating_scale(gain).to_ne_bytes());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn remote_level_changes_gain_and_mute_together() {
+    let level = RemoteLevel::default();
+    assert_eq!(level.get(), (0, false));
+    assert_eq!(level.gain(), Gain::UNITY);
+    assert!(level.set(-6, true));
+    assert_eq!(level.get(), (-6, true));
+    assert!(!level.set(-6, true));
+    assert_eq!(level.gain(), Gain::MUTE);
+    assert!(level.set(-128, false));
+    assert_eq!(level.get(), (-128, false));
+    // Beyond what `Gain` accepts, so capped
+    level.set(127, false);
+    assert_eq!(level.gain(), Gain::from_db(MAX_GAIN_DB).unwrap());
+  }
+
+  #[test]
+  fn muting_yields_silence_in_every_format() {
+    let silent = [
+      (SampleFormat::F32, 0.0f32.to_ne_bytes().to_vec()),
+      (SampleFormat::I16, 0i16.to_ne_bytes().to_vec()),
+      (SampleFormat::U16, 0x8000u16.to_ne_bytes().to_vec()),
+      (SampleFormat::U32, 0x8000_0000u32.to_ne_bytes().to_vec()),
+    ];
+    for (format, silence) in silent {
+      let mut payload: Vec<u8> = [0x5a_u8, 0xc3, 0x12, 0x7e]
+        .iter()
+        .cycle()
+        .take(format.bytes_per_sample() * 3)
+        .copied()
+        .collect();
+      apply_gain_bytes(&mut payload, format, Gain::MUTE);
+      for sample in payload.chunks_exact(format.bytes_per_sample()) {
+        assert_eq!(sample, silence.as_slice(), "{format:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn plus_24_db_saturates_near_full_scale_i16() {
+    let gain = Gain::from_db(24.0).unwrap();
+    let mut samples = [32_000i16, -32_000, 100, 0];
+    apply_gain(&mut samples, gain);
+    assert_eq!(samples[0], i16::MAX);
+    assert_eq!(samples[1], i16::MIN);
+    // 100 * 10^(24/20) = 1584.9
+    assert_eq!(samples[2], 1585);
+    assert_eq!(samples[3], 0);
+
+    let mut bytes: Vec<u8> =
+      [32_000i16].iter().flat_map(|v| v.to_ne_bytes()).collect();
+    apply_gain_bytes(&mut bytes, SampleFormat::I16, gain);
+    assert_eq!(i16::from_ne_bytes([bytes[0], bytes[1]]), i16::MAX);
+  }
+
+  #[test]
+  fn other_formats_saturate_at_their_limits() {
+    let gain = Gain::from_db(24.0).unwrap();
+    assert_eq!(250u8.saturating_scale(gain), u8::MAX);
+    assert_eq!(5u8.saturating_scale(gain), 0);
+    assert_eq!(128u8.saturating_scale(gain), 128);
+    assert_eq!(65_000u16.saturating_scale(gain), u16::MAX);
+    assert_eq!(100u16.saturating_scale(gain), 0);
+    assert_eq!(u32::MAX.saturating_scale(gain), u32::MAX);
+    assert_eq!(0u32.saturating_scale(gain), 0);
+    assert_eq!(I24(I24::MAX - 10).saturating_scale(gain), I24(I24::MAX));
+    assert_eq!(I24(I24::MIN).saturating_scale(gain), I24(I24::MIN));
+    assert_eq!(0.9f32.saturating_scale(gain), 1.0);
+  }
+
+  #[test]
+  fn invalid_gains_are_rejected() {
+    assert!(Gain::from_linear(f64::NAN).is_none());
+    assert!(Gain::from_linear(-1.0).is_none());
+    assert!(Gain::from_linear(f64::INFINITY).is_none());
+    assert!(Gain::from_db(MAX_GAIN_DB + 1.0).is_none());
+    assert!(Gain::from_db(f64::NEG_INFINITY).is_none());
+    assert!(Gain::from_db(MAX_GAIN_DB).is_some());
+    assert_eq!(Gain::from_db(0.0), Some(Gain::UNITY));
+  }
+}
@@ -0,0 +1,258 @@
+// Result of the sender's ping/pong handshake with a receiver.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+use crate::clock::Clock;
+use crate::packet::{Message, decode_message};
+use crate::packet_sync::{SyncMessage, encode_sync};
+use crate::transport::Transport;
+
+/// Outcome of a completed ping/pong handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+  pub peer: SocketAddr,
+  pub attempts: usize,
+  /// Round trip of the matching ping, less the receiver's time between
+  /// receiving it and sending the pong.
+  pub rtt_ms: u64,
+  /// The receiver decodes every packet of a batched datagram.
+  pub accepts_batches: bool,
+}
+
+impl Handshake {
+  /// Round trip of a ping sent at `t0_ms` whose pong, stamped `t1_ms` and
+  /// `t2_ms` by the receiver, arrived at `t3_ms`. t0/t3 and t1/t2 are each
+  /// read from one clock, so the offset between the two cancels.
+  pub fn round_trip_ms(t0_ms: u64, t1_ms: u64, t2_ms: u64, t3_ms: u64) -> u64 {
+    t3_ms
+      .saturating_sub(t0_ms)
+      .saturating_sub(t2_ms.saturating_sub(t1_ms))
+  }
+
+  /// The line `--handshake-json` prints, a stable contract for wrapper
+  /// scripts.
+  pub fn to_json(&self) -> String {
+    // A SocketAddr's Display has no characters needing escapes
+    format!(
+      "{{\"event\":\"handshake\",\"peer\":\"{}\",\"attempts\":{},\"rtt_ms\":\
+       {}}}",
+      self.peer, self.attempts, self.rtt_ms
+    )
+  }
+}
+
+/// Ping `server` until it answers with a matching Pong, waiting `timeout`
+/// for each of up to `max_attempts` pings, then send it `hello`. Other
+/// traffic arriving meanwhile is skipped. Fails with `TimedOut` when no
+/// attempt gets an answer; the transport's receive timeout is left set.
+pub fn wait_for_pong<T: Transport + ?Sized>(
+  clock: &dyn Clock,
+  transport: &T,
+  server: SocketAddr,
+  timeout: Duration,
+  max_attempts: usize,
+  hello: &[u8],
+) -> io::Result<Handshake> {
+  let mut buf = [0u8; 128];
+  for attempt in 1..=max_attempts {
+    let now = clock.now_ms();
+    let ping = encode_sync(&SyncMessage::Ping { t0_ms: now });
+    let _ = transport.send_packet_to(&ping, server);
+
+    let deadline = Instant::now() + timeout;
+    loop {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if remaining.is_zero() {
+        break;
+      }
+      transport.set_recv_timeout(Some(remaining))?;
+      match transport.recv_packet_from(&mut buf) {
+        Ok((n, addr)) => {
+          if let Ok(Message::Sync(SyncMessage::Pong {
+            t0_ms,
+            t1_ms,
+            t2_ms,
+            accepts_batches,
+          })) = decode_message(&buf[..n])
+          {
+            if t0_ms == now {
+              let rtt_ms =
+                Handshake::round_trip_ms(t0_ms, t1_ms, t2_ms, clock.now_ms());
+              info!(
+                "Handshake with {server} complete: received Pong (attempt \
+                 {attempt})"
+              );
+              let _ = transport.send_packet_to(hello, server);
+              return Ok(Handshake {
+                peer: addr,
+                attempts: attempt,
+                rtt_ms,
+                accepts_batches,
+              });
+            }
+          }
+          // Not a matching pong (e.g. traffic from another destination);
+          // keep waiting within this attempt window
+        }
+        Err(e)
+          if e.kind() == io::ErrorKind::WouldBlock
+            || e.kind() == io::ErrorKind::TimedOut =>
+        {
+          debug!("handshake attempt {attempt} to {server} timed out");
+          break;
+        }
+        Err(e) => return Err(e),
+      }
+    }
+  }
+  Err(io::Error::new(
+    io::ErrorKind::TimedOut,
+    format!("no Pong after {max_attempts} pings"),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::VecDeque;
+  use std::sync::Mutex;
+
+  use super::*;
+  use crate::clock::MockClock;
+  use crate::packet_sync::decode_sync;
+
+  const SERVER: &str = "10.0.0.2:5000";
+
+  // A receiver that answers only its `answer_on`th ping, 10 ms later by
+  // `clock`, and never makes the caller wait
+  struct ScriptedPeer {
+    clock: MockClock,
+    answer_on: usize,
+    pings: Mutex<usize>,
+    sent: Mutex<Vec<Vec<u8>>>,
+    queue: Mutex<VecDeque<Vec<u8>>>,
+  }
+
+  impl ScriptedPeer {
+    fn new(clock: &MockClock, answer_on: usize) -> Self {
+      Self {
+        clock: clock.clone(),
+        answer_on,
+        pings: Mutex::new(0),
+        sent: Mutex::new(Vec::new()),
+        queue: Mutex::new(VecDeque::new()),
+      }
+    }
+  }
+
+  impl Transport for ScriptedPeer {
+    fn send_packet_to(
+      &self,
+      packet: &[u8],
+      _: SocketAddr,
+    ) -> io::Result<usize> {
+      self.sent.lock().unwrap().push(packet.to_vec());
+      let Ok(SyncMessage::Ping { t0_ms }) = decode_sync(packet) else {
+        return Ok(packet.len());
+      };
+      let mut pings = self.pings.lock().unwrap();
+      *pings += 1;
+      let mut queue = self.queue.lock().unwrap();
+      // Unrelated traffic first, which must not end the attempt
+      queue.push_back(encode_sync(&SyncMessage::EndOfStream));
+      if *pings == self.answer_on {
+        self.clock.advance(10);
+        let t1_ms = 50_000;
+        queue.push_back(encode_sync(&SyncMessage::Pong {
+          t0_ms,
+          t1_ms,
+          t2_ms: t1_ms + 3,
+          accepts_batches: true,
+        }));
+      }
+      Ok(packet.len())
+    }
+
+    fn recv_packet_from(
+      &self,
+      buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+      let packet = self.queue.lock().unwrap().pop_front().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::WouldBlock, "no packet queued")
+      })?;
+      buf[..packet.len()].copy_from_slice(&packet);
+      Ok((packet.len(), SERVER.parse().unwrap()))
+    }
+
+    fn set_recv_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn handshake_retries_until_a_matching_pong() {
+    let clock = MockClock::new(1_000);
+    let peer = ScriptedPeer::new(&clock, 3);
+    let server = SERVER.parse().unwrap();
+    let timeout = Duration::from_secs(10);
+    let handshake =
+      wait_for_pong(&clock, &peer, server, timeout, 5, b"hello").unwrap();
+    assert_eq!(handshake.attempts, 3);
+    assert_eq!(handshake.peer, server);
+    assert_eq!(handshake.rtt_ms, 7);
+    assert!(handshake.accepts_batches);
+    // Hello follows the pong; nothing is pinged after it
+    let sent = peer.sent.lock().unwrap();
+    assert_eq!(sent.len(), 4);
+    assert_eq!(sent[3], b"hello");
+  }
+
+  #[test]
+  fn handshake_gives_up_after_max_attempts() {
+    let clock = MockClock::new(1_000);
+    let peer = ScriptedPeer::new(&clock, usize::MAX);
+    let server = SERVER.parse().unwrap();
+    let timeout = Duration::from_secs(10);
+    let err =
+      wait_for_pong(&clock, &peer, server, timeout, 4, b"hello").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(*peer.pings.lock().unwrap(), 4);
+    assert_eq!(peer.sent.lock().unwrap().len(), 4);
+  }
+
+  #[test]
+  fn json_line_is_stable() {
+    let mut handshake = Handshake {
+      peer: "192.168.1.20:5000".parse().unwrap(),
+      attempts: 2,
+      rtt_ms: 7,
+      accepts_batches: true,
+    };
+    assert_eq!(
+      handshake.to_json(),
+      concat!(
+        r#"{"event":"handshake","peer":"192.168.1.20:5000","#,
+        r#""attempts":2,"rtt_ms":7}"#
+      )
+    );
+    handshake.peer = "[fe80::1%2]:5000".parse().unwrap();
+    assert_eq!(
+      handshake.to_json(),
+      concat!(
+        r#"{"event":"handshake","peer":"[fe80::1%2]:5000","#,
+        r#""attempts":2,"rtt_ms":7}"#
+      )
+    );
+  }
+
+  #[test]
+  fn round_trip_excludes_receiver_processing() {
+    // Receiver clock 4 s ahead, 5 ms each way, 2 ms between t1 and t2
+    assert_eq!(Handshake::round_trip_ms(1_000, 5_005, 5_007, 1_012), 10);
+    // A receiver claiming more processing than the whole round trip
+    assert_eq!(Handshake::round_trip_ms(1_000, 5_000, 5_050, 1_012), 0);
+  }
+}
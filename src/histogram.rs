@@ -0,0 +1,183 @@
+/// Fixed-bucket histogram of payload sizes in bytes.
+/// Buckets: 0, 1-256, 257-512, 513-1024, >1024.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadHistogram {
+  pub counts: [u64; 5],
+}
+
+const BUCKET_LABELS: [&str; 5] = ["0", "1-256", "257-512", "513-1024", ">1024"];
+
+impl PayloadHistogram {
+  pub fn record(&mut self, payload_len: usize) {
+    let bucket = match payload_len {
+      0 => 0,
+      1..=256 => 1,
+      257..=512 => 2,
+      513..=1024 => 3,
+      _ => 4,
+    };
+    self.counts[bucket] += 1;
+  }
+}
+
+impl core::fmt::Display for PayloadHistogram {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    for (i, (label, count)) in BUCKET_LABELS.iter().zip(self.counts).enumerate()
+    {
+      if i > 0 {
+        write!(f, " ")?;
+      }
+      write!(f, "{label}:{count}")?;
+    }
+    Ok(())
+  }
+}
+
+// Latency buckets: four per doubling from LATENCY_MIN_MS, plus one below
+// it (which also takes negative and NaN values) and one above the top
+const LATENCY_SUB_BUCKETS: usize = 4;
+const LATENCY_OCTAVES: usize = 16;
+const LATENCY_MIN_MS: f64 = 0.125;
+const LATENCY_BUCKETS: usize = LATENCY_SUB_BUCKETS * LATENCY_OCTAVES + 2;
+
+/// Fixed logarithmic histogram of latencies in ms, from 0.125 ms up to
+/// 8192 ms at about 19% bucket width. Recording is one `log2` and an
+/// increment, so it can run for every packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+  counts: [u64; LATENCY_BUCKETS],
+  total: u64,
+}
+
+impl Default for LatencyHistogram {
+  fn default() -> Self {
+    Self {
+      counts: [0; LATENCY_BUCKETS],
+      total: 0,
+    }
+  }
+}
+
+impl LatencyHistogram {
+  pub fn record(&mut self, latency_ms: f64) {
+    let bucket = if latency_ms >= LATENCY_MIN_MS {
+      let steps =
+        (latency_ms / LATENCY_MIN_MS).log2() * LATENCY_SUB_BUCKETS as f64;
+      (1 + steps as usize).min(LATENCY_BUCKETS - 1)
+    } else {
+      0
+    };
+    self.counts[bucket] += 1;
+    self.total += 1;
+  }
+
+  pub fn count(&self) -> u64 {
+    self.total
+  }
+
+  // Lower edge of `bucket` in ms; the underflow bucket starts at -inf
+  fn lower_ms(bucket: usize) -> f64 {
+    if bucket == 0 {
+      return f64::NEG_INFINITY;
+    }
+    let exp = (bucket - 1) as f64 / LATENCY_SUB_BUCKETS as f64;
+    LATENCY_MIN_MS * exp.exp2()
+  }
+
+  // Upper edge of `bucket` in ms; the overflow bucket ends at +inf
+  fn upper_ms(bucket: usize) -> f64 {
+    if bucket + 1 >= LATENCY_BUCKETS {
+      f64::INFINITY
+    } else {
+      Self::lower_ms(bucket + 1)
+    }
+  }
+
+  /// Upper edge of the bucket holding the `p`-th percentile (0..=100), or
+  /// `None` before anything was recorded.
+  pub fn percentile(&self, p: f64) -> Option<f64> {
+    if self.total == 0 {
+      return None;
+    }
+    let rank = ((p / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+    let mut seen = 0;
+    for (bucket, &count) in self.counts.iter().enumerate() {
+      seen += count;
+      if seen >= rank {
+        return Some(Self::upper_ms(bucket));
+      }
+    }
+    Some(f64::INFINITY)
+  }
+
+  /// Non-empty buckets as (lower ms, upper ms, count), in order.
+  pub fn buckets(&self) -> impl Iterator<Item = (f64, f64, u64)> + '_ {
+    self
+      .counts
+      .iter()
+      .enumerate()
+      .filter(|(_, &count)| count > 0)
+      .map(|(b, &count)| (Self::lower_ms(b), Self::upper_ms(b), count))
+  }
+}
+
+/// One line per non-empty bucket, e.g. "  1.000-1.189 ms: 42".
+impl core::fmt::Display for LatencyHistogram {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    for (lower, upper, count) in self.buckets() {
+      if lower.is_infinite() {
+        writeln!(f, "  <{upper:.3} ms: {count}")?;
+      } else if upper.is_infinite() {
+        writeln!(f, "  >={lower:.3} ms: {count}")?;
+      } else {
+        writeln!(f, "  {lower:.3}-{upper:.3} ms: {count}")?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn buckets_by_size() {
+    let mut h = PayloadHistogram::default();
+    for len in [0, 1, 256, 257, 512, 513, 1024, 1025] {
+      h.record(len);
+    }
+    assert_eq!(h.counts, [1, 2, 2, 2, 1]);
+    assert_eq!(h.to_string(), "0:1 1-256:2 257-512:2 513-1024:2 >1024:1");
+  }
+
+  #[test]
+  fn latency_buckets_are_logarithmic() {
+    let mut h = LatencyHistogram::default();
+    for ms in [-3.0, 0.0, 1.0, 1.1, 2.0, 20_000.0] {
+      h.record(ms);
+    }
+    assert_eq!(h.count(), 6);
+    let buckets: Vec<_> = h.buckets().collect();
+    assert_eq!(buckets.len(), 4);
+    // Negative and zero share the underflow bucket
+    assert_eq!(buckets[0], (f64::NEG_INFINITY, 0.125, 2));
+    // 1.0 and 1.1 both fall in [1.0, 2^0.25)
+    assert_eq!(buckets[1].0, 1.0);
+    assert!((buckets[1].1 - 1.189).abs() < 1e-3);
+    assert_eq!(buckets[1].2, 2);
+    assert_eq!(buckets[2].0, 2.0);
+    assert_eq!(buckets[3], (8192.0, f64::INFINITY, 1));
+
+    assert_eq!(
+      h.percentile(50.0).map(|p| (p * 1000.0).round()),
+      Some(1189.0)
+    );
+    assert_eq!(h.percentile(100.0), Some(f64::INFINITY));
+    assert_eq!(LatencyHistogram::default().percentile(50.0), None);
+    assert!(
+      h.to_string()
+        .starts_with("  <0.125 ms: 2\n  1.000-1.189 ms: 2")
+    );
+  }
+}
@@ -0,0 +1,141 @@
+// Choosing a capture config among those an input device supports, for
+// `udp_sender --channels/--rate/--format` on backends that offer several.
+
+use crate::packet::SampleFormat;
+
+/// One block of configs a device supports: a sample format and channel
+/// count over a span of rates. `format` is None for sample types that have
+/// no wire code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfigRange {
+  pub format: Option<SampleFormat>,
+  pub channels: u16,
+  pub min_rate: u32,
+  pub max_rate: u32,
+}
+
+impl ConfigRange {
+  pub fn contains_rate(&self, rate: u32) -> bool {
+    self.min_rate <= rate && rate <= self.max_rate
+  }
+}
+
+/// A single config, such as the device's default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputConfig {
+  pub format: Option<SampleFormat>,
+  pub channels: u16,
+  pub rate: u32,
+}
+
+/// The requested overrides; a None field may take any value.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConfigRequest {
+  pub format: Option<SampleFormat>,
+  pub channels: Option<u16>,
+  pub rate: Option<u32>,
+}
+
+/// Pick the range in `ranges` that satisfies `want` and returns its index
+/// with the rate to use. Fields not requested follow `default` where some
+/// matching range allows it; the rate otherwise falls back to the range's
+/// highest. Returns None if no range matches.
+pub fn select_config(
+  ranges: &[ConfigRange],
+  default: Option<&InputConfig>,
+  want: &ConfigRequest,
+) -> Option<(usize, u32)> {
+  let (index, best) = ranges
+    .iter()
+    .enumerate()
+    .filter(|(_, r)| want.format.is_none_or(|f| r.format == Some(f)))
+    .filter(|(_, r)| want.channels.is_none_or(|c| r.channels == c))
+    .filter(|(_, r)| want.rate.is_none_or(|sr| r.contains_rate(sr)))
+    // Prefer ranges closest to the default config for unspecified fields
+    .max_by_key(|(_, r)| {
+      default.map_or(0, |d| {
+        u8::from(r.format == d.format)
+          + u8::from(r.channels == d.channels)
+          + u8::from(r.contains_rate(d.rate))
+      })
+    })?;
+  let rate = want
+    .rate
+    .or_else(|| default.map(|d| d.rate).filter(|&d| best.contains_rate(d)))
+    .unwrap_or(best.max_rate);
+  Some((index, rate))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn range(
+    format: SampleFormat,
+    channels: u16,
+    rates: (u32, u32),
+  ) -> ConfigRange {
+    ConfigRange {
+      format: Some(format),
+      channels,
+      min_rate: rates.0,
+      max_rate: rates.1,
+    }
+  }
+
+  #[test]
+  fn overrides_pick_a_matching_range_near_the_default() {
+    let ranges = [
+      range(SampleFormat::F32, 2, (44_100, 48_000)),
+      range(SampleFormat::I16, 2, (8_000, 96_000)),
+      range(SampleFormat::I16, 1, (8_000, 96_000)),
+    ];
+    let default = InputConfig {
+      format: Some(SampleFormat::F32),
+      channels: 2,
+      rate: 48_000,
+    };
+    let pick = |want| select_config(&ranges, Some(&default), &want);
+
+    // Only the format is overridden: channels and rate stay at the default
+    let want = ConfigRequest {
+      format: Some(SampleFormat::I16),
+      ..Default::default()
+    };
+    assert_eq!(pick(want), Some((1, 48_000)));
+
+    // A rate outside the default range moves to one that has it
+    let want = ConfigRequest {
+      rate: Some(96_000),
+      channels: Some(1),
+      ..Default::default()
+    };
+    assert_eq!(pick(want), Some((2, 96_000)));
+
+    // Nothing offers U16
+    let want = ConfigRequest {
+      format: Some(SampleFormat::U16),
+      ..Default::default()
+    };
+    assert_eq!(pick(want), None);
+  }
+
+  #[test]
+  fn rate_falls_back_to_the_range_maximum() {
+    let ranges = [range(SampleFormat::I16, 2, (8_000, 44_100))];
+    let default = InputConfig {
+      format: Some(SampleFormat::F32),
+      channels: 2,
+      rate: 48_000,
+    };
+    let want = ConfigRequest {
+      format: Some(SampleFormat::I16),
+      ..Default::default()
+    };
+    assert_eq!(
+      select_config(&ranges, Some(&default), &want),
+      Some((0, 44_100))
+    );
+    assert_eq!(select_config(&ranges, None, &want), Some((0, 44_100)));
+  }
+}
@@ -1,13 +1,63 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod anti_replay;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod clients;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "alloc")]
+pub mod convert;
+pub mod dsp;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gain;
+#[cfg(feature = "std")]
+pub mod handshake;
+#[cfg(feature = "std")]
+pub mod histogram;
+#[cfg(feature = "std")]
+pub mod input_config;
 pub mod packet;
 mod packet_data;
 mod packet_sync;
+#[cfg(feature = "std")]
 pub mod payload_sink;
+#[cfg(feature = "std")]
+pub mod plc;
+#[cfg(feature = "std")]
 pub mod rate;
+#[cfg(feature = "std")]
 pub mod recv_stats;
+#[cfg(feature = "std")]
+pub mod reorder;
+#[cfg(feature = "std")]
 pub mod send_stats;
+#[cfg(feature = "std")]
+pub mod sockopt;
+#[cfg(feature = "std")]
+pub mod spsc;
+#[cfg(feature = "std")]
+pub mod status;
+pub mod stream_header;
+#[cfg(feature = "std")]
 pub mod sync_controller;
+#[cfg(feature = "std")]
 mod timesync;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
 pub mod volume;
+#[cfg(feature = "std")]
+pub mod watchdog;
+#[cfg(feature = "std")]
+pub mod wav;
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "std", target_os = "macos"))]
 pub mod status_icon_mac;
@@ -1,10 +1,22 @@
 // Packet multiplexer: expose data and sync APIs and provide unified decode.
 
 pub use crate::packet_data::{
-  DataPacketError, Decoded, Meta, SampleRateCode, decode_packet, encode_packet,
+  ByteOrder, DataPacketError, DecodePackets, Decoded,
+  HEADER_LEN as DATA_HEADER_LEN, MAX_HEADER_LEN as MAX_DATA_HEADER_LEN, Meta,
+  MetaError, SampleRateCode, TimestampClock, VersionPolicy, decode_packet,
+  decode_packet_strict, decode_packet_with_policy, decode_packets,
+  decode_packets_with_policy, encode_packet_into,
+  encode_packet_into_with_clock, encode_packet_into_with_frame_counter,
 };
+#[cfg(feature = "alloc")]
+pub use crate::packet_data::{
+  encode_packet, encode_packet_with_clock, encode_packet_with_frame_counter,
+};
+#[cfg(feature = "alloc")]
+pub use crate::packet_sync::encode_sync;
 pub use crate::packet_sync::{
-  SyncDecodeError, SyncMessage, decode_sync, encode_sync,
+  SYNC_MAX_LEN, SyncDecodeError, SyncEncodeError, SyncMessage, decode_sync,
+  encode_sync_into,
 };
 // Re-export data and sync constants/types via this facade.
 
@@ -26,7 +38,36 @@ pub enum SampleFormat {
   I16,
   U16,
   U32,
-  Unknown,
+}
+
+impl SampleFormat {
+  /// Size of one sample in bytes.
+  pub fn bytes_per_sample(self) -> usize {
+    match self {
+      SampleFormat::F32 | SampleFormat::U32 => 4,
+      SampleFormat::I16 | SampleFormat::U16 => 2,
+    }
+  }
+
+  /// One-byte wire code shared by data and sync packets.
+  pub fn code(self) -> u8 {
+    match self {
+      SampleFormat::F32 => 1,
+      SampleFormat::I16 => 2,
+      SampleFormat::U16 => 3,
+      SampleFormat::U32 => 4,
+    }
+  }
+
+  pub fn from_code(code: u8) -> Option<Self> {
+    match code {
+      1 => Some(SampleFormat::F32),
+      2 => Some(SampleFormat::I16),
+      3 => Some(SampleFormat::U16),
+      4 => Some(SampleFormat::U32),
+      _ => None,
+    }
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -65,6 +106,62 @@ impl core::fmt::Display for DecodeError {
   }
 }
 
+impl DecodeError {
+  /// The packet is ours but from an incompatible build.
+  pub fn is_version_mismatch(&self) -> bool {
+    matches!(
+      self,
+      DecodeError::Sync(SyncDecodeError::BadVersion)
+        | DecodeError::Data(DataPacketError::BadVersion)
+    )
+  }
+}
+
+/// Decode failures tallied by cause, for diagnosing a stream that is being
+/// dropped rather than played.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeErrorCounts {
+  /// Not one of our packets at all
+  pub magic: u64,
+  /// Ours, but from an incompatible build
+  pub version: u64,
+  /// Truncated, or shorter than its declared length
+  pub length: u64,
+  /// Anything else, such as an unknown message type or bad stream format
+  pub other: u64,
+}
+
+impl DecodeErrorCounts {
+  pub fn record(&mut self, err: &DecodeError) {
+    let counter = match err {
+      _ if err.is_version_mismatch() => &mut self.version,
+      DecodeError::UnknownMagic
+      | DecodeError::Sync(SyncDecodeError::BadMagic)
+      | DecodeError::Data(DataPacketError::BadMagic) => &mut self.magic,
+      DecodeError::Sync(SyncDecodeError::TooShort)
+      | DecodeError::Data(
+        DataPacketError::TooShort | DataPacketError::LengthMismatch,
+      ) => &mut self.length,
+      _ => &mut self.other,
+    };
+    *counter += 1;
+  }
+
+  pub fn total(&self) -> u64 {
+    self.magic + self.version + self.length + self.other
+  }
+}
+
+impl core::fmt::Display for DecodeErrorCounts {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "magic={} ver={} len={} other={}",
+      self.magic, self.version, self.length, self.other
+    )
+  }
+}
+
 pub fn decode_message(data: &[u8]) -> Result<Message<'_>, DecodeError> {
   if data.is_empty() {
     return Err(DecodeError::UnknownMagic);
@@ -80,24 +177,318 @@ pub fn decode_message(data: &[u8]) -> Result<Message<'_>, DecodeError> {
   }
 }
 
-pub fn respond_to_ping(
-  socket: &std::net::UdpSocket,
+/// Like `decode_message`, but a datagram of data packets may hold several
+/// back to back (see `decode_packets`); sync messages are never batched.
+pub fn decode_messages(
+  data: &[u8],
+) -> impl Iterator<Item = Result<Message<'_>, DecodeError>> + '_ {
+  decode_messages_with_policy(data, VersionPolicy::Lenient)
+}
+
+/// Like `decode_messages`, with the accepted data packet versions chosen by
+/// `policy`. Sync messages have a single version and ignore it.
+pub fn decode_messages_with_policy(
+  data: &[u8],
+  policy: VersionPolicy,
+) -> impl Iterator<Item = Result<Message<'_>, DecodeError>> + '_ {
+  let batch = (data.first() == Some(&DATA_PACKET_MAGIC)).then(|| {
+    crate::packet_data::decode_packets_with_policy(data, policy)
+      .map(|r| r.map(Message::Data).map_err(DecodeError::Data))
+  });
+  let single = batch.is_none().then(|| decode_message(data));
+  batch.into_iter().flatten().chain(single)
+}
+
+/// Encode either message kind, the inverse of `decode_message`. Data
+/// payloads are tagged with this host's byte order, as by `encode_packet`.
+#[cfg(feature = "alloc")]
+pub fn encode_message(msg: &Message<'_>) -> alloc::vec::Vec<u8> {
+  match msg {
+    Message::Sync(m) => encode_sync(m),
+    Message::Data(d) => encode_packet_with_frame_counter(
+      d.seq,
+      d.payload,
+      d.meta,
+      d.timestamp_ms,
+      d.clock,
+      d.frame_counter,
+    ),
+  }
+}
+
+/// Presentation time of frame `frame_index` within a packet stamped
+/// `packet_ts_ms`, for placing frames at finer than packet granularity.
+/// `sample_rate` may be the nominal rate or one measured against the
+/// sender's clock. Returns the packet time for a non-positive rate.
+pub fn frame_presentation_ms(
+  packet_ts_ms: u64,
+  frame_index: u64,
+  sample_rate: f64,
+) -> f64 {
+  if sample_rate <= 0.0 {
+    return packet_ts_ms as f64;
+  }
+  packet_ts_ms as f64 + frame_index as f64 * 1000.0 / sample_rate
+}
+
+/// Current wall-clock time in milliseconds since the UNIX epoch.
+#[cfg(feature = "std")]
+pub fn unix_time_ms() -> u64 {
+  std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_else(|_| std::time::Duration::from_millis(0))
+    .as_millis() as u64
+}
+
+/// Reply to a Ping. `t1_ms` is when the ping was received (captured by the
+/// caller right after `recv_from`); t2 is sampled just before sending, so
+/// the peer can subtract our processing time from the round trip.
+/// `accepts_batches` tells a sender it may coalesce data packets.
+#[cfg(feature = "std")]
+pub fn respond_to_ping<T: crate::transport::Transport + ?Sized>(
+  socket: &T,
   src_addr: std::net::SocketAddr,
   t0_ms: u64,
+  t1_ms: u64,
+  accepts_batches: bool,
+) {
+  respond_to_ping_with(
+    &crate::clock::SystemClock,
+    socket,
+    src_addr,
+    t0_ms,
+    t1_ms,
+    accepts_batches,
+  )
+}
+
+/// `respond_to_ping` with t2 read from `clock`.
+#[cfg(feature = "std")]
+pub fn respond_to_ping_with<T: crate::transport::Transport + ?Sized>(
+  clock: &dyn crate::clock::Clock,
+  socket: &T,
+  src_addr: std::net::SocketAddr,
+  t0_ms: u64,
+  t1_ms: u64,
+  accepts_batches: bool,
 ) {
-  let t1 = std::time::SystemTime::now()
-    .duration_since(std::time::UNIX_EPOCH)
-    .unwrap_or_else(|_| std::time::Duration::from_millis(0))
-    .as_millis() as u64;
-  let t2 = std::time::SystemTime::now()
-    .duration_since(std::time::UNIX_EPOCH)
-    .unwrap_or_else(|_| std::time::Duration::from_millis(0))
-    .as_millis() as u64;
   let pong = SyncMessage::Pong {
     t0_ms,
-    t1_ms: t1,
-    t2_ms: t2,
+    t1_ms,
+    t2_ms: clock.now_ms(),
+    accepts_batches,
   };
   let v = encode_sync(&pong);
-  let _ = socket.send_to(&v, src_addr);
+  let _ = socket.send_packet_to(&v, src_addr);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+  use std::net::UdpSocket;
+  use std::time::Duration;
+
+  use super::*;
+  use crate::timesync::TimeSyncEstimator;
+
+  #[test]
+  fn batched_datagram_yields_every_message() {
+    let meta = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let mut batch = encode_packet(7, &[], meta, 1);
+    batch.extend(encode_packet(8, &[1, 2], meta, 2));
+    let seqs: Vec<u64> = decode_messages(&batch)
+      .map(|m| match m {
+        Ok(Message::Data(d)) => d.seq,
+        other => panic!("unexpected {other:?}"),
+      })
+      .collect();
+    assert_eq!(seqs, [7, 8]);
+
+    let ping = encode_sync(&SyncMessage::Ping { t0_ms: 5 });
+    let msgs: Vec<_> = decode_messages(&ping).collect();
+    assert_eq!(msgs, [Ok(Message::Sync(SyncMessage::Ping { t0_ms: 5 }))]);
+    assert_eq!(
+      decode_messages(&[]).collect::<Vec<_>>(),
+      [Err(DecodeError::UnknownMagic)]
+    );
+  }
+
+  #[test]
+  fn frame_times_interpolate_within_packet() {
+    let ts = 1_700_000_000_000;
+    assert_eq!(frame_presentation_ms(ts, 0, 48_000.0), ts as f64);
+    assert_eq!(frame_presentation_ms(ts, 48, 48_000.0), ts as f64 + 1.0);
+    // Last frame of a 1024-byte stereo f32 packet
+    let last = frame_presentation_ms(ts, 127, 48_000.0) - ts as f64;
+    assert!((last - 2.6458).abs() < 1e-3, "{last}");
+    assert_eq!(frame_presentation_ms(ts, 48, 0.0), ts as f64);
+  }
+
+  #[test]
+  fn pong_reports_processing_time() {
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+      .set_read_timeout(Some(Duration::from_secs(2)))
+      .unwrap();
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let t0 = unix_time_ms();
+    let t1 = unix_time_ms();
+    // Simulated server-side processing between receive and reply
+    std::thread::sleep(Duration::from_millis(3));
+    respond_to_ping(&server, client.local_addr().unwrap(), t0, t1, false);
+
+    let mut buf = [0u8; SYNC_MAX_LEN];
+    let (n, _) = client.recv_from(&mut buf).unwrap();
+    let t3 = unix_time_ms();
+    let Ok(SyncMessage::Pong {
+      t0_ms,
+      t1_ms,
+      t2_ms,
+      ..
+    }) = decode_sync(&buf[..n])
+    else {
+      panic!("expected pong");
+    };
+    assert_eq!((t0_ms, t1_ms), (t0, t1));
+    assert!(t2_ms - t1_ms >= 3, "processing was {} ms", t2_ms - t1_ms);
+
+    let s = TimeSyncEstimator::new(0.2, 0.2).update(t0_ms, t1_ms, t2_ms, t3);
+    let round_trip = (t3 - t0) as f64;
+    assert!(s.delay_ms <= round_trip - 3.0, "delay was {}", s.delay_ms);
+  }
+
+  #[test]
+  fn encode_message_roundtrips_both_kinds() {
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let data = encode_packet_with_frame_counter(
+      9,
+      &[1, 2, 3, 4],
+      meta,
+      1234,
+      TimestampClock::Monotonic,
+      Some(4_800),
+    );
+    let Ok(msg @ Message::Data(_)) = decode_message(&data) else {
+      panic!("expected data message");
+    };
+    assert_eq!(encode_message(&msg), data);
+
+    let sync = Message::Sync(SyncMessage::Hello { meta });
+    assert_eq!(decode_message(&encode_message(&sync)).unwrap(), sync);
+  }
+
+  #[test]
+  fn decode_errors_are_counted_by_cause() {
+    let meta = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let good = encode_packet(1, &[0, 0], meta, 0);
+    let mut counts = DecodeErrorCounts::default();
+
+    let mut wrong_version = good.clone();
+    wrong_version[1] = 0xff;
+    for bad in [
+      &b"xyz"[..],
+      &wrong_version,
+      &good[..DATA_HEADER_LEN - 1],
+      &encode_sync(&SyncMessage::EndOfStream)[..2],
+    ] {
+      counts.record(&decode_message(bad).unwrap_err());
+    }
+    assert_eq!(
+      counts,
+      DecodeErrorCounts {
+        magic: 1,
+        version: 1,
+        length: 2,
+        other: 0,
+      }
+    );
+    assert_eq!(counts.total(), 4);
+    assert_eq!(counts.to_string(), "magic=1 ver=1 len=2 other=0");
+  }
+
+  // Small xorshift so the property test below is deterministic and needs no
+  // extra dependencies
+  struct XorShift(u64);
+
+  impl XorShift {
+    fn next(&mut self) -> u64 {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      self.0
+    }
+  }
+
+  fn check_decoders(data: &[u8]) {
+    let range = data.as_ptr_range();
+    let within = |p: &[u8]| {
+      let r = p.as_ptr_range();
+      range.start <= r.start && r.end <= range.end
+    };
+    match decode_message(data) {
+      Ok(Message::Data(d)) => {
+        assert!(within(d.payload));
+        assert!(d.meta.channels > 0);
+      }
+      Ok(Message::Sync(_)) => {}
+      Err(DecodeError::UnknownMagic) => {
+        assert!(data.first().is_none_or(|&m| {
+          m != SYNC_PACKET_MAGIC && m != DATA_PACKET_MAGIC
+        }));
+      }
+      Err(DecodeError::Sync(_) | DecodeError::Data(_)) => {}
+    }
+    if let Ok(d) = decode_packet(data) {
+      assert!(DATA_HEADER_LEN + d.payload.len() <= data.len());
+      assert!(within(d.payload));
+    }
+    if let Ok(d) = decode_packet_strict(data) {
+      assert_eq!(d.payload.len() % d.meta.frame_size(), 0);
+    }
+    let _ = decode_sync(data);
+  }
+
+  #[test]
+  fn decoders_never_panic_on_arbitrary_bytes() {
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let seeds = [
+      encode_packet(7, &[1, 2, 3, 4, 5, 6, 7, 8], meta, 99),
+      encode_sync(&SyncMessage::Pong {
+        t0_ms: 1,
+        t1_ms: 2,
+        t2_ms: 3,
+        accepts_batches: true,
+      }),
+      encode_sync(&SyncMessage::EndOfStream),
+    ];
+    let mut buf = Vec::new();
+    for i in 0..20_000 {
+      buf.clear();
+      if i % 2 == 0 {
+        // Fully random bytes, with the magic forced often enough to get
+        // past the first check
+        let len = (rng.next() % 80) as usize;
+        buf.extend((0..len).map(|_| rng.next() as u8));
+        if let Some(first) = buf.first_mut() {
+          *first = match rng.next() % 3 {
+            0 => SYNC_PACKET_MAGIC,
+            1 => DATA_PACKET_MAGIC,
+            _ => *first,
+          };
+        }
+      } else {
+        // Valid packets with a few bytes flipped and the tail cut or grown
+        let seed = &seeds[(rng.next() % seeds.len() as u64) as usize];
+        buf.extend_from_slice(seed);
+        for _ in 0..(rng.next() % 4) {
+          let at = (rng.next() % buf.len() as u64) as usize;
+          buf[at] = rng.next() as u8;
+        }
+        let len = (rng.next() % (buf.len() as u64 + 8)) as usize;
+        buf.resize(len, rng.next() as u8);
+      }
+      check_decoders(&buf);
+    }
+  }
 }
@@ -1,10 +1,21 @@
 // src/packet_data.rs
 
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
 use crate::packet::{DATA_PACKET_MAGIC, SampleFormat, SampleRate};
 
 // IMPORTANT: Bump PACKET_VERSION whenever the on-wire packet header/layout
-// changes.
-const PACKET_VERSION: u8 = 2;
+// changes. What each version carries:
+// - 2: a 1-byte sample rate code, the format code, byte 7 reserved
+// - 3: version 2 with the flags in byte 7
+// - 4: the format code and flags in bytes 5 and 6, byte 7 reserved, and a u32
+//   sample rate
+// - 5: version 4 plus optional fields announced by flags, as laid out below
+const PACKET_VERSION: u8 = 5;
+// Version 4 is version 5 without optional header fields, so it still
+// decodes under `VersionPolicy::Lenient`
+const PACKET_VERSION_V4: u8 = 4;
 
 /// Data packet format utilities (audio payloads).
 ///
@@ -13,13 +24,67 @@ const PACKET_VERSION: u8 = 2;
 /// - 1 byte : version (bumped when layout changes)
 /// - 2 bytes: payload length (u16)
 /// - 1 byte : channels
-/// - 1 byte : sample rate code (enum, see `SampleRateCode`)
-/// - 1 byte : sample format code (1=F32, 2=I16, 3=U16, 4=U32, 0=unknown)
-/// - 1 byte : reserved (dummy)
+/// - 1 byte : sample format code (1=F32, 2=I16, 3=U16, 4=U32; any other value
+///   is rejected as `BadFormat`)
+/// - 1 byte : flags (bit 0: monotonic timestamp, see `TimestampClock`; bit 1:
+///   big-endian samples, see `ByteOrder`; bit 2: frame counter present)
+/// - 1 byte : reserved (0), keeps the payload 4-byte aligned
+/// - 4 bytes: sample rate in Hz (u32)
 /// - 8 bytes: sequence number (u64)
-/// - 8 bytes: timestamp (u64, ms since UNIX epoch)
+/// - 8 bytes: timestamp (u64, ms since UNIX epoch or since sender start)
+/// - 8 bytes: optional frame counter (u64, frames captured since stream start,
+///   before the first payload frame), if flag bit 2 is set
 /// - N bytes: payload
-const HEADER_LEN: usize = 2 + 2 + 1 + 1 + 1 + 1 + 8 + 8; // 24 bytes
+pub const HEADER_LEN: usize = 2 + 2 + 1 + 1 + 1 + 1 + 4 + 8 + 8; // 28 bytes
+/// Header length with every optional field present.
+pub const MAX_HEADER_LEN: usize = HEADER_LEN + 8;
+
+// Header flag: the timestamp is ms since sender start, not since the epoch
+const FLAG_MONOTONIC_TS: u8 = 0x01;
+// Header flag: payload samples are big-endian
+const FLAG_BIG_ENDIAN: u8 = 0x02;
+// Header flag: an 8-byte frame counter follows the fixed header
+const FLAG_FRAME_COUNTER: u8 = 0x04;
+
+/// Byte order of the samples in a payload. Senders send samples in their
+/// native order and flag it, so receivers only swap when the hosts differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+  Little,
+  Big,
+}
+
+impl ByteOrder {
+  pub const NATIVE: ByteOrder = if cfg!(target_endian = "big") {
+    ByteOrder::Big
+  } else {
+    ByteOrder::Little
+  };
+}
+
+/// Which data packet versions a decoder accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VersionPolicy {
+  /// Only the current version; anything else is `BadVersion`.
+  Strict,
+  /// The current version and older ones known to be compatible, each read
+  /// by its own parser, so receivers keep working through a rolling
+  /// upgrade of their senders.
+  #[default]
+  Lenient,
+}
+
+/// Clock the packet timestamp was taken from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampClock {
+  /// Wall clock, ms since the UNIX epoch. Comparable across hosts once the
+  /// clock offset is known, but jumps when the sender's clock is stepped.
+  #[default]
+  Wall,
+  /// Monotonic ms since the sender started; never jumps, but only the
+  /// differences between packets are meaningful to a receiver.
+  Monotonic,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DataPacketError {
@@ -27,6 +92,10 @@ pub enum DataPacketError {
   BadMagic,
   BadVersion,
   LengthMismatch,
+  BufferTooSmall,
+  BadChannels,
+  PartialFrame,
+  BadFormat,
 }
 
 impl core::fmt::Display for DataPacketError {
@@ -40,11 +109,20 @@ impl core::fmt::Display for DataPacketError {
       DataPacketError::LengthMismatch => {
         write!(f, "declared length exceeds buffer")
       }
+      DataPacketError::BufferTooSmall => {
+        write!(f, "output buffer too small for packet")
+      }
+      DataPacketError::BadChannels => write!(f, "channel count is zero"),
+      DataPacketError::PartialFrame => {
+        write!(f, "payload length is not a whole number of frames")
+      }
+      DataPacketError::BadFormat => write!(f, "unknown sample format code"),
     }
   }
 }
 
-/// Encoded sample-rate choices used on the wire.
+/// Common sample rates, for display. Packets carry the rate in Hz, so
+/// rates outside this list (`Unknown`) are still transmitted exactly.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum SampleRateCode {
@@ -126,51 +204,250 @@ pub struct Meta {
   pub sample_format: SampleFormat,
 }
 
+/// Why `Meta::new` rejected a stream description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaError {
+  BadChannels(u16),
+  ZeroRate,
+}
+
+impl core::fmt::Display for MetaError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      MetaError::BadChannels(n) => {
+        write!(f, "unsupported channel count {n} (expected 1..=255)")
+      }
+      MetaError::ZeroRate => write!(f, "sample rate is zero"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MetaError {}
+
+impl Meta {
+  /// Validated constructor for input sources.
+  pub fn new(
+    channels: u16,
+    sample_rate: u32,
+    sample_format: SampleFormat,
+  ) -> Result<Self, MetaError> {
+    if channels == 0 || channels > 255 {
+      return Err(MetaError::BadChannels(channels));
+    }
+    if sample_rate == 0 {
+      return Err(MetaError::ZeroRate);
+    }
+    Ok(Self {
+      channels: channels as u8,
+      sample_rate: SampleRate(sample_rate),
+      sample_format,
+    })
+  }
+
+  /// Size of one sample in bytes.
+  pub fn bytes_per_sample(&self) -> usize {
+    self.sample_format.bytes_per_sample()
+  }
+
+  /// Size of one interleaved frame (one sample per channel) in bytes.
+  pub fn frame_size(&self) -> usize {
+    self.channels as usize * self.bytes_per_sample()
+  }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Decoded<'a> {
   pub seq: u64,
   pub timestamp_ms: u64,
+  pub clock: TimestampClock,
+  pub byte_order: ByteOrder,
+  /// Frames captured before this packet's first frame, if the sender
+  /// includes it.
+  pub frame_counter: Option<u64>,
   pub meta: Meta,
   pub payload: &'a [u8],
 }
 
+impl Decoded<'_> {
+  /// Size of the encoded packet this was decoded from.
+  pub fn wire_len(&self) -> usize {
+    let header_len = if self.frame_counter.is_some() {
+      MAX_HEADER_LEN
+    } else {
+      HEADER_LEN
+    };
+    header_len + self.payload.len()
+  }
+}
+
+/// Encodes a sequence number, metadata and payload into `out` without
+/// allocating. Returns the number of bytes written.
+pub fn encode_packet_into(
+  seq: u64,
+  payload: &[u8],
+  meta: Meta,
+  timestamp_ms: u64,
+  out: &mut [u8],
+) -> Result<usize, DataPacketError> {
+  encode_packet_into_with_clock(
+    seq,
+    payload,
+    meta,
+    timestamp_ms,
+    TimestampClock::Wall,
+    out,
+  )
+}
+
+/// Like `encode_packet_into`, flagging which clock `timestamp_ms` is from.
+pub fn encode_packet_into_with_clock(
+  seq: u64,
+  payload: &[u8],
+  meta: Meta,
+  timestamp_ms: u64,
+  clock: TimestampClock,
+  out: &mut [u8],
+) -> Result<usize, DataPacketError> {
+  encode_packet_into_with_frame_counter(
+    seq,
+    payload,
+    meta,
+    timestamp_ms,
+    clock,
+    None,
+    out,
+  )
+}
+
+/// Like `encode_packet_into_with_clock`, optionally adding the count of
+/// frames captured before this packet's first frame. Dividing it by the
+/// sample rate gives the capture time on the audio device's clock.
+pub fn encode_packet_into_with_frame_counter(
+  seq: u64,
+  payload: &[u8],
+  meta: Meta,
+  timestamp_ms: u64,
+  clock: TimestampClock,
+  frame_counter: Option<u64>,
+  out: &mut [u8],
+) -> Result<usize, DataPacketError> {
+  let len = payload.len().min(u16::MAX as usize);
+  let header_len = if frame_counter.is_some() {
+    MAX_HEADER_LEN
+  } else {
+    HEADER_LEN
+  };
+  let total = header_len + len;
+  if out.len() < total {
+    return Err(DataPacketError::BufferTooSmall);
+  }
+  out[0] = DATA_PACKET_MAGIC;
+  out[1] = PACKET_VERSION;
+  out[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+  out[4] = meta.channels;
+  // sample format encoded as 1 byte
+  out[5] = meta.sample_format.code();
+  let mut flags = match clock {
+    TimestampClock::Wall => 0,
+    TimestampClock::Monotonic => FLAG_MONOTONIC_TS,
+  };
+  if ByteOrder::NATIVE == ByteOrder::Big {
+    flags |= FLAG_BIG_ENDIAN;
+  }
+  if frame_counter.is_some() {
+    flags |= FLAG_FRAME_COUNTER;
+  }
+  out[6] = flags;
+  out[7] = 0;
+  out[8..12].copy_from_slice(&meta.sample_rate.0.to_be_bytes());
+  out[12..20].copy_from_slice(&seq.to_be_bytes());
+  out[20..28].copy_from_slice(&timestamp_ms.to_be_bytes());
+  if let Some(frames) = frame_counter {
+    out[HEADER_LEN..MAX_HEADER_LEN].copy_from_slice(&frames.to_be_bytes());
+  }
+  out[header_len..total].copy_from_slice(&payload[..len]);
+  Ok(total)
+}
+
 /// Encodes a sequence number, metadata and payload into a packet buffer.
+#[cfg(feature = "alloc")]
 pub fn encode_packet(
   seq: u64,
   payload: &[u8],
   meta: Meta,
   timestamp_ms: u64,
 ) -> Vec<u8> {
-  let len: u16 = payload.len().min(u16::MAX as usize) as u16;
-  let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
-  buf.push(DATA_PACKET_MAGIC);
-  buf.push(PACKET_VERSION);
-  buf.extend_from_slice(&len.to_be_bytes());
-  buf.push(meta.channels);
-  // sample rate encoded as enum code, 1 byte
-  let sr_code = SampleRateCode::from_hz(meta.sample_rate.0).code();
-  buf.push(sr_code);
-  // sample format encoded as 1 byte
-  let sf_code: u8 = match meta.sample_format {
-    SampleFormat::F32 => 1,
-    SampleFormat::I16 => 2,
-    SampleFormat::U16 => 3,
-    SampleFormat::U32 => 4,
-    _ => 0,
-  };
-  buf.push(sf_code);
-  buf.push(0); // reserved/dummy
-  buf.extend_from_slice(&seq.to_be_bytes());
-  buf.extend_from_slice(&timestamp_ms.to_be_bytes());
-  buf.extend_from_slice(payload);
+  encode_packet_with_clock(
+    seq,
+    payload,
+    meta,
+    timestamp_ms,
+    TimestampClock::Wall,
+  )
+}
+
+/// Like `encode_packet`, flagging which clock `timestamp_ms` is from.
+#[cfg(feature = "alloc")]
+pub fn encode_packet_with_clock(
+  seq: u64,
+  payload: &[u8],
+  meta: Meta,
+  timestamp_ms: u64,
+  clock: TimestampClock,
+) -> Vec<u8> {
+  encode_packet_with_frame_counter(
+    seq,
+    payload,
+    meta,
+    timestamp_ms,
+    clock,
+    None,
+  )
+}
+
+/// Like `encode_packet_with_clock`, optionally adding a frame counter; see
+/// `encode_packet_into_with_frame_counter`.
+#[cfg(feature = "alloc")]
+pub fn encode_packet_with_frame_counter(
+  seq: u64,
+  payload: &[u8],
+  meta: Meta,
+  timestamp_ms: u64,
+  clock: TimestampClock,
+  frame_counter: Option<u64>,
+) -> Vec<u8> {
+  let mut buf =
+    alloc::vec![0u8; MAX_HEADER_LEN + payload.len().min(u16::MAX as usize)];
+  let n = encode_packet_into_with_frame_counter(
+    seq,
+    payload,
+    meta,
+    timestamp_ms,
+    clock,
+    frame_counter,
+    &mut buf,
+  )
+  .expect("buffer sized for packet");
+  buf.truncate(n);
   buf
 }
 
-/// Decodes a packet into `Decoded { seq, meta, payload }`.
+/// Decodes a packet into `Decoded { seq, meta, payload }`, accepting every
+/// compatible version (`VersionPolicy::Lenient`).
 /// Returns a slice into the original buffer for the payload to avoid
 /// allocation.
 pub fn decode_packet<'a>(
   data: &'a [u8],
+) -> Result<Decoded<'a>, DataPacketError> {
+  decode_packet_with_policy(data, VersionPolicy::Lenient)
+}
+
+/// Like `decode_packet`, with the accepted versions chosen by `policy`.
+/// The version byte picks the parser for the rest of the header.
+pub fn decode_packet_with_policy<'a>(
+  data: &'a [u8],
+  policy: VersionPolicy,
 ) -> Result<Decoded<'a>, DataPacketError> {
   if data.len() < HEADER_LEN {
     return Err(DataPacketError::TooShort);
@@ -178,43 +455,86 @@ pub fn decode_packet<'a>(
   if data[0] != DATA_PACKET_MAGIC {
     return Err(DataPacketError::BadMagic);
   }
-  if data[1] != PACKET_VERSION {
-    return Err(DataPacketError::BadVersion);
+  match (data[1], policy) {
+    (PACKET_VERSION, _) => decode_v5(data),
+    (PACKET_VERSION_V4, VersionPolicy::Lenient) => decode_v4(data),
+    _ => Err(DataPacketError::BadVersion),
+  }
+}
+
+// Version 5: the fixed header, then optional fields announced by flags
+fn decode_v5(data: &[u8]) -> Result<Decoded<'_>, DataPacketError> {
+  let flags = data[6];
+  if flags & FLAG_FRAME_COUNTER == 0 {
+    return decode_fixed_header(data, HEADER_LEN, None);
+  }
+  if data.len() < MAX_HEADER_LEN {
+    return Err(DataPacketError::TooShort);
   }
+  let mut frames_buf = [0u8; 8];
+  frames_buf.copy_from_slice(&data[HEADER_LEN..MAX_HEADER_LEN]);
+  let frame_counter = u64::from_be_bytes(frames_buf);
+  decode_fixed_header(data, MAX_HEADER_LEN, Some(frame_counter))
+}
+
+// Version 4: the fixed header only; it had no optional fields, so a stray
+// frame counter flag is ignored
+fn decode_v4(data: &[u8]) -> Result<Decoded<'_>, DataPacketError> {
+  decode_fixed_header(data, HEADER_LEN, None)
+}
 
+// The header fields shared by every supported version, with the payload
+// starting at `header_len`
+fn decode_fixed_header(
+  data: &[u8],
+  header_len: usize,
+  frame_counter: Option<u64>,
+) -> Result<Decoded<'_>, DataPacketError> {
   let mut len_buf = [0u8; 2];
   len_buf.copy_from_slice(&data[2..4]);
   let payload_len = u16::from_be_bytes(len_buf) as usize;
 
   let channels = data[4];
-  let sample_rate_code = data[5];
-  let sample_format_code = data[6];
-  // data[7] is reserved/dummy
+  if channels == 0 {
+    return Err(DataPacketError::BadChannels);
+  }
+  let sample_format_code = data[5];
+  let flags = data[6];
+
+  let mut rate_buf = [0u8; 4];
+  rate_buf.copy_from_slice(&data[8..12]);
+  let sample_rate = SampleRate(u32::from_be_bytes(rate_buf));
 
   let mut seq_buf = [0u8; 8];
-  seq_buf.copy_from_slice(&data[8..16]);
+  seq_buf.copy_from_slice(&data[12..20]);
   let seq = u64::from_be_bytes(seq_buf);
 
   let mut ts_buf = [0u8; 8];
-  ts_buf.copy_from_slice(&data[16..24]);
+  ts_buf.copy_from_slice(&data[20..28]);
   let timestamp_ms = u64::from_be_bytes(ts_buf);
 
-  if data.len() < HEADER_LEN + payload_len {
+  if data.len() < header_len + payload_len {
     return Err(DataPacketError::LengthMismatch);
   }
-  let payload = &data[HEADER_LEN..HEADER_LEN + payload_len];
-  let sample_rate =
-    SampleRate(SampleRateCode::from_code(sample_rate_code).to_hz());
-  let sample_format = match sample_format_code {
-    1 => SampleFormat::F32,
-    2 => SampleFormat::I16,
-    3 => SampleFormat::U16,
-    4 => SampleFormat::U32,
-    _ => SampleFormat::F32, // default if unknown
+  let payload = &data[header_len..header_len + payload_len];
+  let sample_format = SampleFormat::from_code(sample_format_code)
+    .ok_or(DataPacketError::BadFormat)?;
+  let clock = if flags & FLAG_MONOTONIC_TS != 0 {
+    TimestampClock::Monotonic
+  } else {
+    TimestampClock::Wall
+  };
+  let byte_order = if flags & FLAG_BIG_ENDIAN != 0 {
+    ByteOrder::Big
+  } else {
+    ByteOrder::Little
   };
   Ok(Decoded {
     seq,
     timestamp_ms,
+    clock,
+    byte_order,
+    frame_counter,
     meta: Meta {
       channels,
       sample_rate,
@@ -224,10 +544,118 @@ pub fn decode_packet<'a>(
   })
 }
 
+/// Like `decode_packet`, but also rejects payloads that are not a whole
+/// number of frames (`channels * bytes_per_sample`).
+pub fn decode_packet_strict<'a>(
+  data: &'a [u8],
+) -> Result<Decoded<'a>, DataPacketError> {
+  let decoded = decode_packet(data)?;
+  if !decoded
+    .payload
+    .len()
+    .is_multiple_of(decoded.meta.frame_size())
+  {
+    return Err(DataPacketError::PartialFrame);
+  }
+  Ok(decoded)
+}
+
+/// Decode back-to-back packets from one buffer, such as a TCP read or a
+/// batched datagram, using each header's length field to find the next.
+/// Stops after the first error, since the next boundary is then unknown.
+pub fn decode_packets(data: &[u8]) -> DecodePackets<'_> {
+  decode_packets_with_policy(data, VersionPolicy::Lenient)
+}
+
+/// Like `decode_packets`, with the accepted versions chosen by `policy`.
+pub fn decode_packets_with_policy(
+  data: &[u8],
+  policy: VersionPolicy,
+) -> DecodePackets<'_> {
+  DecodePackets { rest: data, policy }
+}
+
+/// Iterator returned by `decode_packets`.
+#[derive(Debug, Clone)]
+pub struct DecodePackets<'a> {
+  rest: &'a [u8],
+  policy: VersionPolicy,
+}
+
+impl<'a> Iterator for DecodePackets<'a> {
+  type Item = Result<Decoded<'a>, DataPacketError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.rest.is_empty() {
+      return None;
+    }
+    match decode_packet_with_policy(self.rest, self.policy) {
+      Ok(decoded) => {
+        self.rest = &self.rest[decoded.wire_len()..];
+        Some(Ok(decoded))
+      }
+      Err(e) => {
+        self.rest = &[];
+        Some(Err(e))
+      }
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  #[test]
+  fn meta_new_validates_fields() {
+    let m = Meta::new(2, 44_100, SampleFormat::I16).unwrap();
+    assert_eq!(m.channels, 2);
+    assert_eq!(
+      Meta::new(0, 48_000, SampleFormat::F32),
+      Err(MetaError::BadChannels(0))
+    );
+    assert_eq!(
+      Meta::new(256, 48_000, SampleFormat::F32),
+      Err(MetaError::BadChannels(256))
+    );
+    assert_eq!(Meta::new(2, 0, SampleFormat::F32), Err(MetaError::ZeroRate));
+  }
+
+  #[test]
+  fn meta_sizes_follow_format_and_channels() {
+    let m = Meta::new(6, 48_000, SampleFormat::I16).unwrap();
+    assert_eq!((m.bytes_per_sample(), m.frame_size()), (2, 12));
+    let m = Meta::new(1, 48_000, SampleFormat::U32).unwrap();
+    assert_eq!((m.bytes_per_sample(), m.frame_size()), (4, 4));
+  }
+
+  #[cfg(feature = "alloc")]
+  #[test]
+  fn decode_packets_walks_concatenated_buffer() {
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let mut buf = encode_packet(1, &[1, 2, 3, 4], meta, 10);
+    buf.extend(encode_packet_with_frame_counter(
+      2,
+      &[5, 6, 7, 8, 9, 10, 11, 12],
+      meta,
+      11,
+      TimestampClock::Wall,
+      Some(2),
+    ));
+    let third = encode_packet(3, &[0; 8], meta, 12);
+    buf.extend_from_slice(&third[..third.len() - 3]);
+
+    let mut it = decode_packets(&buf);
+    let a = it.next().unwrap().unwrap();
+    assert_eq!((a.seq, a.payload), (1, &[1u8, 2, 3, 4][..]));
+    let b = it.next().unwrap().unwrap();
+    assert_eq!((b.seq, b.frame_counter), (2, Some(2)));
+    assert_eq!(b.payload, &[5, 6, 7, 8, 9, 10, 11, 12]);
+    assert_eq!(it.next(), Some(Err(DataPacketError::LengthMismatch)));
+    assert_eq!(it.next(), None);
+    assert_eq!(decode_packets(&[]).count(), 0);
+  }
+
   #[test]
   fn encode_then_decode_roundtrip() {
     let seq = 1234567890123456789u64;
@@ -241,8 +669,32 @@ mod tests {
     let d = decode_packet(&pkt).expect("decode ok");
     assert_eq!(d.seq, seq);
     assert_eq!(d.timestamp_ms, 42);
+    assert_eq!(d.clock, TimestampClock::Wall);
     assert_eq!(d.meta, meta);
     assert_eq!(d.payload, payload);
+
+    let pkt = encode_packet_with_clock(
+      seq,
+      payload,
+      meta,
+      7,
+      TimestampClock::Monotonic,
+    );
+    let d = decode_packet(&pkt).expect("decode ok");
+    assert_eq!(d.timestamp_ms, 7);
+    assert_eq!(d.clock, TimestampClock::Monotonic);
+  }
+
+  #[test]
+  fn uncommon_sample_rate_survives_roundtrip() {
+    let meta = Meta::new(2, 37_800, SampleFormat::I16).unwrap();
+    assert_eq!(
+      SampleRateCode::from_hz(meta.sample_rate.0),
+      SampleRateCode::Unknown
+    );
+    let pkt = encode_packet(3, &[0u8; 8], meta, 0);
+    assert_eq!(pkt.len(), HEADER_LEN + 8);
+    assert_eq!(decode_packet(&pkt).unwrap().meta.sample_rate.0, 37_800);
   }
 
   #[test]
@@ -267,5 +719,118 @@ mod tests {
     let mut short = pkt.clone();
     short.truncate(HEADER_LEN + 1);
     assert_eq!(decode_packet(&short), Err(DataPacketError::LengthMismatch));
+
+    // Unknown format codes are rejected, not guessed
+    let mut bad_format = pkt.clone();
+    bad_format[5] = 0;
+    assert_eq!(decode_packet(&bad_format), Err(DataPacketError::BadFormat));
+  }
+
+  #[test]
+  fn frame_counter_is_optional_and_v3_still_decodes() {
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let pkt = encode_packet_with_frame_counter(
+      5,
+      b"abcd",
+      meta,
+      10,
+      TimestampClock::Wall,
+      Some(96_000),
+    );
+    assert_eq!(pkt.len(), MAX_HEADER_LEN + 4);
+    let d = decode_packet(&pkt).unwrap();
+    assert_eq!(d.frame_counter, Some(96_000));
+    assert_eq!(d.payload, b"abcd");
+
+    // A flagged header cut before the counter ends is too short
+    assert_eq!(
+      decode_packet(&pkt[..MAX_HEADER_LEN - 1]),
+      Err(DataPacketError::TooShort)
+    );
+
+    let mut v4 = encode_packet(5, b"abcd", meta, 10);
+    v4[1] = PACKET_VERSION_V4;
+    let d = decode_packet(&v4).unwrap();
+    assert_eq!(d.frame_counter, None);
+    assert_eq!(d.payload, b"abcd");
+  }
+
+  #[test]
+  fn version_policy_selects_accepted_versions() {
+    use VersionPolicy::{Lenient, Strict};
+
+    let meta = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let v5 = encode_packet(1, b"ab", meta, 0);
+    assert!(decode_packet_with_policy(&v5, Strict).is_ok());
+    assert!(decode_packet_with_policy(&v5, Lenient).is_ok());
+
+    // A v4 sender that somehow set the frame counter flag is read by the
+    // v4 parser, so the payload is not shifted by a counter it never sent
+    let mut v4 = encode_packet(1, b"ab", meta, 0);
+    v4[1] = PACKET_VERSION_V4;
+    v4[6] |= FLAG_FRAME_COUNTER;
+    assert_eq!(
+      decode_packet_with_policy(&v4, Strict),
+      Err(DataPacketError::BadVersion)
+    );
+    let d = decode_packet_with_policy(&v4, Lenient).unwrap();
+    assert_eq!((d.frame_counter, d.payload), (None, &b"ab"[..]));
+
+    for version in [PACKET_VERSION_V4 - 1, PACKET_VERSION + 1] {
+      let mut pkt = v5.clone();
+      pkt[1] = version;
+      assert_eq!(
+        decode_packet_with_policy(&pkt, Lenient),
+        Err(DataPacketError::BadVersion)
+      );
+    }
+
+    let mut batch = v4.clone();
+    batch.extend_from_slice(&v5);
+    assert_eq!(decode_packets_with_policy(&batch, Lenient).count(), 2);
+    let mut strict = decode_packets_with_policy(&batch, Strict);
+    assert_eq!(strict.next(), Some(Err(DataPacketError::BadVersion)));
+    assert_eq!(strict.next(), None);
+  }
+
+  #[test]
+  fn validates_channels_and_strict_frames() {
+    let meta = Meta {
+      channels: 2,
+      sample_rate: SampleRate(48_000),
+      sample_format: SampleFormat::I16,
+    };
+    let mut zero_ch = encode_packet(1, b"abcd", meta, 0);
+    zero_ch[4] = 0;
+    assert_eq!(decode_packet(&zero_ch), Err(DataPacketError::BadChannels));
+
+    // 6 bytes is 1.5 stereo i16 frames: accepted leniently, not strictly
+    let ragged = encode_packet(1, b"abcdef", meta, 0);
+    assert!(decode_packet(&ragged).is_ok());
+    assert_eq!(
+      decode_packet_strict(&ragged),
+      Err(DataPacketError::PartialFrame)
+    );
+    let whole = encode_packet(1, b"abcdefgh", meta, 0);
+    assert!(decode_packet_strict(&whole).is_ok());
+  }
+
+  #[test]
+  fn encode_into_matches_vec_encoding() {
+    let meta = Meta {
+      channels: 2,
+      sample_rate: SampleRate(48_000),
+      sample_format: SampleFormat::I16,
+    };
+    let mut buf = [0u8; HEADER_LEN + 8];
+    let n = encode_packet_into(7, b"abcd", meta, 99, &mut buf).unwrap();
+    assert_eq!(n, HEADER_LEN + 4);
+    assert_eq!(&buf[..n], encode_packet(7, b"abcd", meta, 99).as_slice());
+
+    let mut small = [0u8; HEADER_LEN + 3];
+    assert_eq!(
+      encode_packet_into(7, b"abcd", meta, 99, &mut small),
+      Err(DataPacketError::BufferTooSmall)
+    );
   }
 }
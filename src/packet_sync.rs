@@ -1,40 +1,149 @@
-use crate::packet::SYNC_PACKET_MAGIC;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::packet::{Meta, MetaError, SYNC_PACKET_MAGIC, SampleFormat};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SyncMessage {
-  Ping { t0_ms: u64 },
-  Pong { t0_ms: u64, t1_ms: u64, t2_ms: u64 },
+  Ping {
+    t0_ms: u64,
+  },
+  // `accepts_batches` is set by receivers that decode every packet of a
+  // batched datagram; Pongs from older builds lack the byte and read false
+  Pong {
+    t0_ms: u64,
+    t1_ms: u64,
+    t2_ms: u64,
+    accepts_batches: bool,
+  },
+  // Receiver's preferred stream format; a sample_rate of 0 means any rate
+  FormatRequest {
+    sample_format: SampleFormat,
+    sample_rate: u32,
+  },
+  // Sender's source is exhausted; no more data packets will follow
+  EndOfStream,
+  // Sender's stream format, sent before data so a receiver can open its
+  // playback device ahead of the first packet
+  Hello {
+    meta: Meta,
+  },
+  // Receiver asks the sender to scale its level by `gain_db`, or to send
+  // silence while `muted` (push-to-talk, pause). Unauthenticated like every
+  // sync message: anyone who can reach the sender's socket can mute it.
+  Control {
+    gain_db: i8,
+    muted: bool,
+  },
 }
 const SYNC_VERSION: u8 = 1;
 const TYPE_PING: u8 = 1;
 const TYPE_PONG: u8 = 2;
+const TYPE_FORMAT_REQUEST: u8 = 3;
+const TYPE_END_OF_STREAM: u8 = 4;
+const TYPE_HELLO: u8 = 5;
+const TYPE_CONTROL: u8 = 6;
 
-// Encode a sync message to bytes.
-pub fn encode_sync(msg: &SyncMessage) -> Vec<u8> {
+const CONTROL_MUTED: u8 = 0x01;
+const PONG_ACCEPTS_BATCHES: u8 = 0x01;
+
+/// Largest encoded size of any sync message.
+pub const SYNC_MAX_LEN: usize = 1 + 1 + 1 + 8 + 8 + 8 + 1;
+
+impl SyncMessage {
+  /// Number of bytes `encode_sync_into` writes for this message.
+  pub fn encoded_len(&self) -> usize {
+    match self {
+      SyncMessage::Ping { .. } => 1 + 1 + 1 + 8,
+      SyncMessage::Pong { .. } => 1 + 1 + 1 + 8 + 8 + 8 + 1,
+      SyncMessage::FormatRequest { .. } => 1 + 1 + 1 + 1 + 4,
+      SyncMessage::EndOfStream => 1 + 1 + 1,
+      SyncMessage::Hello { .. } => 1 + 1 + 1 + 1 + 1 + 4,
+      SyncMessage::Control { .. } => 1 + 1 + 1 + 1 + 1,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncEncodeError {
+  BufferTooSmall,
+}
+
+impl core::fmt::Display for SyncEncodeError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      SyncEncodeError::BufferTooSmall => {
+        write!(f, "output buffer too small for sync packet")
+      }
+    }
+  }
+}
+
+// Encode a sync message into `out` without allocating; returns bytes written.
+pub fn encode_sync_into(
+  msg: &SyncMessage,
+  out: &mut [u8],
+) -> Result<usize, SyncEncodeError> {
+  let len = msg.encoded_len();
+  if out.len() < len {
+    return Err(SyncEncodeError::BufferTooSmall);
+  }
+  out[0] = SYNC_PACKET_MAGIC;
+  out[1] = SYNC_VERSION;
   match *msg {
     SyncMessage::Ping { t0_ms } => {
-      let mut v = Vec::with_capacity(1 + 1 + 1 + 8);
-      v.push(SYNC_PACKET_MAGIC);
-      v.push(SYNC_VERSION);
-      v.push(TYPE_PING);
-      v.extend_from_slice(&t0_ms.to_be_bytes());
-      v
+      out[2] = TYPE_PING;
+      out[3..11].copy_from_slice(&t0_ms.to_be_bytes());
     }
     SyncMessage::Pong {
       t0_ms,
       t1_ms,
       t2_ms,
+      accepts_batches,
     } => {
-      let mut v = Vec::with_capacity(1 + 1 + 1 + 8 + 8 + 8);
-      v.push(SYNC_PACKET_MAGIC);
-      v.push(SYNC_VERSION);
-      v.push(TYPE_PONG);
-      v.extend_from_slice(&t0_ms.to_be_bytes());
-      v.extend_from_slice(&t1_ms.to_be_bytes());
-      v.extend_from_slice(&t2_ms.to_be_bytes());
-      v
+      out[2] = TYPE_PONG;
+      out[3..11].copy_from_slice(&t0_ms.to_be_bytes());
+      out[11..19].copy_from_slice(&t1_ms.to_be_bytes());
+      out[19..27].copy_from_slice(&t2_ms.to_be_bytes());
+      out[27] = if accepts_batches {
+        PONG_ACCEPTS_BATCHES
+      } else {
+        0
+      };
+    }
+    SyncMessage::FormatRequest {
+      sample_format,
+      sample_rate,
+    } => {
+      out[2] = TYPE_FORMAT_REQUEST;
+      out[3] = sample_format.code();
+      out[4..8].copy_from_slice(&sample_rate.to_be_bytes());
+    }
+    SyncMessage::EndOfStream => {
+      out[2] = TYPE_END_OF_STREAM;
+    }
+    SyncMessage::Hello { meta } => {
+      out[2] = TYPE_HELLO;
+      out[3] = meta.channels;
+      out[4] = meta.sample_format.code();
+      out[5..9].copy_from_slice(&meta.sample_rate.0.to_be_bytes());
+    }
+    SyncMessage::Control { gain_db, muted } => {
+      out[2] = TYPE_CONTROL;
+      out[3] = gain_db as u8;
+      out[4] = if muted { CONTROL_MUTED } else { 0 };
     }
   }
+  Ok(len)
+}
+
+// Encode a sync message to bytes.
+#[cfg(feature = "alloc")]
+pub fn encode_sync(msg: &SyncMessage) -> Vec<u8> {
+  let mut v = alloc::vec![0u8; msg.encoded_len()];
+  let n = encode_sync_into(msg, &mut v).expect("buffer sized for message");
+  v.truncate(n);
+  v
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,6 +152,8 @@ pub enum SyncDecodeError {
   BadMagic,
   BadVersion,
   UnknownType,
+  UnknownFormat,
+  BadMeta(MetaError),
 }
 
 impl core::fmt::Display for SyncDecodeError {
@@ -54,6 +165,10 @@ impl core::fmt::Display for SyncDecodeError {
         write!(f, "unsupported sync packet version")
       }
       SyncDecodeError::UnknownType => write!(f, "unknown sync packet type"),
+      SyncDecodeError::UnknownFormat => {
+        write!(f, "unknown sample format in sync packet")
+      }
+      SyncDecodeError::BadMeta(e) => write!(f, "bad stream format: {e}"),
     }
   }
 }
@@ -100,6 +215,45 @@ pub fn decode_sync(data: &[u8]) -> Result<SyncMessage, SyncDecodeError> {
         t0_ms: u64::from_be_bytes(b0),
         t1_ms: u64::from_be_bytes(b1),
         t2_ms: u64::from_be_bytes(b2),
+        accepts_batches: data
+          .get(27)
+          .is_some_and(|f| f & PONG_ACCEPTS_BATCHES != 0),
+      })
+    }
+    TYPE_FORMAT_REQUEST => {
+      if data.len() < 3 + 1 + 4 {
+        return Err(SyncDecodeError::TooShort);
+      }
+      let sample_format = SampleFormat::from_code(data[3])
+        .ok_or(SyncDecodeError::UnknownFormat)?;
+      let mut b = [0u8; 4];
+      b.copy_from_slice(&data[4..8]);
+      Ok(SyncMessage::FormatRequest {
+        sample_format,
+        sample_rate: u32::from_be_bytes(b),
+      })
+    }
+    TYPE_END_OF_STREAM => Ok(SyncMessage::EndOfStream),
+    TYPE_HELLO => {
+      if data.len() < 3 + 1 + 1 + 4 {
+        return Err(SyncDecodeError::TooShort);
+      }
+      let sample_format = SampleFormat::from_code(data[4])
+        .ok_or(SyncDecodeError::UnknownFormat)?;
+      let mut b = [0u8; 4];
+      b.copy_from_slice(&data[5..9]);
+      let meta =
+        Meta::new(data[3] as u16, u32::from_be_bytes(b), sample_format)
+          .map_err(SyncDecodeError::BadMeta)?;
+      Ok(SyncMessage::Hello { meta })
+    }
+    TYPE_CONTROL => {
+      if data.len() < 3 + 1 + 1 {
+        return Err(SyncDecodeError::TooShort);
+      }
+      Ok(SyncMessage::Control {
+        gain_db: data[3] as i8,
+        muted: data[4] & CONTROL_MUTED != 0,
       })
     }
     _ => Err(SyncDecodeError::UnknownType),
@@ -124,14 +278,125 @@ mod tests {
 
   #[test]
   fn roundtrip_pong() {
+    for accepts_batches in [false, true] {
+      let m = SyncMessage::Pong {
+        t0_ms: 1,
+        t1_ms: 2,
+        t2_ms: 3,
+        accepts_batches,
+      };
+      let v = encode_sync(&m);
+      assert_eq!(v.len(), m.encoded_len());
+      assert_eq!(decode_sync(&v).unwrap(), m);
+    }
+  }
+
+  #[test]
+  fn pong_without_flags_byte_does_not_accept_batches() {
+    // As sent by builds that predate batching
     let m = SyncMessage::Pong {
       t0_ms: 1,
       t1_ms: 2,
       t2_ms: 3,
+      accepts_batches: true,
     };
     let v = encode_sync(&m);
-    let d = decode_sync(&v).unwrap();
-    assert_eq!(m, d);
+    assert_eq!(
+      decode_sync(&v[..27]).unwrap(),
+      SyncMessage::Pong {
+        t0_ms: 1,
+        t1_ms: 2,
+        t2_ms: 3,
+        accepts_batches: false,
+      }
+    );
+  }
+
+  #[test]
+  fn roundtrip_format_request() {
+    let m = SyncMessage::FormatRequest {
+      sample_format: SampleFormat::I16,
+      sample_rate: 44_100,
+    };
+    let v = encode_sync(&m);
+    assert_eq!(v.len(), m.encoded_len());
+    assert_eq!(decode_sync(&v).unwrap(), m);
+
+    let mut bad = v.clone();
+    bad[3] = 0;
+    assert_eq!(decode_sync(&bad), Err(SyncDecodeError::UnknownFormat));
+  }
+
+  #[test]
+  fn roundtrip_end_of_stream() {
+    let v = encode_sync(&SyncMessage::EndOfStream);
+    assert_eq!(v.len(), 3);
+    assert_eq!(decode_sync(&v).unwrap(), SyncMessage::EndOfStream);
+  }
+
+  #[test]
+  fn roundtrip_hello() {
+    let m = SyncMessage::Hello {
+      meta: Meta {
+        channels: 2,
+        sample_rate: SampleRate(44_100),
+        sample_format: SampleFormat::F32,
+      },
+    };
+    let v = encode_sync(&m);
+    assert_eq!(v.len(), m.encoded_len());
+    assert_eq!(decode_sync(&v).unwrap(), m);
+
+    let mut bad = v.clone();
+    bad[3] = 0;
+    assert_eq!(
+      decode_sync(&bad),
+      Err(SyncDecodeError::BadMeta(MetaError::BadChannels(0)))
+    );
+    assert_eq!(decode_sync(&v[..8]), Err(SyncDecodeError::TooShort));
+  }
+
+  #[test]
+  fn roundtrip_control() {
+    for m in [
+      SyncMessage::Control {
+        gain_db: -12,
+        muted: false,
+      },
+      SyncMessage::Control {
+        gain_db: 0,
+        muted: true,
+      },
+    ] {
+      let v = encode_sync(&m);
+      assert_eq!(v.len(), m.encoded_len());
+      assert_eq!(decode_sync(&v).unwrap(), m);
+    }
+    let v = encode_sync(&SyncMessage::Control {
+      gain_db: -6,
+      muted: true,
+    });
+    assert_eq!(&v[3..], &[0xfa, 0x01]);
+    assert_eq!(decode_sync(&v[..4]), Err(SyncDecodeError::TooShort));
+  }
+
+  #[test]
+  fn encode_into_matches_vec_encoding() {
+    let m = SyncMessage::Pong {
+      t0_ms: 4,
+      t1_ms: 5,
+      t2_ms: 6,
+      accepts_batches: true,
+    };
+    let mut buf = [0u8; SYNC_MAX_LEN];
+    let n = encode_sync_into(&m, &mut buf).unwrap();
+    assert_eq!(&buf[..n], encode_sync(&m).as_slice());
+
+    let mut small = [0u8; 8];
+    assert_eq!(
+      encode_sync_into(&SyncMessage::Ping { t0_ms: 1 }, &mut small),
+      Err(SyncEncodeError::BufferTooSmall)
+    );
   }
 
   #[test]
@@ -1,71 +1,370 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::packet::Meta;
+use crate::dsp::{Dither, convert_via_f32};
+use crate::packet::{Message, Meta, SampleFormat, SyncMessage, decode_message};
+use crate::spsc;
+
+/// Longest time payloads may sit in a `BinarySink` buffer before a flush.
+pub const MAX_BUFFER_DELAY: Duration = Duration::from_millis(20);
+/// Audio queued before `SinkTarget::Cpal` starts (or resumes) playback.
+pub const DEFAULT_PLAYBACK_DEPTH: Duration = Duration::from_millis(60);
+// Playback speed change per unit of relative fill error (fill - target) /
+// target, on top of the clock-drift ratio
+const FILL_GAIN: f64 = 0.002;
+// Playback speed never deviates from nominal by more than this (0.5%)
+const MAX_RATE_CORRECTION: f64 = 0.005;
+
+/// Jitter-buffer state of a playing `SinkTarget::Cpal` sink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackStatus {
+  /// Audio currently queued
+  pub buffered: Duration,
+  /// Depth playback waits for and steers toward
+  pub target: Duration,
+  pub underruns: u64,
+  pub overruns: u64,
+}
+
+fn player_not_found(program: &str) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::NotFound,
+    format!(
+      "{program} not found in PATH; install it (pw-cat ships with the \
+       PipeWire tools, paplay with pulseaudio-utils, aplay with alsa-utils) \
+       or omit --pipewire/--aplay to write raw audio to stdout"
+    ),
+  )
+}
+
+/// Check that `program` can be executed, so a missing player is reported
+/// at startup rather than when the first packet arrives.
+pub fn probe_player(program: &str) -> io::Result<()> {
+  match Command::new(program)
+    .arg("--version")
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+  {
+    Ok(_) => Ok(()),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      Err(player_not_found(program))
+    }
+    Err(e) => Err(e),
+  }
+}
+
+/// Where a `BinarySink` writes received payloads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkTarget {
+  /// Raw bytes to this process's stdout.
+  Stdout,
+  /// Playback through a spawned `pw-cat`.
+  PipeWire,
+  /// Playback through a spawned ALSA `aplay`.
+  Aplay,
+  /// Raw bytes to a pre-created named pipe (or any writable file).
+  Fifo(PathBuf),
+  /// Accept payloads and drop them, for when only the stats matter.
+  Discard,
+  /// Direct playback on the default output device, see `CpalSink`.
+  #[cfg(feature = "cpal")]
+  Cpal,
+}
+
+impl SinkTarget {
+  /// Whether every client writes to the same output, so that output
+  /// closing leaves no client anywhere to play.
+  pub fn is_shared(&self) -> bool {
+    matches!(self, SinkTarget::Stdout | SinkTarget::Fifo(_))
+  }
+}
+
+/// Senders whose own output was closed by its reader. Their datagrams are
+/// ignored for the rest of the session; the Hello that starts every session
+/// lets a sender back in with a fresh output.
+#[derive(Debug, Default)]
+pub struct ClosedOutputs {
+  addrs: HashSet<SocketAddr>,
+}
+
+impl ClosedOutputs {
+  pub fn close(&mut self, addr: SocketAddr) {
+    self.addrs.insert(addr);
+  }
+
+  /// Whether a datagram from `addr` should be handled.
+  pub fn admit(&mut self, addr: SocketAddr, datagram: &[u8]) -> bool {
+    if !self.addrs.contains(&addr) {
+      return true;
+    }
+    let hello = matches!(
+      decode_message(datagram),
+      Ok(Message::Sync(SyncMessage::Hello { .. }))
+    );
+    if hello {
+      self.addrs.remove(&addr);
+    }
+    hello
+  }
+}
 
 pub struct BinarySink {
-  use_pipewire: bool,
+  target: SinkTarget,
+  // Player process (pw-cat, paplay or aplay) fed through its stdin
   child: Option<Child>,
-  pw_stdin: Option<std::process::ChildStdin>,
+  child_stdin: Option<std::process::ChildStdin>,
+  fifo: Option<File>,
   last_meta: Option<Meta>,
+  // Play through paplay when pw-cat is not installed
+  paplay_fallback: bool,
+  // Pending payloads (all sharing `buffer_meta`); flushed at `buffer_limit`
+  // bytes or after MAX_BUFFER_DELAY. A limit of 0 writes immediately.
+  buffer: Vec<u8>,
+  buffer_limit: usize,
+  buffer_meta: Option<Meta>,
+  buffered_since: Option<Instant>,
+  // Jitter-buffer depth for direct playback
+  playback_depth: Duration,
+  // Output device name for direct playback; the default device if None
+  #[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+  output_device: Option<String>,
+  // Sender frames per output frame, from the clock-drift estimate
+  resample_ratio: f64,
+  // Payloads are converted to this format before output, if set
+  output_format: Option<SampleFormat>,
+  convert_buf: Vec<u8>,
+  dither: Dither,
+  #[cfg(feature = "cpal")]
+  cpal: Option<CpalSink>,
 }
 
 impl BinarySink {
-  pub fn new(use_pipewire: bool) -> Self {
+  pub fn new(target: SinkTarget) -> Self {
     Self {
-      use_pipewire,
+      target,
       child: None,
-      pw_stdin: None,
+      child_stdin: None,
+      fifo: None,
       last_meta: None,
+      paplay_fallback: false,
+      buffer: Vec::new(),
+      buffer_limit: 0,
+      buffer_meta: None,
+      buffered_since: None,
+      playback_depth: DEFAULT_PLAYBACK_DEPTH,
+      output_device: None,
+      resample_ratio: 1.0,
+      output_format: None,
+      convert_buf: Vec::new(),
+      dither: Dither::default(),
+      #[cfg(feature = "cpal")]
+      cpal: None,
+    }
+  }
+
+  /// Convert every payload to `format` (clamped, and dithered when
+  /// narrowing to 16 bits) before it reaches the output. `None` passes
+  /// payloads through in the sender's format.
+  pub fn set_output_format(&mut self, format: Option<SampleFormat>) {
+    self.output_format = format;
+  }
+
+  // The format actually written for a stream in `meta`
+  fn output_meta(&self, meta: &Meta) -> Meta {
+    match self.output_format {
+      Some(sample_format) => Meta {
+        sample_format,
+        ..*meta
+      },
+      None => *meta,
+    }
+  }
+
+  /// Accumulate up to `bytes` of payload before writing; 0 disables
+  /// buffering.
+  pub fn set_buffer_bytes(&mut self, bytes: usize) {
+    self.buffer_limit = bytes;
+    self.buffer.reserve(bytes);
+  }
+
+  pub fn set_paplay_fallback(&mut self, enabled: bool) {
+    self.paplay_fallback = enabled;
+  }
+
+  /// Jitter-buffer depth for `SinkTarget::Cpal`; ignored by other targets.
+  pub fn set_playback_depth(&mut self, depth: Duration) {
+    self.playback_depth = depth;
+  }
+
+  /// Play direct output on the device called `name` (see
+  /// `output_device`) rather than the default one.
+  pub fn set_output_device(&mut self, name: Option<String>) {
+    self.output_device = name;
+  }
+
+  /// Feed the sender/receiver clock ratio (see
+  /// `TimeSyncState::resample_ratio`) to direct playback, which consumes
+  /// audio that much faster so drift does not drain or flood its buffer.
+  /// Ignored by other targets.
+  pub fn set_resample_ratio(&mut self, ratio: f64) {
+    self.resample_ratio = ratio;
+    #[cfg(feature = "cpal")]
+    if let Some(cpal) = &self.cpal {
+      cpal.set_resample_ratio(ratio);
     }
   }
 
-  fn spawn_pw(&mut self, meta: &Meta) -> io::Result<()> {
-    let fmt = match meta.sample_format {
-      crate::packet::SampleFormat::F32 => "f32",
-      crate::packet::SampleFormat::I16 => "s16",
-      crate::packet::SampleFormat::U16 => "u16",
-      crate::packet::SampleFormat::U32 => "u32",
-      _ => "f32",
+  /// Jitter-buffer state while `SinkTarget::Cpal` is playing.
+  pub fn playback_status(&self) -> Option<PlaybackStatus> {
+    #[cfg(feature = "cpal")]
+    if let Some(cpal) = &self.cpal {
+      return Some(cpal.status());
+    }
+    None
+  }
+
+  fn spawn_child(&mut self, meta: &Meta) -> io::Result<()> {
+    let mut child = match self.target {
+      SinkTarget::Aplay => spawn_player("aplay", aplay_command(meta))?,
+      _ => match spawn_player("pw-cat", pw_cat_command(meta)) {
+        Err(e)
+          if e.kind() == io::ErrorKind::NotFound && self.paplay_fallback =>
+        {
+          spawn_player("paplay", paplay_command(meta)?)?
+        }
+        spawned => spawned?,
+      },
     };
-    let rate = meta.sample_rate.0.to_string();
-    let ch = meta.channels.to_string();
-    let mut child = Command::new("pw-cat")
-      .arg("--playback")
-      .arg("--raw")
-      .arg("--rate")
-      .arg(rate)
-      .arg("--channels")
-      .arg(ch)
-      .arg("--format")
-      .arg(fmt)
-      .arg("--latency")
-      .arg("10ms")
-      .arg("-")
-      .stdin(Stdio::piped())
-      .spawn()?;
-    self.pw_stdin = child.stdin.take();
+    self.child_stdin = child.stdin.take();
     self.child = Some(child);
     self.last_meta = Some(*meta);
     Ok(())
   }
 
   pub fn process(&mut self, meta: &Meta, payload: &[u8]) -> io::Result<()> {
-    if self.use_pipewire {
-      if self.pw_stdin.is_none() || self.meta_changed(meta) {
-        // If format changed, restart pw-cat with new params
+    let out_meta = self.output_meta(meta);
+    if out_meta == *meta {
+      return self.process_native(meta, payload);
+    }
+    let mut buf = std::mem::take(&mut self.convert_buf);
+    convert_via_f32(
+      payload,
+      meta.sample_format,
+      out_meta.sample_format,
+      Some(&mut self.dither),
+      &mut buf,
+    );
+    let result = self.process_native(&out_meta, &buf);
+    self.convert_buf = buf;
+    result
+  }
+
+  // `process` for payloads already in the output format
+  fn process_native(&mut self, meta: &Meta, payload: &[u8]) -> io::Result<()> {
+    if self.buffer_limit == 0 {
+      return self.write_out(meta, payload);
+    }
+    if self.buffer_meta.is_some_and(|m| m != *meta) {
+      // Never mix formats in one write; the player restarts on a change
+      self.flush()?;
+    }
+    self.buffer.extend_from_slice(payload);
+    self.buffer_meta = Some(*meta);
+    let since = *self.buffered_since.get_or_insert_with(Instant::now);
+    if self.buffer.len() >= self.buffer_limit
+      || since.elapsed() >= MAX_BUFFER_DELAY
+    {
+      self.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Open the player or playback stream for `meta` ahead of the first
+  /// payload, so startup latency is not paid on live audio. A no-op for
+  /// stdout and FIFO targets, and when the output is already in `meta`.
+  pub fn prepare(&mut self, meta: &Meta) -> io::Result<()> {
+    let meta = &self.output_meta(meta);
+    if self.buffer_meta.is_some_and(|m| m != *meta) {
+      self.flush()?;
+    }
+    #[cfg(feature = "cpal")]
+    if self.target == SinkTarget::Cpal && self.meta_changed(meta) {
+      self.open_cpal(meta)?;
+      return Ok(());
+    }
+    if matches!(self.target, SinkTarget::PipeWire | SinkTarget::Aplay)
+      && (self.child_stdin.is_none() || self.meta_changed(meta))
+    {
+      let _ = self.teardown_child();
+      self.spawn_child(meta)?;
+    }
+    Ok(())
+  }
+
+  /// Flush buffered payloads if the oldest has waited `MAX_BUFFER_DELAY`.
+  pub fn flush_if_stale(&mut self) -> io::Result<()> {
+    match self.buffered_since {
+      Some(since) if since.elapsed() >= MAX_BUFFER_DELAY => self.flush(),
+      _ => Ok(()),
+    }
+  }
+
+  /// Write out any buffered payloads.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.buffered_since = None;
+    let Some(meta) = self.buffer_meta else {
+      return Ok(());
+    };
+    if self.buffer.is_empty() {
+      return Ok(());
+    }
+    let buf = std::mem::take(&mut self.buffer);
+    let result = self.write_out(&meta, &buf);
+    self.buffer = buf;
+    self.buffer.clear();
+    result
+  }
+
+  fn write_out(&mut self, meta: &Meta, payload: &[u8]) -> io::Result<()> {
+    if self.target == SinkTarget::Discard {
+      return Ok(());
+    }
+    #[cfg(feature = "cpal")]
+    if self.target == SinkTarget::Cpal {
+      if self.cpal.is_none() || self.meta_changed(meta) {
+        self.open_cpal(meta)?;
+      }
+      self.cpal.as_mut().unwrap().push(payload);
+      return Ok(());
+    }
+    if let SinkTarget::Fifo(path) = &self.target {
+      // Opening a FIFO for writing blocks until a reader connects
+      let open = || OpenOptions::new().write(true).open(path);
+      write_reopening(&mut self.fifo, open, payload)?;
+    } else if matches!(self.target, SinkTarget::PipeWire | SinkTarget::Aplay) {
+      if self.child_stdin.is_none() || self.meta_changed(meta) {
+        // If format changed, restart the player with new params
         let _ = self.teardown_child();
-        self.spawn_pw(meta)?;
+        self.spawn_child(meta)?;
       }
-      match self.pw_stdin.as_mut().unwrap().write_all(payload) {
+      match self.child_stdin.as_mut().unwrap().write_all(payload) {
         Ok(()) => {}
         Err(e) => {
           // Try one restart on write failure (e.g., broken pipe), then retry
           // once
           let _ = self.teardown_child();
-          self.spawn_pw(meta)?;
+          self.spawn_child(meta)?;
           self
-            .pw_stdin
+            .child_stdin
             .as_mut()
             .unwrap()
             .write_all(payload)
@@ -73,17 +372,34 @@ impl BinarySink {
               // If retry also fails, return original error context
               io::Error::new(
                 e2.kind(),
-                format!("pipewire write failed after restart: {e}"),
+                format!("player write failed after restart: {e}"),
               )
             })?;
         }
       }
-    } else {
-      io::stdout().write_all(payload)?;
+    } else if let Err(e) = io::stdout().write_all(payload) {
+      // Unlike a player, whatever read our stdout cannot be brought back;
+      // callers treat BrokenPipe as the end of this output
+      if e.kind() == io::ErrorKind::BrokenPipe {
+        return Err(io::Error::new(e.kind(), "stdout reader closed"));
+      }
+      return Err(e);
     }
     Ok(())
   }
 
+  #[cfg(feature = "cpal")]
+  fn open_cpal(&mut self, meta: &Meta) -> io::Result<()> {
+    // Close the old stream before opening one in the new format
+    self.cpal = None;
+    let cpal =
+      CpalSink::open(meta, self.playback_depth, self.output_device.as_deref())?;
+    cpal.set_resample_ratio(self.resample_ratio);
+    self.cpal = Some(cpal);
+    self.last_meta = Some(*meta);
+    Ok(())
+  }
+
   fn meta_changed(&self, meta: &Meta) -> bool {
     match self.last_meta {
       Some(m) => {
@@ -97,8 +413,8 @@ impl BinarySink {
 
   fn teardown_child(&mut self) -> io::Result<()> {
     if let Some(mut child) = self.child.take() {
-      // Close stdin so pw-cat can terminate gracefully
-      self.pw_stdin.take();
+      // Close stdin so the player can terminate gracefully
+      self.child_stdin.take();
       // Attempt to wait; if it errors, ignore (process may have already exited)
       let _ = child.kill();
       let _ = child.wait();
@@ -107,8 +423,665 @@ impl BinarySink {
   }
 }
 
+// Spawn a player reading raw audio on stdin, reporting a missing binary
+// with installation hints
+fn spawn_player(program: &str, mut cmd: Command) -> io::Result<Child> {
+  cmd.stdin(Stdio::piped()).spawn().map_err(|e| {
+    if e.kind() == io::ErrorKind::NotFound {
+      player_not_found(program)
+    } else {
+      e
+    }
+  })
+}
+
+fn pw_cat_command(meta: &Meta) -> Command {
+  let fmt = match meta.sample_format {
+    SampleFormat::F32 => "f32",
+    SampleFormat::I16 => "s16",
+    SampleFormat::U16 => "u16",
+    SampleFormat::U32 => "u32",
+  };
+  let mut cmd = Command::new("pw-cat");
+  cmd
+    .arg("--playback")
+    .arg("--raw")
+    .arg("--rate")
+    .arg(meta.sample_rate.0.to_string())
+    .arg("--channels")
+    .arg(meta.channels.to_string())
+    .arg("--format")
+    .arg(fmt)
+    .arg("--latency")
+    .arg("10ms")
+    .arg("-");
+  cmd
+}
+
+fn paplay_command(meta: &Meta) -> io::Result<Command> {
+  // Payloads are native-endian; paplay has no unsigned 16/32-bit formats
+  let fmt = match meta.sample_format {
+    SampleFormat::F32 => "float32ne",
+    SampleFormat::I16 => "s16ne",
+    other => {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("paplay cannot play {other:?} samples"),
+      ));
+    }
+  };
+  let mut cmd = Command::new("paplay");
+  cmd
+    .arg("--raw")
+    .arg(format!("--rate={}", meta.sample_rate.0))
+    .arg(format!("--channels={}", meta.channels))
+    .arg(format!("--format={fmt}"));
+  Ok(cmd)
+}
+
+// ALSA format name for native-endian samples of `fmt`
+fn aplay_format(fmt: SampleFormat) -> &'static str {
+  let le = cfg!(target_endian = "little");
+  match fmt {
+    SampleFormat::I16 if le => "S16_LE",
+    SampleFormat::I16 => "S16_BE",
+    SampleFormat::U16 if le => "U16_LE",
+    SampleFormat::U16 => "U16_BE",
+    SampleFormat::U32 if le => "U32_LE",
+    SampleFormat::U32 => "U32_BE",
+    SampleFormat::F32 if le => "FLOAT_LE",
+    SampleFormat::F32 => "FLOAT_BE",
+  }
+}
+
+fn aplay_command(meta: &Meta) -> Command {
+  let mut cmd = Command::new("aplay");
+  cmd
+    .arg("-q")
+    .arg("-t")
+    .arg("raw")
+    .arg("-f")
+    .arg(aplay_format(meta.sample_format))
+    .arg("-r")
+    .arg(meta.sample_rate.0.to_string())
+    .arg("-c")
+    .arg(meta.channels.to_string())
+    .arg("-");
+  cmd
+}
+
+/// Byte queue between the receive loop and an audio callback, acting as a
+/// jitter buffer: playback starts only once `prefill` bytes are queued, and
+/// after an underrun waits for the same level again rather than playing
+/// each packet as it trickles in. While playing, `pop_frame` drops or
+/// repeats an occasional frame to hold the fill level near `prefill`.
+///
+/// The receive loop feeds a `PlaybackInput` and the callback drains the
+/// `PlaybackBuffer`; they meet in an `spsc` ring and atomics, so the
+/// callback never waits on a lock.
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+fn playback_buffer(
+  meta: &Meta,
+  depth: Duration,
+) -> (PlaybackInput, PlaybackBuffer) {
+  let frame_bytes = meta.frame_size();
+  let frames = (meta.sample_rate.0 as f64 * depth.as_secs_f64())
+    .ceil()
+    .max(1.0) as usize;
+  let prefill = frames * frame_bytes;
+  // As much again as headroom before new audio is dropped
+  let (producer, consumer) = spsc::ring(2 * prefill);
+  let shared = Arc::new(PlaybackShared {
+    underruns: AtomicU64::new(0),
+    overruns: AtomicU64::new(0),
+    ratio: AtomicU64::new(1.0f64.to_bits()),
+  });
+  let input = PlaybackInput {
+    ring: producer,
+    shared: shared.clone(),
+    frame_bytes,
+    sample_rate: meta.sample_rate.0,
+    prefill,
+  };
+  let buffer = PlaybackBuffer {
+    ring: consumer,
+    shared,
+    frame_bytes,
+    prefill,
+    playing: false,
+    ratio: 1.0,
+    phase: 0.0,
+    last_frame: vec![0; frame_bytes],
+    scratch: vec![0; frame_bytes],
+  };
+  (input, buffer)
+}
+
+// Counters and settings both ends of a playback buffer touch
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+struct PlaybackShared {
+  underruns: AtomicU64,
+  overruns: AtomicU64,
+  // Clock-drift ratio from `BinarySink::set_resample_ratio`, as f64 bits
+  ratio: AtomicU64,
+}
+
+/// Receive-loop end of a `playback_buffer`.
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+struct PlaybackInput {
+  ring: spsc::Producer,
+  shared: Arc<PlaybackShared>,
+  frame_bytes: usize,
+  sample_rate: u32,
+  prefill: usize,
+}
+
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+impl PlaybackInput {
+  /// Queue `payload`, or drop all of it if it does not fit, so the queue
+  /// keeps holding whole frames.
+  fn push(&mut self, payload: &[u8]) {
+    if !self.ring.push(payload) {
+      self.shared.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  fn set_ratio(&self, ratio: f64) {
+    self.shared.ratio.store(ratio.to_bits(), Ordering::Relaxed);
+  }
+
+  fn duration_of(&self, bytes: usize) -> Duration {
+    let frames = (bytes / self.frame_bytes) as f64;
+    Duration::from_secs_f64(frames / self.sample_rate.max(1) as f64)
+  }
+
+  fn status(&self) -> PlaybackStatus {
+    PlaybackStatus {
+      buffered: self.duration_of(self.ring.capacity() - self.ring.free()),
+      target: self.duration_of(self.prefill),
+      underruns: self.shared.underruns.load(Ordering::Relaxed),
+      overruns: self.shared.overruns.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// Callback end of a `playback_buffer`. Never blocks or allocates.
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+struct PlaybackBuffer {
+  ring: spsc::Consumer,
+  shared: Arc<PlaybackShared>,
+  frame_bytes: usize,
+  prefill: usize,
+  playing: bool,
+  ratio: f64,
+  // Accumulated input-minus-output frames; a frame is dropped or repeated
+  // each time it crosses +-1
+  phase: f64,
+  last_frame: Vec<u8>,
+  // Where a skipped frame is popped to
+  scratch: Vec<u8>,
+}
+
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+impl PlaybackBuffer {
+  /// Pick up the latest `PlaybackInput::set_ratio`; once per callback.
+  fn load_ratio(&mut self) {
+    self.ratio = f64::from_bits(self.shared.ratio.load(Ordering::Relaxed));
+  }
+
+  fn underrun(&mut self) {
+    self.playing = false;
+    self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+  }
+
+  // Input frames per output frame: the drift ratio, nudged toward the
+  // target fill level
+  fn effective_ratio(&self) -> f64 {
+    let fill_error =
+      (self.ring.len() as f64 - self.prefill as f64) / self.prefill as f64;
+    (self.ratio * (1.0 + FILL_GAIN * fill_error))
+      .clamp(1.0 - MAX_RATE_CORRECTION, 1.0 + MAX_RATE_CORRECTION)
+  }
+
+  /// Next frame into `out` (one sample per channel); false while filling
+  /// up or after running dry.
+  fn pop_frame<T: bytemuck::Pod>(&mut self, out: &mut [T]) -> bool {
+    if !self.playing && self.ring.len() >= self.prefill {
+      self.playing = true;
+    }
+    if !self.playing {
+      return false;
+    }
+    if self.ring.len() < self.frame_bytes {
+      self.underrun();
+      return false;
+    }
+    self.phase += self.effective_ratio() - 1.0;
+    if self.phase <= -1.0 {
+      // Input arrives slower than we play: hold the previous frame
+      self.phase += 1.0;
+      bytemuck::cast_slice_mut::<T, u8>(out).copy_from_slice(&self.last_frame);
+      return true;
+    }
+    if self.phase >= 1.0 && self.ring.len() >= 2 * self.frame_bytes {
+      // Input arrives faster than we play: skip a frame
+      self.phase -= 1.0;
+      self.ring.pop(&mut self.scratch);
+    }
+    self.ring.pop(bytemuck::cast_slice_mut::<T, u8>(out));
+    self
+      .last_frame
+      .copy_from_slice(bytemuck::cast_slice::<T, u8>(out));
+    true
+  }
+}
+
+/// The cpal output device called `name`, or the default output device for
+/// `None`. An unknown name is an error listing the devices there are.
+#[cfg(feature = "cpal")]
+pub fn output_device(name: Option<&str>) -> io::Result<cpal::Device> {
+  use cpal::traits::HostTrait;
+
+  let host = cpal::default_host();
+  let Some(name) = name else {
+    return host.default_output_device().ok_or_else(|| {
+      io::Error::new(io::ErrorKind::NotFound, "no default output device")
+    });
+  };
+  let devices = host
+    .output_devices()
+    .map_err(|e| io::Error::other(e.to_string()))?;
+  select_device(devices, name, "output")
+}
+
+/// Pick the device called `name` from `devices`; `kind` ("input" or
+/// "output") only words the error, which lists every device's name.
+#[cfg(feature = "cpal")]
+pub fn select_device(
+  devices: impl Iterator<Item = cpal::Device>,
+  name: &str,
+  kind: &str,
+) -> io::Result<cpal::Device> {
+  use cpal::traits::DeviceTrait;
+
+  let mut devices: Vec<(String, cpal::Device)> = devices
+    .map(|d| (d.name().unwrap_or_else(|_| "(unnamed)".into()), d))
+    .collect();
+  let names: Vec<&str> = devices.iter().map(|(n, _)| n.as_str()).collect();
+  match match_device_name(&names, name) {
+    Some(i) => Ok(devices.swap_remove(i).1),
+    None => Err(io::Error::new(
+      io::ErrorKind::NotFound,
+      format!(
+        "no {kind} device named {name:?}; available: {}",
+        if names.is_empty() {
+          "(none)".to_string()
+        } else {
+          names.join(", ")
+        }
+      ),
+    )),
+  }
+}
+
+// An exact match wins; otherwise a match ignoring case, if only one
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+fn match_device_name(names: &[&str], wanted: &str) -> Option<usize> {
+  if let Some(i) = names.iter().position(|n| *n == wanted) {
+    return Some(i);
+  }
+  let mut folded = names
+    .iter()
+    .enumerate()
+    .filter(|(_, n)| n.eq_ignore_ascii_case(wanted));
+  match (folded.next(), folded.next()) {
+    (Some((i, _)), None) => Some(i),
+    _ => None,
+  }
+}
+
+/// Plays payloads on a cpal output device. The receive loop
+/// pushes into a `playback_buffer` that the device callback drains;
+/// underruns play silence until the buffer refills.
+#[cfg(feature = "cpal")]
+pub struct CpalSink {
+  input: PlaybackInput,
+  _stream: cpal::Stream,
+}
+
+#[cfg(feature = "cpal")]
+impl CpalSink {
+  /// Open an output stream matching `meta` on the device called `device`
+  /// (the default device for `None`), buffering `depth` of audio.
+  pub fn open(
+    meta: &Meta,
+    depth: Duration,
+    device: Option<&str>,
+  ) -> io::Result<Self> {
+    use cpal::traits::StreamTrait;
+
+    let device = output_device(device)?;
+    let config = cpal::StreamConfig {
+      channels: meta.channels as u16,
+      sample_rate: cpal::SampleRate(meta.sample_rate.0),
+      buffer_size: cpal::BufferSize::Default,
+    };
+    let (input, buffer) = playback_buffer(meta, depth);
+    let stream = match meta.sample_format {
+      SampleFormat::F32 => build_output_stream::<f32>(&device, &config, buffer),
+      SampleFormat::I16 => build_output_stream::<i16>(&device, &config, buffer),
+      SampleFormat::U16 => build_output_stream::<u16>(&device, &config, buffer),
+      SampleFormat::U32 => build_output_stream::<u32>(&device, &config, buffer),
+    }?;
+    stream.play().map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(Self {
+      input,
+      _stream: stream,
+    })
+  }
+
+  pub fn push(&mut self, payload: &[u8]) {
+    self.input.push(payload);
+  }
+
+  /// Times playback ran dry and times queued audio was dropped for space.
+  pub fn xruns(&self) -> (u64, u64) {
+    let status = self.input.status();
+    (status.underruns, status.overruns)
+  }
+
+  pub fn status(&self) -> PlaybackStatus {
+    self.input.status()
+  }
+
+  pub fn set_resample_ratio(&self, ratio: f64) {
+    self.input.set_ratio(ratio);
+  }
+}
+
+#[cfg(feature = "cpal")]
+fn build_output_stream<T>(
+  device: &cpal::Device,
+  config: &cpal::StreamConfig,
+  mut buffer: PlaybackBuffer,
+) -> io::Result<cpal::Stream>
+where
+  T: cpal::SizedSample + bytemuck::Pod,
+{
+  use cpal::traits::DeviceTrait;
+
+  let channels = config.channels as usize;
+  device
+    .build_output_stream(
+      config,
+      move |data: &mut [T], _| {
+        buffer.load_ratio();
+        for frame in data.chunks_mut(channels) {
+          if !buffer.pop_frame(frame) {
+            frame.fill(T::EQUILIBRIUM);
+          }
+        }
+      },
+      |err| log::error!("output stream error: {err}"),
+      None,
+    )
+    .map_err(|e| io::Error::other(e.to_string()))
+}
+
 impl Drop for BinarySink {
   fn drop(&mut self) {
+    let _ = self.flush();
     let _ = self.teardown_child();
   }
 }
+
+// Write `payload` to the writer in `slot`, opening it first if needed. When
+// the reader went away (EPIPE) the writer is reopened, which for a FIFO
+// waits for a new reader, and the write retried once.
+fn write_reopening<W: Write>(
+  slot: &mut Option<W>,
+  mut open: impl FnMut() -> io::Result<W>,
+  payload: &[u8],
+) -> io::Result<()> {
+  if slot.is_none() {
+    *slot = Some(open()?);
+  }
+  let Err(e) = slot.as_mut().unwrap().write_all(payload) else {
+    return Ok(());
+  };
+  if e.kind() != io::ErrorKind::BrokenPipe {
+    return Err(e);
+  }
+  *slot = None;
+  let writer = slot.insert(open()?);
+  writer.write_all(payload).map_err(|e| {
+    io::Error::new(e.kind(), format!("fifo write failed after reopen: {e}"))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::{encode_packet, encode_sync};
+
+  #[test]
+  fn closed_output_reopens_on_the_next_hello() {
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let data = encode_packet(1, &[0; 4], meta, 0);
+    let hello = encode_sync(&SyncMessage::Hello { meta });
+    let gone: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    let other: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+
+    let mut closed = ClosedOutputs::default();
+    closed.close(gone);
+    assert!(!closed.admit(gone, &data));
+    assert!(closed.admit(other, &data));
+    // A new session from the same address
+    assert!(closed.admit(gone, &hello));
+    assert!(closed.admit(gone, &data));
+  }
+
+  #[test]
+  fn only_stdout_and_fifo_are_shared_by_every_client() {
+    assert!(SinkTarget::Stdout.is_shared());
+    assert!(SinkTarget::Fifo(PathBuf::from("/tmp/fifo")).is_shared());
+    assert!(!SinkTarget::PipeWire.is_shared());
+    assert!(!SinkTarget::Aplay.is_shared());
+    assert!(!SinkTarget::Discard.is_shared());
+  }
+
+  #[test]
+  fn missing_players_are_reported_with_install_hints() {
+    let missing = "sound-send-no-such-player";
+    let errors = [
+      probe_player(missing).unwrap_err(),
+      spawn_player(missing, Command::new(missing)).unwrap_err(),
+    ];
+    for err in errors {
+      assert_eq!(err.kind(), io::ErrorKind::NotFound);
+      let msg = err.to_string();
+      assert!(
+        msg.starts_with(&format!("{missing} not found in PATH")),
+        "{msg}"
+      );
+    }
+  }
+
+  #[test]
+  fn paplay_fallback_plays_native_endian_formats() {
+    let args = |format| {
+      let meta = Meta::new(2, 44_100, format).unwrap();
+      paplay_command(&meta)
+        .map(|cmd| cmd.get_args().map(|a| a.to_owned()).collect::<Vec<_>>())
+    };
+    assert_eq!(
+      args(SampleFormat::I16).unwrap(),
+      ["--raw", "--rate=44100", "--channels=2", "--format=s16ne"]
+    );
+    assert_eq!(
+      args(SampleFormat::F32).unwrap().last().unwrap(),
+      "--format=float32ne"
+    );
+    let err = args(SampleFormat::U16).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn device_names_match_exactly_then_ignoring_case() {
+    let names = ["Speakers", "HDMI", "hdmi"];
+    assert_eq!(match_device_name(&names, "Speakers"), Some(0));
+    assert_eq!(match_device_name(&names, "speakers"), Some(0));
+    assert_eq!(match_device_name(&names, "hdmi"), Some(2));
+    // Two devices differ only in case, so a third spelling is ambiguous
+    assert_eq!(match_device_name(&names, "Hdmi"), None);
+    assert_eq!(match_device_name(&names, "USB"), None);
+  }
+
+  // Accepts `capacity` bytes, then fails as if its reader had gone away
+  struct Reader {
+    written: Vec<u8>,
+    capacity: usize,
+  }
+
+  impl Write for Reader {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      if self.written.len() + buf.len() > self.capacity {
+        return Err(io::ErrorKind::BrokenPipe.into());
+      }
+      self.written.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn fifo_writes_reopen_once_after_the_reader_leaves() {
+    let reader = |capacity| {
+      move || {
+        Ok(Reader {
+          written: Vec::new(),
+          capacity,
+        })
+      }
+    };
+    let mut slot = None;
+    write_reopening(&mut slot, reader(4), b"abcd").unwrap();
+    assert_eq!(slot.as_ref().unwrap().written, b"abcd");
+    // The first reader leaves; the payload goes to the next one whole
+    write_reopening(&mut slot, reader(8), b"efgh").unwrap();
+    assert_eq!(slot.as_ref().unwrap().written, b"efgh");
+    // A reader that leaves at once again is an error, not a loop
+    let err = write_reopening(&mut slot, reader(0), b"ijklmnop").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+  }
+
+  #[test]
+  fn buffered_fifo_writes_in_order_on_flush() {
+    let path = std::env::temp_dir()
+      .join(format!("sound-send-sink-buf-{}", std::process::id()));
+    let meta = Meta {
+      channels: 1,
+      sample_rate: crate::packet::SampleRate(48_000),
+      sample_format: SampleFormat::I16,
+    };
+    std::fs::write(&path, b"").unwrap();
+    {
+      let mut sink = BinarySink::new(SinkTarget::Fifo(path.clone()));
+      sink.set_buffer_bytes(1024);
+      sink.process(&meta, b"ab").unwrap();
+      sink.process(&meta, b"cd").unwrap();
+      // Nothing written until the buffer fills or is flushed
+      assert!(std::fs::read(&path).unwrap().is_empty());
+      let stereo = Meta {
+        channels: 2,
+        ..meta
+      };
+      // A format change flushes what was buffered under the old format
+      sink.process(&stereo, b"efgh").unwrap();
+      assert_eq!(std::fs::read(&path).unwrap(), b"abcd");
+    }
+    // Dropping the sink flushes the rest
+    assert_eq!(std::fs::read(&path).unwrap(), b"abcdefgh");
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn playback_buffer_prefills_and_recovers_from_underrun() {
+    let meta = Meta::new(1, 1_000, SampleFormat::I16).unwrap();
+    // 4 ms at 1 kHz: 4 frames (8 bytes) prefill, 16 bytes capacity
+    let (mut input, mut pb) = playback_buffer(&meta, Duration::from_millis(4));
+    let samples = |v: &[i16]| bytemuck::cast_slice::<i16, u8>(v).to_vec();
+    let pop = |pb: &mut PlaybackBuffer| {
+      let mut frame = [0i16];
+      pb.pop_frame(&mut frame).then_some(frame[0])
+    };
+
+    input.push(&samples(&[1, 2, 3]));
+    assert_eq!(pop(&mut pb), None, "still filling");
+    input.push(&samples(&[4]));
+    assert_eq!(pop(&mut pb), Some(1));
+    for want in 2..=4 {
+      assert_eq!(pop(&mut pb), Some(want));
+    }
+    assert_eq!(pop(&mut pb), None);
+    assert_eq!(input.status().underruns, 1);
+    // Waits for a full prefill again before resuming
+    input.push(&samples(&[5]));
+    assert_eq!(pop(&mut pb), None);
+
+    // A payload that does not fit is dropped whole
+    input.push(&samples(&[6, 7, 8, 9, 10, 11, 12, 13]));
+    assert_eq!(input.status().overruns, 1);
+    input.push(&samples(&[6, 7, 8]));
+    assert_eq!(pop(&mut pb), Some(5));
+    assert_eq!(pop(&mut pb), Some(6));
+  }
+
+  #[test]
+  fn playback_buffer_steers_toward_target_fill() {
+    let meta = Meta::new(1, 1_000, SampleFormat::I16).unwrap();
+    let fill = |input: &mut PlaybackInput| {
+      let v: Vec<i16> = (0..1_000).collect();
+      input.push(bytemuck::cast_slice(&v));
+    };
+    let mut out = [0i16];
+
+    // Sender clock fast: frames are skipped, so playback runs ahead
+    let (mut input, mut pb) = playback_buffer(&meta, Duration::from_secs(1));
+    fill(&mut input);
+    input.set_ratio(1.01);
+    pb.load_ratio();
+    for _ in 0..400 {
+      assert!(pb.pop_frame(&mut out));
+    }
+    assert!(out[0] > 399, "last frame was {}", out[0]);
+
+    // Sender clock slow: frames are repeated, so playback falls behind
+    let (mut input, mut pb) = playback_buffer(&meta, Duration::from_secs(1));
+    fill(&mut input);
+    input.set_ratio(0.99);
+    pb.load_ratio();
+    for _ in 0..400 {
+      assert!(pb.pop_frame(&mut out));
+    }
+    assert!(out[0] < 399, "last frame was {}", out[0]);
+
+    let status = input.status();
+    assert_eq!(status.target, Duration::from_secs(1));
+    assert!(status.buffered > Duration::from_millis(600));
+  }
+
+  #[test]
+  fn aplay_format_matches_sample_format() {
+    if cfg!(target_endian = "little") {
+      assert_eq!(aplay_format(SampleFormat::F32), "FLOAT_LE");
+      assert_eq!(aplay_format(SampleFormat::I16), "S16_LE");
+      assert_eq!(aplay_format(SampleFormat::U32), "U32_LE");
+    } else {
+      assert_eq!(aplay_format(SampleFormat::F32), "FLOAT_BE");
+    }
+  }
+}
@@ -0,0 +1,160 @@
+// Packet loss concealment for the receiver: audio standing in for lost
+// packets, so the sink stays sample-aligned with the sender instead of
+// jumping ahead by the missing frames.
+
+use crate::dsp::{AudioFrameReader, f32_to_sample};
+use crate::packet::Meta;
+
+// Longest fill for one gap; a gap beyond this is more likely an outage
+// than loss, and playing it out would only delay the audio after it
+const MAX_FILL_MS: u64 = 500;
+
+/// What stands in for a lost packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlcMode {
+  /// Nothing; the next packet follows straight on.
+  #[default]
+  None,
+  /// Silence.
+  Silence,
+  /// The last frame received, faded out linearly across the gap, which
+  /// avoids the click of dropping straight to silence.
+  Repeat,
+}
+
+/// Produces fill for gaps in a stream, sized from the packets around them:
+/// each lost packet is taken to be as long as the last one delivered.
+#[derive(Debug, Default)]
+pub struct LossConcealer {
+  mode: PlcMode,
+  last_meta: Option<Meta>,
+  last_len: usize,
+  last_frame: Vec<u8>,
+  fill: Vec<u8>,
+}
+
+impl LossConcealer {
+  pub fn new(mode: PlcMode) -> Self {
+    Self {
+      mode,
+      ..Self::default()
+    }
+  }
+
+  /// Note a payload passed to the sink.
+  pub fn on_deliver(&mut self, meta: &Meta, payload: &[u8]) {
+    if self.mode == PlcMode::None {
+      return;
+    }
+    let frame = meta.frame_size();
+    self.last_meta = Some(*meta);
+    self.last_len = payload.len() - payload.len() % frame;
+    if self.last_len > 0 {
+      self.last_frame.clear();
+      self
+        .last_frame
+        .extend_from_slice(&payload[self.last_len - frame..self.last_len]);
+    }
+  }
+
+  /// Fill for `lost` packets, in the format of the last one delivered.
+  /// `None` when concealment is off or nothing has been delivered yet.
+  pub fn conceal(&mut self, lost: u64) -> Option<(Meta, &[u8])> {
+    let meta = self.last_meta?;
+    let frame = meta.frame_size();
+    let max_frames = meta.sample_rate.0 as u64 * MAX_FILL_MS / 1000;
+    let want = (self.last_len / frame) as u64 * lost;
+    let n_frames = want.min(max_frames) as usize;
+    if n_frames == 0 {
+      return None;
+    }
+    let format = meta.sample_format;
+    let bps = format.bytes_per_sample();
+    let mut silence = [0u8; 4];
+    f32_to_sample(format, 0.0, 0.0, &mut silence);
+    self.fill.clear();
+    self.fill.resize(n_frames * frame, 0);
+    match self.mode {
+      PlcMode::None => return None,
+      PlcMode::Silence => {
+        for sample in self.fill.chunks_exact_mut(bps) {
+          sample.copy_from_slice(&silence[..bps]);
+        }
+      }
+      PlcMode::Repeat => {
+        let levels =
+          AudioFrameReader::new(&self.last_frame, &meta).to_f32_vec();
+        for (i, out) in self.fill.chunks_exact_mut(frame).enumerate() {
+          let gain = 1.0 - (i + 1) as f32 / n_frames as f32;
+          for (sample, level) in out.chunks_exact_mut(bps).zip(&levels) {
+            f32_to_sample(format, level * gain, 0.0, sample);
+          }
+        }
+      }
+    }
+    Some((meta, &self.fill))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::SampleFormat;
+  use crate::reorder::{ReorderBuffer, ReorderEvent};
+
+  #[test]
+  fn one_packet_gap_fills_one_packet_of_silence() {
+    let meta = Meta::new(2, 48_000, SampleFormat::U16).unwrap();
+    let payload = [0x34u8; 256];
+    let mut plc = LossConcealer::new(PlcMode::Silence);
+    let mut reorder = ReorderBuffer::new(0);
+    let mut sink: Vec<u8> = Vec::new();
+    for seq in [0, 1, 3] {
+      reorder
+        .push(seq, meta, &payload, &mut |ev| {
+          match ev {
+            ReorderEvent::Deliver { meta, payload, .. } => {
+              plc.on_deliver(&meta, payload);
+              sink.extend_from_slice(payload);
+            }
+            ReorderEvent::Lost(lost) => {
+              if let Some((_, fill)) = plc.conceal(lost) {
+                sink.extend_from_slice(fill);
+              }
+            }
+            ReorderEvent::Late => {}
+          }
+          Ok::<(), ()>(())
+        })
+        .unwrap();
+    }
+    assert_eq!(sink.len(), 4 * payload.len());
+    let gap = &sink[2 * payload.len()..3 * payload.len()];
+    assert!(gap.chunks_exact(2).all(|s| s == 0x8000u16.to_ne_bytes()));
+  }
+
+  #[test]
+  fn repeat_fades_the_last_frame_out() {
+    let meta = Meta::new(1, 48_000, SampleFormat::F32).unwrap();
+    let mut plc = LossConcealer::new(PlcMode::Repeat);
+    assert_eq!(plc.conceal(1), None);
+    let payload: Vec<u8> = [0.1f32, 0.2, 0.3, 0.8]
+      .iter()
+      .flat_map(|s| s.to_ne_bytes())
+      .collect();
+    plc.on_deliver(&meta, &payload);
+    let (_, fill) = plc.conceal(1).unwrap();
+    let fill: Vec<f32> = fill
+      .chunks_exact(4)
+      .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+      .collect();
+    for (got, want) in fill.iter().zip([0.6, 0.4, 0.2, 0.0]) {
+      assert!((got - want).abs() < 1e-6, "{fill:?}");
+    }
+    assert_eq!(fill.len(), 4);
+
+    let mut off = LossConcealer::new(PlcMode::None);
+    off.on_deliver(&meta, &payload);
+    assert_eq!(off.conceal(1), None);
+  }
+}
@@ -26,12 +26,34 @@ impl RollingRate {
     self.prune(now);
   }
 
+  /// Number of recorded samples in the window as of the last update.
+  pub fn len(&self) -> usize {
+    self.history.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.history.is_empty()
+  }
+
+  pub fn window(&self) -> Duration {
+    self.window
+  }
+
   #[cfg(test)]
   pub fn total_in_window(&mut self, now: Instant) -> u64 {
     self.prune(now);
     self.sum
   }
 
+  /// Rate as of the most recent update, without pruning entries that have
+  /// expired since. Lets read-only callers report a recent value.
+  pub fn last_rate_per_sec(&self) -> f64 {
+    if self.window.is_zero() {
+      return 0.0;
+    }
+    self.sum as f64 / self.window.as_secs_f64()
+  }
+
   pub fn rate_per_sec(&mut self, now: Instant) -> f64 {
     self.prune(now);
     if self.window.is_zero() {
@@ -75,6 +97,28 @@ impl RollingMean {
     self.prune(now);
   }
 
+  /// Number of recorded samples in the window as of the last update.
+  pub fn len(&self) -> usize {
+    self.history.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.history.is_empty()
+  }
+
+  pub fn window(&self) -> Duration {
+    self.window
+  }
+
+  /// Mean as of the most recent update, without pruning expired entries.
+  pub fn last_average(&self) -> f64 {
+    if self.history.is_empty() {
+      0.0
+    } else {
+      self.sum / (self.history.len() as f64)
+    }
+  }
+
   pub fn average(&mut self, now: Instant) -> f64 {
     self.prune(now);
     if self.history.is_empty() {
@@ -123,6 +167,25 @@ mod tests {
     assert_eq!(r.rate_per_sec(now), 0.0);
   }
 
+  #[test]
+  fn len_distinguishes_no_data_from_zero_rate() {
+    let base = Instant::now();
+    let mut r = RollingRate::new(Duration::from_secs(5));
+    assert!(r.is_empty());
+    assert_eq!(r.window(), Duration::from_secs(5));
+    r.record(base, 0);
+    assert_eq!(r.len(), 1);
+    assert_eq!(r.rate_per_sec(base), 0.0);
+
+    let mut m = RollingMean::new(Duration::from_secs(5));
+    m.record(base, 1.0);
+    m.record(base, 2.0);
+    assert_eq!(m.len(), 2);
+    let later = base.checked_add(Duration::from_secs(6)).unwrap();
+    m.average(later);
+    assert!(m.is_empty());
+  }
+
   #[test]
   fn byte_rate_example() {
     let base = Instant::now();
@@ -1,37 +1,232 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
+use crate::anti_replay::AntiReplay;
+use crate::histogram::{LatencyHistogram, PayloadHistogram};
+use crate::packet::{DecodeError, DecodeErrorCounts, Meta, TimestampClock};
 use crate::rate::{RollingMean, RollingRate};
-use crate::sync_controller::{DefaultSyncController, SyncController};
-use crate::volume::VolumeMeter;
+use crate::sync_controller::{
+  DefaultSyncController, SyncController, TimeSyncState,
+};
+use crate::transport::Transport;
+use crate::volume::{SmoothedLevel, VolumeMeter};
+
+// Number of recently seen sequence numbers remembered for duplicate
+// detection. Duplicates arriving further apart than this are not detected.
+const REORDER_WINDOW: usize = 64;
+
+/// Largest useful `ReorderBuffer` window: packets held longer than the
+/// duplicate-detection window could be played twice.
+pub const MAX_REORDER_WINDOW: usize = REORDER_WINDOW;
+
+/// What `RecvStats::admit_seq` makes of a data packet's sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqVerdict {
+  /// A new packet to play.
+  Accept,
+  /// The first packet of a restarted sender: anything held for the old
+  /// stream is flushed before it is played.
+  Restarted,
+  /// Refused by replay protection.
+  Replayed,
+  /// Already received.
+  Duplicate,
+}
+
+// Sliding set of recently seen sequence numbers, bounded to REORDER_WINDOW.
+#[derive(Debug, Default)]
+struct SeenSeqs {
+  recent: VecDeque<u64>,
+}
+
+impl SeenSeqs {
+  // Remember `seq`; returns true if it was already in the window.
+  fn check_and_insert(&mut self, seq: u64) -> bool {
+    if self.recent.contains(&seq) {
+      return true;
+    }
+    if self.recent.len() == REORDER_WINDOW {
+      self.recent.pop_front();
+    }
+    self.recent.push_back(seq);
+    false
+  }
+}
+
+// RFC 3550 interarrival jitter: smoothed mean deviation of the spacing
+// between arrivals from the spacing between send timestamps.
+#[derive(Debug, Default)]
+struct JitterEstimator {
+  last: Option<(Instant, u64)>,
+  jitter_ms: f64,
+}
+
+impl JitterEstimator {
+  fn on_arrival(&mut self, arrival: Instant, sent_ts_ms: u64) {
+    if let Some((prev_arrival, prev_sent)) = self.last {
+      let arrival_delta_ms = if arrival >= prev_arrival {
+        arrival.duration_since(prev_arrival).as_secs_f64() * 1000.0
+      } else {
+        -(prev_arrival.duration_since(arrival).as_secs_f64() * 1000.0)
+      };
+      let sent_delta_ms = sent_ts_ms as f64 - prev_sent as f64;
+      let d = arrival_delta_ms - sent_delta_ms;
+      self.jitter_ms += (d.abs() - self.jitter_ms) / 16.0;
+    }
+    self.last = Some((arrival, sent_ts_ms));
+  }
+}
+
+// Latency for monotonic (sender-start relative) timestamps. Only the
+// change in (arrival - timestamp) is observable, so the smallest value seen
+// is taken as the one-way path delay estimated from the ping RTT. The
+// minimum covers the last one to two windows, so a single early outlier or
+// a route change ages out instead of skewing every later packet.
+#[derive(Debug)]
+struct MonotonicLatency {
+  epoch: Instant,
+  // Minimum over the window in progress, which began at `window_start`
+  current_min_ms: Option<f64>,
+  window_start: Instant,
+  // Minimum over the window before it
+  previous_min_ms: Option<f64>,
+}
+
+impl MonotonicLatency {
+  const WINDOW: Duration = Duration::from_secs(30);
+
+  fn new() -> Self {
+    let now = Instant::now();
+    Self {
+      epoch: now,
+      current_min_ms: None,
+      window_start: now,
+      previous_min_ms: None,
+    }
+  }
+
+  // Forget the skew, e.g. once the sender restarted with a new time base
+  fn reset(&mut self) {
+    self.current_min_ms = None;
+    self.previous_min_ms = None;
+  }
+
+  fn latency_ms(&mut self, now: Instant, sent_ts_ms: u64, rtt_ms: f64) -> f64 {
+    if now.saturating_duration_since(self.window_start) >= Self::WINDOW {
+      self.previous_min_ms = self.current_min_ms.take();
+      self.window_start = now;
+    }
+    let arrival_ms =
+      now.saturating_duration_since(self.epoch).as_secs_f64() * 1000.0;
+    let skew = arrival_ms - sent_ts_ms as f64;
+    let current = self.current_min_ms.map_or(skew, |m| m.min(skew));
+    self.current_min_ms = Some(current);
+    let min = self.previous_min_ms.map_or(current, |m| m.min(current));
+    skew - min + rtt_ms / 2.0
+  }
+}
+
+/// One-line description of a stream format with its uncompressed bit rate,
+/// e.g. "F32 48000 Hz 2 ch (3072 kbit/s)".
+pub fn describe_meta(meta: &Meta) -> String {
+  let bits_per_sec = meta.sample_rate.0 as u64 * meta.frame_size() as u64 * 8;
+  format!(
+    "{:?} {} Hz {} ch ({} kbit/s)",
+    meta.sample_format,
+    meta.sample_rate.0,
+    meta.channels,
+    bits_per_sec / 1000
+  )
+}
+
+/// Plain-data copy of one client's `RecvStats`, safe to hand to other
+/// threads. Rolling values are as of the client's most recent packet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecvStatsSnapshot {
+  pub total_bytes_received: u64,
+  pub total_packets_received: u64,
+  pub lost_packets: u64,
+  pub out_of_order_packets: u64,
+  pub duplicate_packets: u64,
+  pub format_changes: u64,
+  pub sender_restarts: u64,
+  pub replayed_packets: u64,
+  pub decode_errors: DecodeErrorCounts,
+  /// Pongs the time-sync estimator left out as unreliable
+  pub rejected_pongs: u64,
+  pub bytes_per_sec: f64,
+  pub avg_latency_ms: f64,
+  pub jitter_ms: f64,
+  pub offset_ms: f64,
+  pub drift_ppm: f64,
+  pub delay_ms: f64,
+}
+
+/// Stats for every connected client, for a receive loop to publish to
+/// readers on other threads, such as a UI or metrics endpoint, e.g. through
+/// an `Arc<Mutex<StatsSnapshot>>` it refreshes every status interval.
+#[derive(Debug, Clone, Default)]
+pub struct StatsSnapshot {
+  pub clients: Vec<(SocketAddr, RecvStatsSnapshot)>,
+  /// Undecodable datagrams from any source, including departed clients
+  pub decode_errors: DecodeErrorCounts,
+  pub updated: Option<Instant>,
+}
 
 // Collects, computes and prints rolling statistics for the receiver.
-pub struct RecvStats {
+// Generic over the time-sync controller so callers can plug in their own.
+pub struct RecvStats<S: SyncController = DefaultSyncController> {
   total_bytes_received: u64,
   total_packets_received: u64,
   lost_packets: u64,
   out_of_order_packets: u64,
+  duplicate_packets: u64,
+  format_changes: u64,
+  sender_restarts: u64,
+  replayed_packets: u64,
+  decode_errors: DecodeErrorCounts,
+  seen: SeenSeqs,
+  payload_hist: PayloadHistogram,
+  // Every packet's latency since the client appeared, for tail reporting
+  latency_hist: LatencyHistogram,
+  jitter: JitterEstimator,
+  mono_latency: MonotonicLatency,
   byte_rate: RollingRate,
+  recv_rate: RollingRate,
+  lost_rate: RollingRate,
   latency_mean: RollingMean,
-  sync: DefaultSyncController,
+  sync: S,
   pub volume: VolumeMeter,
+  level: SmoothedLevel,
+  lr_levels: [SmoothedLevel; 2],
 }
 
-impl RecvStats {
-  pub fn new(
-    window: Duration,
-    volume_window: Duration,
-    sync: DefaultSyncController,
-  ) -> Self {
+impl<S: SyncController> RecvStats<S> {
+  pub fn new(window: Duration, volume_window: Duration, sync: S) -> Self {
     Self {
       total_bytes_received: 0,
       total_packets_received: 0,
       lost_packets: 0,
       out_of_order_packets: 0,
+      duplicate_packets: 0,
+      format_changes: 0,
+      sender_restarts: 0,
+      replayed_packets: 0,
+      decode_errors: DecodeErrorCounts::default(),
+      seen: SeenSeqs::default(),
+      payload_hist: PayloadHistogram::default(),
+      latency_hist: LatencyHistogram::default(),
+      jitter: JitterEstimator::default(),
+      mono_latency: MonotonicLatency::new(),
       byte_rate: RollingRate::new(window),
+      recv_rate: RollingRate::new(window),
+      lost_rate: RollingRate::new(window),
       latency_mean: RollingMean::new(window),
       sync,
       volume: VolumeMeter::new(volume_window),
+      level: SmoothedLevel::default(),
+      lr_levels: Default::default(),
     }
   }
 
@@ -40,22 +235,129 @@ impl RecvStats {
     bytes_received: usize,
     payload_len: usize,
     latency_ms: f64,
+    sent_ts_ms: u64,
     now: Instant,
   ) {
     self.total_bytes_received += bytes_received as u64;
     self.total_packets_received += 1;
     self.byte_rate.record(now, payload_len as u64);
+    self.recv_rate.record(now, 1);
+    self.payload_hist.record(payload_len);
     self.latency_mean.record(now, latency_ms);
+    self.latency_hist.record(latency_ms);
+    self.jitter.on_arrival(now, sent_ts_ms);
   }
 
-  pub fn mark_lost(&mut self, lost_count: u64) {
+  pub fn mark_lost(&mut self, lost_count: u64, now: Instant) {
     self.lost_packets += lost_count;
+    self.lost_rate.record(now, lost_count);
+  }
+
+  /// Percentage of packets lost within the rolling window.
+  pub fn recent_loss_percentage(&mut self, now: Instant) -> f64 {
+    let lost = self.lost_rate.rate_per_sec(now);
+    let recv = self.recv_rate.rate_per_sec(now);
+    if lost + recv > 0.0 {
+      lost / (lost + recv) * 100.0
+    } else {
+      0.0
+    }
+  }
+
+  /// Records a mid-stream change of the sender's stream format and drops
+  /// level smoothing that assumed the old channel layout.
+  pub fn on_format_change(&mut self) {
+    self.format_changes += 1;
+    self.lr_levels = Default::default();
+  }
+
+  pub fn format_changes(&self) -> u64 {
+    self.format_changes
+  }
+
+  /// Returns true, counting a sender restart, if `seq` is further behind
+  /// `expected_seq` than any reordering we tolerate and within a reorder
+  /// window of 0, where a restarted sender counts from. Anything else that
+  /// far behind is a stale or replayed datagram and must not reset the
+  /// duplicate tracking.
+  pub fn detect_restart(&mut self, expected_seq: u64, seq: u64) -> bool {
+    let window = REORDER_WINDOW as u64;
+    let restarted =
+      seq < expected_seq && expected_seq - seq > window && seq <= window;
+    if restarted {
+      self.sender_restarts += 1;
+      // Sequence numbers from before the restart say nothing about repeats
+      self.seen = SeenSeqs::default();
+      self.mono_latency.reset();
+    }
+    restarted
+  }
+
+  pub fn sender_restarts(&self) -> u64 {
+    self.sender_restarts
+  }
+
+  /// Counts a packet dropped by replay protection.
+  pub fn mark_replayed(&mut self) {
+    self.replayed_packets += 1;
+  }
+
+  pub fn replayed_packets(&self) -> u64 {
+    self.replayed_packets
+  }
+
+  /// Counts a datagram from this client that failed to decode.
+  pub fn on_decode_error(&mut self, err: &DecodeError) {
+    self.decode_errors.record(err);
+  }
+
+  pub fn decode_errors(&self) -> DecodeErrorCounts {
+    self.decode_errors
   }
 
   pub fn mark_out_of_order(&mut self) {
     self.out_of_order_packets += 1;
   }
 
+  /// Records `seq` and returns true (counting a duplicate) if it was
+  /// already received within the reordering window.
+  pub fn check_duplicate(&mut self, seq: u64) -> bool {
+    let dup = self.seen.check_and_insert(seq);
+    if dup {
+      self.duplicate_packets += 1;
+    }
+    dup
+  }
+
+  /// Judges data packet `seq` from a client while `expected_seq` is due
+  /// next. A sender restart is recognized before replay protection runs and
+  /// clears its window, so a sender restarting on the same address is not
+  /// refused as a replay of its own earlier packets.
+  pub fn admit_seq(
+    &mut self,
+    anti_replay: Option<&mut AntiReplay>,
+    expected_seq: u64,
+    seq: u64,
+  ) -> SeqVerdict {
+    let restarted = self.detect_restart(expected_seq, seq);
+    if let Some(ar) = anti_replay {
+      if restarted {
+        ar.reset();
+      }
+      if !ar.check_and_set(seq) {
+        self.mark_replayed();
+        return SeqVerdict::Replayed;
+      }
+    }
+    if self.check_duplicate(seq) {
+      SeqVerdict::Duplicate
+    } else if restarted {
+      SeqVerdict::Restarted
+    } else {
+      SeqVerdict::Accept
+    }
+  }
+
   pub fn format_status_line(
     &mut self,
     now: Instant,
@@ -63,37 +365,131 @@ impl RecvStats {
     src_addr: &SocketAddr,
     offset_ms: f64,
     drift_ppm: f64,
+    rtt_ms: f64,
   ) -> String {
     let bytes_per_sec = self.byte_rate.rate_per_sec(now);
     let average_rate_kbs = bytes_per_sec / 1024.0;
     let avg_latency_ms = self.latency_mean.average(now);
-    let db = self.volume.dbfs(now);
+    let db = self.level.dbfs(&mut self.volume, now);
+    // Show left/right separately for stereo so a dead channel stands out
+    let vol = match self.volume.per_channel_dbfs(now).as_slice() {
+      &[l, r] => {
+        let l = self.lr_levels[0].update(now, l);
+        let r = self.lr_levels[1].update(now, r);
+        format!("L/R: {:.1}/{:.1} dBFS", l, r)
+      }
+      _ => format!("Vol10s: {:>6.1} dBFS", db),
+    };
     let total_expected_packets = expected_sequence;
     let loss_percentage = if total_expected_packets > 0 {
       (self.lost_packets as f64 / total_expected_packets as f64) * 100.0
     } else {
       0.0
     };
+    let recent_loss = self.recent_loss_percentage(now);
     let total_mb = self.total_bytes_received as f64 / (1024.0 * 1024.0);
+    // Only shown once something failed to decode
+    let errors = if self.decode_errors.total() > 0 {
+      format!("| Errs: {}   ", self.decode_errors)
+    } else {
+      String::new()
+    };
 
     format!(
-      "\r[{}] Recv: {} | Lost: {} ({:.2}%) | Late: {} | Total: {:.2} MB | \
-       Avg10s: {:.2} KB/s | Lat10s: {:.2} ms | Vol10s: {:>6.1} dBFS | Off: \
-       {:+.2} ms | Drift: {:+.1} ppm   ",
+      "\r[{}] Recv: {} | Lost: {} ({:.2}%) | Loss10s: {:.2}% | Late: {} | \
+       Dup: {} | Restarts: {} | Total: {:.2} MB | Avg10s: {:.2} KB/s | \
+       Lat10s: {:.2} ms | Jit: {:.2} ms | {} | Off: {:+.2} ms | Drift: {:+.1} \
+       ppm | RTT: {:.2} ms | BadPongs: {}   {}",
       src_addr,
       self.total_packets_received,
       self.lost_packets,
       loss_percentage,
+      recent_loss,
       self.out_of_order_packets,
+      self.duplicate_packets,
+      self.sender_restarts,
       total_mb,
       average_rate_kbs,
       avg_latency_ms,
-      db,
+      self.jitter.jitter_ms,
+      vol,
       offset_ms,
       drift_ppm,
+      rtt_ms,
+      self.sync.rejected_pongs(),
+      errors,
+    )
+  }
+
+  /// Compact alternative to `format_status_line` for the receiver's
+  /// `--info` mode: what the client sends rather than how well it arrives.
+  pub fn format_info_line(
+    &mut self,
+    now: Instant,
+    src_addr: &SocketAddr,
+    meta: Option<Meta>,
+  ) -> String {
+    let format =
+      meta.map_or_else(|| "format unknown".into(), |m| describe_meta(&m));
+    format!(
+      "[{}] {} | Wire: {:.0} kbit/s | Off: {:+.2} ms   ",
+      src_addr,
+      format,
+      self.byte_rate.rate_per_sec(now) * 8.0 / 1000.0,
+      self.sync.offset_ms(),
+    )
+  }
+
+  pub fn snapshot(&self) -> RecvStatsSnapshot {
+    RecvStatsSnapshot {
+      total_bytes_received: self.total_bytes_received,
+      total_packets_received: self.total_packets_received,
+      lost_packets: self.lost_packets,
+      out_of_order_packets: self.out_of_order_packets,
+      duplicate_packets: self.duplicate_packets,
+      format_changes: self.format_changes,
+      sender_restarts: self.sender_restarts,
+      replayed_packets: self.replayed_packets,
+      decode_errors: self.decode_errors,
+      rejected_pongs: self.sync.rejected_pongs(),
+      bytes_per_sec: self.byte_rate.last_rate_per_sec(),
+      avg_latency_ms: self.latency_mean.last_average(),
+      jitter_ms: self.jitter.jitter_ms,
+      offset_ms: self.sync.offset_ms(),
+      drift_ppm: self.sync.drift_ppm(),
+      delay_ms: self.sync.delay_ms(),
+    }
+  }
+
+  pub fn jitter_ms(&self) -> f64 {
+    self.jitter.jitter_ms
+  }
+
+  pub fn latency_histogram(&self) -> &LatencyHistogram {
+    &self.latency_hist
+  }
+
+  /// Latency histogram of every packet so far as printable text: a summary
+  /// line with the count and tail percentiles, then one line per bucket.
+  pub fn dump_histogram(&self, src_addr: &SocketAddr) -> String {
+    let h = &self.latency_hist;
+    let pct = |p| h.percentile(p).unwrap_or(0.0);
+    format!(
+      "Latency histogram for {}: {} packets, p50 <{:.3} ms, p99 <{:.3} ms, \
+       p99.9 <{:.3} ms\n{}",
+      src_addr,
+      h.count(),
+      pct(50.0),
+      pct(99.0),
+      pct(99.9),
+      h
     )
   }
 
+  pub fn payload_hist(&self) -> PayloadHistogram {
+    self.payload_hist
+  }
+
   // Lightweight wrappers to access sync controller from main
   pub fn register_sender(&mut self, addr: SocketAddr) {
     self.sync.register_sender(addr);
@@ -101,12 +497,26 @@ impl RecvStats {
   pub fn on_pong(&mut self, t0_ms: u64, t1_ms: u64, t2_ms: u64) {
     self.sync.on_pong(t0_ms, t1_ms, t2_ms);
   }
-  pub fn compute_latency_ms(&self, sent_ts_ms: u64) -> f64 {
-    self.sync.compute_latency_ms(sent_ts_ms)
+  pub fn compute_latency_ms(
+    &mut self,
+    sent_ts_ms: u64,
+    clock: TimestampClock,
+  ) -> f64 {
+    match clock {
+      TimestampClock::Wall => self.sync.compute_latency_ms(sent_ts_ms),
+      TimestampClock::Monotonic => self.mono_latency.latency_ms(
+        Instant::now(),
+        sent_ts_ms,
+        self.sync.delay_ms(),
+      ),
+    }
   }
-  pub fn maybe_ping(&mut self, sock: &UdpSocket) {
+  pub fn maybe_ping(&mut self, sock: &dyn Transport) {
     self.sync.maybe_send_ping(sock)
   }
+  pub fn request_ping_now(&mut self) {
+    self.sync.request_ping_now();
+  }
 
   pub fn offset_ms(&self) -> f64 {
     self.sync.offset_ms()
@@ -114,4 +524,387 @@ impl RecvStats {
   pub fn drift_ppm(&self) -> f64 {
     self.sync.drift_ppm()
   }
+  pub fn delay_ms(&self) -> f64 {
+    self.sync.delay_ms()
+  }
+  pub fn rejected_pongs(&self) -> u64 {
+    self.sync.rejected_pongs()
+  }
+
+  /// Resample ratio (input frames per output frame) that tracks the
+  /// sender's clock, see `TimeSyncState::resample_ratio`. A playback
+  /// resampler would read this from the receive loop after each pong and
+  /// glide towards it, rather than jumping, since the estimate is noisy.
+  pub fn resample_ratio(&self) -> f64 {
+    TimeSyncState {
+      drift_ppm: self.drift_ppm(),
+      ..Default::default()
+    }
+    .resample_ratio()
+  }
+  pub fn seed_sync_state(&mut self, state: TimeSyncState) {
+    self.sync.seed_state(state);
+  }
+  pub fn converged_sync_state(&self) -> Option<TimeSyncState> {
+    self.sync.converged_state()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sync_controller::SyncAlgorithm;
+
+  #[test]
+  fn snapshot_copies_counters_and_rates() {
+    let base = Instant::now();
+    let sync = DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1);
+    let mut s =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    s.on_packet(110, 100, 5.0, 1_000, base);
+    s.on_packet(110, 100, 7.0, 1_005, base);
+    s.mark_lost(3, base);
+    s.check_duplicate(1);
+    s.check_duplicate(1);
+    s.on_format_change();
+
+    let snap = s.snapshot();
+    assert_eq!(snap.total_packets_received, 2);
+    assert_eq!(snap.total_bytes_received, 220);
+    assert_eq!(snap.lost_packets, 3);
+    assert_eq!(snap.duplicate_packets, 1);
+    assert_eq!(snap.format_changes, 1);
+    assert!((snap.bytes_per_sec - 20.0).abs() < 1e-9);
+    assert!((snap.avg_latency_ms - 6.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn injected_packets_drive_stats_and_sync() {
+    use crate::clock::{Clock, MockClock};
+    use crate::packet::{
+      Message, SampleFormat, SyncMessage, decode_message, encode_packet,
+      respond_to_ping_with,
+    };
+    use crate::transport::MemoryNetwork;
+
+    let net = MemoryNetwork::new();
+    let rx = net.bind("10.0.0.1:4000".parse().unwrap()).unwrap();
+    let tx = net.bind("10.0.0.2:5000".parse().unwrap()).unwrap();
+    for t in [&rx, &tx] {
+      t.set_recv_timeout(Some(Duration::ZERO)).unwrap();
+    }
+    let recv_clock = MockClock::new(1_000_000);
+    // Sender clock 100 ms ahead; every hop below takes 2 ms
+    let send_clock = MockClock::new(1_000_100);
+    let hop = |ms| {
+      recv_clock.advance(ms);
+      send_clock.advance(ms);
+    };
+    let mut sync =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1);
+    sync.set_clock(recv_clock.clone());
+    let mut s =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    s.register_sender(tx.local_addr());
+
+    let mut buf = [0u8; 128];
+    s.maybe_ping(&rx);
+    let (n, from) = tx.recv_packet_from(&mut buf).unwrap();
+    let Ok(Message::Sync(SyncMessage::Ping { t0_ms })) =
+      decode_message(&buf[..n])
+    else {
+      panic!("expected ping");
+    };
+    hop(2);
+    respond_to_ping_with(
+      &send_clock,
+      &tx,
+      from,
+      t0_ms,
+      send_clock.now_ms(),
+      false,
+    );
+    hop(2);
+    let (n, _) = rx.recv_packet_from(&mut buf).unwrap();
+    let Ok(Message::Sync(SyncMessage::Pong {
+      t0_ms,
+      t1_ms,
+      t2_ms,
+      ..
+    })) = decode_message(&buf[..n])
+    else {
+      panic!("expected pong");
+    };
+    s.on_pong(t0_ms, t1_ms, t2_ms);
+    assert_eq!((s.offset_ms(), s.delay_ms()), (100.0, 4.0));
+
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let base = Instant::now();
+    for seq in 0..3u64 {
+      let pkt = encode_packet(seq, &[0; 8], meta, send_clock.now_ms());
+      tx.send_packet_to(&pkt, rx.local_addr()).unwrap();
+      hop(2);
+      let (n, _) = rx.recv_packet_from(&mut buf).unwrap();
+      let Ok(Message::Data(d)) = decode_message(&buf[..n]) else {
+        panic!("expected data");
+      };
+      let latency = s.compute_latency_ms(d.timestamp_ms, d.clock);
+      let now = base + Duration::from_millis(seq * 10);
+      s.on_packet(n, d.payload.len(), latency, d.timestamp_ms, now);
+      hop(8);
+    }
+    assert!(rx.recv_packet_from(&mut buf).is_err());
+
+    let snap = s.snapshot();
+    assert_eq!(snap.total_packets_received, 3);
+    assert_eq!(snap.total_bytes_received, 3 * 36);
+    assert_eq!(snap.avg_latency_ms, 2.0);
+  }
+
+  #[test]
+  fn info_line_shows_format_and_wire_rate() {
+    let base = Instant::now();
+    let sync = DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1);
+    let mut s =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+    assert!(
+      s.format_info_line(base, &addr, None)
+        .contains("format unknown")
+    );
+
+    // 25 000 bytes over the 10 s window
+    s.on_packet(12_500, 12_480, 0.0, 1_000, base);
+    s.on_packet(12_500, 12_480, 0.0, 1_010, base + Duration::from_secs(1));
+    let meta = Meta::new(2, 48_000, crate::packet::SampleFormat::I16).unwrap();
+    let line =
+      s.format_info_line(base + Duration::from_secs(1), &addr, Some(meta));
+    assert!(line.starts_with("[127.0.0.1:9] I16 48000 Hz 2 ch (1536 kbit/s)"));
+    assert!(line.contains("Wire: 20 kbit/s"), "{line}");
+  }
+
+  #[test]
+  fn jitter_zero_for_even_spacing_then_grows() {
+    let base = Instant::now();
+    let mut j = JitterEstimator::default();
+    for i in 0..20u64 {
+      let t = base.checked_add(Duration::from_millis(i * 5)).unwrap();
+      j.on_arrival(t, 1_000 + i * 5);
+    }
+    assert!(j.jitter_ms.abs() < 1e-9, "jitter was {}", j.jitter_ms);
+
+    // Every other packet arrives 2 ms late: |D| = 2 ms on every packet
+    for i in 20..200u64 {
+      let skew = if i.is_multiple_of(2) { 2 } else { 0 };
+      let t = base
+        .checked_add(Duration::from_millis(i * 5 + skew))
+        .unwrap();
+      j.on_arrival(t, 1_000 + i * 5);
+    }
+    assert!(
+      (j.jitter_ms - 2.0).abs() < 0.01,
+      "jitter was {}",
+      j.jitter_ms
+    );
+  }
+
+  #[test]
+  fn monotonic_latency_is_relative_to_fastest_packet() {
+    let mut m = MonotonicLatency::new();
+    let epoch = m.epoch;
+    let at = |ms| epoch.checked_add(Duration::from_millis(ms)).unwrap();
+    // Sender started 500 ms before the receiver: constant skew of 500
+    assert_eq!(m.latency_ms(at(100), 600, 4.0), 2.0);
+    assert_eq!(m.latency_ms(at(110), 610, 4.0), 2.0);
+    // This packet was queued 15 ms longer than the fastest one
+    assert_eq!(m.latency_ms(at(135), 620, 4.0), 17.0);
+  }
+
+  #[test]
+  fn monotonic_latency_forgets_an_early_outlier() {
+    let window = MonotonicLatency::WINDOW.as_millis() as u64;
+    let mut m = MonotonicLatency::new();
+    let epoch = m.epoch;
+    let at = |ms| epoch.checked_add(Duration::from_millis(ms)).unwrap();
+    // One packet arrives 100 ms faster than all the others
+    m.latency_ms(at(100), 600, 4.0);
+    m.latency_ms(at(200), 800, 4.0);
+    assert_eq!(m.latency_ms(at(300), 800, 4.0), 102.0);
+    // Still remembered through the next window, gone after it
+    assert_eq!(m.latency_ms(at(window + 300), window + 800, 4.0), 102.0);
+    assert_eq!(
+      m.latency_ms(at(2 * window + 300), 2 * window + 800, 4.0),
+      2.0
+    );
+
+    // A restart drops the skew at once
+    m.latency_ms(at(2 * window + 400), 2 * window + 900, 4.0);
+    m.reset();
+    assert_eq!(m.latency_ms(at(2 * window + 500), 40, 4.0), 2.0);
+  }
+
+  // Controller that only records the pongs it is given
+  #[derive(Default)]
+  struct RecordingSync {
+    pongs: Vec<(u64, u64, u64)>,
+    drift_ppm: f64,
+  }
+
+  impl SyncController for RecordingSync {
+    fn register_sender(&mut self, _: SocketAddr) {}
+    fn on_pong(&mut self, t0_ms: u64, t1_ms: u64, t2_ms: u64) {
+      self.pongs.push((t0_ms, t1_ms, t2_ms));
+    }
+    fn compute_latency_ms(&self, _: u64) -> f64 {
+      0.0
+    }
+    fn offset_ms(&self) -> f64 {
+      0.0
+    }
+    fn drift_ppm(&self) -> f64 {
+      self.drift_ppm
+    }
+    fn delay_ms(&self) -> f64 {
+      0.0
+    }
+    fn maybe_send_ping(&mut self, _: &dyn Transport) {}
+  }
+
+  #[test]
+  fn accepts_custom_sync_controller() {
+    let mut stats = RecvStats::new(
+      Duration::from_secs(10),
+      Duration::from_secs(1),
+      RecordingSync::default(),
+    );
+    stats.on_pong(1, 2, 3);
+    assert_eq!(stats.sync.pongs, vec![(1, 2, 3)]);
+    assert!(stats.converged_sync_state().is_none());
+  }
+
+  #[test]
+  fn resample_ratio_follows_drift() {
+    let mut stats = RecvStats::new(
+      Duration::from_secs(10),
+      Duration::from_secs(1),
+      RecordingSync::default(),
+    );
+    assert_eq!(stats.resample_ratio(), 1.0);
+    stats.sync.drift_ppm = 100.0;
+    assert!((stats.resample_ratio() - 1.0001).abs() < 1e-12);
+  }
+
+  #[test]
+  fn recent_loss_forgets_old_bursts() {
+    let base = Instant::now();
+    let sync =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let mut stats =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    stats.on_packet(100, 76, 0.0, 0, base);
+    stats.mark_lost(3, base);
+    let t = base.checked_add(Duration::from_secs(1)).unwrap();
+    assert!((stats.recent_loss_percentage(t) - 75.0).abs() < 1e-9);
+
+    let later = base.checked_add(Duration::from_secs(20)).unwrap();
+    stats.on_packet(100, 76, 0.0, 20_000, later);
+    assert_eq!(stats.recent_loss_percentage(later), 0.0);
+  }
+
+  #[test]
+  fn restart_detected_only_beyond_reorder_window() {
+    let sync =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let mut stats =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    let window = REORDER_WINDOW as u64;
+    // Late packets within the window are reordering, not restarts
+    assert!(!stats.detect_restart(1_000, 999));
+    assert!(!stats.detect_restart(1_000, 1_000 - window));
+    assert!(!stats.detect_restart(10, 0));
+    assert!(!stats.detect_restart(1_000, 1_000));
+    assert_eq!(stats.sender_restarts(), 0);
+
+    stats.check_duplicate(5_000);
+    assert!(stats.detect_restart(5_001, 0));
+    assert_eq!(stats.sender_restarts(), 1);
+    // The old sequence numbers were forgotten
+    assert!(!stats.check_duplicate(5_000));
+    assert_eq!(stats.snapshot().sender_restarts, 1);
+  }
+
+  #[test]
+  fn single_stale_packet_is_not_a_restart() {
+    let sync =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let mut stats =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    stats.check_duplicate(5_000);
+    assert!(!stats.detect_restart(5_001, 3_000));
+    assert_eq!(stats.sender_restarts(), 0);
+    // Duplicate tracking survives the stale packet
+    assert!(stats.check_duplicate(5_000));
+  }
+
+  #[test]
+  fn sender_restarting_under_replay_protection_is_played_again() {
+    use crate::packet::SampleFormat;
+    use crate::reorder::{ReorderBuffer, ReorderEvent};
+
+    let sync =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let mut stats =
+      RecvStats::new(Duration::from_secs(10), Duration::from_secs(1), sync);
+    let mut anti_replay = Some(AntiReplay::new(1_024));
+    let mut reorder = ReorderBuffer::new(8);
+    let meta = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let mut played = Vec::new();
+    // What the receiver does with one data packet from the same address
+    let mut receive = |seq: u64| {
+      let verdict =
+        stats.admit_seq(anti_replay.as_mut(), reorder.next_seq(), seq);
+      let mut play = |ev: ReorderEvent<'_>| -> Result<(), ()> {
+        if let ReorderEvent::Deliver { seq, .. } = ev {
+          played.push(seq);
+        }
+        Ok(())
+      };
+      if verdict == SeqVerdict::Restarted {
+        reorder.flush(&mut play).unwrap();
+        reorder.reset();
+      }
+      if matches!(verdict, SeqVerdict::Accept | SeqVerdict::Restarted) {
+        reorder.push(seq, meta, &[], &mut play).unwrap();
+      }
+      verdict
+    };
+
+    for seq in 0..200 {
+      assert_eq!(receive(seq), SeqVerdict::Accept);
+    }
+    assert_eq!(receive(150), SeqVerdict::Replayed);
+    // Restarted on the same address: counting from 0 again
+    assert_eq!(receive(0), SeqVerdict::Restarted);
+    for seq in 1..5 {
+      assert_eq!(receive(seq), SeqVerdict::Accept);
+    }
+    assert_eq!(receive(3), SeqVerdict::Replayed);
+    let expected: Vec<u64> = (0..200).chain(0..5).collect();
+    assert_eq!(played, expected);
+    assert_eq!(stats.sender_restarts(), 1);
+    assert_eq!(stats.replayed_packets(), 2);
+  }
+
+  #[test]
+  fn seen_seqs_detects_repeats_within_window() {
+    let mut seen = SeenSeqs::default();
+    assert!(!seen.check_and_insert(1));
+    assert!(seen.check_and_insert(1));
+    for seq in 2..(2 + REORDER_WINDOW as u64) {
+      assert!(!seen.check_and_insert(seq));
+    }
+    // 1 has been evicted from the window
+    assert!(!seen.check_and_insert(1));
+    assert_eq!(seen.recent.len(), REORDER_WINDOW);
+  }
 }
@@ -0,0 +1,183 @@
+// Reordering buffer for the receiver: packets that arrive ahead of a gap
+// are held, up to a window, so a late packet can still fill the gap before
+// anything is counted as lost.
+
+use std::collections::BTreeMap;
+
+use crate::packet::Meta;
+
+/// Outcome for received packets, reported in stream order.
+#[derive(Debug, PartialEq)]
+pub enum ReorderEvent<'a> {
+  /// Play this payload next.
+  Deliver {
+    seq: u64,
+    meta: Meta,
+    payload: &'a [u8],
+  },
+  /// This many packets were given up on.
+  Lost(u64),
+  /// A packet arrived after its slot had been played or given up on.
+  Late,
+}
+
+/// Holds up to `window` packets waiting on a gap. A window of 0 releases
+/// every packet immediately, counting gaps as lost straight away.
+#[derive(Debug)]
+pub struct ReorderBuffer {
+  window: usize,
+  next: Option<u64>,
+  held: BTreeMap<u64, (Meta, Vec<u8>)>,
+}
+
+impl ReorderBuffer {
+  pub fn new(window: usize) -> Self {
+    Self {
+      window,
+      next: None,
+      held: BTreeMap::new(),
+    }
+  }
+
+  /// Sequence number expected next (0 before the first packet).
+  pub fn next_seq(&self) -> u64 {
+    self.next.unwrap_or(0)
+  }
+
+  /// Number of packets waiting on a gap.
+  pub fn held(&self) -> usize {
+    self.held.len()
+  }
+
+  /// Accept packet `seq`, calling `on_event` for everything this releases.
+  /// The first packet seen sets the starting sequence number.
+  pub fn push<E>(
+    &mut self,
+    seq: u64,
+    meta: Meta,
+    payload: &[u8],
+    on_event: &mut impl FnMut(ReorderEvent<'_>) -> Result<(), E>,
+  ) -> Result<(), E> {
+    let next = *self.next.get_or_insert(seq);
+    if seq < next {
+      return on_event(ReorderEvent::Late);
+    }
+    if seq == next {
+      on_event(ReorderEvent::Deliver { seq, meta, payload })?;
+      self.next = Some(seq.wrapping_add(1));
+      return self.release_ready(on_event);
+    }
+    self
+      .held
+      .entry(seq)
+      .or_insert_with(|| (meta, payload.to_vec()));
+    while self.held.len() > self.window {
+      self.skip_gap(on_event)?;
+    }
+    Ok(())
+  }
+
+  /// Give up on every gap and release all held packets, e.g. when the
+  /// stream pauses or ends.
+  pub fn flush<E>(
+    &mut self,
+    on_event: &mut impl FnMut(ReorderEvent<'_>) -> Result<(), E>,
+  ) -> Result<(), E> {
+    while !self.held.is_empty() {
+      self.skip_gap(on_event)?;
+    }
+    Ok(())
+  }
+
+  /// Forget the stream position and anything held, e.g. after the sender
+  /// restarted its sequence numbers.
+  pub fn reset(&mut self) {
+    self.next = None;
+    self.held.clear();
+  }
+
+  // Count the packets missing before the oldest held one as lost, then
+  // release whatever is now contiguous
+  fn skip_gap<E>(
+    &mut self,
+    on_event: &mut impl FnMut(ReorderEvent<'_>) -> Result<(), E>,
+  ) -> Result<(), E> {
+    let Some(&first) = self.held.keys().next() else {
+      return Ok(());
+    };
+    let next = self.next_seq();
+    if first > next {
+      on_event(ReorderEvent::Lost(first - next))?;
+    }
+    self.next = Some(first);
+    self.release_ready(on_event)
+  }
+
+  fn release_ready<E>(
+    &mut self,
+    on_event: &mut impl FnMut(ReorderEvent<'_>) -> Result<(), E>,
+  ) -> Result<(), E> {
+    while let Some(entry) = self.held.first_entry() {
+      if Some(*entry.key()) != self.next {
+        break;
+      }
+      let (seq, (meta, payload)) = entry.remove_entry();
+      on_event(ReorderEvent::Deliver {
+        seq,
+        meta,
+        payload: &payload,
+      })?;
+      self.next = Some(seq.wrapping_add(1));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::SampleFormat;
+
+  // Feed `seqs` and describe the events as (delivered seq | -lost | "late")
+  fn run(window: usize, seqs: &[u64], flush: bool) -> Vec<i64> {
+    let meta = Meta::new(1, 48_000, SampleFormat::I16).unwrap();
+    let mut rb = ReorderBuffer::new(window);
+    let mut out = Vec::new();
+    let mut record = |ev: ReorderEvent<'_>| -> Result<(), ()> {
+      out.push(match ev {
+        ReorderEvent::Deliver { seq, payload, .. } => {
+          assert_eq!(payload, seq.to_be_bytes());
+          seq as i64
+        }
+        ReorderEvent::Lost(n) => -(n as i64),
+        ReorderEvent::Late => i64::MIN,
+      });
+      Ok(())
+    };
+    for &seq in seqs {
+      rb.push(seq, meta, &seq.to_be_bytes(), &mut record).unwrap();
+    }
+    if flush {
+      rb.flush(&mut record).unwrap();
+    }
+    out
+  }
+
+  #[test]
+  fn swap_within_window_is_not_loss() {
+    assert_eq!(run(4, &[10, 12, 11, 13], false), [10, 11, 12, 13]);
+    // Without a window the same swap counts a loss and a late packet
+    assert_eq!(run(0, &[10, 12, 11, 13], false), [10, -1, 12, i64::MIN, 13]);
+  }
+
+  #[test]
+  fn gap_beyond_window_is_lost_then_late() {
+    assert_eq!(run(2, &[0, 2, 3, 4, 1], false), [0, -1, 2, 3, 4, i64::MIN]);
+  }
+
+  #[test]
+  fn flush_releases_held_packets() {
+    assert_eq!(run(8, &[0, 3, 5], false), [0]);
+    assert_eq!(run(8, &[0, 3, 5], true), [0, -2, 3, -1, 5]);
+  }
+}
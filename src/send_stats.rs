@@ -1,7 +1,281 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::histogram::PayloadHistogram;
+use crate::packet::{DATA_HEADER_LEN, Meta};
+
+// Reporting interval for persistent send failures, doubling up to the max
+const SEND_FAILURE_LOG_INITIAL: Duration = Duration::from_secs(1);
+const SEND_FAILURE_LOG_MAX: Duration = Duration::from_secs(60);
+
+/// Sender statistics. Byte and packet figures count the stream once, not
+/// once per destination.
 #[derive(Debug, Clone, Copy)]
 pub struct SendStats {
   pub total_bytes_sent: u64,
   pub average_rate_bps: f64,
   pub average_packets_per_sec: f64,
   pub average_frame_duration_ms: f64,
+  pub payload_hist: PayloadHistogram,
+  /// Peak sample level over the volume meter's window.
+  pub peak_dbfs: f64,
+  /// Audio packets replaced by empty packets during sustained silence.
+  pub silent_packets_suppressed: u64,
+  /// Wire bytes not sent because of silence suppression.
+  pub bytes_saved: u64,
+  /// Whether the most recent send to any destination failed.
+  pub link_down: bool,
+  /// Packets dropped because a destination's socket buffer was full.
+  pub backpressured_packets: u64,
+}
+
+/// Tracks consecutive send failures and logs them with exponential backoff
+/// so a dead link is reported without a message per packet. A full socket
+/// buffer (`WouldBlock`) is backpressure, not a dead link: it is counted
+/// apart and leaves the link state alone.
+#[derive(Debug)]
+pub struct LinkMonitor {
+  failures: u64,
+  backoff: Duration,
+  next_log: Option<Instant>,
+  backpressured: u64,
+}
+
+impl Default for LinkMonitor {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl LinkMonitor {
+  pub fn new() -> Self {
+    Self {
+      failures: 0,
+      backoff: SEND_FAILURE_LOG_INITIAL,
+      next_log: None,
+      backpressured: 0,
+    }
+  }
+
+  /// Whether the most recent send that got an answer from the OS failed.
+  pub fn is_down(&self) -> bool {
+    self.failures > 0
+  }
+
+  /// Packets dropped so far because the socket buffer was full.
+  pub fn backpressured(&self) -> u64 {
+    self.backpressured
+  }
+
+  pub fn on_send(
+    &mut self,
+    result: &io::Result<usize>,
+    dest: SocketAddr,
+    now: Instant,
+  ) {
+    match result {
+      Ok(_) => {
+        if self.failures > 0 {
+          info!(
+            "send to {dest} recovered after {} failed packets",
+            self.failures
+          );
+        }
+        self.failures = 0;
+        self.backoff = SEND_FAILURE_LOG_INITIAL;
+        self.next_log = None;
+      }
+      Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+        self.backpressured += 1;
+      }
+      Err(e) => {
+        self.failures += 1;
+        if self.failures == 1 {
+          warn!("send to {dest} failed: {e}");
+        } else if self.next_log.is_some_and(|t| now >= t) {
+          warn!(
+            "send to {dest} still failing ({} packets): {e}",
+            self.failures
+          );
+          self.backoff = (self.backoff * 2).min(SEND_FAILURE_LOG_MAX);
+        } else {
+          return;
+        }
+        self.next_log = Some(now + self.backoff);
+      }
+    }
+  }
+}
+
+/// Collapses long runs of silence into empty packets and counts what that
+/// saves. Silence is suppressed once more than `threshold` samples of it
+/// arrived in a row.
+#[derive(Debug, Clone, Copy)]
+pub struct SilenceSuppressor {
+  threshold: u64,
+  silent_samples: u64,
+  packets_suppressed: u64,
+  bytes_saved: u64,
+}
+
+impl SilenceSuppressor {
+  pub fn new(threshold: u64) -> Self {
+    Self {
+      threshold,
+      silent_samples: 0,
+      packets_suppressed: 0,
+      bytes_saved: 0,
+    }
+  }
+
+  /// Account for a chunk of `samples` samples totalling `len` bytes, which
+  /// would go out in packets of at most `max_payload` bytes. Returns true
+  /// when one empty packet should be sent in place of all of them.
+  pub fn suppress(
+    &mut self,
+    silent: bool,
+    samples: u64,
+    len: usize,
+    max_payload: usize,
+  ) -> bool {
+    self.silent_samples = if silent {
+      self.silent_samples.saturating_add(samples)
+    } else {
+      0
+    };
+    if self.silent_samples <= self.threshold {
+      return false;
+    }
+    let packets = len.div_ceil(max_payload.max(1)).max(1) as u64;
+    let full_bytes = len as u64 + packets * DATA_HEADER_LEN as u64;
+    self.packets_suppressed += packets;
+    self.bytes_saved += full_bytes.saturating_sub(DATA_HEADER_LEN as u64);
+    true
+  }
+
+  /// Audio packets replaced by empty ones so far.
+  pub fn packets_suppressed(&self) -> u64 {
+    self.packets_suppressed
+  }
+
+  /// Bytes those packets would have taken, less the empty packets sent.
+  pub fn bytes_saved(&self) -> u64 {
+    self.bytes_saved
+  }
+}
+
+/// What is left of `--duration`, counted in captured audio rather than wall
+/// time so a recording holds exactly the requested length.
+#[derive(Debug, Clone, Copy)]
+pub struct DurationBudget {
+  remaining_bytes: u64,
+}
+
+impl DurationBudget {
+  /// `secs` of audio in whole frames of `meta`; at least one frame.
+  pub fn new(secs: f64, meta: &Meta) -> Self {
+    let frames = (secs * f64::from(meta.sample_rate.0)).round() as u64;
+    Self {
+      remaining_bytes: frames.max(1) * meta.frame_size() as u64,
+    }
+  }
+
+  /// Count a chunk of `len` bytes against the budget and return how many
+  /// of them still fit.
+  pub fn take(&mut self, len: usize) -> usize {
+    let take = (len as u64).min(self.remaining_bytes);
+    self.remaining_bytes -= take;
+    take as usize
+  }
+
+  pub fn is_spent(&self) -> bool {
+    self.remaining_bytes == 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::SampleFormat;
+
+  #[test]
+  fn full_socket_buffer_is_backpressure_not_link_down() {
+    let dest: SocketAddr = "127.0.0.1:9".parse().unwrap();
+    let now = Instant::now();
+    let mut link = LinkMonitor::new();
+    let full = || Err(io::Error::from(io::ErrorKind::WouldBlock));
+    let refused = || Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+
+    for _ in 0..3 {
+      link.on_send(&full(), dest, now);
+    }
+    assert!(!link.is_down());
+    assert_eq!(link.backpressured(), 3);
+
+    // A hard error takes the link down, and backpressure does not bring it
+    // back up; only a delivered packet does
+    link.on_send(&refused(), dest, now);
+    link.on_send(&full(), dest, now);
+    assert!(link.is_down());
+    link.on_send(&Ok(100), dest, now);
+    assert!(!link.is_down());
+    assert_eq!(link.backpressured(), 4);
+  }
+
+  #[test]
+  fn repeated_failures_back_off_the_log_interval() {
+    let dest: SocketAddr = "127.0.0.1:9".parse().unwrap();
+    let start = Instant::now();
+    let mut link = LinkMonitor::new();
+    let refused = || Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+
+    link.on_send(&refused(), dest, start);
+    assert_eq!(link.next_log, Some(start + SEND_FAILURE_LOG_INITIAL));
+    let later = start + SEND_FAILURE_LOG_INITIAL;
+    link.on_send(&refused(), dest, later);
+    assert_eq!(link.next_log, Some(later + 2 * SEND_FAILURE_LOG_INITIAL));
+    assert_eq!(link.failures, 2);
+  }
+
+  #[test]
+  fn duration_budget_counts_whole_frames() {
+    let meta = Meta::new(2, 48_000, SampleFormat::I16).unwrap();
+    let mut budget = DurationBudget::new(0.01, &meta);
+    // 480 frames of 4 bytes, handed out across chunk boundaries
+    assert_eq!(budget.take(1_000), 1_000);
+    assert!(!budget.is_spent());
+    assert_eq!(budget.take(1_000), 920);
+    assert!(budget.is_spent());
+    assert_eq!(budget.take(1_000), 0);
+
+    let mut tiny = DurationBudget::new(1e-9, &meta);
+    assert_eq!(tiny.take(64), 4);
+  }
+
+  #[test]
+  fn silence_is_suppressed_after_the_threshold_and_counted() {
+    let mut silence = SilenceSuppressor::new(10);
+    // 4 samples per chunk: the third silent chunk crosses 10
+    assert!(!silence.suppress(true, 4, 8, 1_024));
+    assert!(!silence.suppress(true, 4, 8, 1_024));
+    assert!(silence.suppress(true, 4, 8, 1_024));
+    assert_eq!(silence.packets_suppressed(), 1);
+    assert_eq!(silence.bytes_saved(), 8);
+
+    // A chunk needing 3 packets collapses into one empty packet
+    assert!(silence.suppress(true, 1_200, 2_400, 1_000));
+    assert_eq!(silence.packets_suppressed(), 4);
+    assert_eq!(
+      silence.bytes_saved(),
+      8 + 2_400 + 2 * DATA_HEADER_LEN as u64
+    );
+
+    // Sound resets the run
+    assert!(!silence.suppress(false, 4, 8, 1_024));
+    assert!(!silence.suppress(true, 4, 8, 1_024));
+    assert_eq!(silence.packets_suppressed(), 4);
+  }
 }
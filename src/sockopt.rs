@@ -0,0 +1,293 @@
+// Socket options that std does not expose, set through socket2 where it
+// covers them. The raw setsockopt calls left are only implemented on unix;
+// other platforms report `Unsupported` so callers can warn and carry on.
+
+use std::io;
+use std::net::{SocketAddrV6, UdpSocket};
+
+use socket2::{Domain, Protocol, SockAddr, SockRef, Socket, Type};
+
+/// Mark outgoing packets with `dscp` (0..=63), e.g. 46 for Expedited
+/// Forwarding. Sets IP_TOS, or IPV6_TCLASS on IPv6 sockets. Some OSes
+/// ignore or override the mark without reporting an error.
+pub fn set_dscp(socket: &UdpSocket, dscp: u8) -> io::Result<()> {
+  if dscp > 63 {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("DSCP {dscp} out of range (0..=63)"),
+    ));
+  }
+  // DSCP occupies the upper six bits of the TOS / traffic class byte
+  let tos = (dscp as i32) << 2;
+  if socket.local_addr()?.is_ipv6() {
+    imp::set_int(socket, imp::IPPROTO_IPV6, imp::IPV6_TCLASS, tos)
+  } else {
+    imp::set_int(socket, imp::IPPROTO_IP, imp::IP_TOS, tos)
+  }
+}
+
+/// The DSCP value currently set on `socket`.
+pub fn dscp(socket: &UdpSocket) -> io::Result<u8> {
+  let tos = if socket.local_addr()?.is_ipv6() {
+    imp::get_int(socket, imp::IPPROTO_IPV6, imp::IPV6_TCLASS)?
+  } else {
+    imp::get_int(socket, imp::IPPROTO_IP, imp::IP_TOS)?
+  };
+  Ok((tos >> 2) as u8 & 0x3f)
+}
+
+/// Limit how many hops outgoing packets may take, 1..=255. Sets the
+/// multicast TTL (IP_MULTICAST_TTL / IPV6_MULTICAST_HOPS) when `multicast`,
+/// otherwise the unicast one (IP_TTL / IPV6_UNICAST_HOPS).
+pub fn set_ttl(socket: &UdpSocket, ttl: u8, multicast: bool) -> io::Result<()> {
+  if ttl == 0 {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "TTL 0 out of range (1..=255)",
+    ));
+  }
+  let sock = SockRef::from(socket);
+  let ttl = u32::from(ttl);
+  match (socket.local_addr()?.is_ipv6(), multicast) {
+    (false, false) => sock.set_ttl_v4(ttl),
+    (false, true) => sock.set_multicast_ttl_v4(ttl),
+    (true, false) => sock.set_unicast_hops_v6(ttl),
+    (true, true) => sock.set_multicast_hops_v6(ttl),
+  }
+}
+
+/// The TTL / hop limit currently set on `socket`; see `set_ttl`.
+pub fn ttl(socket: &UdpSocket, multicast: bool) -> io::Result<u8> {
+  let sock = SockRef::from(socket);
+  let ttl = match (socket.local_addr()?.is_ipv6(), multicast) {
+    (false, false) => sock.ttl_v4(),
+    (false, true) => sock.multicast_ttl_v4(),
+    (true, false) => sock.unicast_hops_v6(),
+    (true, true) => sock.multicast_hops_v6(),
+  }?;
+  Ok(ttl as u8)
+}
+
+/// Request a kernel send buffer (SO_SNDBUF) of `bytes`. The OS may round,
+/// double or cap the request; read back the result with
+/// `send_buffer_size`.
+pub fn set_send_buffer_size(
+  socket: &UdpSocket,
+  bytes: usize,
+) -> io::Result<()> {
+  SockRef::from(socket).set_send_buffer_size(bytes)
+}
+
+/// Kernel send buffer size currently granted to `socket`.
+pub fn send_buffer_size(socket: &UdpSocket) -> io::Result<usize> {
+  SockRef::from(socket).send_buffer_size()
+}
+
+/// Request a kernel receive buffer (SO_RCVBUF) of `bytes`; see
+/// `set_send_buffer_size`.
+pub fn set_recv_buffer_size(
+  socket: &UdpSocket,
+  bytes: usize,
+) -> io::Result<()> {
+  SockRef::from(socket).set_recv_buffer_size(bytes)
+}
+
+/// Kernel receive buffer size currently granted to `socket`.
+pub fn recv_buffer_size(socket: &UdpSocket) -> io::Result<usize> {
+  SockRef::from(socket).recv_buffer_size()
+}
+
+/// Restrict `socket` to traffic on the network interface `name` (such as
+/// "eth0") with SO_BINDTODEVICE, which also separates overlapping subnets
+/// on different NICs. Linux only, where older kernels require CAP_NET_RAW;
+/// other platforms report `Unsupported`.
+pub fn bind_to_device(socket: &UdpSocket, name: &str) -> io::Result<()> {
+  // Interface names are limited to IFNAMSIZ (16) bytes including the NUL
+  if name.is_empty() || name.len() >= 16 || name.contains('\0') {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("invalid interface name {name:?}"),
+    ));
+  }
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  {
+    SockRef::from(socket).bind_device(Some(name.as_bytes()))
+  }
+  #[cfg(not(any(target_os = "linux", target_os = "android")))]
+  {
+    let _ = socket;
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "binding to an interface is only supported on Linux",
+    ))
+  }
+}
+
+/// Bind a UDP socket to the IPv6 `addr` that also accepts IPv4 peers, which
+/// then appear as IPv4-mapped addresses (`::ffff:a.b.c.d`). IPV6_V6ONLY is
+/// cleared before binding, since some OSes (Windows among them) default it
+/// to on; where it cannot be cleared this reports `Unsupported`.
+pub fn bind_dual_stack(addr: SocketAddrV6) -> io::Result<UdpSocket> {
+  let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+  // Some OSes (e.g. OpenBSD) refuse dual-stack sockets outright
+  socket
+    .set_only_v6(false)
+    .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, e))?;
+  socket.bind(&SockAddr::from(addr))?;
+  Ok(socket.into())
+}
+
+/// Whether an IPv6 `socket` is limited to IPv6 peers (IPV6_V6ONLY).
+pub fn only_v6(socket: &UdpSocket) -> io::Result<bool> {
+  SockRef::from(socket).only_v6()
+}
+
+#[cfg(unix)]
+mod imp {
+  use std::io;
+  use std::os::fd::AsRawFd;
+
+  pub use libc::{IP_TOS, IPPROTO_IP, IPPROTO_IPV6, IPV6_TCLASS};
+
+  pub fn set_int(
+    socket: &impl AsRawFd,
+    level: i32,
+    name: i32,
+    value: i32,
+  ) -> io::Result<()> {
+    // SAFETY: the fd is valid for the borrow and `value` outlives the call
+    let ret = unsafe {
+      libc::setsockopt(
+        socket.as_raw_fd(),
+        level,
+        name,
+        &value as *const i32 as *const libc::c_void,
+        size_of::<i32>() as libc::socklen_t,
+      )
+    };
+    if ret == 0 {
+      Ok(())
+    } else {
+      Err(io::Error::last_os_error())
+    }
+  }
+
+  pub fn get_int(
+    socket: &impl AsRawFd,
+    level: i32,
+    name: i32,
+  ) -> io::Result<i32> {
+    let mut value: i32 = 0;
+    let mut len = size_of::<i32>() as libc::socklen_t;
+    // SAFETY: `value` and `len` are valid for writes for the whole call
+    let ret = unsafe {
+      libc::getsockopt(
+        socket.as_raw_fd(),
+        level,
+        name,
+        &mut value as *mut i32 as *mut libc::c_void,
+        &mut len,
+      )
+    };
+    if ret == 0 {
+      Ok(value)
+    } else {
+      Err(io::Error::last_os_error())
+    }
+  }
+}
+
+#[cfg(not(unix))]
+mod imp {
+  use std::io;
+
+  pub const IPPROTO_IP: i32 = 0;
+  pub const IPPROTO_IPV6: i32 = 0;
+  pub const IP_TOS: i32 = 0;
+  pub const IPV6_TCLASS: i32 = 0;
+
+  fn unsupported() -> io::Error {
+    io::Error::new(
+      io::ErrorKind::Unsupported,
+      "socket option not supported on this platform",
+    )
+  }
+
+  pub fn set_int<S>(_: &S, _: i32, _: i32, _: i32) -> io::Result<()> {
+    Err(unsupported())
+  }
+
+  pub fn get_int<S>(_: &S, _: i32, _: i32) -> io::Result<i32> {
+    Err(unsupported())
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dscp_roundtrips_and_rejects_out_of_range() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    set_dscp(&socket, 46).unwrap();
+    assert_eq!(dscp(&socket).unwrap(), 46);
+    let err = set_dscp(&socket, 64).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn ttl_sets_unicast_and_multicast_separately() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    set_ttl(&socket, 7, false).unwrap();
+    set_ttl(&socket, 3, true).unwrap();
+    assert_eq!(ttl(&socket, false).unwrap(), 7);
+    assert_eq!(ttl(&socket, true).unwrap(), 3);
+    let err = set_ttl(&socket, 0, false).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn ttl_sets_ipv6_hop_limits() {
+    let Ok(socket) = UdpSocket::bind("[::1]:0") else {
+      return; // no IPv6 loopback here
+    };
+    set_ttl(&socket, 9, false).unwrap();
+    set_ttl(&socket, 2, true).unwrap();
+    assert_eq!(ttl(&socket, false).unwrap(), 9);
+    assert_eq!(ttl(&socket, true).unwrap(), 2);
+  }
+
+  #[test]
+  fn buffer_sizes_grow_on_request() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    // Small enough to stay under default kernel caps everywhere
+    let want = 64 * 1024;
+    set_send_buffer_size(&socket, want).unwrap();
+    set_recv_buffer_size(&socket, want).unwrap();
+    assert!(send_buffer_size(&socket).unwrap() >= want);
+    assert!(recv_buffer_size(&socket).unwrap() >= want);
+  }
+
+  #[test]
+  fn bind_to_device_rejects_bad_names() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for name in ["", "an-interface-name-too-long", "eth\0"] {
+      let err = bind_to_device(&socket, name).unwrap_err();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+  }
+
+  #[test]
+  fn dual_stack_socket_receives_ipv4_as_mapped() {
+    let addr = "[::]:0".parse().unwrap();
+    let socket = bind_dual_stack(addr).unwrap();
+    assert!(!only_v6(&socket).unwrap());
+    let port = socket.local_addr().unwrap().port();
+    let v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+    v4.send_to(b"hi", ("127.0.0.1", port)).unwrap();
+    let mut buf = [0u8; 4];
+    let (n, from) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hi");
+    assert_eq!(from.ip().to_canonical(), v4.local_addr().unwrap().ip());
+  }
+}
@@ -0,0 +1,201 @@
+// Lock-free single-producer single-consumer byte ring, for handing captured
+// audio from a realtime callback to a thread that may block.
+
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Shared {
+  buf: Box<[UnsafeCell<u8>]>,
+  // Total bytes ever written and read; the difference is the fill level
+  written: AtomicUsize,
+  read: AtomicUsize,
+}
+
+// SAFETY: the producer only writes bytes outside `read..written` and the
+// consumer only reads bytes inside it; each side publishes its counter with
+// Release after touching the bytes and loads the other's with Acquire, so
+// no byte is accessed by both at once
+unsafe impl Sync for Shared {}
+
+impl Shared {
+  fn capacity(&self) -> usize {
+    self.buf.len()
+  }
+
+  // Derived from the whole buffer, so a copy through it may run on past
+  // `index` to the end of the buffer
+  fn ptr(&self, index: usize) -> *mut u8 {
+    let index = index % self.capacity();
+    // SAFETY: `index` is in bounds
+    UnsafeCell::raw_get(unsafe { self.buf.as_ptr().add(index) })
+  }
+}
+
+/// Create a ring holding up to `capacity` bytes (at least one).
+pub fn ring(capacity: usize) -> (Producer, Consumer) {
+  let shared = Arc::new(Shared {
+    buf: (0..capacity.max(1)).map(|_| UnsafeCell::new(0)).collect(),
+    written: AtomicUsize::new(0),
+    read: AtomicUsize::new(0),
+  });
+  (
+    Producer {
+      shared: shared.clone(),
+    },
+    Consumer { shared },
+  )
+}
+
+/// Writing end of a `ring`. Never blocks or allocates.
+pub struct Producer {
+  shared: Arc<Shared>,
+}
+
+impl Producer {
+  /// Append all of `data`, or nothing if it does not fit, so a chunk of
+  /// whole frames is never split by an overrun. Returns whether it fit.
+  pub fn push(&mut self, data: &[u8]) -> bool {
+    let s = &*self.shared;
+    let written = s.written.load(Ordering::Relaxed);
+    let read = s.read.load(Ordering::Acquire);
+    let free = s.capacity() - written.wrapping_sub(read);
+    if data.len() > free {
+      return false;
+    }
+    let start = written % s.capacity();
+    let first = data.len().min(s.capacity() - start);
+    // SAFETY: both ranges lie in the free part of the buffer, which the
+    // consumer does not read until `written` is published below
+    unsafe {
+      std::ptr::copy_nonoverlapping(data.as_ptr(), s.ptr(start), first);
+      std::ptr::copy_nonoverlapping(
+        data[first..].as_ptr(),
+        s.ptr(0),
+        data.len() - first,
+      );
+    }
+    s.written
+      .store(written.wrapping_add(data.len()), Ordering::Release);
+    true
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.shared.capacity()
+  }
+
+  /// Bytes a `push` could take right now; `capacity` when empty.
+  pub fn free(&self) -> usize {
+    let s = &*self.shared;
+    let queued = s
+      .written
+      .load(Ordering::Relaxed)
+      .wrapping_sub(s.read.load(Ordering::Acquire));
+    s.capacity() - queued
+  }
+}
+
+/// Reading end of a `ring`.
+pub struct Consumer {
+  shared: Arc<Shared>,
+}
+
+impl Consumer {
+  /// Move up to `out.len()` queued bytes into `out`, oldest first, and
+  /// return how many were moved.
+  pub fn pop(&mut self, out: &mut [u8]) -> usize {
+    let s = &*self.shared;
+    let read = s.read.load(Ordering::Relaxed);
+    let written = s.written.load(Ordering::Acquire);
+    let n = written.wrapping_sub(read).min(out.len());
+    let start = read % s.capacity();
+    let first = n.min(s.capacity() - start);
+    // SAFETY: both ranges lie in the filled part of the buffer, which the
+    // producer does not overwrite until `read` is published below
+    unsafe {
+      std::ptr::copy_nonoverlapping(s.ptr(start), out.as_mut_ptr(), first);
+      std::ptr::copy_nonoverlapping(
+        s.ptr(0),
+        out[first..].as_mut_ptr(),
+        n - first,
+      );
+    }
+    s.read.store(read.wrapping_add(n), Ordering::Release);
+    n
+  }
+
+  /// Bytes waiting to be popped.
+  pub fn len(&self) -> usize {
+    let s = &*self.shared;
+    s.written
+      .load(Ordering::Acquire)
+      .wrapping_sub(s.read.load(Ordering::Relaxed))
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn wraps_around_and_refuses_what_does_not_fit() {
+    let (mut tx, mut rx) = ring(8);
+    let mut out = [0u8; 8];
+    assert!(tx.push(b"abcdef"));
+    assert_eq!(rx.pop(&mut out[..4]), 4);
+    assert_eq!(&out[..4], b"abcd");
+    // 6 bytes free, split across the end of the buffer
+    assert!(!tx.push(b"1234567"));
+    assert_eq!(tx.free(), 6);
+    assert!(tx.push(b"123456"));
+    assert_eq!(tx.free(), 0);
+    assert_eq!(rx.len(), 8);
+    assert_eq!(rx.pop(&mut out), 8);
+    assert_eq!(&out, b"ef123456");
+    assert!(rx.is_empty());
+    assert_eq!(rx.pop(&mut out), 0);
+  }
+
+  // Run under Miri too (`cargo +nightly miri test --lib spsc`), which checks
+  // that the copies split at the end of the buffer stay in bounds
+  #[test]
+  fn repeated_wraparound_keeps_bytes_in_order() {
+    let (mut tx, mut rx) = ring(5);
+    let mut out = [0u8; 3];
+    for round in 0..8u8 {
+      let data = [round, round + 1, round + 2];
+      assert!(tx.push(&data));
+      assert_eq!(rx.pop(&mut out), 3);
+      assert_eq!(out, data);
+    }
+    assert!(rx.is_empty());
+  }
+
+  #[test]
+  fn bytes_cross_threads_in_order() {
+    let (mut tx, mut rx) = ring(64);
+    let producer = std::thread::spawn(move || {
+      for i in 0..10_000u32 {
+        while !tx.push(&i.to_le_bytes()) {
+          std::thread::yield_now();
+        }
+      }
+    });
+    let mut next = 0u32;
+    let mut word = [0u8; 4];
+    while next < 10_000 {
+      // Ring holds whole words, so a 4-byte pop is always a full word
+      if rx.pop(&mut word) == 4 {
+        assert_eq!(u32::from_le_bytes(word), next);
+        next += 1;
+      } else {
+        std::thread::yield_now();
+      }
+    }
+    producer.join().unwrap();
+  }
+}
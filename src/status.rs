@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Human-readable status always goes to stderr: the receiver's default sink
+// writes raw PCM to stdout, so stdout must carry nothing else.
+static QUIET: AtomicBool = AtomicBool::new(false);
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+// Set while an in-place progress line has left the cursor mid-line
+static MID_LINE: AtomicBool = AtomicBool::new(false);
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// Minimal `log` backend: one line per record on stderr, prefixed by level.
+// A record following an in-place progress line starts on a line of its own.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+    if MID_LINE.swap(false, Ordering::Relaxed) {
+      eprintln!();
+    }
+    let msg = record.args();
+    match record.level() {
+      Level::Error => eprintln!("error: {msg}"),
+      Level::Warn => eprintln!("warning: {msg}"),
+      Level::Info => eprintln!("{msg}"),
+      Level::Debug | Level::Trace => {
+        eprintln!("[{}] {msg}", record.target())
+      }
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+/// Install the stderr logger. Call first thing in `main`; `RUST_LOG` (a
+/// single level such as `debug`) overrides the flags.
+pub fn init_logging() {
+  let _ = log::set_logger(&LOGGER);
+  apply_level();
+}
+
+/// Silence informational output for the whole process (`--quiet`).
+/// Warnings and errors are still shown.
+pub fn set_quiet(quiet: bool) {
+  QUIET.store(quiet, Ordering::Relaxed);
+  apply_level();
+}
+
+pub fn is_quiet() -> bool {
+  QUIET.load(Ordering::Relaxed)
+}
+
+/// Note that a progress line was just printed without a newline, to be
+/// overwritten in place with `\r`; the next log record breaks out of it.
+pub fn mark_progress_line() {
+  MID_LINE.store(true, Ordering::Relaxed);
+}
+
+/// Like `eprintln!`, but suppressed by `--quiet`.
+#[deprecated(note = "use `log::info!`, which `--quiet` also silences")]
+#[macro_export]
+macro_rules! status {
+  ($($arg:tt)*) => {
+    if !$crate::status::is_quiet() {
+      eprintln!($($arg)*);
+    }
+  };
+}
+
+/// Raise the log level: 1 (`-v`) enables debug, 2 (`-vv`) trace.
+pub fn set_verbosity(level: u8) {
+  VERBOSITY.store(level, Ordering::Relaxed);
+  apply_level();
+}
+
+fn apply_level() {
+  let env = std::env::var("RUST_LOG").ok();
+  log::set_max_level(level_for(
+    env.as_deref(),
+    is_quiet(),
+    VERBOSITY.load(Ordering::Relaxed),
+  ));
+}
+
+// A valid `RUST_LOG` wins over the flags; `--quiet` over `-v`
+fn level_for(env: Option<&str>, quiet: bool, verbosity: u8) -> LevelFilter {
+  if let Some(level) = env.and_then(|v| v.parse::<LevelFilter>().ok()) {
+    return level;
+  }
+  if quiet {
+    return LevelFilter::Warn;
+  }
+  match verbosity {
+    0 => LevelFilter::Info,
+    1 => LevelFilter::Debug,
+    _ => LevelFilter::Trace,
+  }
+}
+
+/// Paces a liveness line for log monitoring, such as the receiver's
+/// `--heartbeat-secs`, on the clock alone rather than on traffic.
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+  every: Duration,
+  last: Instant,
+}
+
+impl Heartbeat {
+  pub fn new(every: Duration, now: Instant) -> Self {
+    Self { every, last: now }
+  }
+
+  /// True, once per period, when the next line is due at `now`.
+  pub fn due(&mut self, now: Instant) -> bool {
+    if now.saturating_duration_since(self.last) < self.every {
+      return false;
+    }
+    self.last = now;
+    true
+  }
+
+  pub fn line(clients: usize, total_packets: u64) -> String {
+    format!("alive, {clients} clients, {total_packets} total packets")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn quiet_keeps_only_warnings_and_errors() {
+    assert_eq!(level_for(None, false, 0), LevelFilter::Info);
+    assert_eq!(level_for(None, true, 0), LevelFilter::Warn);
+    assert_eq!(level_for(None, true, 2), LevelFilter::Warn);
+    assert_eq!(level_for(None, false, 1), LevelFilter::Debug);
+    assert_eq!(level_for(None, false, 5), LevelFilter::Trace);
+    assert_eq!(level_for(Some("error"), false, 2), LevelFilter::Error);
+    assert_eq!(level_for(Some("debug"), true, 0), LevelFilter::Debug);
+    // An unparsable RUST_LOG falls back to the flags
+    assert_eq!(level_for(Some("noisy"), true, 0), LevelFilter::Warn);
+  }
+
+  #[test]
+  fn heartbeat_is_due_once_per_period() {
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut heartbeat = Heartbeat::new(Duration::from_secs(1), start);
+    assert!(!heartbeat.due(at(999)));
+    assert!(heartbeat.due(at(1_000)));
+    assert!(!heartbeat.due(at(1_500)));
+    // A late check starts the next period from when it ran
+    assert!(heartbeat.due(at(3_200)));
+    assert!(!heartbeat.due(at(4_100)));
+    assert!(heartbeat.due(at(4_200)));
+    assert_eq!(
+      Heartbeat::line(2, 1_234),
+      "alive, 2 clients, 1234 total packets"
+    );
+  }
+}
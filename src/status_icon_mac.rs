@@ -5,29 +5,34 @@ use system_status_bar_macos::*;
 
 use crate::send_stats::SendStats;
 
+// Menu bar text for one stats update, e.g. "128.0 KB/s -12 dB"
+fn status_title(stat: &SendStats) -> String {
+  format!(
+    "{:.1} KB/s {:.0} dB",
+    stat.average_rate_bps / 1024.0,
+    stat.peak_dbfs
+  )
+}
+
 pub fn show_status_icon(receiver: channel::Receiver<SendStats>) {
-  let (event_loop, terminator) = sync_event_loop(receiver, |_stat| {
-    //let status_text = format!("Sent: {} bytes, Rate: {:.2} bps",
-    // stat.total_bytes_sent, stat.average_rate_bps); status_item.
-    // borrow_mut().menu().
+  // Implementation for showing status icon on macOS
+  let status_item =
+    RefCell::new(StatusItem::new("sound-send", Menu::new(vec![])));
+
+  let (event_loop, terminator) = sync_event_loop(receiver, |stat| {
+    status_item.borrow_mut().set_title(&status_title(&stat));
   });
 
-  // Implementation for showing status icon on macOS
-  let _status_item = RefCell::new(StatusItem::new(
-    "TITLE",
-    Menu::new(vec![
-      MenuItem::new("Stats", None, None),
-      MenuItem::new(
-        "CLICKABLE MENU",
-        Some(Box::new(move || {
-          terminator.terminate();
-          //click_sender.send(()).unwrap();
-        })),
-        None,
-      ),
-    ]),
-  ));
+  status_item.borrow_mut().set_menu(Menu::new(vec![
+    MenuItem::new("Stats", None, None),
+    MenuItem::new(
+      "Quit",
+      Some(Box::new(move || {
+        terminator.terminate();
+      })),
+      None,
+    ),
+  ]));
 
-  //sync_infinite_event_loop(receiver, |_| { });
   event_loop();
 }
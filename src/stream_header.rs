@@ -0,0 +1,90 @@
+// Self-describing header for raw PCM piped into `udp_sender --input stdin`.
+
+use crate::packet::{Meta, MetaError, SampleFormat};
+
+/// Leading header a producer may write before raw samples so the sender
+/// can take the stream format from the stream itself (`--stdin-header`).
+///
+/// Layout (big-endian), 10 bytes:
+/// - 4 bytes: magic (`STREAM_HEADER_MAGIC`)
+/// - 1 byte: channels (1..=255)
+/// - 4 bytes: sample rate in Hz (u32)
+/// - 1 byte: sample format, using the same codes as data packets (1 = f32, 2 =
+///   i16, 3 = u16, 4 = u32)
+///
+/// Samples follow immediately, interleaved and native-endian.
+pub const STREAM_HEADER_MAGIC: &[u8; 4] = b"SSRH";
+
+pub const STREAM_HEADER_LEN: usize = 4 + 1 + 4 + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamHeaderError {
+  BadMagic,
+  BadFormat(u8),
+  Meta(MetaError),
+}
+
+impl core::fmt::Display for StreamHeaderError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      StreamHeaderError::BadMagic => write!(f, "bad stream header magic"),
+      StreamHeaderError::BadFormat(code) => {
+        write!(f, "unknown sample format code {code} in stream header")
+      }
+      StreamHeaderError::Meta(e) => write!(f, "{e}"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StreamHeaderError {}
+
+pub fn encode_stream_header(meta: &Meta) -> [u8; STREAM_HEADER_LEN] {
+  let mut out = [0u8; STREAM_HEADER_LEN];
+  out[..4].copy_from_slice(STREAM_HEADER_MAGIC);
+  out[4] = meta.channels;
+  out[5..9].copy_from_slice(&meta.sample_rate.0.to_be_bytes());
+  out[9] = meta.sample_format.code();
+  out
+}
+
+pub fn decode_stream_header(
+  b: &[u8; STREAM_HEADER_LEN],
+) -> Result<Meta, StreamHeaderError> {
+  if &b[..4] != STREAM_HEADER_MAGIC {
+    return Err(StreamHeaderError::BadMagic);
+  }
+  let rate = u32::from_be_bytes([b[5], b[6], b[7], b[8]]);
+  let format =
+    SampleFormat::from_code(b[9]).ok_or(StreamHeaderError::BadFormat(b[9]))?;
+  Meta::new(b[4] as u16, rate, format).map_err(StreamHeaderError::Meta)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn header_round_trips_and_rejects_garbage() {
+    let meta = Meta::new(6, 96_000, SampleFormat::I16).unwrap();
+    let header = encode_stream_header(&meta);
+    assert_eq!(&header[4..], &[6, 0, 1, 0x77, 0x00, 2]);
+    assert_eq!(decode_stream_header(&header), Ok(meta));
+
+    let mut bad = header;
+    bad[0] = b'X';
+    assert_eq!(decode_stream_header(&bad), Err(StreamHeaderError::BadMagic));
+    let mut bad = header;
+    bad[9] = 0;
+    assert_eq!(
+      decode_stream_header(&bad),
+      Err(StreamHeaderError::BadFormat(0))
+    );
+    let mut bad = header;
+    bad[4] = 0;
+    assert_eq!(
+      decode_stream_header(&bad),
+      Err(StreamHeaderError::Meta(MetaError::BadChannels(0)))
+    );
+  }
+}
@@ -1,40 +1,144 @@
-use std::net::{SocketAddr, UdpSocket};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
 
+use crate::clock::{Clock, SystemClock};
 use crate::packet_sync::{SyncMessage, encode_sync};
 use crate::timesync::TimeSync;
+pub use crate::timesync::TimeSyncState;
+use crate::transport::Transport;
 
-pub(crate) trait SyncController {
+/// Time-sync estimator used by `DefaultSyncController`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAlgorithm {
+  /// Exponentially weighted moving average (`TimeSyncEstimator`).
+  Ewma,
+  /// Median over a window of recent samples (`MedianTimeSync`).
+  Median,
+}
+
+impl SyncAlgorithm {
+  pub fn parse(s: &str) -> Option<Self> {
+    match s.to_ascii_lowercase().as_str() {
+      "ewma" => Some(Self::Ewma),
+      "median" => Some(Self::Median),
+      _ => None,
+    }
+  }
+}
+
+// Number of pongs the median estimator keeps
+const MEDIAN_WINDOW: usize = 9;
+// Pongs needed before an estimate is considered converged enough to reuse
+const CONVERGED_PONGS: u32 = 3;
+
+/// Remembers converged time-sync state per sender host, so a sender that
+/// reconnects (possibly from a new ephemeral port) starts from its previous
+/// estimate instead of cold. Keyed by IP since the clock belongs to the
+/// host, not the socket.
+#[derive(Debug)]
+pub struct SyncStateCache {
+  ttl: Duration,
+  entries: HashMap<IpAddr, (TimeSyncState, Instant)>,
+}
+
+impl SyncStateCache {
+  pub fn new(ttl: Duration) -> Self {
+    Self {
+      ttl,
+      entries: HashMap::new(),
+    }
+  }
+
+  pub fn store(&mut self, ip: IpAddr, state: TimeSyncState, now: Instant) {
+    self.entries.insert(ip, (state, now));
+    let ttl = self.ttl;
+    self
+      .entries
+      .retain(|_, (_, saved)| now.duration_since(*saved) < ttl);
+  }
+
+  /// Returns the saved state for `ip` if it was stored within the TTL.
+  pub fn take(&mut self, ip: IpAddr, now: Instant) -> Option<TimeSyncState> {
+    let (state, saved) = self.entries.remove(&ip)?;
+    (now.duration_since(saved) < self.ttl).then_some(state)
+  }
+}
+
+/// Clock synchronisation with a single sender, as driven by `RecvStats`.
+///
+/// Expected call order: `register_sender` for every packet from the sender
+/// (before anything else), `maybe_send_ping` periodically from the receive
+/// loop, and `on_pong` for each Pong that comes back. The estimate getters
+/// may be called at any time and report the initial state until the first
+/// pong.
+pub trait SyncController {
   fn register_sender(&mut self, addr: SocketAddr);
   fn on_pong(&mut self, t0_ms: u64, t1_ms: u64, t2_ms: u64);
   fn compute_latency_ms(&self, sent_ts_ms: u64) -> f64;
   fn offset_ms(&self) -> f64;
   fn drift_ppm(&self) -> f64;
-  fn maybe_send_ping(&mut self, sock: &UdpSocket);
+  fn delay_ms(&self) -> f64;
+  fn maybe_send_ping(&mut self, sock: &dyn Transport);
+
+  /// Seed the estimator with a previously converged state.
+  fn seed_state(&mut self, _state: TimeSyncState) {}
+
+  /// Make the next `maybe_send_ping` fire regardless of the interval, e.g.
+  /// after a loss burst that likely left the estimate stale.
+  fn request_ping_now(&mut self) {}
+
+  /// Current estimate, once enough pongs have been seen to trust it.
+  fn converged_state(&self) -> Option<TimeSyncState> {
+    None
+  }
+
+  /// Pongs the estimator left out as unreliable.
+  fn rejected_pongs(&self) -> u64 {
+    0
+  }
+}
+
+// Clock estimate for one peer
+struct PeerSync {
+  ts: Box<dyn TimeSync + Send>,
+  last_ping_ms: u64,
+  pongs: u32,
 }
 
+/// Tracks time sync with any number of peers, each with its own estimator.
+/// The `SyncController` methods act on the most recently registered sender;
+/// the `*_peer` methods address a peer explicitly, e.g. a sender mirroring
+/// to several receivers.
 pub struct DefaultSyncController {
-  ts: Box<dyn TimeSync>,
+  make_ts: Box<dyn Fn() -> Box<dyn TimeSync + Send> + Send>,
+  peers: HashMap<SocketAddr, PeerSync>,
   last_sender: Option<SocketAddr>,
-  last_ping_ms: u64,
+  // Applied to the next peer created, for seeding before registration
+  pending_seed: Option<TimeSyncState>,
   ping_interval_ms: u64,
+  clock: Box<dyn Clock>,
 }
 
 impl DefaultSyncController {
-  pub fn new(ts: Box<dyn TimeSync>, ping_interval_ms: u64) -> Self {
+  /// `make_ts` builds a fresh estimator for each new peer.
+  pub fn new<F>(make_ts: F, ping_interval_ms: u64) -> Self
+  where
+    F: Fn() -> Box<dyn TimeSync + Send> + Send + 'static,
+  {
     Self {
-      ts,
+      make_ts: Box::new(make_ts),
+      peers: HashMap::new(),
       last_sender: None,
-      last_ping_ms: 0,
+      pending_seed: None,
       ping_interval_ms,
+      clock: Box::new(SystemClock),
     }
   }
 
-  fn now_ms() -> u64 {
-    SystemTime::now()
-      .duration_since(UNIX_EPOCH)
-      .unwrap_or_else(|_| Duration::from_millis(0))
-      .as_millis() as u64
+  /// Read ping and pong times from `clock` instead of the system clock.
+  pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+    self.clock = Box::new(clock);
   }
 
   /// Convenience: build with the default estimator
@@ -44,45 +148,422 @@ impl DefaultSyncController {
     ping_interval_ms: u64,
   ) -> Self {
     Self::new(
-      Box::new(crate::timesync::TimeSyncEstimator::new(alpha, beta)),
+      move || Box::new(crate::timesync::TimeSyncEstimator::new(alpha, beta)),
       ping_interval_ms,
     )
   }
+
+  /// Build with the estimator selected by `algo` and its default tuning.
+  pub fn with_algorithm(algo: SyncAlgorithm, ping_interval_ms: u64) -> Self {
+    match algo {
+      SyncAlgorithm::Ewma => {
+        Self::with_default_estimator(0.2, 0.2, ping_interval_ms)
+      }
+      SyncAlgorithm::Median => Self::new(
+        || Box::new(crate::timesync::MedianTimeSync::new(MEDIAN_WINDOW)),
+        ping_interval_ms,
+      ),
+    }
+  }
+
+  fn peer_mut(&mut self, addr: SocketAddr) -> &mut PeerSync {
+    let make_ts = &self.make_ts;
+    let pending_seed = &mut self.pending_seed;
+    self.peers.entry(addr).or_insert_with(|| {
+      let mut ts = make_ts();
+      if let Some(state) = pending_seed.take() {
+        ts.seed_state(state);
+      }
+      PeerSync {
+        ts,
+        last_ping_ms: 0,
+        pongs: 0,
+      }
+    })
+  }
+
+  // State of the current sender, or a fresh estimator's before any
+  fn current_state(&self) -> TimeSyncState {
+    match self.last_sender.and_then(|addr| self.peers.get(&addr)) {
+      Some(peer) => peer.ts.state(),
+      None => {
+        let mut ts = (self.make_ts)();
+        if let Some(state) = self.pending_seed {
+          ts.seed_state(state);
+        }
+        ts.state()
+      }
+    }
+  }
+
+  /// Track `addr` as a peer. Peers are also added by pinging them or
+  /// registering them as the sender.
+  pub fn add_peer(&mut self, addr: SocketAddr) {
+    self.peer_mut(addr);
+  }
+
+  /// Feed a Pong received from `addr`. Pongs from addresses that are not
+  /// peers are ignored, so spoofed ones can neither grow the peer map nor
+  /// show up as estimates.
+  pub fn on_pong_from(
+    &mut self,
+    addr: SocketAddr,
+    t0_ms: u64,
+    t1_ms: u64,
+    t2_ms: u64,
+  ) {
+    let t3_ms = self.clock.now_ms();
+    let Some(peer) = self.peers.get_mut(&addr) else {
+      return;
+    };
+    let _ = peer.ts.update(t0_ms, t1_ms, t2_ms, t3_ms);
+    peer.pongs = peer.pongs.saturating_add(1);
+  }
+
+  /// Ping `addr` over `sock` if its ping interval has elapsed.
+  pub fn maybe_ping_peer(&mut self, sock: &dyn Transport, addr: SocketAddr) {
+    let now_ms = self.clock.now_ms();
+    let interval = self.ping_interval_ms;
+    let peer = self.peer_mut(addr);
+    if now_ms.saturating_sub(peer.last_ping_ms) >= interval {
+      let ping = SyncMessage::Ping { t0_ms: now_ms };
+      let v = encode_sync(&ping);
+      let _ = sock.send_packet_to(&v, addr);
+      peer.last_ping_ms = now_ms;
+    }
+  }
+
+  /// Estimate for `addr`, once at least one Pong has come back from it.
+  pub fn peer_state(&self, addr: SocketAddr) -> Option<TimeSyncState> {
+    let peer = self.peers.get(&addr)?;
+    (peer.pongs > 0).then(|| peer.ts.state())
+  }
+
+  /// Estimates for every peer that has answered a ping, ordered by address.
+  pub fn peer_states(&self) -> Vec<(SocketAddr, TimeSyncState)> {
+    let mut states: Vec<_> = self
+      .peers
+      .iter()
+      .filter(|(_, peer)| peer.pongs > 0)
+      .map(|(addr, peer)| (*addr, peer.ts.state()))
+      .collect();
+    states.sort_by_key(|(addr, _)| *addr);
+    states
+  }
+
+  /// Status-line segments for `peer_states`, as the sender's `--sync-stats`
+  /// shows them.
+  pub fn peer_status(&self) -> String {
+    self
+      .peer_states()
+      .iter()
+      .map(|(addr, state)| {
+        format!(
+          "| {addr} Off: {:+.2} ms Drift: {:+.1} ppm RTT: {:.2} ms   ",
+          state.offset_ms, state.drift_ppm, state.delay_ms
+        )
+      })
+      .collect()
+  }
 }
 
 impl SyncController for DefaultSyncController {
   fn register_sender(&mut self, addr: SocketAddr) {
+    self.peer_mut(addr);
     self.last_sender = Some(addr);
   }
 
   fn on_pong(&mut self, t0_ms: u64, t1_ms: u64, t2_ms: u64) {
-    let t3_ms = Self::now_ms();
-    let _ = self.ts.update(t0_ms, t1_ms, t2_ms, t3_ms);
+    if let Some(addr) = self.last_sender {
+      self.on_pong_from(addr, t0_ms, t1_ms, t2_ms);
+    }
   }
 
   fn compute_latency_ms(&self, sent_ts_ms: u64) -> f64 {
-    let now_ms = Self::now_ms();
-    let offset = self.ts.state().offset_ms;
-    let adj_now_ms = (now_ms as i128 - offset as i128).max(0) as u64;
+    let now_ms = self.clock.now_ms();
+    // The offset is the peer's clock minus ours, so this is now on the
+    // peer's clock
+    let offset = self.current_state().offset_ms;
+    let adj_now_ms = (now_ms as i128 + offset as i128).max(0) as u64;
     adj_now_ms.saturating_sub(sent_ts_ms) as f64
   }
 
   fn offset_ms(&self) -> f64 {
-    self.ts.state().offset_ms
+    self.current_state().offset_ms
   }
   fn drift_ppm(&self) -> f64 {
-    self.ts.state().drift_ppm
+    self.current_state().drift_ppm
+  }
+  fn delay_ms(&self) -> f64 {
+    self.current_state().delay_ms
   }
 
-  fn maybe_send_ping(&mut self, sock: &UdpSocket) {
+  fn maybe_send_ping(&mut self, sock: &dyn Transport) {
     if let Some(addr) = self.last_sender {
-      let now_ms = Self::now_ms();
-      if now_ms.saturating_sub(self.last_ping_ms) >= self.ping_interval_ms {
-        let ping = SyncMessage::Ping { t0_ms: now_ms };
-        let v = encode_sync(&ping);
-        let _ = sock.send_to(&v, addr);
-        self.last_ping_ms = now_ms;
-      }
+      self.maybe_ping_peer(sock, addr);
+    }
+  }
+
+  fn seed_state(&mut self, state: TimeSyncState) {
+    match self.last_sender.and_then(|addr| self.peers.get_mut(&addr)) {
+      Some(peer) => peer.ts.seed_state(state),
+      None => self.pending_seed = Some(state),
+    }
+  }
+
+  fn request_ping_now(&mut self) {
+    if let Some(peer) =
+      self.last_sender.and_then(|addr| self.peers.get_mut(&addr))
+    {
+      peer.last_ping_ms = 0;
+    }
+  }
+
+  fn converged_state(&self) -> Option<TimeSyncState> {
+    let peer = self.peers.get(&self.last_sender?)?;
+    (peer.pongs >= CONVERGED_PONGS).then(|| peer.ts.state())
+  }
+
+  fn rejected_pongs(&self) -> u64 {
+    self
+      .last_sender
+      .and_then(|addr| self.peers.get(&addr))
+      .map_or(0, |peer| peer.ts.rejected_samples())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::clock::MockClock;
+  use crate::packet::{SYNC_MAX_LEN, decode_sync, respond_to_ping_with};
+  use crate::transport::{MemoryNetwork, MemoryTransport};
+
+  struct FixedSync(TimeSyncState);
+
+  impl TimeSync for FixedSync {
+    fn update(&mut self, _: u64, _: u64, _: u64, _: u64) -> TimeSyncState {
+      self.0
+    }
+    fn state(&self) -> TimeSyncState {
+      self.0
+    }
+  }
+
+  #[test]
+  fn exposes_estimator_state() {
+    let state = TimeSyncState {
+      offset_ms: 1.5,
+      delay_ms: 12.5,
+      drift_ppm: -3.0,
+    };
+    let ctrl =
+      DefaultSyncController::new(move || Box::new(FixedSync(state)), 1_000);
+    assert_eq!(ctrl.offset_ms(), 1.5);
+    assert_eq!(ctrl.delay_ms(), 12.5);
+    assert_eq!(ctrl.drift_ppm(), -3.0);
+  }
+
+  #[test]
+  fn rejected_pongs_reach_the_controller_and_stats() {
+    let clock = MockClock::new(1_000);
+    let mut ctrl =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    ctrl.set_clock(clock.clone());
+    let sender: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+    ctrl.register_sender(sender);
+    assert_eq!(ctrl.rejected_pongs(), 0);
+    for _ in 0..4 {
+      let t0 = clock.now_ms();
+      clock.advance(20);
+      ctrl.on_pong(t0, t0 + 10, t0 + 10);
+      clock.advance(980);
     }
+    // 100 ms more delay than any pong before it
+    let t0 = clock.now_ms();
+    clock.advance(120);
+    ctrl.on_pong(t0, t0 + 110, t0 + 110);
+    assert_eq!(ctrl.rejected_pongs(), 1);
+
+    let stats = crate::recv_stats::RecvStats::new(
+      Duration::from_secs(10),
+      Duration::from_secs(1),
+      ctrl,
+    );
+    assert_eq!(stats.rejected_pongs(), 1);
+    assert_eq!(stats.snapshot().rejected_pongs, 1);
+  }
+
+  // Two endpoints on an in-memory network, for deterministic delivery
+  fn endpoints() -> (MemoryTransport, MemoryTransport) {
+    let net = MemoryNetwork::new();
+    let local = net.bind("10.0.0.1:4000".parse().unwrap()).unwrap();
+    let peer = net.bind("10.0.0.2:5000".parse().unwrap()).unwrap();
+    for t in [&local, &peer] {
+      t.set_recv_timeout(Some(Duration::ZERO)).unwrap();
+    }
+    (local, peer)
+  }
+
+  #[test]
+  fn request_ping_now_bypasses_interval() {
+    let (local, peer) = endpoints();
+    let clock = MockClock::new(1_000_000);
+    let mut ctrl =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 60_000);
+    ctrl.set_clock(clock.clone());
+    ctrl.register_sender(peer.local_addr());
+    let mut buf = [0u8; 64];
+    let mut recv = || peer.recv_packet_from(&mut buf).is_ok();
+
+    ctrl.maybe_send_ping(&local);
+    assert!(recv());
+    clock.advance(59_999);
+    ctrl.maybe_send_ping(&local);
+    assert!(!recv(), "second ping sent within the interval");
+    ctrl.request_ping_now();
+    ctrl.maybe_send_ping(&local);
+    assert!(recv());
+    clock.advance(60_000);
+    ctrl.maybe_send_ping(&local);
+    assert!(recv());
+  }
+
+  #[test]
+  fn ping_pong_with_controlled_clocks() {
+    let (local_sock, peer_sock) = endpoints();
+    let local = MockClock::new(1_000_000);
+    // Peer clock runs 250 ms ahead of ours
+    let peer = MockClock::new(1_000_250);
+    let mut ctrl =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    ctrl.set_clock(local.clone());
+    let peer_addr = peer_sock.local_addr();
+    ctrl.register_sender(peer_addr);
+
+    let mut buf = [0u8; SYNC_MAX_LEN];
+    ctrl.maybe_send_ping(&local_sock);
+    let (n, from) = peer_sock.recv_packet_from(&mut buf).unwrap();
+    let Ok(SyncMessage::Ping { t0_ms }) = decode_sync(&buf[..n]) else {
+      panic!("expected ping");
+    };
+    assert_eq!(t0_ms, 1_000_000);
+
+    // 5 ms each way, 2 ms of processing at the peer
+    local.advance(5);
+    peer.advance(5);
+    let t1_ms = peer.now_ms();
+    local.advance(2);
+    peer.advance(2);
+    respond_to_ping_with(&peer, &peer_sock, from, t0_ms, t1_ms, false);
+    local.advance(5);
+
+    let (n, _) = local_sock.recv_packet_from(&mut buf).unwrap();
+    let Ok(SyncMessage::Pong {
+      t0_ms,
+      t1_ms,
+      t2_ms,
+      ..
+    }) = decode_sync(&buf[..n])
+    else {
+      panic!("expected pong");
+    };
+    assert_eq!((t0_ms, t1_ms, t2_ms), (1_000_000, 1_000_255, 1_000_257));
+    ctrl.on_pong(t0_ms, t1_ms, t2_ms);
+
+    let state = ctrl.peer_state(peer_addr).unwrap();
+    assert_eq!(state.offset_ms, 250.0);
+    assert_eq!(state.delay_ms, 10.0);
+    // A packet stamped by the peer alongside the pong spent 5 ms in flight
+    assert_eq!(ctrl.compute_latency_ms(t2_ms), 5.0);
+  }
+
+  #[test]
+  fn peers_keep_independent_estimates() {
+    let a: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    let b: SocketAddr = "127.0.0.2:5000".parse().unwrap();
+    let mut ctrl =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let now = 1_000_000;
+    ctrl.set_clock(MockClock::new(now));
+    ctrl.add_peer(a);
+    ctrl.add_peer(b);
+    // Peer clocks 100 ms ahead and 50 ms behind, 10 ms round trip
+    ctrl.on_pong_from(a, now - 10, now + 95, now + 95);
+    ctrl.on_pong_from(b, now - 10, now - 55, now - 55);
+
+    let off = |addr| ctrl.peer_state(addr).unwrap().offset_ms;
+    assert!((off(a) - 100.0).abs() < 5.0, "offset a {}", off(a));
+    assert!((off(b) + 50.0).abs() < 5.0, "offset b {}", off(b));
+    let unknown: SocketAddr = "127.0.0.3:5000".parse().unwrap();
+    assert!(ctrl.peer_state(unknown).is_none());
+    let order: Vec<_> = ctrl.peer_states().iter().map(|(p, _)| *p).collect();
+    assert_eq!(order, [a, b]);
+
+    // The trait view follows the registered sender
+    let off_b = off(b);
+    ctrl.register_sender(b);
+    assert_eq!(ctrl.offset_ms(), off_b);
+  }
+
+  #[test]
+  fn peer_status_shows_peers_that_answered() {
+    let a: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    let b: SocketAddr = "127.0.0.2:5000".parse().unwrap();
+    let mut ctrl =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let now = 1_000_000;
+    ctrl.set_clock(MockClock::new(now));
+    ctrl.add_peer(a);
+    ctrl.add_peer(b);
+    assert_eq!(ctrl.peer_status(), "");
+
+    // 100 ms ahead over a 10 ms round trip; b has not answered
+    ctrl.on_pong_from(a, now - 10, now + 95, now + 95);
+    let status = ctrl.peer_status();
+    assert!(
+      status.starts_with("| 127.0.0.1:5000 Off: +100.00 ms Drift: "),
+      "{status}"
+    );
+    assert!(status.ends_with(" RTT: 10.00 ms   "), "{status}");
+    assert!(!status.contains("127.0.0.2"));
+  }
+
+  #[test]
+  fn pongs_from_unknown_addresses_are_ignored() {
+    let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    let mut ctrl =
+      DefaultSyncController::with_algorithm(SyncAlgorithm::Ewma, 1_000);
+    let now = 1_000_000;
+    ctrl.set_clock(MockClock::new(now));
+    ctrl.add_peer(peer);
+    for port in 6000..6100 {
+      let spoofed = SocketAddr::new(peer.ip(), port);
+      ctrl.on_pong_from(spoofed, now - 10, now + 5_000, now + 5_000);
+    }
+    assert!(ctrl.peer_states().is_empty());
+    assert_eq!(ctrl.peers.len(), 1);
+    ctrl.on_pong_from(peer, now - 10, now - 5, now - 5);
+    assert_eq!(ctrl.peer_states().len(), 1);
+  }
+
+  #[test]
+  fn state_cache_expires_after_ttl() {
+    let base = Instant::now();
+    let ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let state = TimeSyncState {
+      offset_ms: 4.0,
+      ..Default::default()
+    };
+    let mut cache = SyncStateCache::new(Duration::from_secs(30));
+    cache.store(ip, state, base);
+    let soon = base.checked_add(Duration::from_secs(10)).unwrap();
+    assert_eq!(cache.take(ip, soon).map(|s| s.offset_ms), Some(4.0));
+    // take() consumes the entry
+    assert!(cache.take(ip, soon).is_none());
+
+    cache.store(ip, state, base);
+    let late = base.checked_add(Duration::from_secs(31)).unwrap();
+    assert!(cache.take(ip, late).is_none());
   }
 }
@@ -1,5 +1,15 @@
 // Time synchronization estimator: offset (ms) and drift (ppm).
 
+use std::collections::VecDeque;
+
+// A pong whose round trip exceeds this multiple of the smoothed delay was
+// probably queued on one leg only, which skews its offset by up to half
+// the extra delay
+const OUTLIER_DELAY_FACTOR: f64 = 3.0;
+// Smoothed delay assumed when judging outliers, so ordinary jitter on a
+// sub-millisecond LAN is not mistaken for one
+const OUTLIER_MIN_DELAY_MS: f64 = 2.0;
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct TimeSyncState {
   pub offset_ms: f64,
@@ -7,6 +17,15 @@ pub struct TimeSyncState {
   pub drift_ppm: f64,
 }
 
+impl TimeSyncState {
+  /// Sender frames per receiver frame implied by `drift_ppm`: above 1.0 the
+  /// sender's clock runs fast, so playback must consume slightly more input
+  /// per output frame to keep its buffer level steady.
+  pub fn resample_ratio(&self) -> f64 {
+    1.0 + self.drift_ppm / 1_000_000.0
+  }
+}
+
 pub trait TimeSync {
   fn update(
     &mut self,
@@ -16,6 +35,13 @@ pub trait TimeSync {
     t3_ms: u64,
   ) -> TimeSyncState;
   fn state(&self) -> TimeSyncState;
+  /// Start from a previously converged state instead of cold. Estimators
+  /// that cannot make use of a seed may ignore it.
+  fn seed_state(&mut self, _state: TimeSyncState) {}
+  /// Pongs left out of the offset estimate as unreliable.
+  fn rejected_samples(&self) -> u64 {
+    0
+  }
 }
 
 #[derive(Debug)]
@@ -25,6 +51,7 @@ pub struct TimeSyncEstimator {
   last_offset_ms: Option<f64>,
   last_t3_ms: Option<u64>,
   state: TimeSyncState,
+  rejected_samples: u64,
 }
 
 impl TimeSyncEstimator {
@@ -35,6 +62,7 @@ impl TimeSyncEstimator {
       last_offset_ms: None,
       last_t3_ms: None,
       state: Default::default(),
+      rejected_samples: 0,
     }
   }
 
@@ -60,9 +88,17 @@ impl TimeSyncEstimator {
       self.state.offset_ms = offset;
       self.state.delay_ms = delay.max(0.0);
     } else {
-      self.state.offset_ms = (1.0 - a) * self.state.offset_ms + a * offset;
+      let limit =
+        OUTLIER_DELAY_FACTOR * self.state.delay_ms.max(OUTLIER_MIN_DELAY_MS);
+      // Delay is still folded in, so a lasting rise in latency soon
+      // raises the limit and is accepted again
       self.state.delay_ms =
         (1.0 - a) * self.state.delay_ms + a * delay.max(0.0);
+      if delay > limit {
+        self.rejected_samples += 1;
+        return self.state;
+      }
+      self.state.offset_ms = (1.0 - a) * self.state.offset_ms + a * offset;
     }
 
     // Drift as change in offset over change in t3
@@ -86,6 +122,12 @@ impl TimeSyncEstimator {
   pub fn state(&self) -> TimeSyncState {
     self.state
   }
+
+  /// Pongs whose round trip was too long for their offset to be trusted.
+  /// Their delay still counts toward `delay_ms`.
+  pub fn rejected_samples(&self) -> u64 {
+    self.rejected_samples
+  }
 }
 
 impl TimeSync for TimeSyncEstimator {
@@ -101,6 +143,100 @@ impl TimeSync for TimeSyncEstimator {
   fn state(&self) -> TimeSyncState {
     TimeSyncEstimator::state(self)
   }
+  fn seed_state(&mut self, state: TimeSyncState) {
+    // Treat the seed as the running average; the first real pong is then
+    // blended in with alpha rather than replacing it. Drift needs a fresh
+    // t3 baseline, so last_t3_ms stays unset.
+    self.state = state;
+    self.last_offset_ms = Some(state.offset_ms);
+  }
+  fn rejected_samples(&self) -> u64 {
+    TimeSyncEstimator::rejected_samples(self)
+  }
+}
+
+/// Median-filter estimator: keeps the last `window` (offset, delay) samples
+/// and reports their medians, so a single outlier pong cannot move the
+/// estimate. Drift is the median of the offset slopes between every pair of
+/// samples in the window (Theil-Sen), so an outlier at either end of the
+/// window cannot tilt it either.
+#[derive(Debug)]
+pub struct MedianTimeSync {
+  window: usize,
+  // (offset_ms, delay_ms, t3_ms)
+  samples: VecDeque<(f64, f64, u64)>,
+  state: TimeSyncState,
+}
+
+impl MedianTimeSync {
+  pub fn new(window: usize) -> Self {
+    Self {
+      window: window.max(1),
+      samples: VecDeque::new(),
+      state: Default::default(),
+    }
+  }
+
+  fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+      (values[mid - 1] + values[mid]) / 2.0
+    } else {
+      values[mid]
+    }
+  }
+
+  // Median of the pairwise offset slopes in ms per ms, if any two samples
+  // are apart in time
+  fn slope(&self) -> Option<f64> {
+    let mut slopes = Vec::new();
+    for (i, a) in self.samples.iter().enumerate() {
+      for b in self.samples.iter().skip(i + 1) {
+        let dt = b.2.saturating_sub(a.2) as f64;
+        if dt > 0.0 {
+          slopes.push((b.0 - a.0) / dt);
+        }
+      }
+    }
+    (!slopes.is_empty()).then(|| Self::median(slopes))
+  }
+}
+
+impl TimeSync for MedianTimeSync {
+  fn update(
+    &mut self,
+    t0_ms: u64,
+    t1_ms: u64,
+    t2_ms: u64,
+    t3_ms: u64,
+  ) -> TimeSyncState {
+    let t0 = t0_ms as f64;
+    let t1 = t1_ms as f64;
+    let t2 = t2_ms as f64;
+    let t3 = t3_ms as f64;
+
+    let delay = ((t3 - t0) - (t2 - t1)).max(0.0);
+    let offset = ((t1 - t0) + (t2 - t3)) / 2.0;
+
+    if self.samples.len() == self.window {
+      self.samples.pop_front();
+    }
+    self.samples.push_back((offset, delay, t3_ms));
+
+    self.state.offset_ms =
+      Self::median(self.samples.iter().map(|s| s.0).collect());
+    self.state.delay_ms =
+      Self::median(self.samples.iter().map(|s| s.1).collect());
+    if let Some(slope) = self.slope() {
+      self.state.drift_ppm = slope * 1_000_000.0;
+    }
+    self.state
+  }
+
+  fn state(&self) -> TimeSyncState {
+    self.state
+  }
 }
 
 #[cfg(test)]
@@ -123,4 +259,84 @@ mod tests {
     let _ = est.update(1000, 1015, 1015, 1020);
     assert!(est.state().offset_ms > 0.0);
   }
+
+  #[test]
+  fn seeded_estimator_blends_first_sample() {
+    let mut est = TimeSyncEstimator::new(0.5, 0.5);
+    est.seed_state(TimeSyncState {
+      offset_ms: 10.0,
+      delay_ms: 20.0,
+      drift_ppm: 0.0,
+    });
+    // zero-offset sample moves the seeded offset halfway
+    let s = est.update(1000, 1010, 1010, 1020);
+    assert!(
+      (s.offset_ms - 5.0).abs() < 1e-9,
+      "offset was {}",
+      s.offset_ms
+    );
+  }
+
+  #[test]
+  fn single_outlier_moves_neither_estimate() {
+    let mut ewma = TimeSyncEstimator::new(0.2, 0.2);
+    let mut median = MedianTimeSync::new(5);
+    let mut t = 1000u64;
+    for _ in 0..4 {
+      ewma.update(t, t + 10, t + 10, t + 20);
+      TimeSync::update(&mut median, t, t + 10, t + 10, t + 20);
+      t += 1000;
+    }
+    // One pong with a 100 ms asymmetric delay (offset +50 ms)
+    let e = ewma.update(t, t + 110, t + 110, t + 120);
+    let m = TimeSync::update(&mut median, t, t + 110, t + 110, t + 120);
+    assert!(e.offset_ms.abs() < 1e-9, "ewma offset was {}", e.offset_ms);
+    assert_eq!(e.drift_ppm, 0.0);
+    assert_eq!(ewma.rejected_samples(), 1);
+    assert!(
+      m.offset_ms.abs() < 1e-9,
+      "median offset was {}",
+      m.offset_ms
+    );
+    assert!((m.delay_ms - 20.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn median_drift_ignores_an_outlier_at_the_window_edge() {
+    // The sender clock gains 1 ms every 10 s (100 ppm); one pong carries
+    // 100 ms of asymmetric delay (offset +50 ms), first or last in the window
+    for outlier in [0, 4] {
+      let mut median = MedianTimeSync::new(5);
+      let mut m = TimeSyncState::default();
+      for i in 0..5u64 {
+        let t = 1000 + i * 10_000;
+        let skew = if i == outlier { i + 100 } else { i };
+        m = TimeSync::update(&mut median, t, t + 10 + skew, t + 10 + i, t + 20);
+      }
+      assert!(
+        (m.drift_ppm - 100.0).abs() < 1e-6,
+        "outlier at {outlier}: drift was {}",
+        m.drift_ppm
+      );
+    }
+  }
+
+  #[test]
+  fn lasting_delay_rise_is_accepted_again() {
+    let mut est = TimeSyncEstimator::new(0.2, 0.2);
+    let mut t = 1000u64;
+    est.update(t, t + 10, t + 10, t + 20);
+    // The route got slower for good, with the extra 100 ms on the way out
+    let mut accepted_after = None;
+    for i in 1..=10 {
+      t += 1000;
+      est.update(t, t + 110, t + 110, t + 120);
+      if accepted_after.is_none() && est.state().offset_ms > 0.0 {
+        accepted_after = Some(i);
+      }
+    }
+    let accepted_after = accepted_after.expect("offset never moved");
+    assert!(accepted_after <= 3, "accepted after {accepted_after} pongs");
+    assert_eq!(est.rejected_samples(), accepted_after - 1);
+  }
 }
@@ -0,0 +1,581 @@
+// Packet transports: plain UDP datagrams, or length-prefixed frames over TCP
+// for paths where UDP is blocked or too lossy.
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+
+// Upper bound on a TCP frame; anything larger is treated as a corrupt stream
+const MAX_FRAME_LEN: usize = 1 << 20;
+/// Connections a `TcpServer::bind` server holds at once; later ones are
+/// closed as soon as they are accepted.
+pub const DEFAULT_MAX_TCP_PEERS: usize = 64;
+
+/// Moves whole packets between peers, like `UdpSocket::send_to`/`recv_from`.
+pub trait Transport: Send + Sync {
+  fn send_packet_to(
+    &self,
+    packet: &[u8],
+    addr: SocketAddr,
+  ) -> io::Result<usize>;
+  fn recv_packet_from(&self, buf: &mut [u8])
+  -> io::Result<(usize, SocketAddr)>;
+
+  /// Make `recv_packet_from` fail with `WouldBlock` or `TimedOut` after
+  /// waiting this long; `None` blocks indefinitely.
+  fn set_recv_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "receive timeout not supported by this transport",
+    ))
+  }
+}
+
+impl Transport for UdpSocket {
+  fn send_packet_to(
+    &self,
+    packet: &[u8],
+    addr: SocketAddr,
+  ) -> io::Result<usize> {
+    self.send_to(packet, addr)
+  }
+
+  fn recv_packet_from(
+    &self,
+    buf: &mut [u8],
+  ) -> io::Result<(usize, SocketAddr)> {
+    self.recv_from(buf)
+  }
+
+  fn set_recv_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.set_read_timeout(timeout)
+  }
+}
+
+/// A UDP socket bound with `sockopt::bind_dual_stack`. IPv4 peers are
+/// reported in plain IPv4 form rather than IPv4-mapped, so they read the
+/// same as on an IPv4 socket, and are mapped back when sending.
+pub struct DualStackUdp {
+  socket: UdpSocket,
+}
+
+impl DualStackUdp {
+  pub fn new(socket: UdpSocket) -> Self {
+    Self { socket }
+  }
+}
+
+impl Transport for DualStackUdp {
+  fn send_packet_to(
+    &self,
+    packet: &[u8],
+    addr: SocketAddr,
+  ) -> io::Result<usize> {
+    let addr = match addr {
+      SocketAddr::V4(v4) => {
+        SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port())
+      }
+      v6 => v6,
+    };
+    self.socket.send_to(packet, addr)
+  }
+
+  fn recv_packet_from(
+    &self,
+    buf: &mut [u8],
+  ) -> io::Result<(usize, SocketAddr)> {
+    let (n, addr) = self.socket.recv_from(buf)?;
+    Ok((n, SocketAddr::new(addr.ip().to_canonical(), addr.port())))
+  }
+
+  fn set_recv_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.socket.set_read_timeout(timeout)
+  }
+}
+
+/// Bind a sender's UDP socket to `bind`, e.g. a fixed port that firewall
+/// rules and the receiver's per-source tracking can rely on. Every
+/// destination must share the address family of `bind`, since the socket
+/// cannot reach any other.
+pub fn bind_udp_sender(
+  bind: SocketAddr,
+  dests: &[SocketAddr],
+) -> io::Result<UdpSocket> {
+  if let Some(dest) = dests.iter().find(|d| d.is_ipv4() != bind.is_ipv4()) {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("cannot reach {dest} from local address {bind}"),
+    ));
+  }
+  UdpSocket::bind(bind)
+}
+
+/// Receive one packet, or None if the wait ran out or was interrupted
+/// first, so a loop with a receive timeout set still wakes up regularly
+/// while nothing arrives.
+pub fn recv_or_idle<T: Transport + ?Sized>(
+  transport: &T,
+  buf: &mut [u8],
+) -> io::Result<Option<(usize, SocketAddr)>> {
+  match transport.recv_packet_from(buf) {
+    Ok(r) => Ok(Some(r)),
+    Err(e)
+      if matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+          | io::ErrorKind::TimedOut
+          | io::ErrorKind::Interrupted
+      ) =>
+    {
+      Ok(None)
+    }
+    Err(e) => Err(e),
+  }
+}
+
+/// Write `packet` as one frame: u32 big-endian length, then the bytes.
+pub fn write_frame<W: Write>(w: &mut W, packet: &[u8]) -> io::Result<()> {
+  let mut frame = Vec::with_capacity(4 + packet.len());
+  frame.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+  frame.extend_from_slice(packet);
+  // One write per frame so frames from different threads never interleave
+  w.write_all(&frame)
+}
+
+/// Read one frame into `buf`. Returns false on a clean end of stream.
+pub fn read_frame<R: Read>(r: &mut R, buf: &mut Vec<u8>) -> io::Result<bool> {
+  let mut len = [0u8; 4];
+  match r.read_exact(&mut len) {
+    Ok(()) => {}
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+    Err(e) => return Err(e),
+  }
+  let len = u32::from_be_bytes(len) as usize;
+  if len > MAX_FRAME_LEN {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("frame of {len} bytes exceeds limit"),
+    ));
+  }
+  buf.resize(len, 0);
+  r.read_exact(buf)?;
+  Ok(true)
+}
+
+fn copy_packet(packet: &[u8], buf: &mut [u8]) -> usize {
+  let n = packet.len().min(buf.len());
+  buf[..n].copy_from_slice(&packet[..n]);
+  n
+}
+
+/// Sender side of a TCP connection. All packets go to the connected peer
+/// regardless of the address passed to `send_packet_to`.
+pub struct TcpClient {
+  writer: Mutex<TcpStream>,
+  reader: Mutex<(BufReader<TcpStream>, Vec<u8>)>,
+  peer: SocketAddr,
+}
+
+impl TcpClient {
+  pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let peer = stream.peer_addr()?;
+    let reader = BufReader::new(stream.try_clone()?);
+    Ok(Self {
+      writer: Mutex::new(stream),
+      reader: Mutex::new((reader, Vec::new())),
+      peer,
+    })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.writer.lock().unwrap().local_addr()
+  }
+}
+
+impl Transport for TcpClient {
+  fn send_packet_to(
+    &self,
+    packet: &[u8],
+    _addr: SocketAddr,
+  ) -> io::Result<usize> {
+    write_frame(&mut *self.writer.lock().unwrap(), packet)?;
+    Ok(packet.len())
+  }
+
+  fn recv_packet_from(
+    &self,
+    buf: &mut [u8],
+  ) -> io::Result<(usize, SocketAddr)> {
+    let mut guard = self.reader.lock().unwrap();
+    let (reader, frame) = &mut *guard;
+    if !read_frame(reader, frame)? {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "tcp connection closed",
+      ));
+    }
+    Ok((copy_packet(frame, buf), self.peer))
+  }
+}
+
+/// Receiver side: accepts TCP senders, up to a limit, and presents their
+/// frames as packets tagged with the sender's address.
+pub struct TcpServer {
+  incoming: Mutex<mpsc::Receiver<(SocketAddr, Vec<u8>)>>,
+  // Each writer has its own lock, so a peer that stops reading only stalls
+  // the sends addressed to it
+  peers: Arc<Mutex<HashMap<SocketAddr, Arc<Mutex<TcpStream>>>>>,
+  local_addr: SocketAddr,
+  recv_timeout: Mutex<Option<Duration>>,
+}
+
+impl TcpServer {
+  pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+    Self::bind_with_limit(addr, DEFAULT_MAX_TCP_PEERS)
+  }
+
+  /// Like `bind`, holding at most `max_peers` connections at once.
+  pub fn bind_with_limit<A: ToSocketAddrs>(
+    addr: A,
+    max_peers: usize,
+  ) -> io::Result<Self> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let (tx, rx) = mpsc::channel();
+    let peers = Arc::new(Mutex::new(HashMap::new()));
+    let accept_peers = peers.clone();
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let (Ok(peer), Ok(writer)) = (stream.peer_addr(), stream.try_clone())
+        else {
+          continue;
+        };
+        {
+          let mut peers = accept_peers.lock().unwrap();
+          if peers.len() >= max_peers {
+            // Dropping the stream closes the connection
+            continue;
+          }
+          peers.insert(peer, Arc::new(Mutex::new(writer)));
+        }
+        let _ = stream.set_nodelay(true);
+        let tx = tx.clone();
+        let peers = accept_peers.clone();
+        std::thread::spawn(move || {
+          let mut reader = BufReader::new(stream);
+          let mut frame = Vec::new();
+          while let Ok(true) = read_frame(&mut reader, &mut frame) {
+            if tx.send((peer, frame.clone())).is_err() {
+              break;
+            }
+          }
+          peers.lock().unwrap().remove(&peer);
+        });
+      }
+    });
+    Ok(Self {
+      incoming: Mutex::new(rx),
+      peers,
+      local_addr,
+      recv_timeout: Mutex::new(None),
+    })
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+}
+
+impl Transport for TcpServer {
+  fn send_packet_to(
+    &self,
+    packet: &[u8],
+    addr: SocketAddr,
+  ) -> io::Result<usize> {
+    let stream = self.peers.lock().unwrap().get(&addr).cloned();
+    let stream = stream.ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::NotConnected,
+        format!("no tcp connection from {addr}"),
+      )
+    })?;
+    write_frame(&mut *stream.lock().unwrap(), packet)?;
+    Ok(packet.len())
+  }
+
+  fn recv_packet_from(
+    &self,
+    buf: &mut [u8],
+  ) -> io::Result<(usize, SocketAddr)> {
+    let stopped =
+      || io::Error::new(io::ErrorKind::BrokenPipe, "tcp listener stopped");
+    let incoming = self.incoming.lock().unwrap();
+    let (peer, packet) = match *self.recv_timeout.lock().unwrap() {
+      None => incoming.recv().map_err(|_| stopped())?,
+      Some(t) => incoming.recv_timeout(t).map_err(|e| match e {
+        mpsc::RecvTimeoutError::Timeout => {
+          io::Error::new(io::ErrorKind::TimedOut, "no tcp frame received")
+        }
+        mpsc::RecvTimeoutError::Disconnected => stopped(),
+      })?,
+    };
+    Ok((copy_packet(&packet, buf), peer))
+  }
+
+  fn set_recv_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    *self.recv_timeout.lock().unwrap() = timeout;
+    Ok(())
+  }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+/// A datagram in flight on a `MemoryNetwork`: source address and payload.
+type MemoryDatagram = (SocketAddr, Vec<u8>);
+
+#[cfg(any(test, feature = "test-util"))]
+/// In-process stand-in for a network, for testing code that talks through
+/// `Transport` without real sockets. Delivery is instant and in order;
+/// packets sent to an address nobody has bound are dropped, as with UDP.
+/// Only built for tests and with the `test-util` feature.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+  endpoints: Arc<Mutex<HashMap<SocketAddr, mpsc::Sender<MemoryDatagram>>>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MemoryNetwork {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Claim `addr` on this network. Fails with `AddrInUse` while another
+  /// transport holds it.
+  pub fn bind(&self, addr: SocketAddr) -> io::Result<MemoryTransport> {
+    let mut endpoints = self.endpoints.lock().unwrap();
+    if endpoints.contains_key(&addr) {
+      return Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("{addr} is already bound"),
+      ));
+    }
+    let (tx, rx) = mpsc::channel();
+    endpoints.insert(addr, tx);
+    Ok(MemoryTransport {
+      network: self.clone(),
+      local_addr: addr,
+      incoming: Mutex::new(rx),
+      recv_timeout: Mutex::new(None),
+    })
+  }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+/// One endpoint of a `MemoryNetwork`; unbinds its address when dropped.
+pub struct MemoryTransport {
+  network: MemoryNetwork,
+  local_addr: SocketAddr,
+  incoming: Mutex<mpsc::Receiver<MemoryDatagram>>,
+  recv_timeout: Mutex<Option<Duration>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MemoryTransport {
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Drop for MemoryTransport {
+  fn drop(&mut self) {
+    self
+      .network
+      .endpoints
+      .lock()
+      .unwrap()
+      .remove(&self.local_addr);
+  }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Transport for MemoryTransport {
+  fn send_packet_to(
+    &self,
+    packet: &[u8],
+    addr: SocketAddr,
+  ) -> io::Result<usize> {
+    if let Some(tx) = self.network.endpoints.lock().unwrap().get(&addr) {
+      let _ = tx.send((self.local_addr, packet.to_vec()));
+    }
+    Ok(packet.len())
+  }
+
+  fn recv_packet_from(
+    &self,
+    buf: &mut [u8],
+  ) -> io::Result<(usize, SocketAddr)> {
+    let incoming = self.incoming.lock().unwrap();
+    let timed_out =
+      || io::Error::new(io::ErrorKind::WouldBlock, "no packet queued");
+    let (peer, packet) = match *self.recv_timeout.lock().unwrap() {
+      // The sender half lives in the network map, so this never disconnects
+      None => incoming.recv().map_err(|_| timed_out())?,
+      Some(t) => incoming.recv_timeout(t).map_err(|_| timed_out())?,
+    };
+    Ok((copy_packet(&packet, buf), peer))
+  }
+
+  fn set_recv_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    *self.recv_timeout.lock().unwrap() = timeout;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+
+  use super::*;
+
+  #[test]
+  fn tcp_frames_roundtrip_both_ways() {
+    let server = TcpServer::bind("127.0.0.1:0").unwrap();
+    let client = TcpClient::connect(server.local_addr()).unwrap();
+    let unused: SocketAddr = "127.0.0.1:9".parse().unwrap();
+
+    client.send_packet_to(b"first", unused).unwrap();
+    client.send_packet_to(b"", unused).unwrap();
+    let mut buf = [0u8; 64];
+    let (n, peer) = server.recv_packet_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"first");
+    assert_eq!(peer, client.local_addr().unwrap());
+    let (n, _) = server.recv_packet_from(&mut buf).unwrap();
+    assert_eq!(n, 0);
+
+    server.send_packet_to(b"reply", peer).unwrap();
+    let (n, _) = client.recv_packet_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"reply");
+
+    server
+      .set_recv_timeout(Some(Duration::from_millis(10)))
+      .unwrap();
+    let err = server.recv_packet_from(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+  }
+
+  #[test]
+  fn tcp_server_closes_connections_beyond_its_limit() {
+    let server = TcpServer::bind_with_limit("127.0.0.1:0", 1).unwrap();
+    let first = TcpClient::connect(server.local_addr()).unwrap();
+    let second = TcpClient::connect(server.local_addr()).unwrap();
+    let unused: SocketAddr = "127.0.0.1:9".parse().unwrap();
+
+    let mut buf = [0u8; 64];
+    assert!(second.recv_packet_from(&mut buf).is_err());
+    first.send_packet_to(b"kept", unused).unwrap();
+    let (n, peer) = server.recv_packet_from(&mut buf).unwrap();
+    assert_eq!(
+      (&buf[..n], peer),
+      (&b"kept"[..], first.local_addr().unwrap())
+    );
+  }
+
+  #[test]
+  fn memory_network_delivers_between_bound_addresses() {
+    let net = MemoryNetwork::new();
+    let a_addr: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+    let b_addr: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+    let a = net.bind(a_addr).unwrap();
+    let b = net.bind(b_addr).unwrap();
+    assert_eq!(
+      net.bind(a_addr).err().map(|e| e.kind()),
+      Some(io::ErrorKind::AddrInUse)
+    );
+    b.set_recv_timeout(Some(Duration::ZERO)).unwrap();
+
+    a.send_packet_to(b"one", b_addr).unwrap();
+    a.send_packet_to(b"two", b_addr).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(b.recv_packet_from(&mut buf).unwrap(), (3, a_addr));
+    assert_eq!(&buf[..3], b"one");
+    b.recv_packet_from(&mut buf).unwrap();
+    assert_eq!(&buf[..3], b"two");
+    let err = b.recv_packet_from(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    // Unbound destinations swallow packets; dropping frees the address
+    drop(b);
+    assert_eq!(a.send_packet_to(b"lost", b_addr).unwrap(), 4);
+    assert!(net.bind(b_addr).is_ok());
+  }
+
+  #[test]
+  fn oversized_frame_is_rejected() {
+    let mut data = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(b"xx");
+    let mut frame = Vec::new();
+    let err = read_frame(&mut data.as_slice(), &mut frame).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(!read_frame(&mut &[][..], &mut frame).unwrap());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn dual_stack_reports_and_answers_ipv4_peers_as_ipv4() {
+    let socket =
+      crate::sockopt::bind_dual_stack("[::]:0".parse().unwrap()).unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let server = DualStackUdp::new(socket);
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(b"ping", ("127.0.0.1", port)).unwrap();
+
+    let mut buf = [0u8; 8];
+    let (n, from) = server.recv_packet_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"ping");
+    assert_eq!(from, client.local_addr().unwrap());
+    server.send_packet_to(b"pong", from).unwrap();
+    let (n, _) = client.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"pong");
+  }
+
+  #[test]
+  fn udp_sender_sends_from_its_bind_address() {
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let dest = peer.local_addr().unwrap();
+    // A port known to be free a moment ago, as a user would pick one
+    let fixed = UdpSocket::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let sender = bind_udp_sender(fixed, &[dest]).unwrap();
+    sender.send_to(b"x", dest).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(peer.recv_from(&mut buf).unwrap(), (1, fixed));
+
+    let v6: SocketAddr = "[::1]:0".parse().unwrap();
+    let err = bind_udp_sender(v6, &[dest]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn idle_receives_time_out_instead_of_blocking() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let local = socket.local_addr().unwrap();
+    socket
+      .set_recv_timeout(Some(Duration::from_millis(20)))
+      .unwrap();
+    let mut buf = [0u8; 8];
+    let started = Instant::now();
+    assert_eq!(recv_or_idle(&socket, &mut buf).unwrap(), None);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    socket.send_to(b"x", local).unwrap();
+    assert_eq!(recv_or_idle(&socket, &mut buf).unwrap(), Some((1, local)));
+  }
+}
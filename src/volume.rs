@@ -1,80 +1,152 @@
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
+use crate::dsp::{AudioFrameReader, sample_to_f32};
+use crate::packet::SampleFormat::{self, I16, U16, U32};
+
+// Integer samples are scaled exactly as payloads read through `dsp` are
+fn normalize(format: SampleFormat, b: &[u8]) -> f64 {
+  f64::from(sample_to_f32(format, b))
+}
+
 #[derive(Debug)]
 pub struct VolumeMeter {
   window: Duration,
-  history: VecDeque<(Instant, f64, usize)>,
+  // Channel count of the plain `add_samples_*` input, if known; those
+  // samples are then also tracked per channel
+  channels: Option<usize>,
+  // (time, sum of squares, sample count, peak magnitude)
+  history: VecDeque<(Instant, f64, usize, f64)>,
   sum_sq: f64,
   count: usize,
+  // Per-channel (sum_sq per channel, frames) for interleaved input
+  channel_history: VecDeque<(Instant, Vec<f64>, usize)>,
+  channel_sum_sq: Vec<f64>,
+  channel_frames: usize,
 }
 
 impl VolumeMeter {
   pub fn new(window: Duration) -> Self {
     Self {
       window,
+      channels: None,
       history: VecDeque::new(),
       sum_sq: 0.0,
       count: 0,
+      channel_history: VecDeque::new(),
+      channel_sum_sq: Vec::new(),
+      channel_frames: 0,
+    }
+  }
+
+  /// A meter whose plain `add_samples_*` input is interleaved `channels`
+  /// channels, tracked per channel as well as combined. Zero channels, as
+  /// from a corrupt header, leaves the layout unknown as with `new`.
+  pub fn with_channels(window: Duration, channels: usize) -> Self {
+    Self {
+      channels: (channels > 0).then_some(channels),
+      ..Self::new(window)
     }
   }
 
+  pub fn channels(&self) -> Option<usize> {
+    self.channels
+  }
+
   pub fn add_samples_f32(&mut self, now: Instant, data: &[f32]) {
-    let sum_sq = data.iter().map(|&v| (v as f64) * (v as f64)).sum();
-    self.push(now, sum_sq, data.len());
+    self.push_samples(now, data.iter().map(|&v| v as f64));
   }
 
   pub fn add_samples_i16(&mut self, now: Instant, data: &[i16]) {
-    let norm = 32768.0f64;
-    let sum_sq = data
-      .iter()
-      .map(|&v| {
-        let x = (v as f64) / norm;
-        x * x
-      })
-      .sum();
-    self.push(now, sum_sq, data.len());
+    let samples = data.iter().map(|v| normalize(I16, &v.to_ne_bytes()));
+    self.push_samples(now, samples);
   }
 
   pub fn add_samples_u16(&mut self, now: Instant, data: &[u16]) {
-    let center = 32768.0f64;
-    let norm = 32768.0f64;
-    let sum_sq = data
-      .iter()
-      .map(|&v| {
-        let x = ((v as f64) - center) / norm;
-        x * x
-      })
-      .sum();
-    self.push(now, sum_sq, data.len());
+    let samples = data.iter().map(|v| normalize(U16, &v.to_ne_bytes()));
+    self.push_samples(now, samples);
   }
 
   pub fn add_samples_u32(&mut self, now: Instant, data: &[u32]) {
-    let center = 2_147_483_648.0f64; // 2^31
-    let norm = 2_147_483_648.0f64; // scale to approx [-1,1]
-    let sum_sq = data
-      .iter()
-      .map(|&v| {
-        let x = ((v as f64) - center) / norm;
-        x * x
-      })
-      .sum();
-    self.push(now, sum_sq, data.len());
+    let samples = data.iter().map(|v| normalize(U32, &v.to_ne_bytes()));
+    self.push_samples(now, samples);
+  }
+
+  /// Add every sample `reader` holds, tracked per channel of its `Meta`.
+  pub fn add_frames(&mut self, now: Instant, reader: &AudioFrameReader) {
+    let channels = reader.meta().channels as usize;
+    self.push_interleaved(now, channels, reader.samples_f32().map(f64::from));
   }
 
   pub fn add_samples_raw(&mut self, now: Instant, sum: f64, len: usize) {
-    self.push(now, sum, len);
+    self.push(now, sum, len, 0.0);
+  }
+
+  fn push_samples(&mut self, now: Instant, samples: impl Iterator<Item = f64>) {
+    if let Some(channels) = self.channels {
+      return self.push_interleaved(now, channels, samples);
+    }
+    let (mut sum_sq, mut n, mut peak) = (0.0f64, 0usize, 0.0f64);
+    for x in samples {
+      sum_sq += x * x;
+      n += 1;
+      peak = peak.max(x.abs());
+    }
+    self.push(now, sum_sq, n, peak);
+  }
+
+  /// Like `add_samples_f32`, but also tracks each channel of interleaved
+  /// `data` separately for `per_channel_dbfs`.
+  pub fn add_samples_f32_interleaved(
+    &mut self,
+    now: Instant,
+    data: &[f32],
+    channels: usize,
+  ) {
+    self.push_interleaved(now, channels, data.iter().map(|&v| v as f64));
   }
 
-  fn push(&mut self, now: Instant, sum_sq: f64, n: usize) {
-    self.history.push_back((now, sum_sq, n));
+  fn push_interleaved(
+    &mut self,
+    now: Instant,
+    channels: usize,
+    samples: impl Iterator<Item = f64>,
+  ) {
+    let channels = channels.max(1);
+    if self.channel_sum_sq.len() != channels {
+      // Channel layout changed; restart per-channel accounting
+      self.channel_history.clear();
+      self.channel_sum_sq = vec![0.0; channels];
+      self.channel_frames = 0;
+    }
+    let mut sums = vec![0.0f64; channels];
+    let mut n = 0usize;
+    let mut peak = 0.0f64;
+    for (i, x) in samples.enumerate() {
+      sums[i % channels] += x * x;
+      n += 1;
+      peak = peak.max(x.abs());
+    }
+    self.push(now, sums.iter().sum(), n, peak);
+
+    let frames = n / channels;
+    for (acc, s) in self.channel_sum_sq.iter_mut().zip(&sums) {
+      *acc += s;
+    }
+    self.channel_frames += frames;
+    self.channel_history.push_back((now, sums, frames));
+    self.prune(now);
+  }
+
+  fn push(&mut self, now: Instant, sum_sq: f64, n: usize, peak: f64) {
+    self.history.push_back((now, sum_sq, n, peak));
     self.sum_sq += sum_sq;
     self.count += n;
     self.prune(now);
   }
 
   fn prune(&mut self, now: Instant) {
-    while let Some(&(t, s, n)) = self.history.front() {
+    while let Some(&(t, s, n, _)) = self.history.front() {
       if now.duration_since(t) > self.window {
         self.sum_sq -= s;
         self.count -= n;
@@ -83,6 +155,17 @@ impl VolumeMeter {
         break;
       }
     }
+    while let Some((t, _, _)) = self.channel_history.front() {
+      if now.duration_since(*t) > self.window {
+        let (_, sums, frames) = self.channel_history.pop_front().unwrap();
+        for (acc, s) in self.channel_sum_sq.iter_mut().zip(&sums) {
+          *acc -= s;
+        }
+        self.channel_frames -= frames;
+      } else {
+        break;
+      }
+    }
   }
 
   pub fn rms(&mut self, now: Instant) -> f64 {
@@ -96,10 +179,300 @@ impl VolumeMeter {
 
   pub fn dbfs(&mut self, now: Instant) -> f64 {
     let rms = self.rms(now);
-    if rms <= 0.0 {
-      -120.0
-    } else {
-      20.0 * rms.log10()
+    rms_to_dbfs(rms)
+  }
+
+  /// Largest sample magnitude within the window, in dBFS.
+  pub fn peak_dbfs(&mut self, now: Instant) -> f64 {
+    self.prune(now);
+    let peak = self.history.iter().fold(0.0f64, |m, e| m.max(e.3));
+    rms_to_dbfs(peak)
+  }
+
+  /// RMS of each channel fed through `add_frames` or
+  /// `add_samples_f32_interleaved`, or to a meter made `with_channels`, in
+  /// channel order. Empty if no samples were recorded per channel.
+  pub fn rms_per_channel(&mut self, now: Instant) -> Vec<f64> {
+    self.prune(now);
+    let frames = self.channel_frames;
+    self
+      .channel_sum_sq
+      .iter()
+      .map(|&s| {
+        if frames == 0 {
+          0.0
+        } else {
+          (s.max(0.0) / frames as f64).sqrt()
+        }
+      })
+      .collect()
+  }
+
+  /// `rms_per_channel` in dBFS.
+  pub fn per_channel_dbfs(&mut self, now: Instant) -> Vec<f64> {
+    self
+      .rms_per_channel(now)
+      .into_iter()
+      .map(rms_to_dbfs)
+      .collect()
+  }
+}
+
+/// RMS over exactly the last `window` samples, regardless of when they
+/// arrived. Useful when the analysis period is defined in samples (e.g. one
+/// video frame's worth) rather than wall-clock time.
+#[derive(Debug)]
+pub struct FixedWindowMeter {
+  window: usize,
+  squares: VecDeque<f64>,
+  sum_sq: f64,
+}
+
+impl FixedWindowMeter {
+  pub fn new(window: usize) -> Self {
+    Self {
+      window: window.max(1),
+      squares: VecDeque::with_capacity(window.max(1)),
+      sum_sq: 0.0,
     }
   }
+
+  pub fn add_samples_f32(&mut self, data: &[f32]) {
+    self.push_samples(data.iter().map(|&v| v as f64));
+  }
+
+  pub fn add_samples_i16(&mut self, data: &[i16]) {
+    self.push_samples(data.iter().map(|v| normalize(I16, &v.to_ne_bytes())));
+  }
+
+  pub fn add_samples_u16(&mut self, data: &[u16]) {
+    self.push_samples(data.iter().map(|v| normalize(U16, &v.to_ne_bytes())));
+  }
+
+  pub fn add_samples_u32(&mut self, data: &[u32]) {
+    self.push_samples(data.iter().map(|v| normalize(U32, &v.to_ne_bytes())));
+  }
+
+  fn push_samples(&mut self, samples: impl Iterator<Item = f64>) {
+    for x in samples {
+      let sq = x * x;
+      self.squares.push_back(sq);
+      self.sum_sq += sq;
+      if self.squares.len() > self.window {
+        self.sum_sq -= self.squares.pop_front().unwrap_or(0.0);
+      }
+    }
+  }
+
+  /// RMS of the last `window` samples, or None until that many arrived.
+  pub fn rms(&self) -> Option<f64> {
+    (self.squares.len() == self.window)
+      .then(|| (self.sum_sq.max(0.0) / self.window as f64).sqrt())
+  }
+
+  pub fn dbfs(&self) -> Option<f64> {
+    self.rms().map(rms_to_dbfs)
+  }
+}
+
+/// Converts a normalized RMS level to dBFS, flooring silence at -120 dB.
+pub fn rms_to_dbfs(rms: f64) -> f64 {
+  if rms <= 0.0 {
+    -120.0
+  } else {
+    20.0 * rms.log10()
+  }
+}
+
+/// RMS level of a whole chunk in dBFS, normalized like `VolumeMeter`. A
+/// trailing partial sample is ignored.
+pub fn chunk_dbfs(format: SampleFormat, data: &[u8]) -> f64 {
+  let bps = format.bytes_per_sample();
+  if bps == 0 || data.len() < bps {
+    return rms_to_dbfs(0.0);
+  }
+  let sum_sq: f64 = data
+    .chunks_exact(bps)
+    .map(|b| normalize(format, b).powi(2))
+    .sum();
+  rms_to_dbfs((sum_sq / (data.len() / bps) as f64).sqrt())
+}
+
+/// Default attack time constant, approximating standard VU ballistics.
+pub const VU_ATTACK: Duration = Duration::from_millis(300);
+/// Default release time constant, approximating standard VU ballistics.
+pub const VU_RELEASE: Duration = Duration::from_millis(300);
+
+/// Exponential smoothing for a displayed dBFS level.
+/// Rising levels follow the attack time constant and falling levels follow
+/// the release time constant, like the needle of a VU meter.
+#[derive(Debug)]
+pub struct SmoothedLevel {
+  attack: Duration,
+  release: Duration,
+  level_db: Option<f64>,
+  last_update: Option<Instant>,
+}
+
+impl SmoothedLevel {
+  pub fn new(attack: Duration, release: Duration) -> Self {
+    Self {
+      attack,
+      release,
+      level_db: None,
+      last_update: None,
+    }
+  }
+
+  /// Feed a new instantaneous level and return the smoothed level.
+  pub fn update(&mut self, now: Instant, db: f64) -> f64 {
+    let smoothed = match (self.level_db, self.last_update) {
+      (Some(prev), Some(last)) => {
+        let tau = if db > prev { self.attack } else { self.release };
+        if tau.is_zero() {
+          db
+        } else {
+          let dt = now.saturating_duration_since(last).as_secs_f64();
+          let k = 1.0 - (-dt / tau.as_secs_f64()).exp();
+          prev + (db - prev) * k
+        }
+      }
+      _ => db,
+    };
+    self.level_db = Some(smoothed);
+    self.last_update = Some(now);
+    smoothed
+  }
+
+  /// Read the meter's current dBFS and return the smoothed level.
+  pub fn dbfs(&mut self, meter: &mut VolumeMeter, now: Instant) -> f64 {
+    let db = meter.dbfs(now);
+    self.update(now, db)
+  }
+}
+
+impl Default for SmoothedLevel {
+  fn default() -> Self {
+    Self::new(VU_ATTACK, VU_RELEASE)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn per_channel_levels_expose_dead_channel() {
+    let now = Instant::now();
+    let mut m = VolumeMeter::new(Duration::from_secs(1));
+    // Left at full scale, right silent
+    let data: Vec<f32> = (0..64)
+      .map(|i| if i % 2 == 0 { 1.0 } else { 0.0 })
+      .collect();
+    m.add_samples_f32_interleaved(now, &data, 2);
+    let levels = m.per_channel_dbfs(now);
+    assert_eq!(levels.len(), 2);
+    assert!(levels[0].abs() < 1e-9, "left was {}", levels[0]);
+    assert_eq!(levels[1], -120.0);
+    // Combined level still reflects both channels
+    assert!((m.dbfs(now) - 20.0 * 0.5f64.sqrt().log10()).abs() < 1e-9);
+  }
+
+  #[test]
+  fn channel_count_splits_plain_samples() {
+    let now = Instant::now();
+    let mut mono = VolumeMeter::with_channels(Duration::from_secs(1), 1);
+    mono.add_samples_i16(now, &[16384, -16384, 16384, -16384]);
+    assert_eq!(mono.channels(), Some(1));
+    assert_eq!(mono.rms_per_channel(now), vec![0.5]);
+    assert_eq!(mono.rms(now), 0.5);
+
+    // Left at half scale, right at quarter scale
+    let mut stereo = VolumeMeter::with_channels(Duration::from_secs(1), 2);
+    stereo.add_samples_f32(now, &[0.5, 0.25, -0.5, -0.25]);
+    assert_eq!(stereo.rms_per_channel(now), vec![0.5, 0.25]);
+    let combined = ((0.25 + 0.0625) / 2.0f64).sqrt();
+    assert!((stereo.rms(now) - combined).abs() < 1e-12);
+
+    // A zero channel count tracks nothing per channel and divides by
+    // nothing, but still meters the samples combined
+    let mut unknown = VolumeMeter::with_channels(Duration::from_secs(1), 0);
+    unknown.add_samples_f32(now, &[0.5, -0.5]);
+    assert_eq!(unknown.channels(), None);
+    assert!(unknown.rms_per_channel(now).is_empty());
+    assert_eq!(unknown.rms(now), 0.5);
+  }
+
+  #[test]
+  fn peak_tracks_largest_sample_in_window() {
+    let base = Instant::now();
+    let mut m = VolumeMeter::new(Duration::from_secs(1));
+    m.add_samples_i16(base, &[0, -16384, 100]);
+    assert!((m.peak_dbfs(base) - 20.0 * 0.5f64.log10()).abs() < 1e-9);
+    let later = base.checked_add(Duration::from_secs(2)).unwrap();
+    m.add_samples_f32(later, &[0.0]);
+    assert_eq!(m.peak_dbfs(later), -120.0);
+  }
+
+  #[test]
+  fn fixed_window_rms_over_exact_sample_count() {
+    let mut m = FixedWindowMeter::new(4);
+    m.add_samples_f32(&[0.5, -0.5, 0.5]);
+    assert_eq!(m.rms(), None);
+    m.add_samples_f32(&[-0.5]);
+    assert!((m.rms().unwrap() - 0.5).abs() < 1e-12);
+    // Oldest three samples fall out: window is [-0.5, 1.0, 1.0, 1.0]
+    m.add_samples_f32(&[1.0, 1.0, 1.0]);
+    let expected = ((0.25 + 3.0) / 4.0f64).sqrt();
+    assert!((m.rms().unwrap() - expected).abs() < 1e-12);
+    assert!((m.dbfs().unwrap() - 20.0 * expected.log10()).abs() < 1e-9);
+  }
+
+  #[test]
+  fn smoothed_level_starts_at_first_value() {
+    let base = Instant::now();
+    let mut s = SmoothedLevel::default();
+    assert_eq!(s.update(base, -20.0), -20.0);
+  }
+
+  #[test]
+  fn smoothed_level_moves_one_time_constant() {
+    let base = Instant::now();
+    let mut s = SmoothedLevel::new(
+      Duration::from_millis(100),
+      Duration::from_millis(1000),
+    );
+    s.update(base, -60.0);
+    // Rising by one attack time constant covers ~63% of the step
+    let t = base.checked_add(Duration::from_millis(100)).unwrap();
+    let up = s.update(t, 0.0);
+    let expected = -60.0 * (-1.0f64).exp();
+    assert!((up - expected).abs() < 1e-9, "up was {up}");
+    // Falling uses the slower release constant
+    let t = t.checked_add(Duration::from_millis(100)).unwrap();
+    let down = s.update(t, -60.0);
+    assert!(down > up - 10.0, "down was {down}");
+  }
+
+  #[test]
+  fn chunk_dbfs_matches_the_meter_scale() {
+    let i16s = |v: &[i16]| -> Vec<u8> {
+      v.iter().flat_map(|s| s.to_ne_bytes()).collect()
+    };
+    let full = chunk_dbfs(I16, &i16s(&[i16::MAX, i16::MIN]));
+    assert!(full.abs() < 0.01, "full scale was {full}");
+    let half = chunk_dbfs(I16, &i16s(&[16384, -16384]));
+    assert!((half + 6.02).abs() < 0.01, "half scale was {half}");
+    // The odd trailing byte is not a sample
+    let mut torn = i16s(&[16384, -16384]);
+    torn.push(0x7f);
+    assert_eq!(chunk_dbfs(I16, &torn), half);
+
+    let mid: Vec<u8> = [0x8000u16; 4]
+      .iter()
+      .flat_map(|s| s.to_ne_bytes())
+      .collect();
+    assert_eq!(chunk_dbfs(U16, &mid), -120.0);
+    assert_eq!(chunk_dbfs(SampleFormat::F32, &[]), -120.0);
+  }
 }
@@ -0,0 +1,179 @@
+// Stall detection for capture inputs. An input feeds its heartbeat whenever
+// it shows it is alive, which for a loopback capture includes the waits
+// that time out while nothing plays, not only delivered audio.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// Handle an input uses to tell its `InputWatchdog` it is alive. Cheap to
+/// clone and safe to call from a realtime callback.
+#[derive(Clone)]
+pub struct InputHeartbeat {
+  clock: Arc<dyn Clock>,
+  last_ms: Arc<AtomicU64>,
+}
+
+impl InputHeartbeat {
+  pub fn beat(&self) {
+    self.last_ms.store(self.clock.now_ms(), Ordering::Relaxed);
+  }
+}
+
+/// A change reported by `InputWatchdog::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogEvent {
+  /// No heartbeat for this long, at least the timeout.
+  Stalled(Duration),
+  /// Heartbeats arrived again after a stall.
+  Resumed,
+}
+
+pub struct InputWatchdog {
+  heartbeat: InputHeartbeat,
+  timeout: Duration,
+  stalled: bool,
+}
+
+impl InputWatchdog {
+  /// A watchdog that fires once `timeout` passes without a heartbeat,
+  /// counting from now.
+  pub fn new(clock: Arc<dyn Clock>, timeout: Duration) -> Self {
+    let heartbeat = InputHeartbeat {
+      clock,
+      last_ms: Arc::new(AtomicU64::new(0)),
+    };
+    heartbeat.beat();
+    Self {
+      heartbeat,
+      timeout,
+      stalled: false,
+    }
+  }
+
+  pub fn heartbeat(&self) -> InputHeartbeat {
+    self.heartbeat.clone()
+  }
+
+  pub fn timeout(&self) -> Duration {
+    self.timeout
+  }
+
+  /// Time since the last heartbeat.
+  pub fn idle(&self) -> Duration {
+    let last = self.heartbeat.last_ms.load(Ordering::Relaxed);
+    Duration::from_millis(self.heartbeat.clock.now_ms().saturating_sub(last))
+  }
+
+  pub fn is_stalled(&self) -> bool {
+    self.stalled
+  }
+
+  /// Whether the input stalled or resumed since the last call.
+  pub fn check(&mut self) -> Option<WatchdogEvent> {
+    let idle = self.idle();
+    match (self.stalled, idle >= self.timeout) {
+      (false, true) => {
+        self.stalled = true;
+        Some(WatchdogEvent::Stalled(idle))
+      }
+      (true, false) => {
+        self.stalled = false;
+        Some(WatchdogEvent::Resumed)
+      }
+      _ => None,
+    }
+  }
+}
+
+/// Run `capture` on `device` until it ends. When it fails because the
+/// device went away, as `is_lost` tells, `reacquire` supplies the device to
+/// carry on with, such as the new system default; any other outcome is
+/// returned.
+pub fn capture_following<D, E>(
+  mut device: D,
+  mut capture: impl FnMut(&D) -> Result<(), E>,
+  is_lost: impl Fn(&E) -> bool,
+  mut reacquire: impl FnMut() -> D,
+) -> Result<(), E> {
+  loop {
+    match capture(&device) {
+      Err(err) if is_lost(&err) => device = reacquire(),
+      result => return result,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::clock::MockClock;
+
+  #[test]
+  fn heartbeats_without_audio_keep_the_input_alive() {
+    let clock = MockClock::new(1_000);
+    let mut dog =
+      InputWatchdog::new(Arc::new(clock.clone()), Duration::from_secs(2));
+    let heartbeat = dog.heartbeat();
+    // A silent loopback capture still beats on every event wait
+    for _ in 0..10 {
+      clock.advance(1_000);
+      heartbeat.beat();
+      assert_eq!(dog.check(), None);
+    }
+    clock.advance(2_500);
+    assert_eq!(
+      dog.check(),
+      Some(WatchdogEvent::Stalled(Duration::from_millis(2_500)))
+    );
+    assert!(dog.is_stalled());
+    clock.advance(1_000);
+    assert_eq!(dog.check(), None);
+    heartbeat.beat();
+    assert_eq!(dog.check(), Some(WatchdogEvent::Resumed));
+    assert!(!dog.is_stalled());
+  }
+
+  #[test]
+  fn capture_moves_to_a_new_device_only_when_the_old_one_is_lost() {
+    #[derive(Debug, PartialEq)]
+    enum Failure {
+      Lost,
+      Other,
+    }
+    let mut used = Vec::new();
+    let mut next = 1;
+    let result = capture_following(
+      0,
+      |&device| {
+        used.push(device);
+        match device {
+          0 | 1 => Err(Failure::Lost),
+          _ => Err(Failure::Other),
+        }
+      },
+      |e| *e == Failure::Lost,
+      || {
+        next += 1;
+        next - 1
+      },
+    );
+    assert_eq!(result, Err(Failure::Other));
+    assert_eq!(used, [0, 1, 2]);
+
+    // A capture that ends normally is not restarted
+    let mut runs = 0;
+    let result = capture_following(
+      0,
+      |_| -> Result<(), Failure> {
+        runs += 1;
+        Ok(())
+      },
+      |_| true,
+      || unreachable!(),
+    );
+    assert_eq!((result, runs), (Ok(()), 1));
+  }
+}
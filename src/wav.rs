@@ -0,0 +1,322 @@
+// RIFF/WAVE header parsing for `udp_sender --input file`.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::packet::{Meta, MetaError, SampleFormat};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug)]
+pub enum WavError {
+  /// Shorter than the 12-byte RIFF header.
+  TooShort,
+  NotWave,
+  /// The chunks ran out before a `data` chunk.
+  MissingData,
+  DataBeforeFmt,
+  FmtTooShort(u32),
+  ExtensibleTooShort,
+  UnsupportedBits(u16),
+  UnsupportedEncoding {
+    tag: u16,
+    bits: u16,
+  },
+  Meta(MetaError),
+  Io(io::Error),
+}
+
+impl core::fmt::Display for WavError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      WavError::TooShort => write!(f, "file too short"),
+      WavError::NotWave => write!(f, "not a RIFF/WAVE file"),
+      WavError::MissingData => write!(f, "missing data chunk in WAV file"),
+      WavError::DataBeforeFmt => write!(f, "data chunk before fmt chunk"),
+      WavError::FmtTooShort(len) => {
+        write!(f, "fmt chunk too short ({len} bytes)")
+      }
+      WavError::ExtensibleTooShort => {
+        write!(f, "WAVE_FORMAT_EXTENSIBLE fmt chunk too short")
+      }
+      WavError::UnsupportedBits(bits) => write!(
+        f,
+        "unsupported PCM bit depth {bits} (supported: 16-bit PCM, 32-bit \
+         float)"
+      ),
+      WavError::UnsupportedEncoding { tag, bits } => write!(
+        f,
+        "unsupported WAV encoding (format tag {tag:#06x}, {bits} bits; \
+         supported: 16-bit PCM, 32-bit float)"
+      ),
+      WavError::Meta(e) => write!(f, "{e}"),
+      WavError::Io(e) => write!(f, "{e}"),
+    }
+  }
+}
+
+impl std::error::Error for WavError {}
+
+impl From<io::Error> for WavError {
+  fn from(e: io::Error) -> Self {
+    WavError::Io(e)
+  }
+}
+
+/// Parses RIFF/WAVE chunks up to `data`, leaving `r` positioned at the start
+/// of the sample data. Returns the stream metadata and the data length.
+pub fn read_wav_header<R: Read + Seek>(
+  r: &mut R,
+) -> Result<(Meta, u64), WavError> {
+  let mut riff = [0u8; 12];
+  r.read_exact(&mut riff).map_err(|_| WavError::TooShort)?;
+  if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+    return Err(WavError::NotWave);
+  }
+
+  let mut meta: Option<Meta> = None;
+  loop {
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)
+      .map_err(|_| WavError::MissingData)?;
+    let id = &header[0..4];
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    match id {
+      b"fmt " => {
+        if len < 16 {
+          return Err(WavError::FmtTooShort(len));
+        }
+        let mut fmt = vec![0u8; len as usize];
+        r.read_exact(&mut fmt)?;
+        meta = Some(parse_fmt_chunk(&fmt)?);
+      }
+      b"data" => {
+        let meta = meta.ok_or(WavError::DataBeforeFmt)?;
+        return Ok((meta, len as u64));
+      }
+      _ => {
+        r.seek(SeekFrom::Current(len as i64))?;
+      }
+    }
+    // Chunks are padded to an even size
+    if !len.is_multiple_of(2) {
+      r.seek(SeekFrom::Current(1))?;
+    }
+  }
+}
+
+// `fmt` holds at least the 16 bytes every fmt chunk has
+fn parse_fmt_chunk(fmt: &[u8]) -> Result<Meta, WavError> {
+  let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+  let mut tag = u16_at(0);
+  let channels = u16_at(2);
+  let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+  let bits = u16_at(14);
+  if tag == WAVE_FORMAT_EXTENSIBLE {
+    // The first two bytes of the SubFormat GUID carry the real format tag
+    if fmt.len() < 26 {
+      return Err(WavError::ExtensibleTooShort);
+    }
+    tag = u16_at(24);
+  }
+  let sample_format = match (tag, bits) {
+    (WAVE_FORMAT_PCM, 16) => SampleFormat::I16,
+    (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::F32,
+    (WAVE_FORMAT_PCM, b) => return Err(WavError::UnsupportedBits(b)),
+    (tag, bits) => return Err(WavError::UnsupportedEncoding { tag, bits }),
+  };
+  Meta::new(channels, sample_rate, sample_format).map_err(WavError::Meta)
+}
+
+/// The whole frames of a WAV data chunk. With `looping`, reading carries
+/// on from the start of the data at its end, so a pass that ends mid-read
+/// runs seamlessly into the next.
+pub struct DataReader<R> {
+  inner: io::Take<R>,
+  start: u64,
+  len: u64,
+  looping: bool,
+}
+
+impl<R: Read + Seek> DataReader<R> {
+  /// `r` must be positioned at the start of the data, as `read_wav_header`
+  /// leaves it. A trailing partial frame is never read.
+  pub fn new(
+    mut r: R,
+    data_len: u64,
+    frame_size: usize,
+    looping: bool,
+  ) -> io::Result<Self> {
+    let start = r.stream_position()?;
+    let len = data_len - data_len % frame_size.max(1) as u64;
+    Ok(Self {
+      inner: r.take(len),
+      start,
+      len,
+      looping,
+    })
+  }
+}
+
+impl<R: Read + Seek> Read for DataReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    if n > 0 || buf.is_empty() || !self.looping || self.len == 0 {
+      return Ok(n);
+    }
+    self.inner.get_mut().seek(SeekFrom::Start(self.start))?;
+    self.inner.set_limit(self.len);
+    self.inner.read(buf)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+
+  fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = id.to_vec();
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if !body.len().is_multiple_of(2) {
+      out.push(0);
+    }
+    out
+  }
+
+  fn fmt_body(tag: u16, channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let mut out = Vec::new();
+    out.extend_from_slice(&tag.to_le_bytes());
+    out.extend_from_slice(&channels.to_le_bytes());
+    out.extend_from_slice(&rate.to_le_bytes());
+    out.extend_from_slice(&(rate * u32::from(block_align)).to_le_bytes());
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&bits.to_le_bytes());
+    out
+  }
+
+  fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let body: Vec<u8> = chunks.concat();
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(4 + body.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(&body);
+    out
+  }
+
+  fn read(bytes: Vec<u8>) -> Result<(Meta, u64, u64), WavError> {
+    let mut r = Cursor::new(bytes);
+    let (meta, len) = read_wav_header(&mut r)?;
+    Ok((meta, len, r.position()))
+  }
+
+  #[test]
+  fn reads_pcm_and_float() {
+    let file = wav(&[
+      chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 2, 48_000, 16)),
+      chunk(b"data", &[0; 8]),
+    ]);
+    let (meta, len, pos) = read(file).unwrap();
+    assert_eq!(meta, Meta::new(2, 48_000, SampleFormat::I16).unwrap());
+    assert_eq!((len, pos), (8, 12 + 24 + 8));
+
+    let file = wav(&[
+      chunk(b"fmt ", &fmt_body(WAVE_FORMAT_IEEE_FLOAT, 1, 44_100, 32)),
+      chunk(b"data", &[]),
+    ]);
+    let (meta, _, _) = read(file).unwrap();
+    assert_eq!(meta, Meta::new(1, 44_100, SampleFormat::F32).unwrap());
+  }
+
+  #[test]
+  fn reads_the_subformat_of_extensible() {
+    let mut fmt = fmt_body(WAVE_FORMAT_EXTENSIBLE, 2, 48_000, 32);
+    // cbSize, valid bits, channel mask, then the SubFormat GUID
+    fmt.extend_from_slice(&22u16.to_le_bytes());
+    fmt.extend_from_slice(&32u16.to_le_bytes());
+    fmt.extend_from_slice(&3u32.to_le_bytes());
+    fmt.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+    fmt.extend_from_slice(&[0; 14]);
+    let file = wav(&[chunk(b"fmt ", &fmt), chunk(b"data", &[0; 8])]);
+    let (meta, _, _) = read(file).unwrap();
+    assert_eq!(meta, Meta::new(2, 48_000, SampleFormat::F32).unwrap());
+
+    // Without room for the SubFormat
+    let fmt = fmt_body(WAVE_FORMAT_EXTENSIBLE, 2, 48_000, 32);
+    let file = wav(&[chunk(b"fmt ", &fmt), chunk(b"data", &[])]);
+    assert!(matches!(read(file), Err(WavError::ExtensibleTooShort)));
+  }
+
+  #[test]
+  fn skips_the_pad_byte_after_an_odd_sized_chunk() {
+    let file = wav(&[
+      chunk(b"LIST", b"abc"),
+      chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 2, 48_000, 16)),
+      chunk(b"data", &[1, 2, 3, 4]),
+    ]);
+    let (_, len, pos) = read(file).unwrap();
+    assert_eq!((len, pos), (4, 12 + 12 + 24 + 8));
+  }
+
+  #[test]
+  fn rejects_a_missing_fmt_chunk() {
+    let file = wav(&[chunk(b"data", &[0; 4])]);
+    assert!(matches!(read(file), Err(WavError::DataBeforeFmt)));
+  }
+
+  #[test]
+  fn rejects_truncated_input() {
+    let file = wav(&[
+      chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 2, 48_000, 16)),
+      chunk(b"data", &[0; 4]),
+    ]);
+    assert!(matches!(read(file[..8].to_vec()), Err(WavError::TooShort)));
+    // Inside the fmt chunk
+    assert!(matches!(read(file[..30].to_vec()), Err(WavError::Io(_))));
+    // Before the data chunk header
+    assert!(matches!(
+      read(file[..40].to_vec()),
+      Err(WavError::MissingData)
+    ));
+  }
+
+  #[test]
+  fn rejects_unsupported_encodings() {
+    let file = wav(&[
+      chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 2, 48_000, 24)),
+      chunk(b"data", &[]),
+    ]);
+    assert!(matches!(read(file), Err(WavError::UnsupportedBits(24))));
+    let file = wav(&[chunk(b"fmt ", &[0; 8]), chunk(b"data", &[])]);
+    assert!(matches!(read(file), Err(WavError::FmtTooShort(8))));
+  }
+
+  #[test]
+  fn data_reader_loops_whole_frames() {
+    // 2 leading bytes, 5 data bytes of 2-byte frames, then trailing junk
+    let file = [9, 9, 1, 2, 3, 4, 5, 8, 8];
+    let mut r = Cursor::new(&file[..]);
+    r.set_position(2);
+    let mut data = DataReader::new(r, 5, 2, true).unwrap();
+    let mut buf = [0u8; 10];
+    data.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 4, 1, 2, 3, 4, 1, 2]);
+
+    let mut r = Cursor::new(&file[..]);
+    r.set_position(2);
+    let mut data = DataReader::new(r, 5, 2, false).unwrap();
+    let mut out = Vec::new();
+    data.read_to_end(&mut out).unwrap();
+    assert_eq!(out, [1, 2, 3, 4]);
+
+    // Data too short for one frame ends instead of spinning
+    let mut r = Cursor::new(&file[..]);
+    r.set_position(2);
+    let mut data = DataReader::new(r, 1, 2, true).unwrap();
+    assert_eq!(data.read(&mut buf).unwrap(), 0);
+  }
+}